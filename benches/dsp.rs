@@ -0,0 +1,41 @@
+//! `cargo bench` for the EQ kernels: an 8-band chain over a second of
+//! stereo audio, on the native kernel (SIMD on aarch64) and the scalar one.
+//! The crate has no library target, so the modules are included by path.
+
+use std::hint::black_box;
+
+use criterion::{Criterion, Throughput, criterion_group, criterion_main};
+
+#[path = "../src/bench.rs"]
+#[allow(dead_code, unused_imports)]
+mod bench;
+#[path = "../src/eq.rs"]
+#[allow(dead_code, unused_imports)]
+mod eq;
+#[path = "../src/response.rs"]
+#[allow(dead_code, unused_imports)]
+mod response;
+
+use bench::{CHANNELS, FRAMES, NATIVE, SAMPLE_RATE};
+use eq::ParametricEq;
+
+fn eq_kernels(c: &mut Criterion) {
+    let mut data = bench::signal(FRAMES);
+    let mut group = c.benchmark_group(format!("{}_bands", bench::BANDS));
+    group.throughput(Throughput::Elements(data.len() as u64));
+    if NATIVE != "scalar" {
+        let mut native = ParametricEq::from_profile(&bench::profile(), SAMPLE_RATE, CHANNELS);
+        group.bench_function(NATIVE, |b| {
+            b.iter(|| native.process_block(black_box(&mut data)))
+        });
+    }
+    let mut scalar =
+        ParametricEq::from_profile(&bench::profile(), SAMPLE_RATE, CHANNELS).to_scalar();
+    group.bench_function("scalar", |b| {
+        b.iter(|| scalar.process_block(black_box(&mut data)))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, eq_kernels);
+criterion_main!(benches);
@@ -0,0 +1,112 @@
+//! Throughput of the EQ kernels, for `--bench-dsp` and `benches/dsp.rs`.
+
+use std::time::{Duration, Instant};
+
+use crate::eq::{EqProfile, Filter, FilterType, ParametricEq};
+
+pub const BANDS: usize = 8;
+pub const SAMPLE_RATE: f32 = 48000.0;
+pub const CHANNELS: usize = 2;
+/// One second of stereo audio per pass
+pub const FRAMES: usize = 48000;
+
+/// The kernel [`ParametricEq::process_block`] runs on this architecture.
+pub const NATIVE: &str = if cfg!(target_arch = "aarch64") {
+    "simd"
+} else {
+    "scalar"
+};
+
+/// `BANDS` peaking bands spread over the audible range.
+pub fn profile() -> EqProfile {
+    let filters = (0..BANDS)
+        .map(|i| Filter {
+            filter_type: FilterType::Peaking,
+            frequency: 40.0 * 2.0f64.powi(i as i32),
+            gain: if i % 2 == 0 { 3.0 } else { -3.0 },
+            q_factor: 1.0,
+            ..Default::default()
+        })
+        .collect();
+    EqProfile {
+        preamp_db: -3.0,
+        filters,
+        ..Default::default()
+    }
+}
+
+/// Interleaved white noise at -6 dBFS, the same on every run.
+pub fn signal(frames: usize) -> Vec<f32> {
+    let mut state = 0x2545_f491u32;
+    (0..frames * CHANNELS)
+        .map(|_| {
+            // xorshift32
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as f32 / u32::MAX as f32 - 0.5
+        })
+        .collect()
+}
+
+/// Samples per second `process` gets through, running it over `data` again
+/// and again for at least `duration`.
+pub fn throughput(
+    mut process: impl FnMut(&mut [f32]),
+    data: &mut [f32],
+    duration: Duration,
+) -> f64 {
+    let start = Instant::now();
+    let mut samples = 0;
+    while start.elapsed() < duration {
+        process(data);
+        samples += data.len();
+    }
+    samples as f64 / start.elapsed().as_secs_f64()
+}
+
+/// Prints the throughput of the native kernel and, where that is SIMD, of
+/// the scalar one too.
+pub fn print_report() {
+    println!(
+        "{} bands, {} channels at {} Hz",
+        BANDS, CHANNELS, SAMPLE_RATE
+    );
+    if NATIVE != "scalar" {
+        let mut native = ParametricEq::from_profile(&profile(), SAMPLE_RATE, CHANNELS);
+        report(NATIVE, |data| native.process_block(data));
+    }
+    let mut scalar = ParametricEq::from_profile(&profile(), SAMPLE_RATE, CHANNELS).to_scalar();
+    report("scalar", |data| scalar.process_block(data));
+}
+
+fn report(name: &str, process: impl FnMut(&mut [f32])) {
+    let mut data = signal(FRAMES);
+    let rate = throughput(process, &mut data, Duration::from_secs(1));
+    println!(
+        "{:>6}: {:.1} M samples/s, {:.2} ns per sample and band",
+        name,
+        rate / 1e6,
+        1e9 / (rate * BANDS as f64)
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_throughput() {
+        let profile = profile();
+        assert_eq!(profile.active_filters().count(), BANDS);
+        let mut eq = ParametricEq::from_profile(&profile, SAMPLE_RATE, CHANNELS);
+        let mut data = signal(480);
+        let rate = throughput(
+            |data| eq.process_block(data),
+            &mut data,
+            Duration::from_millis(10),
+        );
+        assert!(rate > 0.0);
+        assert!(data.iter().all(|sample| sample.is_finite()));
+    }
+}
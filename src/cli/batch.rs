@@ -0,0 +1,268 @@
+use std::{
+    io::IsTerminal,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU32, AtomicUsize, Ordering},
+};
+
+use anyhow::{Context, Result, bail};
+use clap::Args;
+use rayon::prelude::*;
+
+use crate::{
+    eq::{EqProfile, ParametricEq},
+    wav::Wav,
+};
+
+#[derive(Debug, Args)]
+pub struct BatchArgs {
+    /// Equalizer APO text or JSON profile
+    #[clap(long, short, visible_alias = "eq")]
+    pub eq_file: PathBuf,
+    /// Directory of WAV files, searched recursively
+    pub input: PathBuf,
+    /// Where the processed files go, under the same relative paths
+    pub output: PathBuf,
+}
+
+/// Files under `dir`, relative to it, in a stable order.
+fn relative_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut pending = vec![PathBuf::new()];
+    while let Some(relative) = pending.pop() {
+        let entries = std::fs::read_dir(dir.join(&relative))
+            .with_context(|| format!("Failed to read {}", dir.join(&relative).display()))?;
+        for entry in entries {
+            let entry = entry?;
+            let path = relative.join(entry.file_name());
+            if entry.file_type()?.is_dir() {
+                pending.push(path);
+            } else {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+fn is_wav(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("wav"))
+}
+
+/// Frames between progress reports, a second or so at common rates.
+const PROGRESS_FRAMES: usize = 65536;
+const BAR_WIDTH: usize = 30;
+
+/// Runs one file through a fresh EQ at its own sample rate and writes it
+/// in the format it came in. `progress` gets the fraction of the frames
+/// processed every [`PROGRESS_FRAMES`], and 1.0 once all are.
+fn process_file(
+    profile: &EqProfile,
+    input: &Path,
+    output: &Path,
+    mut progress: impl FnMut(f32),
+) -> Result<usize> {
+    let bytes =
+        std::fs::read(input).with_context(|| format!("Failed to read {}", input.display()))?;
+    let mut wav = Wav::parse(&bytes)?;
+    let channels = wav.format.channels as usize;
+    let mut eq = ParametricEq::from_profile(profile, wav.format.sample_rate as f32, channels);
+    let total = wav.samples.len();
+    let mut done = 0;
+    for chunk in wav.samples.chunks_mut(PROGRESS_FRAMES * channels) {
+        eq.process_block(chunk);
+        done += chunk.len();
+        progress(done as f32 / total as f32);
+    }
+    progress(1.0);
+    if let Some(parent) = output.parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create {}", parent.display()))?;
+    }
+    std::fs::write(output, wav.to_bytes())
+        .with_context(|| format!("Failed to write {}", output.display()))?;
+    Ok(wav.frames())
+}
+
+/// `[#####     ]  50%`
+fn progress_bar(fraction: f32) -> String {
+    let fraction = fraction.clamp(0.0, 1.0);
+    let filled = (fraction * BAR_WIDTH as f32).round() as usize;
+    format!(
+        "[{}{}] {:3.0}%",
+        "#".repeat(filled),
+        " ".repeat(BAR_WIDTH - filled),
+        fraction * 100.0
+    )
+}
+
+pub fn batch(args: &BatchArgs) -> Result<()> {
+    let profile = EqProfile::load(&args.eq_file)?;
+    let (wavs, others): (Vec<_>, Vec<_>) = relative_files(&args.input)?
+        .into_iter()
+        .partition(|path| is_wav(path));
+    for path in &others {
+        eprintln!("warning: skipping {}: not a WAV file", path.display());
+    }
+
+    // The bar on stderr shows the mean of the files' fractions, redrawn
+    // when its percentage changes; only for a terminal
+    let show_bar = std::io::stderr().is_terminal();
+    let fractions: Vec<AtomicU32> = wavs.iter().map(|_| AtomicU32::new(0)).collect();
+    let shown_percent = AtomicU32::new(0);
+    let report = |index: usize, fraction: f32| {
+        fractions[index].store(fraction.to_bits(), Ordering::Relaxed);
+        let sum: f32 = fractions
+            .iter()
+            .map(|f| f32::from_bits(f.load(Ordering::Relaxed)))
+            .sum();
+        let overall = sum / wavs.len() as f32;
+        let percent = (overall * 100.0) as u32;
+        if show_bar && shown_percent.swap(percent, Ordering::Relaxed) != percent {
+            eprint!("\r{}", progress_bar(overall));
+        }
+    };
+    let done = AtomicUsize::new(0);
+    let failed = AtomicUsize::new(0);
+    wavs.par_iter().enumerate().for_each(|(index, path)| {
+        let result = process_file(
+            &profile,
+            &args.input.join(path),
+            &args.output.join(path),
+            |fraction| report(index, fraction),
+        );
+        // A failed file counts as done for the bar
+        report(index, 1.0);
+        let n = done.fetch_add(1, Ordering::Relaxed) + 1;
+        if show_bar {
+            // Clears the bar for the line; the next report draws it again
+            eprint!("\r\x1b[2K");
+            shown_percent.store(u32::MAX, Ordering::Relaxed);
+        }
+        match result {
+            Ok(frames) => println!(
+                "[{}/{}] {} ({} frames)",
+                n,
+                wavs.len(),
+                path.display(),
+                frames
+            ),
+            Err(e) => {
+                failed.fetch_add(1, Ordering::Relaxed);
+                eprintln!("[{}/{}] {}: {:#}", n, wavs.len(), path.display(), e);
+            }
+        }
+    });
+    let failed = failed.into_inner();
+    if failed > 0 {
+        bail!("{} of {} files failed", failed, wavs.len());
+    }
+    println!(
+        "Processed {} files into {}",
+        wavs.len(),
+        args.output.display()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wav::WavFormat;
+
+    #[test]
+    fn test_batch_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("in");
+        std::fs::create_dir_all(input.join("drums/kicks")).unwrap();
+        let wav = |channels, bits, float, frames: usize| Wav {
+            format: WavFormat {
+                channels,
+                sample_rate: 44100,
+                bits,
+                float,
+            },
+            samples: (0..frames * channels as usize)
+                .map(|i| (i as f32 * 0.01).sin() * 0.5)
+                .collect(),
+        };
+        let stereo = wav(2, 16, false, 1000);
+        let mono = wav(1, 32, true, 500);
+        std::fs::write(input.join("pad.wav"), stereo.to_bytes()).unwrap();
+        std::fs::write(input.join("drums/kicks/kick.WAV"), mono.to_bytes()).unwrap();
+        std::fs::write(input.join("drums/readme.txt"), "not audio").unwrap();
+        // Half the level and nothing else
+        let eq_file = dir.path().join("eq.txt");
+        std::fs::write(&eq_file, "Preamp: -6.0206 dB\n").unwrap();
+
+        let args = BatchArgs {
+            eq_file,
+            input,
+            output: dir.path().join("out"),
+        };
+        batch(&args).unwrap();
+
+        let read =
+            |path: &str| Wav::parse(&std::fs::read(args.output.join(path)).unwrap()).unwrap();
+        let pad = read("pad.wav");
+        assert_eq!(pad.format, stereo.format);
+        assert_eq!(pad.frames(), 1000);
+        for (out, original) in pad.samples.iter().zip(&stereo.samples) {
+            assert!((out - original / 2.0).abs() < 1e-4, "{} {}", out, original);
+        }
+        let kick = read("drums/kicks/kick.WAV");
+        assert_eq!(kick.format, mono.format);
+        assert_eq!(kick.frames(), 500);
+        assert!(!args.output.join("drums/readme.txt").exists());
+
+        // A broken WAV fails the run but not the other files
+        std::fs::write(args.input.join("broken.wav"), "RIFF").unwrap();
+        std::fs::remove_dir_all(&args.output).unwrap();
+        assert!(batch(&args).is_err());
+        assert!(args.output.join("pad.wav").exists());
+    }
+
+    #[test]
+    fn test_progress_reaches_end() {
+        let dir = tempfile::tempdir().unwrap();
+        let wav = Wav {
+            format: WavFormat {
+                channels: 2,
+                sample_rate: 48000,
+                bits: 24,
+                float: false,
+            },
+            samples: vec![0.1; (PROGRESS_FRAMES * 5 / 2) * 2],
+        };
+        let input = dir.path().join("long.wav");
+        std::fs::write(&input, wav.to_bytes()).unwrap();
+        let mut reports = Vec::new();
+        let frames = process_file(
+            &EqProfile::default(),
+            &input,
+            &dir.path().join("out.wav"),
+            |fraction| reports.push(fraction),
+        )
+        .unwrap();
+        assert_eq!(frames, PROGRESS_FRAMES * 5 / 2);
+        // Every 65536 frames, then the end
+        assert_eq!(reports.len(), 4, "{:?}", reports);
+        assert!((reports[0] - 0.4).abs() < 1e-6);
+        assert!(reports.windows(2).all(|w| w[0] <= w[1]));
+        assert!((reports.last().unwrap() - 1.0).abs() < 1e-6);
+
+        assert_eq!(
+            progress_bar(0.0),
+            format!("[{}]   0%", " ".repeat(BAR_WIDTH))
+        );
+        assert_eq!(
+            progress_bar(0.5),
+            format!("[{}{}]  50%", "#".repeat(15), " ".repeat(15))
+        );
+        assert_eq!(
+            progress_bar(2.0),
+            format!("[{}] 100%", "#".repeat(BAR_WIDTH))
+        );
+    }
+}
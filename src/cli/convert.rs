@@ -0,0 +1,151 @@
+use std::{io::Write, path::PathBuf, str::FromStr};
+
+use anyhow::{Context, Result, bail};
+use clap::{Args, ValueEnum};
+
+use crate::{
+    eq::EqProfile,
+    graphic_eq::{GraphicEq, STANDARD_FREQS},
+    wav::wav_bytes,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum Format {
+    /// Equalizer APO text
+    Apo,
+    Json,
+    /// An Equalizer APO `GraphicEQ:` line, sampled at the 31 standard
+    /// frequencies; imported by fitting peaking filters
+    GraphicEq,
+    /// CamillaDSP YAML filters and pipeline (export only)
+    Camilladsp,
+    /// Impulse response as a mono 32-bit float WAV for convolution engines
+    /// (export only, by `convert`)
+    Wav,
+}
+
+impl Format {
+    pub fn parse(&self, content: &str, fs: f64) -> Result<EqProfile> {
+        match self {
+            Self::Apo => Ok(EqProfile::from_str(content)?),
+            Self::Json => Ok(EqProfile::from_json(content)?),
+            Self::GraphicEq => Ok(GraphicEq::from_str(content)?.to_profile(fs)),
+            Self::Camilladsp => bail!("CamillaDSP is an export-only format"),
+            Self::Wav => bail!("WAV is an export-only format"),
+        }
+    }
+
+    /// The profile as text; WAV files are binary and written by `convert`.
+    pub fn write(&self, profile: &EqProfile, fs: f64) -> Result<String> {
+        Ok(match self {
+            Self::Apo => profile.to_string(),
+            Self::Json => profile.to_json()?,
+            Self::GraphicEq => GraphicEq::from_profile(profile, &STANDARD_FREQS, fs).to_string(),
+            Self::Camilladsp => profile.to_camilladsp_yaml(fs),
+            Self::Wav => bail!("WAV impulse responses can only be written by convert"),
+        })
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct ConvertArgs {
+    /// Input format; sniffed from the file when omitted
+    #[clap(long)]
+    pub from: Option<Format>,
+    #[clap(long)]
+    pub to: Format,
+    /// Sample rate used by formats that depend on it
+    #[clap(long, default_value_t = 48000.0)]
+    pub fs: f64,
+    /// Sample a GraphicEQ at this many log-spaced frequencies instead of the
+    /// 31 standard ones
+    #[clap(long)]
+    pub points: Option<usize>,
+    /// Length of a WAV impulse response in samples; linear phase rounds it
+    /// up to an odd number
+    #[clap(long, default_value_t = 4096)]
+    pub taps: usize,
+    /// Make a WAV impulse response minimum phase instead of linear phase:
+    /// no delay or pre-ringing, but the phase shifts of the filters
+    #[clap(long)]
+    pub minimum_phase: bool,
+    pub input: PathBuf,
+    /// Output file; stdout when omitted
+    pub output: Option<PathBuf>,
+}
+
+pub fn convert(args: &ConvertArgs) -> Result<()> {
+    let profile = match args.from {
+        Some(format) => {
+            let content = std::fs::read_to_string(&args.input)
+                .with_context(|| format!("Failed to read {}", args.input.display()))?;
+            format.parse(&content, args.fs)?
+        }
+        None => EqProfile::load(&args.input)?,
+    };
+    let output = match (args.to, args.points) {
+        (Format::GraphicEq, Some(points)) => {
+            GraphicEq::from_profile(&profile, &GraphicEq::log_freqs(points), args.fs)
+                .to_string()
+                .into_bytes()
+        }
+        (Format::Wav, _) => {
+            if args.taps == 0 {
+                bail!("--taps must be at least 1");
+            }
+            let ir = profile.to_impulse_response(args.taps, args.fs, args.minimum_phase);
+            wav_bytes(&ir, args.fs.round() as u32)
+        }
+        _ => args.to.write(&profile, args.fs)?.into_bytes(),
+    };
+    match args.output.as_ref() {
+        Some(path) => std::fs::write(path, output)
+            .with_context(|| format!("Failed to write {}", path.display()))?,
+        None => std::io::stdout()
+            .write_all(&output)
+            .context("Failed to write to stdout")?,
+    }
+    eprintln!("Converted {} filters.", profile.filters.len());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apo_json_apo() {
+        let apo = "Preamp: -6.2 dB
+Filter 1: ON PK Fc 105 Hz Gain 4.1 dB Q 0.7
+Filter 2: OFF LSC Fc 50 Hz Gain -2 dB Q 0.71
+Filter 3: ON HSC Fc 9000 Hz Gain 3.3 dB Q 0.5
+Filter 4: ON HPQ Fc 25 Hz Q 0.707
+";
+        let profile = Format::Apo.parse(apo, 48000.0).unwrap();
+        assert_eq!(profile.filters.len(), 4);
+
+        let json = Format::Json.write(&profile, 48000.0).unwrap();
+        let from_json = Format::Json.parse(&json, 48000.0).unwrap();
+        assert_eq!(from_json, profile);
+
+        let apo_again = Format::Apo.write(&from_json, 48000.0).unwrap();
+        assert_eq!(apo_again, apo);
+        assert_eq!(Format::Apo.parse(&apo_again, 48000.0).unwrap(), profile);
+    }
+
+    #[test]
+    fn test_camilladsp_is_export_only() {
+        assert!(Format::Camilladsp.parse("filters: {}", 48000.0).is_err());
+        let profile = Format::Apo
+            .parse("Filter 1: ON PK Fc 100 Gain 1 Q 1", 48000.0)
+            .unwrap();
+        assert!(
+            Format::Camilladsp
+                .write(&profile, 48000.0)
+                .unwrap()
+                .contains("band_1")
+        );
+        assert!(Format::Wav.parse("RIFF", 48000.0).is_err());
+        assert!(Format::Wav.write(&profile, 48000.0).is_err());
+    }
+}
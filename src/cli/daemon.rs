@@ -0,0 +1,196 @@
+//! Daemon mode: the session verbs as line-delimited commands over a Unix
+//! domain socket. Each request line gets exactly one reply line.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+
+use crate::cli::session::Session;
+
+/// In the per-user `$XDG_RUNTIME_DIR` when there is one, else the temp
+/// directory.
+pub fn default_socket() -> PathBuf {
+    std::env::var_os("XDG_RUNTIME_DIR")
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_absolute())
+        .unwrap_or_else(std::env::temp_dir)
+        .join("eq_layer.sock")
+}
+
+#[cfg(unix)]
+pub use unix::{Daemon, send};
+
+#[cfg(unix)]
+mod unix {
+    use std::{
+        fs::Permissions,
+        io::{BufRead, BufReader, Write},
+        os::unix::{
+            fs::PermissionsExt,
+            net::{UnixListener, UnixStream},
+        },
+        path::{Path, PathBuf},
+    };
+
+    use anyhow::{Result, bail};
+    use tracing::{info, warn};
+
+    use crate::cli::session::{Reply, Session};
+
+    pub struct Daemon {
+        listener: UnixListener,
+        path: PathBuf,
+    }
+
+    impl Daemon {
+        pub fn bind(path: &Path) -> Result<Self> {
+            if path.exists() {
+                if UnixStream::connect(path).is_ok() {
+                    bail!("Another daemon is already listening on {}", path.display());
+                }
+                // Stale socket left behind by a daemon that didn't exit cleanly
+                std::fs::remove_file(path)?;
+            }
+            let listener = UnixListener::bind(path)?;
+            // Only the owner may connect, even in a shared temp directory
+            std::fs::set_permissions(path, Permissions::from_mode(0o600))?;
+            info!("Daemon listening on {}", path.display());
+            Ok(Self {
+                listener,
+                path: path.to_path_buf(),
+            })
+        }
+
+        /// Serves clients one at a time until one of them sends `quit`. A
+        /// client that fails is dropped; the daemon keeps serving.
+        pub fn serve(self, session: &mut Session) -> Result<()> {
+            for stream in self.listener.incoming() {
+                let (mut writer, reader) = match stream.and_then(|s| Ok((s.try_clone()?, s))) {
+                    Ok((writer, stream)) => (writer, BufReader::new(stream)),
+                    Err(e) => {
+                        warn!("Failed to accept client: {:?}", e);
+                        continue;
+                    }
+                };
+                for line in reader.lines() {
+                    let line = match line {
+                        Ok(line) => line,
+                        Err(e) => {
+                            warn!("Failed to read from client: {:?}", e);
+                            break;
+                        }
+                    };
+                    let (reply, quit) = match session.handle(&line) {
+                        Ok(Reply::Message(message)) => (message, false),
+                        Ok(Reply::Quit) => ("Quitting...".to_string(), true),
+                        Err(e) => (format!("error: {:#}", e), false),
+                    };
+                    if let Err(e) = writeln!(writer, "{}", reply.replace('\n', " ")) {
+                        warn!("Failed to reply to client: {:?}", e);
+                        break;
+                    }
+                    if quit {
+                        session.stop();
+                        return Ok(());
+                    }
+                }
+            }
+            Ok(())
+        }
+    }
+
+    impl Drop for Daemon {
+        fn drop(&mut self) {
+            std::fs::remove_file(&self.path).ok();
+        }
+    }
+
+    /// Client helper: sends one command and returns the daemon's reply.
+    pub fn send(path: &Path, command: &str) -> Result<String> {
+        let mut stream = UnixStream::connect(path)?;
+        writeln!(stream, "{}", command)?;
+        let mut reply = String::new();
+        BufReader::new(stream).read_line(&mut reply)?;
+        Ok(reply.trim_end().to_string())
+    }
+}
+
+#[cfg(not(unix))]
+pub fn run_daemon(_socket: &Path, _session: &mut Session) -> Result<()> {
+    anyhow::bail!("Daemon mode requires Unix domain sockets")
+}
+
+#[cfg(not(unix))]
+pub fn send(_socket: &Path, _command: &str) -> Result<String> {
+    anyhow::bail!("Daemon mode requires Unix domain sockets")
+}
+
+#[cfg(unix)]
+pub fn run_daemon(socket: &Path, session: &mut Session) -> Result<()> {
+    Daemon::bind(socket)?.serve(session)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::thread;
+
+    use super::*;
+    use crate::{
+        settings::{PersistentSettings, Settings},
+        testing::MockEngine,
+    };
+
+    #[test]
+    fn test_start_stop_over_socket() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("eq_layer.sock");
+        let engine = MockEngine::default();
+        let settings = Settings::from(&PersistentSettings::default());
+        let mut session = Session::new(settings, Default::default(), None, engine.spawn());
+        session.start();
+
+        let daemon = Daemon::bind(&socket).unwrap();
+        let server = thread::spawn(move || daemon.serve(&mut session));
+
+        assert_eq!(send(&socket, "status").unwrap(), "running eq=on");
+        assert_eq!(send(&socket, "stop").unwrap(), "Stopped.");
+        assert_eq!(engine.active(), 0);
+        assert_eq!(send(&socket, "status").unwrap(), "stopped eq=on");
+        assert_eq!(send(&socket, "start").unwrap(), "Started.");
+        assert_eq!(engine.active(), 1);
+        assert_eq!(send(&socket, "d").unwrap(), "EQ disabled.");
+        // Profiles go to the running engine without a restart
+        let eq_file = dir.path().join("eq.txt");
+        std::fs::write(&eq_file, "Preamp: -3 dB\n").unwrap();
+        let load = format!("load {}", eq_file.display());
+        assert!(send(&socket, &load).unwrap().starts_with("Loaded"));
+        assert_eq!(engine.next_profile().unwrap().preamp_db, -3.0);
+        assert_eq!(engine.peak(), 1);
+        assert!(
+            send(&socket, "set-band 1 gain 3")
+                .unwrap()
+                .starts_with("error:")
+        );
+        // A client sending garbage is dropped, not the daemon
+        let mut stream = std::os::unix::net::UnixStream::connect(&socket).unwrap();
+        std::io::Write::write_all(&mut stream, b"\xff\xfe\n").unwrap();
+        drop(stream);
+        assert_eq!(send(&socket, "status").unwrap(), "running eq=off");
+        assert_eq!(send(&socket, "quit").unwrap(), "Quitting...");
+
+        server.join().unwrap().unwrap();
+        assert_eq!(engine.active(), 0);
+        assert!(!socket.exists());
+    }
+
+    #[test]
+    fn test_socket_is_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("eq_layer.sock");
+        let _daemon = Daemon::bind(&socket).unwrap();
+        let mode = std::fs::metadata(&socket).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+}
@@ -0,0 +1,82 @@
+use std::path::PathBuf;
+
+use anyhow::{Context, Result, bail};
+use clap::Args;
+
+use crate::{cli::convert::Format, eq::EqProfile};
+
+#[derive(Debug, Args)]
+pub struct FitArgs {
+    /// Target curve: a frequency (Hz) and a gain (dB) per line, separated by
+    /// a comma, semicolon or whitespace
+    #[clap(long)]
+    pub target: PathBuf,
+    /// Most peaking filters to use
+    #[clap(long, default_value_t = 8)]
+    pub bands: usize,
+    #[clap(long, value_enum, default_value_t = Format::Apo)]
+    pub to: Format,
+    /// Sample rate the filters are designed for
+    #[clap(long, default_value_t = 48000.0)]
+    pub fs: f64,
+    /// Output file; stdout when omitted
+    pub output: Option<PathBuf>,
+}
+
+/// Reads (frequency, dB) pairs, skipping blank lines, `#` comments and a
+/// header line. The result is sorted by frequency.
+pub fn parse_target(content: &str) -> Result<Vec<(f64, f64)>> {
+    let mut points = Vec::new();
+    for (number, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut fields = line
+            .split(|c: char| c == ',' || c == ';' || c.is_whitespace())
+            .filter(|field| !field.is_empty())
+            .map(str::parse::<f64>);
+        match (fields.next(), fields.next()) {
+            (Some(Ok(freq)), Some(Ok(db))) => points.push((freq, db)),
+            // e.g. "frequency,raw"
+            _ if points.is_empty() => {}
+            _ => bail!("Line {}: expected frequency and gain: {}", number + 1, line),
+        }
+    }
+    if points.is_empty() {
+        bail!("No points in the target curve");
+    }
+    points.sort_by(|a, b| a.0.total_cmp(&b.0));
+    Ok(points)
+}
+
+pub fn fit(args: &FitArgs) -> Result<()> {
+    let content = std::fs::read_to_string(&args.target)
+        .with_context(|| format!("Failed to read {}", args.target.display()))?;
+    let points = parse_target(&content)?;
+    let profile = EqProfile::fit_to_target(&points, args.bands, args.fs);
+    let output = args.to.write(&profile, args.fs)?;
+    match args.output.as_ref() {
+        Some(path) => std::fs::write(path, output)
+            .with_context(|| format!("Failed to write {}", path.display()))?,
+        None => print!("{}", output),
+    }
+    eprintln!("Fitted {} filters.", profile.filters.len());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_target() {
+        let csv = "frequency,raw\n# comment\n1000, -1.5\n20;3\n\n20000\t-6e-1\n";
+        assert_eq!(
+            parse_target(csv).unwrap(),
+            [(20.0, 3.0), (1000.0, -1.5), (20000.0, -0.6)]
+        );
+        assert!(parse_target("20,1\n1000\n").is_err());
+        assert!(parse_target("frequency,raw\n").is_err());
+    }
+}
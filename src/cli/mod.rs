@@ -1,195 +1,340 @@
-use std::{
-    cell::RefCell,
-    io::Write,
-    str::FromStr,
-    sync::{Arc, atomic::AtomicBool},
-    thread,
-};
+use std::{io::Write, path::PathBuf, sync::atomic::Ordering, thread};
 
+use anyhow::{Context, Result, anyhow};
 use cpal::{
-    Host,
+    Device, Host,
     traits::{DeviceTrait, HostTrait},
 };
 
-use clap::Parser;
+use clap::{Parser, Subcommand};
+
+use crate::{
+    bench,
+    cli::{
+        batch::{BatchArgs, batch},
+        convert::{ConvertArgs, convert},
+        fit::{FitArgs, fit},
+        once::{RunArgs, run_once},
+        response::{ResponseArgs, response},
+        session::{Reply, Session, load_profile},
+        validate::{ValidateArgs, validate},
+    },
+    config::{CrossfeedConfig, DcBlockerConfig},
+    run, settings,
+};
 
-use crate::{eq, run, settings};
+mod batch;
+mod convert;
+mod daemon;
+pub mod fit;
+mod once;
+pub mod response;
+pub mod session;
+mod validate;
 
+/// Command-line interface. `main` dispatches here whenever any argument is
+/// given; a bare launch opens the GUI. Without a subcommand the top-level
+/// flags start an interactive (or `--daemon`) session.
 #[derive(Debug, Parser)]
 struct Args {
+    #[command(subcommand)]
+    pub command: Option<Commands>,
+    #[command(flatten)]
+    pub engine: EngineArgs,
     #[clap(long, short)]
+    pub list: bool,
+    /// Serve commands on a Unix socket instead of reading stdin
+    #[clap(long)]
+    pub daemon: bool,
+    /// Socket path for --daemon and --send
+    #[clap(long)]
+    pub socket: Option<PathBuf>,
+    /// Send one command to a running daemon and print its reply
+    #[clap(long)]
+    pub send: Option<String>,
+    /// Print the throughput of the EQ kernels
+    #[clap(long, hide = true)]
+    pub bench_dsp: bool,
+}
+
+/// Devices, settings and profile of the engine, for the interactive session
+/// and the `run` subcommand.
+#[derive(Debug, clap::Args)]
+struct EngineArgs {
+    /// Input device name or index from `--list`
+    #[clap(long, short, visible_alias = "input")]
     pub input_device: Option<String>,
-    #[clap(long, short)]
+    /// Output device name or index from `--list`
+    #[clap(long, short, visible_alias = "output")]
     pub output_device: Option<String>,
-    #[clap(long, short)]
-    pub list: bool,
+    /// Audio host backend, e.g. `jack` or `asio`; see `--list`
+    #[clap(long)]
+    pub host: Option<String>,
+    /// Record what an output device plays instead of an input device
+    /// (WASAPI and CoreAudio). `--input-device` then names the output device
+    /// to capture, defaulting to the system output.
+    #[clap(long)]
+    pub loopback: bool,
     #[clap(long, short = 'L', default_value_t = 100)]
     pub latency: u32,
-    #[clap(long, short)]
+    /// Fraction of the latency target buffered as silence at the start;
+    /// less lowers the latency but may gap if the input is slow to start
+    #[clap(long)]
+    pub priming: Option<f32>,
+    /// Ring buffer capacity as a multiple of the latency target (default 2);
+    /// more room for the input to run ahead under load
+    #[clap(long)]
+    pub ring_factor: Option<f32>,
+    /// Sample rate in Hz; chosen automatically when unsupported
+    #[clap(long)]
+    pub sample_rate: Option<u32>,
+    /// Buffer size in frames; chosen automatically when unsupported
+    #[clap(long)]
+    pub buffer_size: Option<u32>,
+    /// Linear-phase FIR EQ instead of biquads; adds about 6000 frames of latency
+    #[clap(long)]
+    pub linear_phase: bool,
+    /// Master volume in dB, applied after the EQ
+    #[clap(long, default_value_t = 0.0, allow_hyphen_values = true)]
+    pub output_gain: f32,
+    /// Stereo balance from -1 (left) to 1 (right)
+    #[clap(long, default_value_t = 0.0, allow_hyphen_values = true)]
+    pub balance: f32,
+    /// Swap the left and right channels
+    #[clap(long)]
+    pub swap_channels: bool,
+    /// Bleed some of each channel into the other, for headphones
+    #[clap(long)]
+    pub crossfeed: bool,
+    /// Don't remove DC offset before the EQ
+    #[clap(long)]
+    pub no_dc_blocker: bool,
+    /// Keep the profile's preamp while the EQ is toggled off
+    #[clap(long)]
+    pub matched_bypass: bool,
+    /// Equalizer APO text or JSON profile
+    #[clap(long, short, visible_alias = "eq")]
     pub eq_file: Option<String>,
 }
 
-fn cli_main() {
-    let mut args = Args::parse();
-    let host = cpal::default_host();
-    if args.list {
-        list_devices(&host);
-        return;
-    }
-    let devices = host.devices().unwrap();
-    let mut input_device = None;
-    let mut output_device = None;
+#[derive(Debug, Subcommand)]
+enum Commands {
+    /// Convert a profile between formats, e.g. `convert --to json in.txt out.json`
+    Convert(ConvertArgs),
+    /// Approximate a target curve with peaking filters, e.g. `fit --target target.csv --bands 8`
+    Fit(FitArgs),
+    /// Print a profile's frequency response, e.g. `response --eq eq.txt --format csv`
+    Response(ResponseArgs),
+    /// Check a profile for broken or unstable bands and clipping, e.g. `validate eq.txt`
+    Validate(ValidateArgs),
+    /// Run the engine for a while and exit, e.g. `run -i 0 -o 1 --eq eq.txt --duration 5`
+    Run(RunArgs),
+    /// Apply a profile to every WAV in a directory, e.g. `batch --eq eq.txt in/ out/`
+    Batch(BatchArgs),
+}
+
+/// Pairs each device with its display name, skipping devices without one.
+fn named_devices(devices: impl Iterator<Item = Device>) -> Vec<(String, Device)> {
+    devices
+        .filter_map(|device| {
+            let name = device.description().ok()?.name().to_string();
+            Some((name, device))
+        })
+        .collect()
+}
 
-    let mut eq_profile = if let Some(eq_file) = args.eq_file.as_ref() {
-        let eq_contents = std::fs::read_to_string(eq_file).expect("Failed to read EQ file");
-        eq::EqProfile::from_str(&eq_contents).expect("Failed to parse EQ profile")
+/// Picks a device by exact name, or by its index in `devices` when `spec` is
+/// a number that isn't also a device name.
+fn resolve_device<T>(devices: Vec<(String, T)>, spec: &str) -> Option<T> {
+    let index = if let Some(index) = devices.iter().position(|(name, _)| name == spec) {
+        index
     } else {
-        Default::default()
+        spec.parse::<usize>().ok().filter(|i| *i < devices.len())?
     };
+    devices.into_iter().nth(index).map(|(_, device)| device)
+}
 
-    for device in devices {
-        if device.name().as_ref().unwrap()
-            == args
-                .input_device
-                .as_ref()
-                .expect("Input device not specified")
-        {
-            input_device = Some(device);
-            continue;
-        }
-        if device.name().as_ref().unwrap()
-            == args
-                .output_device
-                .as_ref()
-                .expect("Output device not specified")
-        {
-            output_device = Some(device);
-        }
+fn find_input_device(host: &Host, spec: &str) -> Result<Device> {
+    resolve_device(named_devices(host.input_devices()?), spec)
+        .ok_or_else(|| anyhow!("Input device not found: {}", spec))
+}
+
+fn find_output_device(host: &Host, spec: &str) -> Result<Device> {
+    resolve_device(named_devices(host.output_devices()?), spec)
+        .ok_or_else(|| anyhow!("Output device not found: {}", spec))
+}
+
+pub fn cli_main() -> Result<()> {
+    let args = Args::parse();
+    match args.command {
+        Some(Commands::Convert(convert_args)) => return convert(&convert_args),
+        Some(Commands::Fit(fit_args)) => return fit(&fit_args),
+        Some(Commands::Response(response_args)) => return response(&response_args),
+        Some(Commands::Validate(validate_args)) => return validate(&validate_args),
+        Some(Commands::Run(run_args)) => return run_once(&run_args),
+        Some(Commands::Batch(batch_args)) => return batch(&batch_args),
+        None => {}
+    }
+    if args.bench_dsp {
+        bench::print_report();
+        return Ok(());
+    }
+    let host = run::resolve_host(args.engine.host.as_deref());
+    if args.list {
+        return list_devices(&host);
+    }
+    let socket = args.socket.clone().unwrap_or_else(daemon::default_socket);
+    if let Some(command) = args.send.as_ref() {
+        println!("{}", daemon::send(&socket, command)?);
+        return Ok(());
     }
-    let input_device = input_device.expect("Input device not found");
-    let output_device = output_device.expect("Output device not found");
-    let mut settings = settings::Settings {
-        latency: args.latency,
-        enable_eq: Arc::new(AtomicBool::new(true)),
-        instance_id: Arc::new(std::sync::atomic::AtomicUsize::new(0)),
-        eq_profile: eq_profile,
-    };
 
-    let runner_handle = Arc::new(RefCell::new(thread::spawn(|| {})));
+    let mut session = engine_session(&host, &args.engine)?;
+    session.start();
 
-    let start = |settings| {
-        let input_device_cloned = input_device.clone();
-        let output_device_cloned = output_device.clone();
-        runner_handle.replace(thread::spawn(|| {
-            run::run(input_device_cloned, output_device_cloned, settings)
-                .expect("Failed to run audio processing");
-        }));
-    };
-    start(settings.clone());
+    if args.daemon {
+        return daemon::run_daemon(&socket, &mut session);
+    }
 
-    let running = true;
     let mut command = String::new();
     let mut stdout = std::io::stdout();
     let stdin = std::io::stdin();
     loop {
         print!(">>>");
-        stdout.flush().unwrap();
+        stdout.flush()?;
         command.clear();
-        stdin.read_line(&mut command).unwrap();
+        if stdin.read_line(&mut command)? == 0 {
+            break;
+        }
         println!("{}", command);
-        match command.trim() {
-            "quit" | "q" => {
+        match session.handle(&command) {
+            Ok(Reply::Message(message)) => println!("{}", message),
+            Ok(Reply::Quit) => {
                 println!("Quitting...");
                 break;
             }
-            "stop" => {
-                if running {
-                    settings
-                        .instance_id
-                        .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
-                    println!("Stopped.");
-                } else {
-                    println!("Already stoppped.");
-                }
-            }
-            "start" => {
-                if !running || runner_handle.borrow().is_finished() {
-                    start(settings.clone());
-                    println!("Started.");
-                } else {
-                    println!("Already started.");
-                }
-            }
-            "reload" => {
-                settings
-                    .instance_id
-                    .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
-                println!("Reloading EQ profile...");
-                eq_profile = if let Some(eq_file) = args.eq_file.as_ref() {
-                    let eq_contents =
-                        std::fs::read_to_string(eq_file).expect("Failed to read EQ file");
-                    eq::EqProfile::from_str(&eq_contents).expect("Failed to parse EQ profile")
-                } else {
-                    Default::default()
-                };
-                settings.eq_profile = eq_profile;
-                start(settings.clone());
-            }
-            "disable" | "d" => {
-                let currently_enabled = settings
-                    .enable_eq
-                    .load(std::sync::atomic::Ordering::Relaxed);
-                if currently_enabled {
-                    settings
-                        .enable_eq
-                        .store(false, std::sync::atomic::Ordering::Relaxed);
-                    println!("EQ disabled.");
-                } else {
-                    println!("EQ is already disabled.");
-                }
-            }
-            "enable" | "e" => {
-                let currently_enabled = settings
-                    .enable_eq
-                    .load(std::sync::atomic::Ordering::Relaxed);
-                if !currently_enabled {
-                    settings
-                        .enable_eq
-                        .store(true, std::sync::atomic::Ordering::Relaxed);
-                    println!("EQ enabled.");
-                } else {
-                    println!("EQ is already enabled.");
-                }
-            }
-            x if x.starts_with("load") => {
-                if let Some(space_index) = x.find(' ') {
-                    let eq_file = x[space_index + 1..].to_string().replace("\\ ", " ");
-                    println!("Loading EQ profile from {}...", eq_file);
-                    let eq_contents =
-                        std::fs::read_to_string(eq_file.as_str()).expect("Failed to read EQ file");
-                    eq_profile =
-                        eq::EqProfile::from_str(&eq_contents).expect("Failed to parse EQ profile");
-                    settings.eq_profile = eq_profile;
-                    args.eq_file = Some(eq_file.to_string());
-                    settings
-                        .instance_id
-                        .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
-                    start(settings.clone());
-                } else {
-                    println!("Usage: load <eq_file>");
-                    continue;
-                }
-            }
-            _ => {
-                println!("Unknown command. Use 'e' to toggle EQ, 'q' to quit.");
-            }
+            Err(e) => println!("{:?}", e),
         }
     }
+    session.stop();
+    Ok(())
+}
+
+/// Finds the devices and loads the profile `args` name, for a session that
+/// is yet to be started.
+fn engine_session(host: &Host, args: &EngineArgs) -> Result<Session> {
+    let eq_profile = load_profile(args.eq_file.as_deref())?;
+
+    let input = if args.loopback {
+        run::Input::Loopback(match args.input_device.as_ref() {
+            Some(spec) => find_output_device(host, spec)?,
+            None => host
+                .default_output_device()
+                .context("No default output device to capture")?,
+        })
+    } else {
+        run::Input::Device(find_input_device(
+            host,
+            args.input_device
+                .as_ref()
+                .context("Input device not specified")?,
+        )?)
+    };
+    let output_device = find_output_device(
+        host,
+        args.output_device
+            .as_ref()
+            .context("Output device not specified")?,
+    )?;
+    let settings = settings::Settings::from(&settings::PersistentSettings {
+        latency: args.latency,
+        priming: args.priming,
+        ring_factor: args.ring_factor,
+        sample_rate: args.sample_rate,
+        buffer_size: args.buffer_size,
+        linear_phase: args.linear_phase,
+        output_gain_db: args.output_gain,
+        balance: args.balance,
+        swap_channels: args.swap_channels,
+        matched_bypass: args.matched_bypass,
+        crossfeed: CrossfeedConfig {
+            enabled: args.crossfeed,
+            ..Default::default()
+        },
+        dc_blocker: DcBlockerConfig {
+            enabled: !args.no_dc_blocker,
+        },
+        ..Default::default()
+    });
+
+    let spawn = move |settings: settings::Settings, profile, receiver| {
+        let input_cloned = input.clone();
+        let output_device_cloned = output_device.clone();
+        let instance_id = settings.instance_id.load(Ordering::Relaxed);
+        thread::spawn(move || {
+            run::run(
+                input_cloned,
+                output_device_cloned,
+                settings,
+                profile,
+                None,
+                receiver,
+                instance_id,
+            )
+            .inspect_err(|e| println!("Audio processing failed: {:?}", e))
+            .ok();
+        })
+    };
+    Ok(Session::new(
+        settings,
+        eq_profile,
+        args.eq_file.clone(),
+        Box::new(spawn),
+    ))
+}
+
+pub fn list_devices(host: &Host) -> Result<()> {
+    let hosts: Vec<&str> = cpal::available_hosts().iter().map(|id| id.name()).collect();
+    println!("Hosts: {} (using {})", hosts.join(", "), host.id().name());
+    println!("Input devices:");
+    for (i, (name, _)) in named_devices(host.input_devices()?).iter().enumerate() {
+        println!("  {}: {}", i, name);
+    }
+    println!("Output devices:");
+    for (i, (name, _)) in named_devices(host.output_devices()?).iter().enumerate() {
+        println!("  {}: {}", i, name);
+    }
+    if cfg!(any(target_os = "windows", target_os = "macos")) {
+        println!("Output devices can be captured as input with --loopback.");
+    }
+    Ok(())
 }
 
-pub fn list_devices(host: &Host) {
-    let devices = host.devices().unwrap();
-    for device in devices {
-        println!("{}", device.name().unwrap());
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fake_devices() -> Vec<(String, u32)> {
+        vec![
+            ("BlackHole 2ch".to_string(), 10),
+            ("USB Audio".to_string(), 11),
+            ("USB Audio".to_string(), 12),
+            ("2".to_string(), 13),
+        ]
+    }
+
+    #[test]
+    fn test_resolve_device_by_index() {
+        assert_eq!(resolve_device(fake_devices(), "0"), Some(10));
+        assert_eq!(resolve_device(fake_devices(), "2"), Some(13));
+        assert_eq!(resolve_device(fake_devices(), "1"), Some(11));
+        assert_eq!(resolve_device(fake_devices(), "4"), None);
+    }
+
+    #[test]
+    fn test_resolve_device_by_name() {
+        assert_eq!(resolve_device(fake_devices(), "BlackHole 2ch"), Some(10));
+        assert_eq!(resolve_device(fake_devices(), "USB Audio"), Some(11));
+        assert_eq!(resolve_device(fake_devices(), "Missing"), None);
     }
 }
@@ -0,0 +1,85 @@
+use std::{
+    thread::sleep,
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result, bail};
+use clap::Args;
+
+use crate::{
+    cli::{EngineArgs, engine_session, session::Session},
+    run::resolve_host,
+};
+
+/// How often the runner is checked for stopping on its own
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+#[derive(Debug, Args)]
+pub struct RunArgs {
+    #[command(flatten)]
+    pub engine: EngineArgs,
+    /// Seconds to run before stopping
+    #[clap(long)]
+    pub duration: f64,
+}
+
+pub fn run_once(args: &RunArgs) -> Result<()> {
+    let duration = Duration::try_from_secs_f64(args.duration)
+        .with_context(|| format!("Invalid duration: {}", args.duration))?;
+    let host = resolve_host(args.engine.host.as_deref());
+    let mut session = engine_session(&host, &args.engine)?;
+    run_for(&mut session, duration)?;
+    println!("Ran for {} s", args.duration);
+    Ok(())
+}
+
+/// Runs the engine for `duration`. The streams are closed by the time this
+/// returns; it fails when the engine stopped on its own before then, e.g.
+/// on a device error.
+fn run_for(session: &mut Session, duration: Duration) -> Result<()> {
+    session.start();
+    let deadline = Instant::now() + duration;
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            break;
+        }
+        if !session.is_running() {
+            session.stop();
+            bail!("The engine stopped before the end of the run");
+        }
+        sleep(remaining.min(POLL_INTERVAL));
+    }
+    session.stop();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+    use crate::{
+        settings::{PersistentSettings, Settings},
+        testing::MockEngine,
+    };
+
+    #[test]
+    fn test_run_for_duration() {
+        let engine = MockEngine::default();
+        let settings = Settings::from(&PersistentSettings::default());
+        let mut session = Session::new(settings, Default::default(), None, engine.spawn());
+        let started = Instant::now();
+        run_for(&mut session, Duration::from_millis(50)).unwrap();
+        assert!(started.elapsed() >= Duration::from_millis(50));
+        // Stopped and joined before returning
+        assert!(!session.is_running());
+        assert_eq!(engine.active(), 0);
+
+        // A runner failing at once, like one whose device can't be opened
+        let settings = Settings::from(&PersistentSettings::default());
+        let spawn = |_, _, _| thread::spawn(|| {});
+        let mut session = Session::new(settings, Default::default(), None, Box::new(spawn));
+        assert!(run_for(&mut session, Duration::from_secs(5)).is_err());
+    }
+}
@@ -0,0 +1,145 @@
+use std::{fmt::Write, path::PathBuf};
+
+use anyhow::Result;
+use clap::{Args, ValueEnum};
+
+use crate::{eq::EqProfile, response::log_freq, svg::response_svg};
+
+/// Columns either side of 0 dB in the ASCII plot.
+const PLOT_HALF_WIDTH: usize = 30;
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum ResponseFormat {
+    Csv,
+    Tsv,
+    /// One line per frequency with a mark at its gain, 0 dB in the middle
+    AsciiPlot,
+    /// A plot with axes and grid, for reports
+    Svg,
+}
+
+#[derive(Debug, Args)]
+pub struct ResponseArgs {
+    /// Equalizer APO text or JSON profile
+    #[clap(long, short)]
+    pub eq: PathBuf,
+    /// Sample rate the filters are designed for
+    #[clap(long, default_value_t = 48000.0)]
+    pub fs: f64,
+    /// Frequencies to print, spaced evenly on a log scale from 20 Hz to
+    /// 20 kHz (or Nyquist)
+    #[clap(long, default_value_t = 256)]
+    pub points: usize,
+    #[clap(long, value_enum, default_value_t = ResponseFormat::Csv)]
+    pub format: ResponseFormat,
+}
+
+/// (frequency, dB) of the profile including the preamp, at `points` log-spaced
+/// frequencies below Nyquist.
+pub fn response_points(profile: &EqProfile, fs: f64, points: usize) -> Vec<(f64, f64)> {
+    let steps = points.saturating_sub(1).max(1);
+    let freqs: Vec<f64> = (0..points)
+        .map(|i| log_freq(i as f64 / steps as f64))
+        .filter(|freq| *freq < fs / 2.0)
+        .collect();
+    let gains = profile.magnitude_response(&freqs, fs);
+    freqs
+        .into_iter()
+        .zip(gains)
+        .map(|(freq, db)| (freq, db + profile.preamp_db))
+        .collect()
+}
+
+pub fn render(points: &[(f64, f64)], fs: f64, format: ResponseFormat) -> String {
+    let mut out = String::new();
+    match format {
+        ResponseFormat::Csv | ResponseFormat::Tsv => {
+            let separator = if format == ResponseFormat::Csv {
+                ','
+            } else {
+                '\t'
+            };
+            writeln!(out, "frequency_hz{}gain_db", separator).unwrap();
+            for (freq, db) in points {
+                writeln!(out, "{:.2}{}{:.3}", freq, separator, db).unwrap();
+            }
+        }
+        ResponseFormat::AsciiPlot => {
+            // At least ±6 dB across, in whole dB
+            let range = points
+                .iter()
+                .map(|(_, db)| db.abs().ceil())
+                .fold(6.0, f64::max);
+            // Lined up with the plot after the "{freq} Hz {gain} dB " columns
+            let low = format!("{}", -range);
+            writeln!(
+                out,
+                "{:23}{:<w$}0{:>w$}",
+                "",
+                low,
+                range,
+                w = PLOT_HALF_WIDTH
+            )
+            .unwrap();
+            for (freq, db) in points {
+                let column = ((db / range + 1.0) * PLOT_HALF_WIDTH as f64).round() as usize;
+                let mut line: Vec<char> = vec![' '; PLOT_HALF_WIDTH * 2 + 1];
+                line[PLOT_HALF_WIDTH] = '|';
+                line[column.min(PLOT_HALF_WIDTH * 2)] = '*';
+                let line: String = line.into_iter().collect();
+                writeln!(out, "{:>8.1} Hz {:>+7.2} dB {}", freq, db, line.trim_end()).unwrap();
+            }
+        }
+        ResponseFormat::Svg => out = response_svg(&[("Response", points)], fs),
+    }
+    out
+}
+
+pub fn response(args: &ResponseArgs) -> Result<()> {
+    let profile = EqProfile::load(&args.eq)?;
+    let points = response_points(&profile, args.fs, args.points);
+    print!("{}", render(&points, args.fs, args.format));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_single_band_output() {
+        let profile =
+            EqProfile::parse("Preamp: -1 dB\nFilter 1: ON PK Fc 1000 Hz Gain 6 dB Q 1").unwrap();
+        let points = response_points(&profile, 48000.0, 4);
+        let csv = render(&points, 48000.0, ResponseFormat::Csv);
+        assert_eq!(
+            csv.lines().collect::<Vec<_>>(),
+            [
+                "frequency_hz,gain_db",
+                // Far from the band only the preamp is left
+                "20.00,-0.997",
+                "200.00,-0.734",
+                "2000.00,0.866",
+                "20000.00,-0.998",
+            ]
+        );
+        let tsv = render(&points, 48000.0, ResponseFormat::Tsv);
+        assert_eq!(tsv.lines().nth(3), Some("2000.00\t0.866"));
+
+        // On a ±6 dB scale, 30 columns either side of the 0 dB line
+        let points = response_points(&profile, 48000.0, 3);
+        let plot = render(&points, 48000.0, ResponseFormat::AsciiPlot);
+        let lines: Vec<&str> = plot.lines().collect();
+        assert_eq!(lines[0].find('0'), lines[1].find('|'), "{}", plot);
+        assert_eq!(
+            lines[1],
+            "    20.0 Hz   -1.00 dB                          *    |"
+        );
+        assert_eq!(
+            lines[2],
+            "   632.5 Hz   +2.15 dB                               |          *"
+        );
+        // Nothing above Nyquist
+        assert_eq!(response_points(&profile, 32000.0, 3).len(), 2);
+    }
+}
@@ -0,0 +1,211 @@
+use std::{
+    path::Path,
+    sync::{
+        atomic::Ordering,
+        mpsc::{Receiver, Sender, channel},
+    },
+    thread::JoinHandle,
+};
+
+use anyhow::{Context, Result, anyhow, bail};
+
+use crate::{eq::EqProfile, settings::Settings};
+
+/// Spawns an audio runner for the given settings and profile, which takes
+/// the profiles sent on the receiver without a restart.
+pub type Spawn = Box<dyn FnMut(Settings, EqProfile, Receiver<EqProfile>) -> JoinHandle<()> + Send>;
+
+pub enum Reply {
+    Message(String),
+    Quit,
+}
+
+pub fn load_profile(eq_file: Option<&str>) -> Result<EqProfile> {
+    match eq_file {
+        Some(eq_file) => EqProfile::load(Path::new(eq_file))
+            .with_context(|| format!("Failed to load EQ profile {}", eq_file)),
+        None => Ok(Default::default()),
+    }
+}
+
+/// Line-based command interpreter shared by the interactive prompt and the
+/// daemon socket. Restarts go through `instance_id`, same as the GUI executor,
+/// and profile changes go to the running engine like the GUI's.
+pub struct Session {
+    settings: Settings,
+    profile: EqProfile,
+    eq_file: Option<String>,
+    spawn: Spawn,
+    runner: Option<JoinHandle<()>>,
+    profile_sender: Option<Sender<EqProfile>>,
+}
+
+impl Session {
+    pub fn new(
+        settings: Settings,
+        profile: EqProfile,
+        eq_file: Option<String>,
+        spawn: Spawn,
+    ) -> Self {
+        Self {
+            settings,
+            profile,
+            eq_file,
+            spawn,
+            runner: None,
+            profile_sender: None,
+        }
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.runner.as_ref().is_some_and(|h| !h.is_finished())
+    }
+
+    pub fn start(&mut self) {
+        let (sender, receiver) = channel();
+        self.runner = Some((self.spawn)(
+            self.settings.clone(),
+            self.profile.clone(),
+            receiver,
+        ));
+        self.profile_sender = Some(sender);
+    }
+
+    /// Signals the runner to exit and waits for it.
+    pub fn stop(&mut self) {
+        self.profile_sender = None;
+        self.settings.instance_id.fetch_add(1, Ordering::Relaxed);
+        if let Some(runner) = self.runner.take() {
+            runner.join().ok();
+        }
+    }
+
+    /// Hands the profile to the running engine; a stopped one picks it up
+    /// on the next start.
+    fn push_profile(&self) {
+        if let Some(sender) = &self.profile_sender {
+            sender.send(self.profile.clone()).ok();
+        }
+    }
+
+    /// The estimated and target latency while the engine reports one, and
+    /// the ring buffer's fill over the last second.
+    fn stream_status(&self) -> String {
+        let status = &self.settings.status;
+        let latency_ms = status.latency_ms.load();
+        let mut out = String::new();
+        if latency_ms > 0.0 {
+            out = format!(
+                " latency=~{:.1}ms target={}ms",
+                latency_ms,
+                self.settings.latency.load(Ordering::Relaxed)
+            );
+        }
+        if let Some((min, max)) = status.ring_fill.percent() {
+            out += &format!(" fill={:.0}-{:.0}%", min, max);
+        }
+        out
+    }
+
+    fn set_band(&mut self, args: &[&str]) -> Result<String> {
+        let [index, field, rest @ ..] = args else {
+            bail!("Usage: set-band <n> <on|off|fc|gain|q> [value]");
+        };
+        let index: usize = index.parse().context("Invalid band number")?;
+        let band = index
+            .checked_sub(1)
+            .and_then(|i| self.profile.filters.get_mut(i))
+            .ok_or_else(|| anyhow!("No band {}", index))?;
+        let value = || -> Result<f64> {
+            rest.first()
+                .context("Missing value")?
+                .parse()
+                .context("Invalid value")
+        };
+        match field.to_lowercase().as_str() {
+            "on" => band.enabled = true,
+            "off" => band.enabled = false,
+            "fc" | "freq" => band.frequency = value()?,
+            "gain" => band.gain = value()?,
+            "q" => band.q_factor = value()?,
+            _ => bail!("Unknown band field: {}", field),
+        }
+        self.push_profile();
+        Ok(format!("Band {} updated.", index))
+    }
+
+    pub fn handle(&mut self, line: &str) -> Result<Reply> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let message = match words.as_slice() {
+            ["quit" | "q"] => return Ok(Reply::Quit),
+            ["stop"] => {
+                if self.is_running() {
+                    self.stop();
+                    "Stopped.".to_string()
+                } else {
+                    "Already stoppped.".to_string()
+                }
+            }
+            ["start"] => {
+                if self.is_running() {
+                    "Already started.".to_string()
+                } else {
+                    self.start();
+                    "Started.".to_string()
+                }
+            }
+            ["status"] => format!(
+                "{} eq={}{}",
+                if self.is_running() {
+                    "running"
+                } else {
+                    "stopped"
+                },
+                if self.settings.enable_eq.load(Ordering::Relaxed) {
+                    "on"
+                } else {
+                    "off"
+                },
+                self.stream_status()
+            ),
+            ["reload"] => {
+                self.profile = load_profile(self.eq_file.as_deref())?;
+                self.push_profile();
+                "Reloaded EQ profile.".to_string()
+            }
+            ["disable" | "d"] => {
+                if self.settings.enable_eq.swap(false, Ordering::Relaxed) {
+                    "EQ disabled.".to_string()
+                } else {
+                    "EQ is already disabled.".to_string()
+                }
+            }
+            ["enable" | "e"] => {
+                if !self.settings.enable_eq.swap(true, Ordering::Relaxed) {
+                    "EQ enabled.".to_string()
+                } else {
+                    "EQ is already enabled.".to_string()
+                }
+            }
+            ["load", ..] => {
+                let Some((_, eq_file)) = line.trim().split_once(' ') else {
+                    bail!("Usage: load <eq_file>");
+                };
+                let eq_file = eq_file.trim().replace("\\ ", " ");
+                self.profile = load_profile(Some(&eq_file))?;
+                self.eq_file = Some(eq_file.clone());
+                self.push_profile();
+                format!("Loaded EQ profile from {}.", eq_file)
+            }
+            ["set-band", args @ ..] => self.set_band(args)?,
+            // The running engine resizes its buffer, no restart needed
+            ["latency", ms] => {
+                let ms: u32 = ms.parse().context("Invalid latency")?;
+                self.settings.latency.store(ms, Ordering::Relaxed);
+                format!("Latency target set to {} ms.", ms)
+            }
+            _ => "Unknown command. Use 'e' to toggle EQ, 'q' to quit.".to_string(),
+        };
+        Ok(Reply::Message(message))
+    }
+}
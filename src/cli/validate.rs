@@ -0,0 +1,148 @@
+use std::path::PathBuf;
+
+use anyhow::{Result, bail};
+use clap::Args;
+
+use crate::eq::EqProfile;
+
+/// Bands narrower than this are most likely a typo.
+const MAX_Q: f64 = 20.0;
+const MAX_GAIN_DB: f64 = 30.0;
+
+#[derive(Debug, Args)]
+pub struct ValidateArgs {
+    /// Equalizer APO text or JSON profile
+    pub profile: PathBuf,
+    /// Sample rate the filters are checked at
+    #[clap(long, default_value_t = 48000.0)]
+    pub fs: f64,
+}
+
+/// Findings for a profile. Errors are bands that can't run as written,
+/// warnings ones that run but are probably not meant that way.
+#[derive(Debug, Default)]
+struct Report {
+    errors: Vec<String>,
+    warnings: Vec<String>,
+    /// Headroom left at the loudest frequency, negative when it clips
+    clip_margin_db: f64,
+}
+
+fn check(profile: &EqProfile, fs: f64) -> Report {
+    let mut report = Report::default();
+    let nyquist = fs / 2.0;
+    for (i, filter) in profile.filters.iter().enumerate() {
+        let name = format!("Filter {}", i + 1);
+        let mut errors = Vec::new();
+        if !(filter.frequency > 0.0 && filter.frequency < nyquist) {
+            errors.push(format!(
+                "frequency {} Hz is outside 0 - {} Hz (Nyquist at {} Hz)",
+                filter.frequency, nyquist, fs
+            ));
+        }
+        let q = filter.effective_q();
+        if !(q > 0.0 && q.is_finite()) {
+            errors.push(format!("Q {} is not positive", q));
+        }
+        if !filter.gain.is_finite() {
+            errors.push(format!("gain {} dB is not a number", filter.gain));
+        }
+        if errors.is_empty() && !filter.is_stable(fs as f32) {
+            errors.push(format!("unstable at {} Hz", fs));
+        }
+        if !errors.is_empty() {
+            let errors = errors.into_iter().map(|e| format!("{}: {}", name, e));
+            report.errors.extend(errors);
+            continue;
+        }
+        let mut warning = |message: String| report.warnings.push(format!("{}: {}", name, message));
+        if !(20.0..=20000.0).contains(&filter.frequency) {
+            warning(format!(
+                "frequency {} Hz is outside 20 Hz - 20 kHz",
+                filter.frequency
+            ));
+        }
+        if q > MAX_Q {
+            warning(format!("Q {:.2} is very narrow", q));
+        }
+        if filter.gain.abs() > MAX_GAIN_DB {
+            warning(format!("gain {} dB is very large", filter.gain));
+        }
+    }
+    report.clip_margin_db = -profile.peak_db(fs);
+    if report.clip_margin_db < 0.0 {
+        report.warnings.push(format!(
+            "Clips by {:.1} dB; lower the preamp to fix",
+            -report.clip_margin_db
+        ));
+    }
+    report
+}
+
+pub fn validate(args: &ValidateArgs) -> Result<()> {
+    let profile = EqProfile::load(&args.profile)?;
+    let report = check(&profile, args.fs);
+    let enabled = profile.filters.iter().filter(|f| f.enabled).count();
+    println!("Filters: {} ({} enabled)", profile.filters.len(), enabled);
+    println!("Clip margin: {:.1} dB", report.clip_margin_db);
+    for warning in &report.warnings {
+        println!("warning: {}", warning);
+    }
+    for error in &report.errors {
+        println!("error: {}", error);
+    }
+    if !report.errors.is_empty() {
+        bail!(
+            "{} has {} error(s)",
+            args.profile.display(),
+            report.errors.len()
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_broken_profile_fails() {
+        let dir = tempfile::tempdir().unwrap();
+        let args = |name: &str, content: &str| {
+            let path = dir.path().join(name);
+            std::fs::write(&path, content).unwrap();
+            ValidateArgs {
+                profile: path,
+                fs: 48000.0,
+            }
+        };
+
+        let good = args(
+            "good.txt",
+            "Preamp: -4 dB\nFilter 1: ON PK Fc 100 Hz Gain 3 dB Q 1\nFilter 2: ON LSC Fc 10 Hz Gain 2 dB Q 0.7\n",
+        );
+        assert!(validate(&good).is_ok());
+        let report = check(&EqProfile::load(&good.profile).unwrap(), 48000.0);
+        assert!(report.errors.is_empty());
+        assert_eq!(
+            report.warnings,
+            ["Filter 2: frequency 10 Hz is outside 20 Hz - 20 kHz"]
+        );
+        assert!(report.clip_margin_db > 0.5, "{}", report.clip_margin_db);
+
+        let broken = args(
+            "broken.txt",
+            "Filter 1: ON PK Fc 30000 Hz Gain 3 dB Q 1\nFilter 2: ON PK Fc 1000 Hz Gain 6 dB Q 0\n",
+        );
+        assert!(validate(&broken).is_err());
+        let report = check(&EqProfile::load(&broken.profile).unwrap(), 48000.0);
+        assert_eq!(report.errors.len(), 2, "{:?}", report.errors);
+        assert!(report.errors[0].starts_with("Filter 1: frequency 30000 Hz"));
+        assert!(report.errors[1].starts_with("Filter 2: Q 0"));
+
+        // Fine at 48 kHz, above Nyquist at 32 kHz
+        let high = EqProfile::parse("Filter 1: ON HSC Fc 18000 Hz Gain -3 dB Q 0.7").unwrap();
+        assert!(check(&high, 48000.0).errors.is_empty());
+        assert_eq!(check(&high, 32000.0).errors.len(), 1);
+    }
+}
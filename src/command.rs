@@ -0,0 +1,172 @@
+//! What the GUI, the OSC and HTTP servers send the executor, which owns
+//! the engines and the config.
+
+use cpal::{
+    Device, Host,
+    traits::{DeviceTrait, HostTrait},
+};
+use tracing::warn;
+
+use crate::{
+    config::{ChannelMode, InstanceKey, Lang, Setup, WindowState},
+    eq::EqProfile,
+    midi::{BandParam, MidiConfig},
+    run::StreamOptions,
+    settings::Settings,
+    utils::OneShot,
+};
+
+#[derive(Clone, Copy, Debug)]
+pub struct State {
+    pub enabled: bool,
+    pub running: bool,
+    pub realtime: bool,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            running: true,
+            realtime: false,
+        }
+    }
+}
+
+/// Device names of one host, as shown in the device selectors.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DeviceList {
+    pub device_names: Vec<String>,
+    /// Output devices that can be captured in loopback mode
+    pub loopback_names: Vec<String>,
+}
+
+impl DeviceList {
+    pub fn from_host(host: &Host) -> Self {
+        Self {
+            device_names: names(host.devices()),
+            // Only WASAPI and CoreAudio can record an output device
+            loopback_names: if cfg!(any(target_os = "windows", target_os = "macos")) {
+                names(host.output_devices())
+            } else {
+                Vec::new()
+            },
+        }
+    }
+}
+
+/// Device names, or none when the host can't list them; a flaky driver
+/// shouldn't take the UI down.
+fn names<E: std::fmt::Display>(devices: Result<impl Iterator<Item = Device>, E>) -> Vec<String> {
+    match devices {
+        Ok(devices) => devices
+            .filter_map(|v| v.description().ok().map(|v| v.name().to_string()))
+            .collect(),
+        Err(e) => {
+            warn!("Failed to list devices: {}", e);
+            Vec::new()
+        }
+    }
+}
+
+#[derive(Debug)]
+#[cfg_attr(not(feature = "gui"), allow(dead_code))]
+pub enum SetDevice {
+    Input,
+    Output,
+    /// Use an output device's playback as the input
+    Loopback,
+}
+
+/// One of the two profiles compared by A/B switching.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum AbSlot {
+    #[default]
+    A,
+    B,
+}
+
+impl AbSlot {
+    pub fn other(self) -> Self {
+        match self {
+            Self::A => Self::B,
+            Self::B => Self::A,
+        }
+    }
+}
+
+impl std::fmt::Display for AbSlot {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::A => "A",
+            Self::B => "B",
+        })
+    }
+}
+
+#[derive(Debug)]
+#[cfg_attr(not(feature = "gui"), allow(dead_code))]
+pub enum Command {
+    SetState(State),
+    UpdateSettings(Settings),
+    UpdateProfile(EqProfile),
+    Save(Settings, EqProfile),
+    GetState(OneShot<State>),
+    /// The main profile as the engine runs it
+    #[cfg_attr(not(feature = "http"), allow(dead_code))]
+    GetProfile(OneShot<EqProfile>),
+    /// Re-enumerates the host's devices, picking up ones plugged in since
+    RefreshDevices(OneShot<DeviceList>),
+    /// Rates and buffer sizes the selected devices support
+    GetStreamOptions(OneShot<StreamOptions>),
+    SetDevice(SetDevice, String),
+    /// Switches the audio host backend by `HostId` name
+    SetHost(String),
+    /// Realtime editing of the main profile: the GUI sends each edit as
+    /// `UpdateProfile`, and the main pair runs as `run::run_realtime`
+    SetRealtime(bool),
+    SavePreset(String, EqProfile),
+    /// Makes the named preset the active profile, like `UpdateProfile`
+    LoadPreset(String),
+    DeletePreset(String),
+    SaveSetup(Setup),
+    /// Switches the main pair to the named setup's devices, profile and
+    /// latency, and restarts it
+    ApplySetup(String),
+    DeleteSetup(String),
+    SetChannelMode(ChannelMode),
+    UpdateSideProfile(EqProfile),
+    /// Stores the given profile as the active A/B slot's and switches the
+    /// main pair to the other slot's profile, without restarting it. Both
+    /// slots start out as the configured profile.
+    SwapAB(EqProfile),
+    /// Stores the window geometry in the config, sent when the window is
+    /// closed and on quit
+    SaveWindow(WindowState),
+    SetLang(Lang),
+    /// Stores the MIDI bindings learned in the GUI
+    SetMidi(MidiConfig),
+    /// Turns the EQ on or off, like `SetState` without touching `running`
+    SetEnabled(bool),
+    /// Edits of the main profile as the engine runs it, sent by the OSC
+    /// server and for MIDI knobs; the band is counted from 0. They are saved
+    /// and shown in the GUI.
+    SetPreamp(f64),
+    SetBand(usize, BandParam, f64),
+    /// Replaces the main profile like the two above, sent by the HTTP server
+    #[cfg_attr(not(feature = "http"), allow(dead_code))]
+    SetProfile(EqProfile),
+    Restart,
+    /// (Re)starts the pair of that name with its devices and profile from the
+    /// config, sent by the OSC server. The commands above all act on the main
+    /// pair.
+    StartInstance(InstanceKey),
+    StopInstance(InstanceKey),
+    /// Like `UpdateProfile`, for the pair of that name, with a profile
+    /// changed outside the GUI; a new main profile is shown in the GUI
+    UpdateInstanceProfile(InstanceKey, EqProfile),
+    /// Sent by a runner with its pair and instance id when a stream lost its
+    /// device
+    DeviceLost(InstanceKey, usize),
+    Shutdown,
+}
@@ -1,27 +1,698 @@
-use std::path::PathBuf;
+use std::{
+    collections::BTreeMap,
+    ffi::OsString,
+    io::Write,
+    net::{IpAddr, Ipv4Addr},
+    ops::RangeInclusive,
+    path::{Path, PathBuf},
+};
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
+use tracing::warn;
 
-use crate::eq::EqProfile;
+use crate::{eq::EqProfile, midi::MidiConfig, osc::OscConfig, settings::PersistentSettings};
+
+/// Names an input/output pair with an engine of its own.
+pub type InstanceKey = String;
+
+/// Key of the pair at the top level of the config, the one the GUI controls.
+pub const MAIN_INSTANCE: &str = "main";
 
 #[derive(Serialize, Deserialize, Clone, Default)]
 pub struct Config {
+    /// cpal `HostId` name; the platform default when unset
+    #[serde(default)]
+    pub host: Option<String>,
+    pub input_dev_name: Option<String>,
+    pub output_dev_name: Option<String>,
+    /// cpal device ids of the two, looked up before the names: names can
+    /// change with a driver update and repeat for identical hardware
+    #[serde(default)]
+    pub input_dev_id: Option<String>,
+    #[serde(default)]
+    pub output_dev_id: Option<String>,
+    /// `input_dev_name` is an output device recorded in loopback mode
+    #[serde(default)]
+    pub loopback: bool,
+    /// Engine and output stage settings, at the top level of the file
+    #[serde(flatten)]
+    pub settings: PersistentSettings,
+    pub eq_profile: EqProfile,
+    #[serde(default)]
+    pub presets: Vec<(String, EqProfile)>,
+    #[serde(default)]
+    pub setups: Vec<Setup>,
+    #[serde(default)]
+    pub window: WindowState,
+    #[serde(default)]
+    pub channel_mode: ChannelMode,
+    /// Language of the GUI labels
+    #[serde(default)]
+    pub lang: Lang,
+    /// Font family name, or path to a font file, tried before the built-in
+    /// list of CJK-capable fonts
+    #[serde(default)]
+    pub font: Option<String>,
+    /// Control surface input and its knob bindings
+    #[serde(default)]
+    pub midi: MidiConfig,
+    /// Remote control server
+    #[serde(default)]
+    pub osc: OscConfig,
+    /// REST API, served when built with the `http` feature
+    #[serde(default)]
+    pub http: HttpConfig,
+    /// What the band editor and the graph span
+    #[serde(default)]
+    pub ranges: RangeConfig,
+    /// Profile for the side channel in mid/side mode; `eq_profile` is the mid
+    #[serde(default)]
+    pub side_profile: EqProfile,
+    /// Further device pairs running at the same time as the main one, each
+    /// under `[instances.<name>]`
+    #[serde(default)]
+    pub instances: BTreeMap<InstanceKey, InstanceConfig>,
+}
+
+/// Devices, profile and latency of the main pair, switched between in one go,
+/// e.g. headphones and monitors.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Default)]
+#[serde(default)]
+pub struct Setup {
+    pub name: String,
+    pub input: Option<String>,
+    /// `input` is an output device recorded in loopback mode
+    pub loopback: bool,
+    pub output: Option<String>,
+    pub profile: EqProfile,
+    pub latency: u32,
+}
+
+/// Devices, settings and profile of one pair, see [`Config::instance`].
+/// Mid/side mode is only available on the main pair.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Default)]
+pub struct InstanceConfig {
     pub input_dev_name: Option<String>,
     pub output_dev_name: Option<String>,
+    #[serde(default)]
+    pub input_dev_id: Option<String>,
+    #[serde(default)]
+    pub output_dev_id: Option<String>,
+    #[serde(default)]
+    pub loopback: bool,
+    #[serde(flatten)]
+    pub settings: PersistentSettings,
+    #[serde(default)]
     pub eq_profile: EqProfile,
 }
 
+/// Where the REST API listens, see `http`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct HttpConfig {
+    pub enabled: bool,
+    /// Interface to listen on; only this machine by default
+    pub address: IpAddr,
+    pub port: u16,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            address: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            port: 8080,
+        }
+    }
+}
+
+/// Under `[ranges]`: the frequencies and gains bands can be set to, e.g.
+/// below 20 Hz for a subwoofer or beyond ±12 dB for mastering.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(default)]
+pub struct RangeConfig {
+    pub min_freq_hz: f64,
+    pub max_freq_hz: f64,
+    /// Largest boost or cut of a band
+    pub max_gain_db: f64,
+}
+
+impl Default for RangeConfig {
+    fn default() -> Self {
+        Self {
+            min_freq_hz: 20.0,
+            max_freq_hz: 20000.0,
+            max_gain_db: 12.0,
+        }
+    }
+}
+
+impl RangeConfig {
+    /// The configured ranges made usable: 1 Hz - 100 kHz with the maximum
+    /// above the minimum, and up to ±48 dB.
+    pub fn sanitized(self) -> Self {
+        let min_freq_hz = self.min_freq_hz.clamp(1.0, 50000.0);
+        Self {
+            min_freq_hz,
+            max_freq_hz: self.max_freq_hz.clamp(min_freq_hz * 2.0, 100000.0),
+            max_gain_db: self.max_gain_db.clamp(1.0, 48.0),
+        }
+    }
+
+    pub fn freq(&self) -> RangeInclusive<f64> {
+        self.min_freq_hz..=self.max_freq_hz
+    }
+
+    pub fn gain(&self) -> RangeInclusive<f64> {
+        -self.max_gain_db..=self.max_gain_db
+    }
+
+    /// The preamp goes down far enough to make up for the largest boost.
+    pub fn preamp(&self) -> RangeInclusive<f64> {
+        -self.max_gain_db.max(12.0)..=12.0
+    }
+
+    /// Q, bandwidth in octaves and tilt in dB per octave aren't
+    /// configurable.
+    pub fn q(&self) -> RangeInclusive<f64> {
+        0.01..=10.0
+    }
+
+    pub fn bandwidth(&self) -> RangeInclusive<f64> {
+        0.01..=10.0
+    }
+
+    pub fn tilt(&self) -> RangeInclusive<f64> {
+        -1.0..=1.0
+    }
+}
+
+/// `value` clamped to `range`, or None when it isn't a finite number; for
+/// values that come from outside the editor.
+pub fn clamp_finite(value: f64, range: RangeInclusive<f64>) -> Option<f64> {
+    value
+        .is_finite()
+        .then(|| value.clamp(*range.start(), *range.end()))
+}
+
+/// Headphone crossfeed, see `dsp::Crossfeed`.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(default)]
+pub struct CrossfeedConfig {
+    pub enabled: bool,
+    /// Level of the cross-fed signal relative to the direct one, 0.0..=1.0
+    pub amount: f32,
+    pub cutoff_hz: f32,
+}
+
+impl Default for CrossfeedConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            amount: 0.3,
+            cutoff_hz: 700.0,
+        }
+    }
+}
+
+/// Highpass removing DC offset before the EQ, see `dsp::DcBlocker`.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(default)]
+pub struct DcBlockerConfig {
+    pub enabled: bool,
+}
+
+impl Default for DcBlockerConfig {
+    fn default() -> Self {
+        Self { enabled: true }
+    }
+}
+
+/// Dither for output devices that store integer samples, see `dsp::Dither`.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(default)]
+pub struct DitherConfig {
+    pub enabled: bool,
+    /// Pushes the dither noise towards high frequencies, where it is harder to hear
+    pub noise_shaping: bool,
+}
+
+impl Default for DitherConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            noise_shaping: false,
+        }
+    }
+}
+
+/// What catches samples above the ceiling at the very end of the chain.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+pub enum LimiterMode {
+    #[default]
+    Off,
+    /// Bends peaks smoothly towards the ceiling
+    SoftClip,
+    /// Turns the gain down instantly and recovers over the release time
+    Brickwall,
+}
+
+impl std::fmt::Display for LimiterMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LimiterMode::Off => write!(f, "Off"),
+            LimiterMode::SoftClip => write!(f, "Soft Clip"),
+            LimiterMode::Brickwall => write!(f, "Limiter"),
+        }
+    }
+}
+
+/// Output protection, see `dsp::Limiter`.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(default)]
+pub struct LimiterConfig {
+    pub mode: LimiterMode,
+    pub ceiling_db: f32,
+    /// Brickwall mode only
+    pub release_ms: f32,
+    /// 1 (off), 2 or 4 times the sample rate while limiting, see `dsp::Oversampler`
+    pub oversampling: u8,
+}
+
+impl Default for LimiterConfig {
+    fn default() -> Self {
+        Self {
+            mode: LimiterMode::default(),
+            ceiling_db: -0.3,
+            release_ms: 50.0,
+            oversampling: 1,
+        }
+    }
+}
+
+/// What gives when the input runs ahead of the output and the ring buffer
+/// between them fills up. Either way it counts as an overrun.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+pub enum OverflowPolicy {
+    /// Input that doesn't fit is discarded. What is buffered plays on
+    /// without a jump, but the latency stays at the buffer's maximum until
+    /// an underrun drains it.
+    #[default]
+    DropNewest,
+    /// The output skips the oldest buffered audio, back down to the latency
+    /// target. Latency stays bounded at the cost of an audible jump.
+    DropOldest,
+}
+
+impl std::fmt::Display for OverflowPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OverflowPolicy::DropNewest => write!(f, "Drop Newest"),
+            OverflowPolicy::DropOldest => write!(f, "Drop Oldest"),
+        }
+    }
+}
+
+/// Language of the GUI, see `ui::i18n`.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+pub enum Lang {
+    #[default]
+    #[serde(rename = "en")]
+    En,
+    #[serde(rename = "zh-CN")]
+    ZhCn,
+}
+
+impl std::fmt::Display for Lang {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Each in its own language, so it can be found when the UI isn't readable
+        match self {
+            Lang::En => write!(f, "English"),
+            Lang::ZhCn => write!(f, "简体中文"),
+        }
+    }
+}
+
+/// How the EQ is applied to a stereo stream.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+pub enum ChannelMode {
+    #[default]
+    Stereo,
+    MidSide,
+}
+
+impl std::fmt::Display for ChannelMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ChannelMode::Stereo => write!(f, "L/R"),
+            ChannelMode::MidSide => write!(f, "Mid/Side"),
+        }
+    }
+}
+
+/// Window geometry in points and view toggles, restored on launch.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(default)]
+pub struct WindowState {
+    pub position: Option<[f32; 2]>,
+    pub size: [f32; 2],
+    /// Size of the monitor the window was last on
+    pub monitor_size: Option<[f32; 2]>,
+    pub show_graph: bool,
+    pub show_meters: bool,
+    pub show_spectrum: bool,
+}
+
+impl Default for WindowState {
+    fn default() -> Self {
+        Self {
+            position: None,
+            size: [1000.0, 450.0],
+            monitor_size: None,
+            show_graph: true,
+            show_meters: true,
+            show_spectrum: false,
+        }
+    }
+}
+
+impl WindowState {
+    /// The saved position, pulled onto the monitor when its size differs from
+    /// the saved one (e.g. the window was on a display that is now unplugged).
+    #[cfg_attr(not(feature = "gui"), allow(dead_code))]
+    pub fn clamped_position(&self, monitor_size: [f32; 2]) -> Option<[f32; 2]> {
+        let position = self.position?;
+        if self.monitor_size == Some(monitor_size) {
+            return Some(position);
+        }
+        let clamp = |pos: f32, size: f32, monitor: f32| pos.clamp(0.0, (monitor - size).max(0.0));
+        Some([
+            clamp(position[0], self.size[0], monitor_size[0]),
+            clamp(position[1], self.size[1], monitor_size[1]),
+        ])
+    }
+}
+
+/// Path of the config file. Systems without a config directory get one in
+/// the working directory.
 pub fn config_dir() -> PathBuf {
-    let mut dir = dirs::config_dir().unwrap();
+    let mut dir = dirs::config_dir().unwrap_or_else(|| {
+        warn!("No config directory found, using the working directory");
+        PathBuf::from(".")
+    });
     dir.push("eq_layer");
     dir.push("config.toml");
     dir
 }
 
+/// `path` with `suffix` appended to the file name, e.g. `config.toml.bak`.
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
 impl Config {
     pub fn save(&self) -> Result<()> {
-        std::fs::write(config_dir(), toml::to_string(&self)?)?;
+        self.save_to(&config_dir())
+    }
+
+    /// Writes a temp file next to `path` and renames it over the target, so
+    /// a crash mid-write leaves the old config intact. The previous config
+    /// is kept as `<path>.bak`. Missing parent directories are created.
+    pub fn save_to(&self, path: &Path) -> Result<()> {
+        let contents = toml::to_string(&self)?;
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("Failed to create {}", dir.display()))?;
+        }
+        let tmp = with_suffix(path, ".tmp");
+        let mut file = std::fs::File::create(&tmp)
+            .with_context(|| format!("Failed to create {}", tmp.display()))?;
+        file.write_all(contents.as_bytes())?;
+        file.sync_all()?;
+        drop(file);
+        if path.exists() {
+            std::fs::copy(path, with_suffix(path, ".bak"))
+                .with_context(|| format!("Failed to back up {}", path.display()))?;
+        }
+        std::fs::rename(&tmp, path)
+            .with_context(|| format!("Failed to replace {}", path.display()))?;
         Ok(())
     }
+
+    /// Reads `path`, falling back to the `.bak` from the previous save when
+    /// the config is unreadable.
+    pub fn load(path: &Path) -> Result<Self> {
+        Self::read(path).or_else(|e| {
+            let backup = with_suffix(path, ".bak");
+            if !backup.exists() {
+                return Err(e);
+            }
+            warn!("{:?}, falling back to {}", e, backup.display());
+            Self::read(&backup)
+        })
+    }
+
+    /// The pair called `key`: [`MAIN_INSTANCE`] reads the top-level fields,
+    /// anything else an entry of `instances`.
+    pub fn instance(&self, key: &str) -> Option<InstanceConfig> {
+        if key == MAIN_INSTANCE {
+            return Some(InstanceConfig {
+                input_dev_name: self.input_dev_name.clone(),
+                output_dev_name: self.output_dev_name.clone(),
+                input_dev_id: self.input_dev_id.clone(),
+                output_dev_id: self.output_dev_id.clone(),
+                loopback: self.loopback,
+                settings: self.settings.clone(),
+                eq_profile: self.eq_profile.clone(),
+            });
+        }
+        self.instances.get(key).cloned()
+    }
+
+    pub fn preset(&self, name: &str) -> Option<&EqProfile> {
+        self.presets.iter().find(|(n, _)| n == name).map(|(_, p)| p)
+    }
+
+    /// Overwrites the preset called `name`, or appends a new one.
+    pub fn save_preset(&mut self, name: String, profile: EqProfile) {
+        match self.presets.iter_mut().find(|(n, _)| *n == name) {
+            Some((_, preset)) => *preset = profile,
+            None => self.presets.push((name, profile)),
+        }
+    }
+
+    pub fn delete_preset(&mut self, name: &str) {
+        self.presets.retain(|(n, _)| n != name);
+    }
+
+    pub fn setup(&self, name: &str) -> Option<&Setup> {
+        self.setups.iter().find(|s| s.name == name)
+    }
+
+    /// Overwrites the setup with the same name, or appends a new one.
+    pub fn save_setup(&mut self, setup: Setup) {
+        match self.setups.iter_mut().find(|s| s.name == setup.name) {
+            Some(existing) => *existing = setup,
+            None => self.setups.push(setup),
+        }
+    }
+
+    pub fn delete_setup(&mut self, name: &str) {
+        self.setups.retain(|s| s.name != name);
+    }
+
+    /// Reads `path` without the backup fallback.
+    pub fn read(path: &Path) -> Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        toml::from_str(&contents).with_context(|| format!("Failed to parse {}", path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(latency: u32) -> Config {
+        Config {
+            input_dev_name: Some("Mic".to_string()),
+            settings: PersistentSettings {
+                latency,
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_partial_write_keeps_config() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        config(20).save_to(&path).unwrap();
+        config(30).save_to(&path).unwrap();
+        assert_eq!(Config::load(&path).unwrap().settings.latency, 30);
+        assert_eq!(
+            Config::load(&with_suffix(&path, ".bak"))
+                .unwrap()
+                .settings
+                .latency,
+            20
+        );
+
+        // Killed while writing the temp file: the target is untouched
+        let contents = toml::to_string(&config(40)).unwrap();
+        std::fs::write(with_suffix(&path, ".tmp"), &contents[..contents.len() / 2]).unwrap();
+        assert_eq!(Config::load(&path).unwrap().settings.latency, 30);
+
+        // A config truncated by an older, non-atomic save falls back to the backup
+        std::fs::write(&path, &contents[..contents.len() / 2]).unwrap();
+        let loaded = Config::load(&path).unwrap();
+        assert_eq!(loaded.settings.latency, 20);
+        assert_eq!(loaded.input_dev_name.as_deref(), Some("Mic"));
+    }
+
+    #[test]
+    fn test_save_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        // The first save also creates the config directory
+        let path = dir.path().join("eq_layer").join("config.toml");
+        config(20).save_to(&path).unwrap();
+        assert_eq!(Config::load(&path).unwrap().settings.latency, 20);
+
+        // A file where the directory should be fails with the path in the
+        // message, for the status line, instead of panicking
+        let blocker = dir.path().join("blocker");
+        std::fs::write(&blocker, "").unwrap();
+        let error = config(30)
+            .save_to(&blocker.join("config.toml"))
+            .unwrap_err();
+        assert!(format!("{:#}", error).contains("blocker"), "{:#}", error);
+
+        // Failing to replace the config leaves the old one readable
+        std::fs::create_dir(with_suffix(&path, ".tmp")).unwrap();
+        assert!(config(40).save_to(&path).is_err());
+        assert_eq!(Config::load(&path).unwrap().settings.latency, 20);
+    }
+
+    #[test]
+    fn test_window_clamped_to_monitor() {
+        let window = WindowState {
+            position: Some([2500.0, 300.0]),
+            monitor_size: Some([3840.0, 1080.0]),
+            ..Default::default()
+        };
+        assert_eq!(
+            window.clamped_position([3840.0, 1080.0]),
+            Some([2500.0, 300.0])
+        );
+        // Second monitor unplugged
+        assert_eq!(
+            window.clamped_position([1920.0, 1080.0]),
+            Some([920.0, 300.0])
+        );
+        assert_eq!(
+            WindowState::default().clamped_position([1920.0, 1080.0]),
+            None
+        );
+    }
+
+    #[test]
+    fn test_presets() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        // Configs written before presets existed still load
+        std::fs::write(
+            &path,
+            toml::to_string(&config(20))
+                .unwrap()
+                .replace("presets = []", ""),
+        )
+        .unwrap();
+        let mut loaded = Config::load(&path).unwrap();
+        assert!(loaded.presets.is_empty());
+
+        let mut bass = EqProfile {
+            preamp_db: -3.0,
+            ..Default::default()
+        };
+        loaded.save_preset("Bass".to_string(), bass.clone());
+        loaded.save_preset("Flat".to_string(), EqProfile::default());
+        bass.preamp_db = -4.0;
+        loaded.save_preset("Bass".to_string(), bass.clone());
+        loaded.save_to(&path).unwrap();
+
+        let mut loaded = Config::load(&path).unwrap();
+        assert_eq!(loaded.presets.len(), 2);
+        assert_eq!(loaded.preset("Bass"), Some(&bass));
+        loaded.delete_preset("Bass");
+        assert_eq!(loaded.preset("Bass"), None);
+        assert!(loaded.preset("Flat").is_some());
+    }
+
+    #[test]
+    fn test_instances() {
+        let mut config = config(20);
+        let headphones = InstanceConfig {
+            output_dev_name: Some("Headphone Amp".to_string()),
+            settings: PersistentSettings {
+                latency: 10,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        config
+            .instances
+            .insert("headphones".to_string(), headphones.clone());
+        let toml = toml::to_string(&config).unwrap();
+        assert!(toml.contains("[instances.headphones]"), "{}", toml);
+
+        let loaded: Config = toml::from_str(&toml).unwrap();
+        assert_eq!(loaded.instance("headphones"), Some(headphones));
+        let main = loaded.instance(MAIN_INSTANCE).unwrap();
+        assert_eq!(main.input_dev_name.as_deref(), Some("Mic"));
+        assert_eq!(main.settings.latency, 20);
+        assert_eq!(loaded.instance("speakers"), None);
+    }
+
+    #[test]
+    fn test_setups() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        // Configs written before setups existed still load
+        std::fs::write(
+            &path,
+            toml::to_string(&config(20))
+                .unwrap()
+                .replace("setups = []", ""),
+        )
+        .unwrap();
+        let mut loaded = Config::load(&path).unwrap();
+        assert!(loaded.setups.is_empty());
+
+        let mut headphones = Setup {
+            name: "Headphones".to_string(),
+            input: Some("BlackHole 2ch".to_string()),
+            output: Some("USB DAC".to_string()),
+            latency: 20,
+            ..Default::default()
+        };
+        loaded.save_setup(headphones.clone());
+        loaded.save_setup(Setup {
+            name: "Monitors".to_string(),
+            ..Default::default()
+        });
+        headphones.profile.preamp_db = -3.0;
+        loaded.save_setup(headphones.clone());
+        loaded.save_to(&path).unwrap();
+
+        let mut loaded = Config::load(&path).unwrap();
+        assert_eq!(loaded.setups.len(), 2);
+        assert_eq!(loaded.setup("Headphones"), Some(&headphones));
+        loaded.delete_setup("Headphones");
+        assert_eq!(loaded.setup("Headphones"), None);
+        assert!(loaded.setup("Monitors").is_some());
+    }
 }
@@ -0,0 +1,758 @@
+//! Processing stages around the EQ that are driven by live controls in
+//! [`Settings`] and can change without restarting the streams.
+
+use std::sync::atomic::Ordering;
+
+use cpal::SampleFormat;
+
+use crate::{
+    config::LimiterMode,
+    eq::ParametricEq,
+    presets,
+    settings::{DitherControls, LimiterControls, OVERSAMPLING_FACTORS, Settings},
+};
+
+pub fn db_to_gain(db: f32) -> f32 {
+    10.0f32.powf(db / 20.0)
+}
+
+/// Per-channel gains for a balance in -1.0..=1.0; the louder side stays at
+/// unity. Out-of-range values are clamped.
+pub fn balance_gains(balance: f32) -> (f32, f32) {
+    let balance = if balance.is_nan() {
+        0.0
+    } else {
+        balance.clamp(-1.0, 1.0)
+    };
+    ((1.0 - balance).min(1.0), (1.0 + balance).min(1.0))
+}
+
+/// Corner frequency of [`DcBlocker`], well below anything audible.
+const DC_BLOCKER_CUTOFF_HZ: f32 = 5.0;
+
+/// First-order highpass per channel, `y[n] = x[n] - x[n-1] + r * y[n-1]`,
+/// removing DC offset before it eats into the EQ's headroom.
+pub struct DcBlocker {
+    r: f32,
+    channels: usize,
+    // previous input and output of each channel
+    state: Vec<(f32, f32)>,
+}
+
+impl DcBlocker {
+    pub fn new(channels: u16, sample_rate: u32) -> Self {
+        Self {
+            r: 1.0 - 2.0 * std::f32::consts::PI * DC_BLOCKER_CUTOFF_HZ / sample_rate as f32,
+            channels: channels.max(1) as usize,
+            state: vec![(0.0, 0.0); channels.max(1) as usize],
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.state.fill((0.0, 0.0));
+    }
+
+    /// Processes interleaved samples in place.
+    pub fn process(&mut self, data: &mut [f32]) {
+        for frame in data.chunks_exact_mut(self.channels) {
+            for (sample, (prev_in, prev_out)) in frame.iter_mut().zip(self.state.iter_mut()) {
+                let out = *sample - *prev_in + self.r * *prev_out;
+                *prev_in = *sample;
+                *prev_out = out;
+                *sample = out;
+            }
+        }
+    }
+}
+
+/// Bit depth to dither to for a device sample format. Floats and integers
+/// wider than an f32's 24-bit mantissa take the samples as they are.
+pub fn dither_bits(format: SampleFormat) -> Option<u32> {
+    let bits = format.bits_per_sample();
+    (!format.is_float() && bits <= 24).then_some(bits)
+}
+
+/// TPDF dither: noise of two uniform random values, one step of the target
+/// bit depth wide each, is added before rounding to that depth, so the
+/// rounding error stops following the signal and quiet passages don't
+/// distort. Noise shaping feeds each channel's rounding error back,
+/// tilting the noise towards high frequencies.
+pub struct Dither {
+    channels: usize,
+    // xorshift32 state
+    rng: u32,
+    // last rounding error of each channel
+    error: Vec<f32>,
+}
+
+impl Dither {
+    pub fn new(channels: u16) -> Self {
+        Self {
+            channels: channels.max(1) as usize,
+            rng: 0x9e37_79b9,
+            error: vec![0.0; channels.max(1) as usize],
+        }
+    }
+
+    /// Uniform in -0.5..0.5.
+    fn uniform(&mut self) -> f32 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 17;
+        self.rng ^= self.rng << 5;
+        (self.rng >> 8) as f32 / (1 << 24) as f32 - 0.5
+    }
+
+    /// Triangular in -1.0..1.0, in steps of the target depth.
+    fn tpdf(&mut self) -> f32 {
+        self.uniform() + self.uniform()
+    }
+
+    /// Rounds interleaved samples to `bits` in place, after dithering them.
+    /// Full scale is -1.0..1.0, as the integer conversion assumes.
+    pub fn process(&mut self, data: &mut [f32], bits: u32, dither: &DitherControls) {
+        if !dither.enabled.load(Ordering::Relaxed) {
+            self.error.fill(0.0);
+            return;
+        }
+        let shaping = dither.noise_shaping.load(Ordering::Relaxed);
+        let steps = (1u32 << (bits - 1)) as f32;
+        for frame in data.chunks_exact_mut(self.channels) {
+            for (channel, sample) in frame.iter_mut().enumerate() {
+                let wanted = if shaping {
+                    *sample * steps - self.error[channel]
+                } else {
+                    *sample * steps
+                };
+                let rounded = (wanted + self.tpdf()).round();
+                self.error[channel] = rounded - wanted;
+                *sample = rounded / steps;
+            }
+        }
+    }
+}
+
+/// Bauer-style crossfeed for headphones: each channel gets a lowpassed copy
+/// of the other, and the sum is scaled so centered sounds keep their level.
+pub struct Crossfeed {
+    sample_rate: f32,
+    // one-pole lowpass state of the left and right input
+    lowpassed: [f32; 2],
+}
+
+impl Crossfeed {
+    pub fn new(sample_rate: f32) -> Self {
+        Self {
+            sample_rate,
+            lowpassed: [0.0; 2],
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.lowpassed = [0.0; 2];
+    }
+
+    /// Processes interleaved stereo samples in place.
+    pub fn process(&mut self, data: &mut [f32], amount: f32, cutoff_hz: f32) {
+        let amount = amount.clamp(0.0, 1.0);
+        let cutoff_hz = cutoff_hz.clamp(20.0, self.sample_rate * 0.45);
+        let coeff = 1.0 - (-2.0 * std::f32::consts::PI * cutoff_hz / self.sample_rate).exp();
+        let norm = 1.0 / (1.0 + amount);
+        for frame in data.chunks_exact_mut(2) {
+            let (left, right) = (frame[0], frame[1]);
+            self.lowpassed[0] += coeff * (left - self.lowpassed[0]);
+            self.lowpassed[1] += coeff * (right - self.lowpassed[1]);
+            frame[0] = (left + amount * self.lowpassed[1]) * norm;
+            frame[1] = (right + amount * self.lowpassed[0]) * norm;
+        }
+    }
+}
+
+pub fn gain_to_db(gain: f32) -> f32 {
+    20.0 * gain.log10()
+}
+
+/// Lowpass taps per unit of oversampling factor, either side of the centre.
+const OVERSAMPLING_TAPS: usize = 16;
+/// Passband edge of the oversampling filters, as a fraction of the original
+/// Nyquist frequency.
+const OVERSAMPLING_PASSBAND: f32 = 0.9;
+/// Frames oversampled at a time, so the buffer is allocated once, up front.
+const OVERSAMPLING_BLOCK: usize = 1024;
+
+/// FIR filter memory for one channel.
+struct FirHistory {
+    samples: Vec<f32>,
+    // where the next sample goes
+    pos: usize,
+}
+
+impl FirHistory {
+    fn new(len: usize) -> Self {
+        Self {
+            samples: vec![0.0; len],
+            pos: 0,
+        }
+    }
+
+    fn clear(&mut self) {
+        self.samples.fill(0.0);
+        self.pos = 0;
+    }
+
+    fn push(&mut self, sample: f32) {
+        self.samples[self.pos] = sample;
+        self.pos = (self.pos + 1) % self.samples.len();
+    }
+
+    /// Filter output for the samples pushed so far, newest first.
+    fn output(&self, kernel: &[f32]) -> f32 {
+        let (older, newer) = self.samples.split_at(self.pos);
+        newer
+            .iter()
+            .chain(older)
+            .rev()
+            .zip(kernel)
+            .map(|(sample, h)| sample * h)
+            .sum()
+    }
+}
+
+/// Runs a nonlinear stage at a multiple of the sample rate, so the harmonics
+/// it adds above the original Nyquist frequency are filtered out instead of
+/// folding back down as aliases. Both conversions use the same windowed-sinc
+/// lowpass, delaying the signal by `OVERSAMPLING_TAPS * 2` frames in total.
+pub struct Oversampler {
+    factor: usize,
+    kernel: Vec<f32>,
+    up: Vec<FirHistory>,
+    down: Vec<FirHistory>,
+    // interleaved audio at the higher rate, for `OVERSAMPLING_BLOCK` frames
+    buffer: Vec<f32>,
+}
+
+impl Oversampler {
+    pub fn new(factor: usize, channels: usize) -> Self {
+        let len = OVERSAMPLING_TAPS * 2 * factor + 1;
+        let cutoff = OVERSAMPLING_PASSBAND / 2.0 / factor as f32;
+        let centre = (len / 2) as f32;
+        let kernel = (0..len)
+            .map(|i| {
+                let x = i as f32 - centre;
+                let sinc = if x == 0.0 {
+                    2.0 * cutoff
+                } else {
+                    (std::f32::consts::TAU * cutoff * x).sin() / (std::f32::consts::PI * x)
+                };
+                // Blackman window
+                let phase = std::f32::consts::TAU * i as f32 / (len - 1) as f32;
+                sinc * (0.42 - 0.5 * phase.cos() + 0.08 * (2.0 * phase).cos())
+            })
+            .collect();
+        Self {
+            factor,
+            kernel,
+            up: (0..channels).map(|_| FirHistory::new(len)).collect(),
+            down: (0..channels).map(|_| FirHistory::new(len)).collect(),
+            buffer: Vec::with_capacity(OVERSAMPLING_BLOCK * factor * channels),
+        }
+    }
+
+    /// Forgets the audio seen so far, without allocating.
+    pub fn reset(&mut self) {
+        for history in self.up.iter_mut().chain(self.down.iter_mut()) {
+            history.clear();
+        }
+    }
+
+    /// Upsamples interleaved `data`, runs `stage` on the result and filters
+    /// and decimates it back into `data`, `OVERSAMPLING_BLOCK` frames at a
+    /// time. Doesn't allocate.
+    pub fn process(&mut self, data: &mut [f32], mut stage: impl FnMut(&mut [f32])) {
+        let channels = self.up.len();
+        for block in data.chunks_mut(OVERSAMPLING_BLOCK * channels) {
+            self.process_block(block, &mut stage);
+        }
+    }
+
+    fn process_block(&mut self, data: &mut [f32], stage: &mut impl FnMut(&mut [f32])) {
+        let channels = self.up.len();
+        self.buffer.clear();
+        for frame in data.chunks_exact(channels) {
+            for phase in 0..self.factor {
+                for (sample, history) in frame.iter().zip(self.up.iter_mut()) {
+                    // Zero stuffing; the gain makes up for the inserted zeros
+                    history.push(if phase == 0 {
+                        *sample * self.factor as f32
+                    } else {
+                        0.0
+                    });
+                    self.buffer.push(history.output(&self.kernel));
+                }
+            }
+        }
+        stage(&mut self.buffer);
+        for (frame, upsampled) in data
+            .chunks_exact_mut(channels)
+            .zip(self.buffer.chunks_exact(channels * self.factor))
+        {
+            for step in upsampled.chunks_exact(channels) {
+                for (sample, history) in step.iter().zip(self.down.iter_mut()) {
+                    history.push(*sample);
+                }
+            }
+            for (sample, history) in frame.iter_mut().zip(self.down.iter()) {
+                *sample = history.output(&self.kernel);
+            }
+        }
+    }
+}
+
+/// Keeps samples at or below a ceiling. Soft clipping is linear up to
+/// [`SOFT_CLIP_KNEE`] of the ceiling and approaches it smoothly above; the
+/// brickwall mode scales whole frames down with instant attack, so nothing
+/// passes the ceiling, and recovers over the release time. Either can run
+/// oversampled to keep the distortion they add from aliasing.
+pub struct Limiter {
+    sample_rate: f32,
+    channels: usize,
+    // brickwall gain currently applied
+    gain: f32,
+    // one per factor above 1, built up front so switching doesn't allocate
+    oversamplers: Vec<Oversampler>,
+    // the factor in use, 1 when not oversampling
+    factor: usize,
+}
+
+/// Fraction of the ceiling below which soft clipping leaves samples alone.
+const SOFT_CLIP_KNEE: f32 = 0.7;
+
+impl Limiter {
+    pub fn new(channels: u16, sample_rate: u32) -> Self {
+        Self {
+            sample_rate: sample_rate as f32,
+            channels: channels.max(1) as usize,
+            gain: 1.0,
+            oversamplers: OVERSAMPLING_FACTORS
+                .into_iter()
+                .filter(|factor| *factor > 1)
+                .map(|factor| Oversampler::new(factor as usize, channels.max(1) as usize))
+                .collect(),
+            factor: 1,
+        }
+    }
+
+    /// Processes interleaved samples in place and returns the largest gain
+    /// reduction applied, in positive dB.
+    pub fn process(&mut self, data: &mut [f32], limiter: &LimiterControls) -> f32 {
+        let mode = limiter.mode();
+        let factor = limiter.oversampling() as usize;
+        let index = self.oversamplers.iter().position(|o| o.factor == factor);
+        let Some(index) = index.filter(|_| mode != LimiterMode::Off) else {
+            self.factor = 1;
+            return self.limit(data, limiter, mode, self.sample_rate);
+        };
+        if self.factor != factor {
+            // Left from the last time this factor ran
+            self.oversamplers[index].reset();
+            self.factor = factor;
+        }
+        // Taken out for the closure; moving a Vec doesn't allocate
+        let mut oversamplers = std::mem::take(&mut self.oversamplers);
+        let sample_rate = self.sample_rate * factor as f32;
+        let mut reduction_db = 0.0f32;
+        oversamplers[index].process(data, |upsampled| {
+            let block_db = self.limit(upsampled, limiter, mode, sample_rate);
+            reduction_db = reduction_db.max(block_db);
+        });
+        self.oversamplers = oversamplers;
+        // The filters ring a little past the ceiling
+        let ceiling = db_to_gain(limiter.ceiling_db.load().min(0.0));
+        for sample in data.iter_mut() {
+            *sample = sample.clamp(-ceiling, ceiling);
+        }
+        reduction_db
+    }
+
+    fn limit(
+        &mut self,
+        data: &mut [f32],
+        limiter: &LimiterControls,
+        mode: LimiterMode,
+        sample_rate: f32,
+    ) -> f32 {
+        let ceiling = db_to_gain(limiter.ceiling_db.load().min(0.0));
+        let mut min_gain = 1.0f32;
+        match mode {
+            LimiterMode::Off => self.gain = 1.0,
+            LimiterMode::SoftClip => {
+                let knee = ceiling * SOFT_CLIP_KNEE;
+                for sample in data.iter_mut() {
+                    let magnitude = sample.abs();
+                    if magnitude > knee {
+                        let clipped = knee
+                            + (ceiling - knee) * ((magnitude - knee) / (ceiling - knee)).tanh();
+                        min_gain = min_gain.min(clipped / magnitude);
+                        *sample = clipped.copysign(*sample);
+                    }
+                }
+            }
+            LimiterMode::Brickwall => {
+                let release_ms = limiter.release_ms.load().max(1.0);
+                let release = 1.0 - (-1000.0 / (release_ms * sample_rate)).exp();
+                for frame in data.chunks_exact_mut(self.channels) {
+                    let peak = frame.iter().fold(0.0f32, |m, s| m.max(s.abs()));
+                    let target = if peak > ceiling { ceiling / peak } else { 1.0 };
+                    self.gain += (1.0 - self.gain) * release;
+                    self.gain = self.gain.min(target);
+                    for sample in frame.iter_mut() {
+                        // The clamp only absorbs rounding in `ceiling / peak`
+                        *sample = (*sample * self.gain).clamp(-ceiling, ceiling);
+                    }
+                    min_gain = min_gain.min(self.gain);
+                }
+            }
+        }
+        -gain_to_db(min_gain)
+    }
+}
+
+/// The last step of the output callback, after the EQ.
+pub struct OutputStage {
+    settings: Settings,
+    channels: usize,
+    crossfeed: Crossfeed,
+    // loudness shelves, built up front and retuned in place
+    loudness: ParametricEq,
+    // the volume the shelves are tuned for, None while loudness is off
+    loudness_for: Option<f32>,
+    limiter: Limiter,
+}
+
+/// The loudness shelves are retuned once the volume moves this far.
+const LOUDNESS_STEP_DB: f32 = 0.5;
+
+impl OutputStage {
+    pub fn new(settings: Settings, channels: u16, sample_rate: u32) -> Self {
+        Self {
+            settings,
+            channels: channels as usize,
+            crossfeed: Crossfeed::new(sample_rate as f32),
+            loudness: ParametricEq::from_profile(
+                &presets::loudness(0.0),
+                sample_rate as f32,
+                channels as usize,
+            ),
+            loudness_for: None,
+            limiter: Limiter::new(channels, sample_rate),
+        }
+    }
+
+    /// Processes interleaved samples in place.
+    pub fn process(&mut self, data: &mut [f32]) {
+        if self.channels == 2 {
+            self.process_stereo(data);
+        }
+        let gain_db = self.settings.output_gain_db.load();
+        self.process_loudness(data, gain_db);
+        if gain_db != 0.0 {
+            let gain = db_to_gain(gain_db);
+            for sample in data.iter_mut() {
+                *sample *= gain;
+            }
+        }
+        let limiter = &self.settings.limiter;
+        let reduction_db = self.limiter.process(data, limiter);
+        limiter.gain_reduction_db.store(reduction_db);
+    }
+
+    /// Runs the loudness compensation for the current volume, separate from
+    /// the user's EQ. The shelves are retuned in place, keeping their filter
+    /// state, so this doesn't allocate.
+    fn process_loudness(&mut self, data: &mut [f32], volume_db: f32) {
+        if !self.settings.loudness.load(Ordering::Relaxed) {
+            self.loudness_for = None;
+            return;
+        }
+        let retune = match self.loudness_for {
+            Some(tuned_for) => (tuned_for - volume_db).abs() >= LOUDNESS_STEP_DB,
+            None => {
+                // Turned on: the state left from before is stale
+                self.loudness.reset();
+                true
+            }
+        };
+        if retune {
+            let shelves = presets::loudness_shelves(volume_db as f64);
+            for (index, shelf) in shelves.iter().enumerate() {
+                self.loudness.update_band(
+                    index,
+                    shelf.filter_type,
+                    shelf.frequency as f32,
+                    shelf.effective_q() as f32,
+                    shelf.gain as f32,
+                );
+            }
+            self.loudness_for = Some(volume_db);
+        }
+        self.loudness.process_block(data);
+    }
+
+    fn process_stereo(&mut self, data: &mut [f32]) {
+        let crossfeed = &self.settings.crossfeed;
+        if crossfeed.enabled.load(Ordering::Relaxed) {
+            self.crossfeed
+                .process(data, crossfeed.amount.load(), crossfeed.cutoff_hz.load());
+        } else {
+            self.crossfeed.reset();
+        }
+        let swap = self.settings.swap_channels.load(Ordering::Relaxed);
+        let balance = self.settings.balance.load();
+        if !swap && balance == 0.0 {
+            return;
+        }
+        let (left_gain, right_gain) = balance_gains(balance);
+        for frame in data.chunks_exact_mut(2) {
+            if swap {
+                frame.swap(0, 1);
+            }
+            frame[0] *= left_gain;
+            frame[1] *= right_gain;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::PersistentSettings;
+
+    fn settings() -> Settings {
+        Settings::from(&PersistentSettings::default())
+    }
+
+    #[test]
+    fn test_output_gain() {
+        let settings = settings();
+        let mut stage = OutputStage::new(settings.clone(), 2, 48000);
+        let mut data = [0.5, -0.5];
+        stage.process(&mut data);
+        assert_eq!(data, [0.5, -0.5]);
+
+        settings.output_gain_db.store(-6.0);
+        let mut data = [0.5, -0.5];
+        stage.process(&mut data);
+        assert!((data[0] - 0.5 * 0.501_187).abs() < 1e-6);
+        assert!((data[1] + 0.5 * 0.501_187).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_balance_and_swap() {
+        let settings = settings();
+        let mut stage = OutputStage::new(settings.clone(), 2, 48000);
+        settings.balance.store(-1.0);
+        let mut data = [0.5, 0.25, -0.5, -0.25];
+        stage.process(&mut data);
+        assert_eq!(data, [0.5, 0.0, -0.5, 0.0]);
+
+        settings.balance.store(0.5);
+        settings.swap_channels.store(true, Ordering::Relaxed);
+        let mut data = [0.5, 0.25];
+        stage.process(&mut data);
+        assert_eq!(data, [0.125, 0.5]);
+
+        // Clamped, and left alone on mono streams
+        assert_eq!(balance_gains(3.0), (0.0, 1.0));
+        assert_eq!(balance_gains(f32::NAN), (1.0, 1.0));
+        let mut mono = OutputStage::new(settings, 1, 48000);
+        let mut data = [0.5, 0.25];
+        mono.process(&mut data);
+        assert_eq!(data, [0.5, 0.25]);
+    }
+
+    #[test]
+    fn test_crossfeed_bleeds_into_other_channel() {
+        let settings = settings();
+        let mut stage = OutputStage::new(settings.clone(), 2, 48000);
+        let hard_left: Vec<f32> = (0..4800)
+            .flat_map(|i| [(i as f32 * 0.02).sin(), 0.0])
+            .collect();
+        let mut data = hard_left.clone();
+        stage.process(&mut data);
+        assert!(data.iter().skip(1).step_by(2).all(|s| *s == 0.0));
+
+        settings.crossfeed.enabled.store(true, Ordering::Relaxed);
+        let mut data = hard_left.clone();
+        stage.process(&mut data);
+        let right_peak = data
+            .iter()
+            .skip(1)
+            .step_by(2)
+            .fold(0.0f32, |m, s| m.max(s.abs()));
+        assert!(right_peak > 0.05, "right peak {}", right_peak);
+        assert!(data.iter().all(|s| s.abs() <= 1.0));
+    }
+
+    #[test]
+    fn test_dc_blocker_removes_offset() {
+        let mut blocker = DcBlocker::new(2, 48000);
+        let mut data: Vec<f32> = (0..48000)
+            .flat_map(|i| {
+                let s = (i as f32 * 0.05).sin() * 0.3;
+                [s + 0.5, s - 0.2]
+            })
+            .collect();
+        blocker.process(&mut data);
+        // The last 100 ms, long after the filter has settled
+        let tail = &data[data.len() - 9600..];
+        for channel in 0..2 {
+            let mean = tail.iter().skip(channel).step_by(2).sum::<f32>() / 4800.0;
+            assert!(mean.abs() < 0.01, "channel {} mean {}", channel, mean);
+        }
+    }
+
+    #[test]
+    fn test_limiter_holds_ceiling() {
+        let hot: Vec<f32> = (0..9600)
+            .map(|i| (i as f32 * 0.03).sin() * 2.5 + if i % 97 == 0 { 4.0 } else { 0.0 })
+            .collect();
+        for mode in [LimiterMode::SoftClip, LimiterMode::Brickwall] {
+            let controls = LimiterControls::default();
+            controls.set_mode(mode);
+            let ceiling = db_to_gain(controls.ceiling_db.load());
+            let mut limiter = Limiter::new(2, 48000);
+            let mut data = hot.clone();
+            let reduction_db = limiter.process(&mut data, &controls);
+            assert!(
+                data.iter().all(|s| s.abs() <= ceiling),
+                "{} went over the ceiling",
+                mode
+            );
+            assert!(reduction_db > 6.0, "{} reported {} dB", mode, reduction_db);
+        }
+
+        // Quiet signals pass untouched
+        let controls = LimiterControls::default();
+        controls.set_mode(LimiterMode::SoftClip);
+        let mut limiter = Limiter::new(2, 48000);
+        let mut data = [0.5, -0.25];
+        assert_eq!(limiter.process(&mut data, &controls), 0.0);
+        assert_eq!(data, [0.5, -0.25]);
+    }
+
+    #[test]
+    fn test_oversampling_reduces_aliasing() {
+        use rustfft::{FftPlanner, num_complex::Complex};
+
+        const LEN: usize = 16384;
+        // Clipping a 7 kHz tone adds harmonics at 21, 35, 49 kHz and so on;
+        // at 48 kHz the ones past Nyquist fold back to 13, 15 and 19 kHz.
+        let tone: Vec<f32> = (0..LEN + 4800)
+            .map(|i| (i as f32 * 7000.0 / 48000.0 * std::f32::consts::TAU).sin() * 3.0)
+            .collect();
+        let aliasing = |factor: u8| {
+            let controls = LimiterControls::default();
+            controls.set_mode(LimiterMode::SoftClip);
+            controls.set_oversampling(factor);
+            let mut limiter = Limiter::new(1, 48000);
+            let mut data = tone.clone();
+            limiter.process(&mut data, &controls);
+            let mut spectrum: Vec<Complex<f32>> = data[4800..]
+                .iter()
+                .enumerate()
+                .map(|(i, s)| {
+                    let window = 0.5 - 0.5 * (i as f32 / LEN as f32 * std::f32::consts::TAU).cos();
+                    Complex::new(s * window, 0.0)
+                })
+                .collect();
+            FftPlanner::new()
+                .plan_fft_forward(LEN)
+                .process(&mut spectrum);
+            let bin = |hz: f32| (hz / 48000.0 * LEN as f32) as usize;
+            let energy: f32 = spectrum[bin(12000.0)..bin(20000.0)]
+                .iter()
+                .map(|c| c.norm_sqr())
+                .sum();
+            10.0 * energy.log10()
+        };
+        let plain = aliasing(1);
+        let oversampled = aliasing(4);
+        assert!(
+            plain - oversampled > 10.0,
+            "aliasing only fell from {} to {} dB",
+            plain,
+            oversampled
+        );
+        // A setting the limiter does not support is treated as off
+        let controls = LimiterControls::default();
+        controls.set_oversampling(3);
+        assert_eq!(controls.oversampling(), 1);
+    }
+
+    #[test]
+    fn test_oversampler_buffer_does_not_grow() {
+        let mut oversampler = Oversampler::new(4, 2);
+        let capacity = oversampler.buffer.capacity();
+        let mut data = vec![0.5; 2 * (OVERSAMPLING_BLOCK * 3 + 7)];
+        let mut samples = 0;
+        oversampler.process(&mut data, |upsampled| samples += upsampled.len());
+        assert_eq!(samples, data.len() * 4);
+        assert_eq!(oversampler.buffer.capacity(), capacity);
+    }
+
+    #[test]
+    fn test_dither_noise_is_triangular() {
+        let mut dither = Dither::new(1);
+        let noise: Vec<f32> = (0..100_000).map(|_| dither.tpdf()).collect();
+        let mean = noise.iter().sum::<f32>() / noise.len() as f32;
+        let variance = noise.iter().map(|n| n * n).sum::<f32>() / noise.len() as f32;
+        let central = noise.iter().filter(|n| n.abs() < 0.5).count() as f32 / noise.len() as f32;
+        assert!(noise.iter().all(|n| n.abs() < 1.0));
+        assert!(mean.abs() < 0.01, "mean {}", mean);
+        // Triangular over -1..1: variance 1/6, three quarters within +-0.5
+        assert!((variance - 1.0 / 6.0).abs() < 0.01, "variance {}", variance);
+        assert!((central - 0.75).abs() < 0.01, "{} within +-0.5", central);
+
+        // A quarter step of DC rounds to silence undithered; dithered, the
+        // output lands on the 16-bit grid and averages out to the input
+        let controls = DitherControls::default();
+        let step = 1.0 / 32768.0;
+        let mut data = vec![0.25 * step; 100_000];
+        dither.process(&mut data, 16, &controls);
+        assert!(data.iter().all(|s| (s / step).fract() == 0.0));
+        let mean = data.iter().sum::<f32>() / data.len() as f32 / step;
+        assert!((mean - 0.25).abs() < 0.02, "mean {} steps", mean);
+
+        assert_eq!(dither_bits(SampleFormat::I16), Some(16));
+        assert_eq!(dither_bits(SampleFormat::I24), Some(24));
+        assert_eq!(dither_bits(SampleFormat::I32), None);
+        assert_eq!(dither_bits(SampleFormat::F32), None);
+    }
+
+    #[test]
+    fn test_loudness_lifts_bass_at_low_volume() {
+        let settings = settings();
+        settings.output_gain_db.store(-30.0);
+        let bass: Vec<f32> = (0..48000)
+            .map(|i| (i as f32 * 40.0 / 48000.0 * std::f32::consts::TAU).sin() * 0.5)
+            .collect();
+        let peak = |settings: &Settings| {
+            let mut stage = OutputStage::new(settings.clone(), 1, 48000);
+            let mut data = bass.clone();
+            stage.process(&mut data);
+            data[24000..].iter().fold(0.0f32, |m, s| m.max(s.abs()))
+        };
+        let plain = peak(&settings);
+        settings.loudness.store(true, Ordering::Relaxed);
+        let boost_db = gain_to_db(peak(&settings) / plain);
+        assert!(boost_db > 6.0, "boosted by {} dB", boost_db);
+
+        // Turning the volume up retunes the same shelves flat
+        let mut stage = OutputStage::new(settings.clone(), 1, 48000);
+        stage.process(&mut bass.clone());
+        settings.output_gain_db.store(0.0);
+        let mut data = bass.clone();
+        stage.process(&mut data);
+        let peak = data[24000..].iter().fold(0.0f32, |m, s| m.max(s.abs()));
+        assert!((peak - 0.5).abs() < 0.01, "{}", peak);
+    }
+}
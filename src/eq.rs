@@ -1,6 +1,13 @@
+use anyhow::Context;
 use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use rustfft::{FftPlanner, num_complex::Complex};
+
+use crate::response::{BiquadResponse, log_freq_between};
 
 use std::num::ParseFloatError;
+use std::path::Path;
 use std::str::FromStr;
 
 /// Equalizer APO FilterType
@@ -34,21 +41,32 @@ impl FromStr for FilterType {
             "PK" | "PEAK" => Ok(FilterType::Peaking),
             "LSC" | "LOWSHELF" => Ok(FilterType::LowShelf),
             "HSC" | "HIGHSHELF" => Ok(FilterType::HighShelf),
-            "LP" | "LOWPASS" => Ok(FilterType::LowPass),
-            "HP" | "HIGHPASS" => Ok(FilterType::HighPass),
+            // Equalizer APO's LP and HP have a fixed Q of 0.707, which is
+            // also what a band without a Q gets
+            "LP" | "LPQ" | "LOWPASS" => Ok(FilterType::LowPass),
+            "HP" | "HPQ" | "HIGHPASS" => Ok(FilterType::HighPass),
             _ => Err(EqParseError::UnknownFilterType),
         }
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Filter {
     pub enabled: bool,
     pub filter_type: FilterType,
     pub frequency: f64, // Hz
     pub gain: f64,      // dB
     pub q_factor: f64,
+    /// Bandwidth in octaves; used instead of `q_factor` when set
     pub bandwidth: Option<f64>,
+    /// Keeps the band editor from changing the band; it is still heard and
+    /// drawn. Not in Equalizer APO text.
+    #[serde(default)]
+    pub locked: bool,
+    /// While any band is soloed only soloed bands are heard. Monitoring only,
+    /// never saved.
+    #[serde(skip)]
+    pub solo: bool,
 }
 
 impl Default for Filter {
@@ -60,14 +78,72 @@ impl Default for Filter {
             gain: 0.0,
             q_factor: 0.707,
             bandwidth: None,
+            locked: false,
+            solo: false,
         }
     }
 }
 
-#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+const TILT_PIVOT_HZ: f64 = 1000.0;
+/// Octaves from 20 Hz to 20 kHz; the tilt's shelves differ by this many
+/// times the slope.
+pub const TILT_SPAN_OCTAVES: f64 = 9.966;
+// wide shelves, so the change is spread over the band
+const TILT_Q: f64 = 0.4;
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
 pub struct EqProfile {
     pub preamp_db: f64,
     pub filters: Vec<Filter>,
+    /// Broadband tilt around 1 kHz, positive for brighter. Applied on top of
+    /// the bands by `tilt_filters`.
+    #[serde(default)]
+    pub tilt_db_per_oct: f64,
+    #[serde(default, skip_serializing_if = "ProfileMetadata::is_empty")]
+    pub metadata: ProfileMetadata,
+}
+
+/// Keys of the [`ProfileMetadata`] fields in APO text, in the order written.
+const METADATA_KEYS: [&str; 3] = ["Name", "Source", "Notes"];
+
+/// What a profile is, for a library of them; never heard. APO text keeps
+/// it as `# Name:`, `# Source:` and `# Notes:` comments, which Equalizer APO
+/// skips, one per line of the field.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ProfileMetadata {
+    pub name: Option<String>,
+    /// Where the profile came from, e.g. the measurement it was fit to
+    pub source: Option<String>,
+    pub notes: Option<String>,
+}
+
+impl ProfileMetadata {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// The fields in the order of [`METADATA_KEYS`].
+    fn fields(&self) -> [&Option<String>; 3] {
+        [&self.name, &self.source, &self.notes]
+    }
+
+    /// Adds a line read from APO text to the field `key` names, one of
+    /// [`METADATA_KEYS`].
+    fn push_line(&mut self, key: &str, line: &str) {
+        let field = match key {
+            "Name" => &mut self.name,
+            "Source" => &mut self.source,
+            _ => &mut self.notes,
+        };
+        match field {
+            Some(text) => {
+                text.push('\n');
+                text.push_str(line);
+            }
+            None => *field = Some(line.to_string()),
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -76,59 +152,565 @@ pub enum EqParseError {
     UnknownFilterType,
 }
 
+impl std::fmt::Display for EqParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::ParseFloatError => f.write_str("invalid number in EQ profile"),
+            Self::UnknownFilterType => f.write_str("unknown filter type in EQ profile"),
+        }
+    }
+}
+
+impl std::error::Error for EqParseError {}
+
 impl From<ParseFloatError> for EqParseError {
     fn from(_: ParseFloatError) -> Self {
         EqParseError::ParseFloatError
     }
 }
 
+/// Bands are added to a fit until the remaining error is below this.
+const FIT_TOLERANCE_DB: f64 = 0.5;
+const FIT_MAX_GAIN_DB: f64 = 12.0;
+// keeps a band on a single sharp point of the target from getting absurdly narrow
+const FIT_MIN_OCTAVES: f64 = 0.1;
+
+/// Q of a band `octaves` wide, measured between its -3 dB points.
+pub fn bandwidth_to_q(octaves: f64) -> f64 {
+    let ratio = octaves.exp2();
+    ratio.sqrt() / (ratio - 1.0)
+}
+
+/// Inverse of [`bandwidth_to_q`].
+pub fn q_to_bandwidth(q: f64) -> f64 {
+    2.0 / std::f64::consts::LN_2 * (1.0 / (2.0 * q)).asinh()
+}
+
 impl Filter {
     pub(crate) fn effective_q(&self) -> f64 {
-        if let Some(bw) = self.bandwidth {
-            if bw > 0.0 { self.frequency / bw } else { self.q_factor }
-        } else {
-            self.q_factor
+        match self.bandwidth {
+            Some(bw) if bw > 0.0 => bandwidth_to_q(bw),
+            _ => self.q_factor,
         }
     }
+
+    /// Whether the band can run as a stable biquad at `sample_rate`;
+    /// [`ParametricEq`] passes the ones that can't through unfiltered.
+    pub fn is_stable(&self, sample_rate: f32) -> bool {
+        BiquadCoeffs::calculate(
+            self.filter_type,
+            self.frequency as f32,
+            self.effective_q() as f32,
+            self.gain as f32,
+            sample_rate,
+        )
+        .is_stable()
+    }
 }
 
-impl FromStr for EqProfile {
-    type Err = EqParseError;
+impl FilterType {
+    /// Every type, in the order the UI lists them
+    #[cfg_attr(not(feature = "gui"), allow(dead_code))]
+    pub const ALL: [FilterType; 5] = [
+        Self::Peaking,
+        Self::LowShelf,
+        Self::HighShelf,
+        Self::LowPass,
+        Self::HighPass,
+    ];
 
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
+    /// Equalizer APO abbreviation, of the variant that takes a Q
+    fn apo_name(&self) -> &'static str {
+        match self {
+            Self::Peaking => "PK",
+            Self::LowShelf => "LSC",
+            Self::HighShelf => "HSC",
+            Self::LowPass => "LPQ",
+            Self::HighPass => "HPQ",
+        }
+    }
+
+    pub fn has_gain(&self) -> bool {
+        !matches!(self, Self::LowPass | Self::HighPass)
+    }
+
+    /// Biquad type name used by CamillaDSP
+    fn camilladsp_name(&self) -> &'static str {
+        match self {
+            Self::Peaking => "Peaking",
+            Self::LowShelf => "Lowshelf",
+            Self::HighShelf => "Highshelf",
+            Self::LowPass => "Lowpass",
+            Self::HighPass => "Highpass",
+        }
+    }
+}
+
+impl EqProfile {
+    /// The bands that are processed: enabled ones, narrowed down to the
+    /// soloed ones while any band is soloed.
+    pub fn active_filters(&self) -> impl Iterator<Item = &Filter> {
+        let soloing = self.filters.iter().any(|f| f.solo);
+        self.filters
+            .iter()
+            .filter(move |f| f.enabled && (f.solo || !soloing))
+    }
+
+    /// The shelf pair that makes up the tilt: a low shelf cutting and a high
+    /// shelf boosting by half the total, both at the pivot. None when flat.
+    pub fn tilt_filters(&self) -> Vec<Filter> {
+        if self.tilt_db_per_oct == 0.0 {
+            return Vec::new();
+        }
+        let half = self.tilt_db_per_oct * TILT_SPAN_OCTAVES / 2.0;
+        [(FilterType::LowShelf, -half), (FilterType::HighShelf, half)]
+            .into_iter()
+            .map(|(filter_type, gain)| Filter {
+                filter_type,
+                frequency: TILT_PIVOT_HZ,
+                gain,
+                q_factor: TILT_Q,
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    /// Combined gain in dB of the processed bands and the tilt at each of
+    /// `freqs`, without the preamp.
+    pub fn magnitude_response(&self, freqs: &[f64], fs: f64) -> Vec<f64> {
+        let tilt = self.tilt_filters();
+        let coeffs: Vec<BiquadResponse> = self
+            .active_filters()
+            .chain(&tilt)
+            .map(|f| BiquadResponse::calc(f, fs))
+            .collect();
+        freqs
+            .iter()
+            .map(|freq| {
+                coeffs
+                    .iter()
+                    .map(|coeffs| coeffs.calc_magnitude_db(*freq, fs))
+                    .sum()
+            })
+            .collect()
+    }
+
+    /// Impulse response of the profile, preamp included, at `fs`: linear
+    /// phase (centred, `taps` rounded up to odd) or minimum phase.
+    pub fn to_impulse_response(&self, taps: usize, fs: f64, minimum_phase: bool) -> Vec<f32> {
+        let eq = ParametricEq::from_profile(self, fs as f32, 1);
+        if minimum_phase {
+            eq.to_min_phase_fir(taps)
+        } else {
+            eq.to_fir(taps)
+        }
+    }
+
+    /// Highest gain between 20 Hz and 20 kHz (or Nyquist), preamp included.
+    /// Above 0 dB a full-scale signal at that frequency would clip.
+    pub fn peak_db(&self, fs: f64) -> f64 {
+        self.peak_db_between(fs, 20.0, 20000.0)
+    }
+
+    /// [`Self::peak_db`] between `min` and `max` Hz.
+    pub fn peak_db_between(&self, fs: f64, min: f64, max: f64) -> f64 {
+        const POINTS: usize = 1000;
+        let freqs: Vec<f64> = (0..=POINTS)
+            .map(|i| log_freq_between(i as f64 / POINTS as f64, min, max))
+            .filter(|freq| *freq < fs / 2.0)
+            .collect();
+        let peak = self
+            .magnitude_response(&freqs, fs)
+            .into_iter()
+            .fold(f64::NEG_INFINITY, f64::max);
+        peak + self.preamp_db
+    }
+
+    /// The complement of this profile: band gains, the preamp and the tilt
+    /// negated, so running both leaves the signal flat. Low and high passes
+    /// have no gain to negate and are kept as they are; see
+    /// [`Self::is_invertible`].
+    #[cfg_attr(not(feature = "gui"), allow(dead_code))]
+    pub fn inverted(&self) -> EqProfile {
+        // `0.0 - x` rather than `-x`, which turns 0 into -0
+        let negate = |db: f64| 0.0 - db;
+        EqProfile {
+            preamp_db: negate(self.preamp_db),
+            filters: self
+                .filters
+                .iter()
+                .map(|filter| Filter {
+                    gain: if filter.filter_type.has_gain() {
+                        negate(filter.gain)
+                    } else {
+                        filter.gain
+                    },
+                    ..filter.clone()
+                })
+                .collect(),
+            tilt_db_per_oct: negate(self.tilt_db_per_oct),
+            metadata: self.metadata.clone(),
+        }
+    }
+
+    /// Zeroes every band gain, the preamp and the tilt, keeping the bands'
+    /// types, frequencies and widths, to hear the signal without correction.
+    /// Low and high passes still filter.
+    #[cfg_attr(not(feature = "gui"), allow(dead_code))]
+    pub fn flatten(&mut self) {
+        self.preamp_db = 0.0;
+        self.tilt_db_per_oct = 0.0;
+        for filter in &mut self.filters {
+            filter.gain = 0.0;
+        }
+    }
+
+    /// Whether [`Self::inverted`] undoes the whole profile, i.e. there are no
+    /// low or high passes.
+    #[cfg_attr(not(feature = "gui"), allow(dead_code))]
+    pub fn is_invertible(&self) -> bool {
+        self.filters.iter().all(|f| f.filter_type.has_gain())
+    }
+
+    /// Approximates a target curve of (frequency, dB) points, sorted by
+    /// frequency, with up to `max_bands` peaking filters. Each band goes where
+    /// the remaining error is largest, as high as the error there and as wide
+    /// as the stretch above half of it, and its response is subtracted before
+    /// placing the next.
+    pub fn fit_to_target(points: &[(f64, f64)], max_bands: usize, fs: f64) -> EqProfile {
+        let points: Vec<(f64, f64)> = points
+            .iter()
+            .copied()
+            .filter(|(freq, _)| *freq > 0.0 && *freq < fs / 2.0)
+            .collect();
+        let mut residual: Vec<f64> = points.iter().map(|(_, db)| *db).collect();
         let mut profile = EqProfile::default();
+        for _ in 0..max_bands {
+            let Some((peak, error)) = residual
+                .iter()
+                .copied()
+                .enumerate()
+                .max_by(|a, b| a.1.abs().total_cmp(&b.1.abs()))
+            else {
+                break;
+            };
+            if error.abs() < FIT_TOLERANCE_DB {
+                break;
+            }
+            let above_half = |i: usize| residual[i] * error.signum() > error.abs() / 2.0;
+            let mut low = peak;
+            while low > 0 && above_half(low - 1) {
+                low -= 1;
+            }
+            let mut high = peak;
+            while high + 1 < residual.len() && above_half(high + 1) {
+                high += 1;
+            }
+            let octaves = (points[high].0 / points[low].0).log2().max(FIT_MIN_OCTAVES);
+            let filter = Filter {
+                frequency: points[peak].0.round(),
+                gain: (error.clamp(-FIT_MAX_GAIN_DB, FIT_MAX_GAIN_DB) * 10.0).round() / 10.0,
+                q_factor: (bandwidth_to_q(octaves).clamp(0.1, 10.0) * 100.0).round() / 100.0,
+                ..Default::default()
+            };
+            let response = BiquadResponse::calc(&filter, fs);
+            for ((freq, _), error) in points.iter().zip(residual.iter_mut()) {
+                *error -= response.calc_magnitude_db(*freq, fs);
+            }
+            profile.filters.push(filter);
+        }
+        profile
+    }
 
-        for line in s.lines() {
-            let line = line.trim();
+    /// Emits a CamillaDSP (v3) `filters:` map and a `pipeline:` that applies
+    /// them in order. The preamp becomes a global `Gain` filter in front of
+    /// the bands, and the tilt its two shelves after them. Bands at or above
+    /// Nyquist for `fs` are skipped.
+    pub fn to_camilladsp_yaml(&self, fs: f64) -> String {
+        use std::fmt::Write;
 
-            if line.is_empty() || line.starts_with('#') {
+        let mut names = vec!["preamp".to_string()];
+        let mut out = String::from("filters:\n");
+        writeln!(out, "  preamp:").unwrap();
+        writeln!(out, "    type: Gain").unwrap();
+        writeln!(out, "    parameters:").unwrap();
+        writeln!(out, "      gain: {}", self.preamp_db).unwrap();
+
+        let bands = (1..).map(|i| format!("band_{}", i)).zip(&self.filters);
+        let tilt = self.tilt_filters();
+        let tilt = ["tilt_low", "tilt_high"]
+            .map(String::from)
+            .into_iter()
+            .zip(&tilt);
+        for (name, filter) in bands.chain(tilt) {
+            if !filter.enabled {
+                continue;
+            }
+            if filter.frequency >= fs / 2.0 {
+                warn!(
+                    "Skipping {} at {} Hz: above Nyquist for {} Hz",
+                    name, filter.frequency, fs
+                );
                 continue;
             }
+            writeln!(out, "  {}:", name).unwrap();
+            writeln!(out, "    type: Biquad").unwrap();
+            writeln!(out, "    parameters:").unwrap();
+            writeln!(out, "      type: {}", filter.filter_type.camilladsp_name()).unwrap();
+            writeln!(out, "      freq: {}", filter.frequency).unwrap();
+            writeln!(out, "      q: {}", filter.effective_q()).unwrap();
+            if filter.filter_type.has_gain() {
+                writeln!(out, "      gain: {}", filter.gain).unwrap();
+            }
+            names.push(name);
+        }
 
-            if line.to_uppercase().starts_with("PREAMP:") {
-                let parts: Vec<&str> = line.split_whitespace().collect();
-                if parts.len() >= 2 {
-                    let val_str = if parts[0].ends_with(':') {
-                        parts[1]
-                    } else {
-                        parts[2]
-                    };
-                    profile.preamp_db = val_str.parse()?;
+        out.push_str("pipeline:\n");
+        out.push_str("  - type: Filter\n");
+        out.push_str("    names:\n");
+        for name in names {
+            writeln!(out, "      - {}", name).unwrap();
+        }
+        out
+    }
+}
+
+impl EqProfile {
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string_pretty(self)
+    }
+
+    pub fn from_json(s: &str) -> serde_json::Result<Self> {
+        serde_json::from_str(strip_bom(s))
+    }
+
+    /// Parses either JSON or Equalizer APO text, picking JSON when the
+    /// content looks like a JSON object.
+    #[cfg_attr(not(feature = "gui"), allow(dead_code))]
+    pub fn parse(s: &str) -> anyhow::Result<Self> {
+        if strip_bom(s).trim_start().starts_with('{') {
+            Ok(Self::from_json(s)?)
+        } else {
+            Ok(Self::from_str(s)?)
+        }
+    }
+
+    /// Loads a profile from disk. `.json` files use the JSON parser, anything
+    /// else is sniffed by content.
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let content = std::fs::read_to_string(path)?;
+        if is_json_path(path) || strip_bom(&content).trim_start().starts_with('{') {
+            return Ok(Self::from_json(&content)?);
+        }
+        let (profile, warnings) = Self::load_apo(path)?;
+        for warning in warnings {
+            warn!("{}", warning);
+        }
+        Ok(profile)
+    }
+
+    /// Writes the profile to disk, as JSON for `.json` files and as
+    /// Equalizer APO text otherwise.
+    #[cfg_attr(not(feature = "gui"), allow(dead_code))]
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        let content = if is_json_path(path) {
+            self.to_json()?
+        } else {
+            self.to_string()
+        };
+        std::fs::write(path, content).with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    /// Reads an Equalizer APO config, merging `Include:` files (relative to
+    /// the including file) in place. Also returns a warning for each line it
+    /// skipped, e.g. `Device:` or `Convolution:`.
+    pub fn load_apo(path: &Path) -> anyhow::Result<(Self, Vec<String>)> {
+        let mut profile = EqProfile::default();
+        let mut warnings = Vec::new();
+        profile.merge_apo(path, &mut warnings, 0)?;
+        Ok((profile, warnings))
+    }
+
+    fn merge_apo(
+        &mut self,
+        path: &Path,
+        warnings: &mut Vec<String>,
+        depth: usize,
+    ) -> anyhow::Result<()> {
+        if depth > MAX_INCLUDE_DEPTH {
+            anyhow::bail!("Includes nested too deep at {}", path.display());
+        }
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        for (number, line) in strip_bom(&content).lines().enumerate() {
+            let parsed = parse_apo_line(line)
+                .with_context(|| format!("{} line {}", path.display(), number + 1))?;
+            match parsed {
+                None => {}
+                // An included file's comments describe that file
+                Some(ApoLine::Metadata(key, line)) if depth == 0 => {
+                    self.metadata.push_line(key, line)
                 }
-                continue;
+                Some(ApoLine::Metadata(..)) => {}
+                Some(ApoLine::Preamp(db)) => self.preamp_db += db,
+                Some(ApoLine::Filter(filter)) => self.filters.push(filter),
+                Some(ApoLine::Include(name)) => {
+                    let dir = path.parent().unwrap_or(Path::new("."));
+                    self.merge_apo(&dir.join(name), warnings, depth + 1)?;
+                }
+                Some(ApoLine::Other(line)) => warnings.push(format!(
+                    "{} line {}: ignoring \"{}\"",
+                    path.display(),
+                    number + 1,
+                    line
+                )),
             }
+        }
+        Ok(())
+    }
+}
 
-            if line.to_uppercase().starts_with("FILTER") {
-                let filter = parse_filter_line(line)?;
-                profile.filters.push(filter);
-                continue;
+/// Writes the profile as Equalizer APO text, readable by `FromStr`. Numbers
+/// are written in full, so parsing the text gives back the same values; what
+/// it can't hold is lost: the tilt, locks, the Q of a band set in octaves and
+/// the gain of low and high pass bands. The metadata comes first, as
+/// comments; spaces around its lines are lost.
+impl std::fmt::Display for EqProfile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (key, text) in METADATA_KEYS.into_iter().zip(self.metadata.fields()) {
+            for line in text.iter().flat_map(|text| text.split('\n')) {
+                writeln!(f, "{}", format!("# {}: {}", key, line.trim()).trim_end())?;
             }
         }
+        writeln!(f, "Preamp: {} dB", self.preamp_db)?;
+        for (i, filter) in self.filters.iter().enumerate() {
+            write!(
+                f,
+                "Filter {}: {} {} Fc {} Hz",
+                i + 1,
+                if filter.enabled { "ON" } else { "OFF" },
+                filter.filter_type.apo_name(),
+                filter.frequency
+            )?;
+            if filter.filter_type.has_gain() {
+                write!(f, " Gain {} dB", filter.gain)?;
+            }
+            match filter.bandwidth {
+                Some(bw) => writeln!(f, " BW Oct {}", bw)?,
+                None => writeln!(f, " Q {}", filter.q_factor)?,
+            }
+        }
+        Ok(())
+    }
+}
 
+/// Files saved by Windows editors often start with a UTF-8 byte order mark.
+fn strip_bom(s: &str) -> &str {
+    s.strip_prefix('\u{feff}').unwrap_or(s)
+}
+
+/// Equalizer APO text. `Preamp` lines may appear anywhere and add up, as
+/// they do in Equalizer APO; other lines, `Include:` among them, are skipped
+/// (see [`EqProfile::load_apo`] for a file with its includes).
+impl FromStr for EqProfile {
+    type Err = EqParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut profile = EqProfile::default();
+        for line in strip_bom(s).lines() {
+            match parse_apo_line(line)? {
+                Some(ApoLine::Metadata(key, line)) => profile.metadata.push_line(key, line),
+                Some(ApoLine::Preamp(db)) => profile.preamp_db += db,
+                Some(ApoLine::Filter(filter)) => profile.filters.push(filter),
+                Some(ApoLine::Include(_) | ApoLine::Other(_)) | None => {}
+            }
+        }
         Ok(profile)
     }
 }
 
+fn is_json_path(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("json"))
+}
+
+// guards against files including each other
+const MAX_INCLUDE_DEPTH: usize = 8;
+
+/// A line of Equalizer APO text.
+enum ApoLine<'a> {
+    /// A line of a [`ProfileMetadata`] field, with its key
+    Metadata(&'static str, &'a str),
+    Preamp(f64),
+    Filter(Filter),
+    /// File name, relative to the including file
+    Include(&'a str),
+    /// A directive this crate doesn't model, e.g. `Channel: L`
+    Other(&'a str),
+}
+
+/// The text after `name:` when the line is that directive.
+fn directive<'a>(line: &'a str, name: &str) -> Option<&'a str> {
+    let prefix = line.get(..name.len() + 1)?;
+    (prefix[..name.len()].eq_ignore_ascii_case(name) && prefix.ends_with(':'))
+        .then(|| line[name.len() + 1..].trim())
+}
+
+/// `None` for blank and comment lines other than metadata.
+fn parse_apo_line(line: &str) -> Result<Option<ApoLine<'_>>, EqParseError> {
+    // Checked first, so a `;` in the notes stays
+    if let Some(comment) = line.trim().strip_prefix('#') {
+        let comment = comment.trim_start();
+        return Ok(METADATA_KEYS
+            .into_iter()
+            .find_map(|key| Some(ApoLine::Metadata(key, directive(comment, key)?))));
+    }
+    // `;` starts a comment anywhere on the line; trimming drops a stray `\r`
+    let line = line.split(';').next().unwrap_or_default().trim();
+    if line.is_empty() {
+        return Ok(None);
+    }
+    if let Some(value) = directive(line, "PREAMP") {
+        let db = match value.split_whitespace().next() {
+            Some(value) => parse_value(value)?,
+            None => 0.0,
+        };
+        return Ok(Some(ApoLine::Preamp(db)));
+    }
+    if let Some(name) = directive(line, "INCLUDE") {
+        return Ok(Some(ApoLine::Include(name)));
+    }
+    if is_filter_line(line) {
+        return Ok(Some(ApoLine::Filter(parse_filter_line(line)?)));
+    }
+    Ok(Some(ApoLine::Other(line)))
+}
+
+/// Units some generators glue onto the number, e.g. `Gain -3dB`.
+const UNIT_SUFFIXES: [&str; 3] = ["HZ", "DB", "OCT"];
+
+/// Parses a number, plain or in scientific notation, ignoring a trailing
+/// unit from [`UNIT_SUFFIXES`].
+fn parse_value(token: &str) -> Result<f64, EqParseError> {
+    let upper = token.to_ascii_uppercase();
+    let number = UNIT_SUFFIXES
+        .iter()
+        .find_map(|unit| upper.strip_suffix(unit))
+        .map_or(token, |number| &token[..number.len()]);
+    Ok(number.parse()?)
+}
+
+/// `Filter:` or `Filter <n>:`, and not e.g. REW's `Filter Settings file`
+/// header.
+fn is_filter_line(line: &str) -> bool {
+    let Some((head, _)) = line.split_once(':') else {
+        return false;
+    };
+    head.get(..6)
+        .is_some_and(|name| name.eq_ignore_ascii_case("FILTER"))
+        && head[6..].trim().chars().all(|c| c.is_ascii_digit())
+}
+
 fn parse_filter_line(line: &str) -> Result<Filter, EqParseError> {
     let parts: Vec<&str> = line.splitn(2, ':').collect();
     if parts.len() < 2 {
@@ -146,22 +728,32 @@ fn parse_filter_line(line: &str) -> Result<Filter, EqParseError> {
             "ON" => filter.enabled = true,
             "OFF" => filter.enabled = false,
             "FC" if i + 1 < tokens.len() => {
-                filter.frequency = tokens[i + 1].parse()?;
+                filter.frequency = parse_value(tokens[i + 1])?;
                 i += 1;
             }
             "GAIN" if i + 1 < tokens.len() => {
-                filter.gain = tokens[i + 1].parse()?;
+                filter.gain = parse_value(tokens[i + 1])?;
                 i += 1;
             }
             "Q" if i + 1 < tokens.len() => {
-                filter.q_factor = tokens[i + 1].parse()?;
+                filter.q_factor = parse_value(tokens[i + 1])?;
                 i += 1;
             }
             "BW" if i + 1 < tokens.len() => {
-                filter.bandwidth = Some(tokens[i + 1].parse()?);
+                // Equalizer APO puts the unit first: "BW Oct 1.5"
+                if tokens[i + 1].eq_ignore_ascii_case("OCT") && i + 2 < tokens.len() {
+                    i += 1;
+                }
+                filter.bandwidth = Some(parse_value(tokens[i + 1])?);
                 i += 1;
             }
             "HZ" | "DB" => {}
+            // REW's unused slots, e.g. "Filter 2: ON None"; kept as a band
+            // that passes audio through
+            "NONE" => {
+                filter.enabled = false;
+                filter.gain = 0.0;
+            }
             _ => {
                 filter.filter_type = FilterType::from_str(token)?;
             }
@@ -189,6 +781,21 @@ struct BiquadCoeffs {
 }
 
 impl BiquadCoeffs {
+    /// Leaves the signal unchanged.
+    const PASS_THROUGH: Self = Self {
+        b0: 1.0,
+        b1: 0.0,
+        b2: 0.0,
+        a1: 0.0,
+        a2: 0.0,
+    };
+
+    /// Both poles inside the unit circle, so the filter's output stays
+    /// bounded. NaN coefficients fail too.
+    fn is_stable(&self) -> bool {
+        self.a2.abs() < 1.0 && self.a1.abs() < 1.0 + self.a2
+    }
+
     /// Calculates coefficients based on RBJ Audio EQ Cookbook formulas
     fn calculate(
         filter_type: FilterType,
@@ -302,6 +909,15 @@ impl ScalarBiquad {
         }
     }
 
+    /// Swaps the coefficients but keeps the history, so the change doesn't click.
+    fn set_coeffs(&mut self, coeffs: BiquadCoeffs) {
+        self.b0 = coeffs.b0;
+        self.b1 = coeffs.b1;
+        self.b2 = coeffs.b2;
+        self.a1 = coeffs.a1;
+        self.a2 = coeffs.a2;
+    }
+
     #[inline(always)]
     fn process(&mut self, input: f32) -> f32 {
         let output = self.b0 * input + self.b1 * self.x1 + self.b2 * self.x2
@@ -354,6 +970,18 @@ impl SimdBiquad {
         }
     }
 
+    /// Swaps the coefficients but keeps the history, so the change doesn't click.
+    #[cfg(target_arch = "aarch64")]
+    fn set_coeffs(&mut self, coeffs: BiquadCoeffs) {
+        unsafe {
+            self.b0 = vdupq_n_f32(coeffs.b0);
+            self.b1 = vdupq_n_f32(coeffs.b1);
+            self.b2 = vdupq_n_f32(coeffs.b2);
+            self.a1 = vdupq_n_f32(coeffs.a1);
+            self.a2 = vdupq_n_f32(coeffs.a2);
+        }
+    }
+
     /// Process a single "Quad-Sample" (4 channels at the same time step).
     /// Returns the filtered Quad-Sample.
     /// Direct Form I Difference Equation:
@@ -385,30 +1013,77 @@ impl SimdBiquad {
     }
 }
 
-/// The main Equalizer struct.
-/// "Dynamic" means it can handle a variable number of bands.
+#[cfg(target_arch = "aarch64")]
+type Biquad = SimdBiquad;
+#[cfg(not(target_arch = "aarch64"))]
+type Biquad = ScalarBiquad;
+
+/// Channels one `Biquad` filters side by side.
+#[cfg(target_arch = "aarch64")]
+const LANES: usize = 4;
+#[cfg(not(target_arch = "aarch64"))]
+const LANES: usize = 1;
+
+/// Runs one frame of `LANES` channels through a chain of bands.
+#[cfg(target_arch = "aarch64")]
+fn process_chain(chain: &mut [Biquad], input: [f32; LANES]) -> [f32; LANES] {
+    let mut output = [0.0; LANES];
+    unsafe {
+        let mut quad = vld1q_f32(input.as_ptr());
+        for band in chain {
+            quad = band.process_quad(quad);
+        }
+        vst1q_f32(output.as_mut_ptr(), quad);
+    }
+    output
+}
+
+/// Runs one frame of `LANES` channels through a chain of bands.
+#[cfg(not(target_arch = "aarch64"))]
+fn process_chain(chain: &mut [Biquad], input: [f32; LANES]) -> [f32; LANES] {
+    [chain.iter_mut().fold(input[0], |s, band| band.process(s))]
+}
+
+/// The equalizer the engine, the offline paths and the loudness compensation
+/// run: a preamp and a chain of biquads, built from an [`EqProfile`] with
+/// [`from_profile`](Self::from_profile) and edited band by band.
+///
+/// Audio goes through [`process_block`](Self::process_block) as interleaved
+/// frames of the channel count the EQ was built for. Every channel of a frame
+/// has filter state of its own, kept from one call to the next by its
+/// position in the frame, so channel 1 never hears channel 0's history;
+/// [`reset`](Self::reset) starts over, e.g. after a stream restart.
 pub struct ParametricEq {
     sample_rate: f32,
+    channels: usize,
     preamp_db: f64,
-    #[cfg(target_arch = "aarch64")]
-    bands: Vec<SimdBiquad>,
-    #[cfg(not(target_arch = "aarch64"))]
-    bands: Vec<ScalarBiquad>,
+    // kept for `to_fir` and `to_scalar`; the biquads hold their own copies
+    coeffs: Vec<BiquadCoeffs>,
+    // one chain of bands per `LANES` channels, all with the same
+    // coefficients, sized when the EQ is built; there is always at least one
+    chains: Vec<Vec<Biquad>>,
 }
 
 impl ParametricEq {
-    pub fn new(sample_rate: f32) -> Self {
+    /// An EQ without bands for interleaved audio with `channels` channels.
+    pub fn new(sample_rate: f32, channels: usize) -> Self {
+        let channels = channels.max(1);
         Self {
             sample_rate,
+            channels,
             preamp_db: 0.0,
-            bands: Vec::with_capacity(8),
+            coeffs: Vec::with_capacity(8),
+            chains: (0..channels.div_ceil(LANES))
+                .map(|_| Vec::with_capacity(8))
+                .collect(),
         }
     }
 
-    pub fn from_profile(profile: &EqProfile, sample_rate: f32) -> Self {
-        let mut eq = Self::new(sample_rate);
+    pub fn from_profile(profile: &EqProfile, sample_rate: f32, channels: usize) -> Self {
+        let mut eq = Self::new(sample_rate, channels);
         eq.preamp_db = profile.preamp_db;
-        for band in profile.filters.iter().filter(|f| f.enabled) {
+        let tilt = profile.tilt_filters();
+        for band in profile.active_filters().chain(&tilt) {
             eq.add_band(
                 band.filter_type,
                 band.frequency as f32,
@@ -419,55 +1094,327 @@ impl ParametricEq {
         eq
     }
 
+    /// Coefficients for a band, or a pass-through if the band would be
+    /// unstable; an unstable biquad's output grows without bound.
+    fn stable_coeffs(
+        &self,
+        filter_type: FilterType,
+        freq: f32,
+        q: f32,
+        gain_db: f32,
+    ) -> BiquadCoeffs {
+        let coeffs = BiquadCoeffs::calculate(filter_type, freq, q, gain_db, self.sample_rate);
+        if coeffs.is_stable() {
+            coeffs
+        } else {
+            warn!(
+                "{} band at {} Hz, Q {}, {} dB is unstable at {} Hz, bypassing it",
+                filter_type, freq, q, gain_db, self.sample_rate
+            );
+            BiquadCoeffs::PASS_THROUGH
+        }
+    }
+
     /// Add a new band to the chain
     pub fn add_band(&mut self, filter_type: FilterType, freq: f32, q: f32, gain_db: f32) {
-        let coeffs = BiquadCoeffs::calculate(filter_type, freq, q, gain_db, self.sample_rate);
-        #[cfg(target_arch = "aarch64")]
-        {
-            self.bands.push(SimdBiquad::new(coeffs));
+        let coeffs = self.stable_coeffs(filter_type, freq, q, gain_db);
+        self.coeffs.push(coeffs);
+        for chain in &mut self.chains {
+            chain.push(Biquad::new(coeffs));
         }
-        #[cfg(not(target_arch = "aarch64"))]
-        {
-            self.bands.push(ScalarBiquad::new(coeffs));
+    }
+
+    /// Changes the band at `index` in place, keeping its filter state.
+    /// Out-of-range indices are ignored.
+    pub fn update_band(
+        &mut self,
+        index: usize,
+        filter_type: FilterType,
+        freq: f32,
+        q: f32,
+        gain_db: f32,
+    ) {
+        if index >= self.coeffs.len() {
+            return;
+        }
+        let coeffs = self.stable_coeffs(filter_type, freq, q, gain_db);
+        self.coeffs[index] = coeffs;
+        for chain in &mut self.chains {
+            chain[index].set_coeffs(coeffs);
         }
     }
 
-    /// Process a buffer of interleaved audio.
-    ///
-    /// SAFETY: This function expects `data` to contain interleaved Quad-Channel audio.
-    /// E.g., [L, R, Aux1, Aux2, L, R, Aux1, Aux2...]
-    /// If you only have Stereo [L, R], padding to 4 channels is required to use this specific SIMD kernel,
-    /// or you can process 2 stereo frames at once (De-interleaving required).
-    ///
-    /// The buffer length must be a multiple of 4.
-    pub fn process_buffer(&mut self, data: &mut [f32]) {
-        #[cfg(target_arch = "aarch64")]
-        unsafe {
-            let preamp_gain = 10.0f32.powf(self.preamp_db as f32 / 20.0);
-            let gain_v = vdupq_n_f32(preamp_gain);
-            let len = data.len();
-            let chunks = len / 4;
-            let ptr = data.as_mut_ptr();
-            for i in 0..chunks {
-                let mut current_quad = vmulq_f32(gain_v, vld1q_f32(ptr.add(i * 4)));
-                for band in &mut self.bands {
-                    current_quad = band.process_quad(current_quad);
-                }
-                vst1q_f32(ptr.add(i * 4), current_quad);
+    /// Number of bands in the chain, bypassed unstable ones included.
+    #[allow(dead_code)]
+    pub fn len(&self) -> usize {
+        self.coeffs.len()
+    }
+
+    #[allow(dead_code)]
+    pub fn is_empty(&self) -> bool {
+        self.coeffs.is_empty()
+    }
+
+    /// Takes the band at `index` out of the chain. The bands after it move
+    /// down one place and keep their filter state. Out-of-range indices are
+    /// ignored.
+    #[allow(dead_code)]
+    pub fn remove_band(&mut self, index: usize) {
+        if index < self.coeffs.len() {
+            self.coeffs.remove(index);
+            for chain in &mut self.chains {
+                chain.remove(index);
+            }
+        }
+    }
+
+    /// Takes over `profile`'s preamp and bands. When it has as many bands as
+    /// the chain, they are changed in place and keep their filter state, so a
+    /// small edit doesn't click.
+    #[allow(dead_code)]
+    pub fn update(&mut self, profile: &EqProfile) {
+        self.take_coeffs(Self::from_profile(profile, self.sample_rate, self.channels));
+    }
+
+    /// Takes over the preamp and bands of `new`, built for the same sample
+    /// rate and channel count, keeping the filter state as [`update`](Self::update) does.
+    pub fn take_coeffs(&mut self, mut new: Self) {
+        self.swap_coeffs(&mut new);
+    }
+
+    /// Like [`take_coeffs`](Self::take_coeffs), but leaves the old preamp
+    /// and bands (or the whole old chain, when the band count differs) in
+    /// `other` instead of freeing them, for the audio callback.
+    pub fn swap_coeffs(&mut self, other: &mut Self) {
+        if other.coeffs.len() != self.coeffs.len() {
+            std::mem::swap(self, other);
+            return;
+        }
+        std::mem::swap(&mut self.preamp_db, &mut other.preamp_db);
+        for chain in &mut self.chains {
+            for (band, coeffs) in chain.iter_mut().zip(&other.coeffs) {
+                band.set_coeffs(*coeffs);
             }
         }
-        #[cfg(not(target_arch = "aarch64"))]
-        {
-            let preamp_gain = 10.0f32.powf(self.preamp_db as f32 / 20.0);
-            for sample in data.iter_mut() {
-                let mut s = *sample * preamp_gain;
-                for band in &mut self.bands {
-                    s = band.process(s);
+        std::mem::swap(&mut self.coeffs, &mut other.coeffs);
+    }
+
+    /// Clears the filter state of every channel, as if no audio had been
+    /// processed yet. Doesn't allocate.
+    pub fn reset(&mut self) {
+        for chain in &mut self.chains {
+            for (band, coeffs) in chain.iter_mut().zip(&self.coeffs) {
+                *band = Biquad::new(*coeffs);
+            }
+        }
+    }
+
+    /// Removes every band; the preamp stays.
+    #[allow(dead_code)]
+    pub fn clear(&mut self) {
+        self.coeffs.clear();
+        for chain in &mut self.chains {
+            chain.clear();
+        }
+    }
+
+    /// Runs interleaved audio through the preamp and the bands, each
+    /// channel with its own filter state. Channels are filtered `LANES` at a
+    /// time, which on aarch64 is a NEON register's worth. A trailing partial
+    /// frame is left alone. Doesn't allocate.
+    pub fn process_block(&mut self, data: &mut [f32]) {
+        let preamp_gain = self.preamp_gain();
+        for frame in data.chunks_exact_mut(self.channels) {
+            for (lanes, chain) in frame.chunks_mut(LANES).zip(&mut self.chains) {
+                let mut input = [0.0; LANES];
+                for (lane, sample) in input.iter_mut().zip(lanes.iter()) {
+                    *lane = sample * preamp_gain;
                 }
-                *sample = s;
+                let output = process_chain(chain, input);
+                lanes.copy_from_slice(&output[..lanes.len()]);
             }
         }
     }
+
+    /// Gain of the whole chain, preamp included, at `w` radians per sample.
+    pub(crate) fn magnitude(&self, w: f64) -> f64 {
+        let (z1, z2) = (
+            Complex::from_polar(1.0, -w),
+            Complex::from_polar(1.0, -2.0 * w),
+        );
+        self.coeffs
+            .iter()
+            .fold(self.preamp_gain() as f64, |gain, c| {
+                let num = c.b0 as f64 + z1 * c.b1 as f64 + z2 * c.b2 as f64;
+                let den = 1.0 + z1 * c.a1 as f64 + z2 * c.a2 as f64;
+                gain * (num / den).norm()
+            })
+    }
+
+    /// Linear-phase FIR kernel with the same magnitude response as the
+    /// biquads, for this EQ's sample rate. `taps` is rounded up to an odd
+    /// number so the kernel delays by a whole `taps / 2` samples.
+    ///
+    /// The magnitude is sampled on a grid finer than the kernel, turned into
+    /// a zero-phase impulse response by an inverse FFT, centred and windowed.
+    pub fn to_fir(&self, taps: usize) -> Vec<f32> {
+        let taps = taps | 1;
+        let size = (taps * 4).next_power_of_two();
+        let mut spectrum: Vec<Complex<f64>> = (0..size)
+            .map(|k| {
+                let w = std::f64::consts::TAU * k as f64 / size as f64;
+                Complex::new(self.magnitude(w), 0.0)
+            })
+            .collect();
+        FftPlanner::new()
+            .plan_fft_inverse(size)
+            .process(&mut spectrum);
+        let half = taps / 2;
+        (0..taps)
+            .map(|i| {
+                // Blackman window
+                let phase = std::f64::consts::TAU * i as f64 / (taps - 1) as f64;
+                let window = 0.42 - 0.5 * phase.cos() + 0.08 * (2.0 * phase).cos();
+                let sample = spectrum[(i + size - half) % size].re / size as f64;
+                (sample * window) as f32
+            })
+            .collect()
+    }
+
+    /// Minimum-phase FIR kernel with the same magnitude response as the
+    /// biquads: no pre-ringing and next to no delay, for convolution hosts.
+    ///
+    /// The phase comes from the cepstrum of the log magnitude, folded onto
+    /// positive quefrencies. The kernel fades out over its tail, where
+    /// little energy is left.
+    pub fn to_min_phase_fir(&self, taps: usize) -> Vec<f32> {
+        let taps = taps.max(1);
+        let size = (taps * 4).next_power_of_two();
+        let mut planner = FftPlanner::new();
+        let forward = planner.plan_fft_forward(size);
+        let inverse = planner.plan_fft_inverse(size);
+        let mut cepstrum: Vec<Complex<f64>> = (0..size)
+            .map(|k| {
+                let w = std::f64::consts::TAU * k as f64 / size as f64;
+                Complex::new(self.magnitude(w).max(1e-9).ln(), 0.0)
+            })
+            .collect();
+        inverse.process(&mut cepstrum);
+        for (n, c) in cepstrum.iter_mut().enumerate() {
+            let fold = match n {
+                0 => 1.0,
+                n if n < size / 2 => 2.0,
+                n if n == size / 2 => 1.0,
+                _ => 0.0,
+            };
+            *c *= fold / size as f64;
+        }
+        forward.process(&mut cepstrum);
+        let mut spectrum: Vec<Complex<f64>> = cepstrum.iter().map(|c| c.exp()).collect();
+        inverse.process(&mut spectrum);
+        (0..taps)
+            .map(|i| {
+                // Right half of a Blackman window
+                let phase = std::f64::consts::PI * i as f64 / taps as f64;
+                let window = 0.42 + 0.5 * phase.cos() + 0.08 * (2.0 * phase).cos();
+                (spectrum[i].re / size as f64 * window) as f32
+            })
+            .collect()
+    }
+
+    pub fn preamp_gain(&self) -> f32 {
+        10.0f32.powf(self.preamp_db as f32 / 20.0)
+    }
+
+    /// Runs one sample through the bands, without the preamp. The filter state
+    /// belongs to a single channel, unlike `process_block`'s.
+    fn process_bands(&mut self, sample: f32) -> f32 {
+        process_chain(&mut self.chains[0], [sample; LANES])[0]
+    }
+
+    /// The same bands on the scalar kernel, with fresh filter state.
+    pub fn to_scalar(&self) -> ScalarEq {
+        ScalarEq {
+            preamp_gain: self.preamp_gain(),
+            channels: self.channels,
+            chains: (0..self.channels)
+                .map(|_| self.coeffs.iter().map(|c| ScalarBiquad::new(*c)).collect())
+                .collect(),
+        }
+    }
+}
+
+/// A [`ParametricEq`] on scalar biquads, one chain per channel: the path
+/// `process_block` takes where there is no SIMD kernel. Available on every
+/// architecture so the two can be benchmarked against each other.
+pub struct ScalarEq {
+    preamp_gain: f32,
+    channels: usize,
+    chains: Vec<Vec<ScalarBiquad>>,
+}
+
+impl ScalarEq {
+    /// Same as [`ParametricEq::process_block`].
+    pub fn process_block(&mut self, data: &mut [f32]) {
+        for frame in data.chunks_exact_mut(self.channels) {
+            for (sample, chain) in frame.iter_mut().zip(&mut self.chains) {
+                *sample = chain
+                    .iter_mut()
+                    .fold(*sample * self.preamp_gain, |s, band| band.process(s));
+            }
+        }
+    }
+}
+
+/// Mid/side processing for interleaved stereo. Each frame is encoded as
+/// M = (L+R)/2, S = (L-R)/2, the two signals go through their own
+/// equalizers, and L = M+S, R = M-S restores the pair.
+pub struct MidSide {
+    mid: ParametricEq,
+    side: ParametricEq,
+}
+
+impl MidSide {
+    pub fn from_profiles(mid: &EqProfile, side: &EqProfile, sample_rate: f32) -> Self {
+        Self {
+            // mono: the bands run one sample at a time
+            mid: ParametricEq::from_profile(mid, sample_rate, 1),
+            side: ParametricEq::from_profile(side, sample_rate, 1),
+        }
+    }
+
+    /// Takes over the profiles of `other` as
+    /// [`ParametricEq::swap_coeffs`] does, each keeping its filter state.
+    pub fn swap_coeffs(&mut self, other: &mut Self) {
+        self.mid.swap_coeffs(&mut other.mid);
+        self.side.swap_coeffs(&mut other.side);
+    }
+
+    /// `data` must be interleaved stereo.
+    pub fn process_buffer(&mut self, data: &mut [f32]) {
+        let mid_gain = self.mid.preamp_gain();
+        let side_gain = self.side.preamp_gain();
+        for frame in data.chunks_exact_mut(2) {
+            let (left, right) = (frame[0], frame[1]);
+            let mid = self.mid.process_bands((left + right) * 0.5 * mid_gain);
+            let side = self.side.process_bands((left - right) * 0.5 * side_gain);
+            frame[0] = mid + side;
+            frame[1] = mid - side;
+        }
+    }
+
+    /// Only the two preamps, without the bands; `data` as in
+    /// [`process_buffer`](Self::process_buffer).
+    pub fn process_preamps(&self, data: &mut [f32]) {
+        let mid_gain = self.mid.preamp_gain();
+        let side_gain = self.side.preamp_gain();
+        for frame in data.chunks_exact_mut(2) {
+            let mid = (frame[0] + frame[1]) * 0.5 * mid_gain;
+            let side = (frame[0] - frame[1]) * 0.5 * side_gain;
+            frame[0] = mid + side;
+            frame[1] = mid - side;
+        }
+    }
 }
 
 #[cfg(test)]
@@ -513,4 +1460,987 @@ Filter 2: OFF LSC Fc 80 Gain -2.0 Q 0.71
         assert!(!profile.filters[1].enabled);
         assert_eq!(profile.filters[1].filter_type, FilterType::LowShelf);
     }
+
+    #[test]
+    fn test_rew_export() {
+        let config = "Filter Settings file
+
+Room EQ V5.20.13
+Dated: 15-Oct-2026 10:12:05
+
+Notes:
+
+Equaliser: Generic
+Average 1
+Filter  1: ON  PK       Fc   63.00 Hz  Gain  -3.50 dB  Q  2.500
+Filter  2: ON  LSC      Fc   105.0 Hz  Gain   4.00 dB  Q  0.707
+Filter  3: ON  None
+Filter 10: ON  HP       Fc   20.00 Hz
+";
+        let profile: EqProfile = config.parse().unwrap();
+        assert_eq!(profile.filters.len(), 4);
+        assert_eq!(profile.filters[0].frequency, 63.0);
+        assert_eq!(profile.filters[0].gain, -3.5);
+        assert_eq!(profile.filters[0].q_factor, 2.5);
+        assert_eq!(profile.filters[1].filter_type, FilterType::LowShelf);
+        assert_eq!(profile.filters[1].q_factor, 0.707);
+        assert_eq!(profile.filters[3].filter_type, FilterType::HighPass);
+        // The empty slot is there but changes nothing
+        assert!(!profile.filters[2].enabled);
+        assert_eq!(profile.filters[2].gain, 0.0);
+        assert_eq!(profile.active_filters().count(), 3);
+    }
+
+    #[test]
+    fn test_preamp_lines_add_up() {
+        let config = "
+Preamp: -3 dB
+Filter 1: ON PK Fc 100 Hz Gain 4 dB Q 1
+Preamp: -1.5 dB
+";
+        let profile: EqProfile = config.parse().unwrap();
+        assert_eq!(profile.preamp_db, -4.5);
+        assert_eq!(profile.filters.len(), 1);
+
+        // Only after the filters, and without a space after the colon
+        let config = "Filter 1: ON PK Fc 100 Hz Gain 4 dB Q 1\npreamp:-2dB\n";
+        let profile: EqProfile = config.parse().unwrap();
+        assert_eq!(profile.preamp_db, -2.0);
+        // What gets written back is the single total
+        assert_eq!(
+            profile.to_string().parse::<EqProfile>().unwrap().preamp_db,
+            -2.0
+        );
+    }
+
+    #[test]
+    fn test_apo_include_and_ignored_directives() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("eq")).unwrap();
+        std::fs::write(
+            dir.path().join("config.txt"),
+            "Device: Speakers\nPreamp: -2 dB\nFilter 1: ON PK Fc 100 Hz Gain 3 dB Q 1\n\
+             Include: eq/sub.txt\nChannel: L\nFilter 2: ON PK Fc 5000 Hz Gain 1 dB Q 1\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.path().join("eq").join("sub.txt"),
+            "Preamp: -1 dB\nFilter 1: ON LSC Fc 60 Hz Gain 4 dB Q 0.7\nStage: post-mix\n",
+        )
+        .unwrap();
+
+        let path = dir.path().join("config.txt");
+        let (profile, warnings) = EqProfile::load_apo(&path).unwrap();
+        assert_eq!(profile.preamp_db, -3.0);
+        // Included filters land where the Include line was
+        let freqs: Vec<f64> = profile.filters.iter().map(|f| f.frequency).collect();
+        assert_eq!(freqs, [100.0, 60.0, 5000.0]);
+        assert_eq!(warnings.len(), 3, "{:?}", warnings);
+        assert!(warnings[0].ends_with("config.txt line 1: ignoring \"Device: Speakers\""));
+        assert!(warnings[1].ends_with("sub.txt line 3: ignoring \"Stage: post-mix\""));
+        assert!(warnings[2].ends_with("config.txt line 5: ignoring \"Channel: L\""));
+        assert_eq!(EqProfile::load(&path).unwrap(), profile);
+
+        // A file including itself stops instead of recursing forever
+        std::fs::write(&path, "Include: config.txt\n").unwrap();
+        assert!(EqProfile::load_apo(&path).is_err());
+    }
+
+    #[test]
+    fn test_save_by_extension() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut profile: EqProfile = "Preamp: -2 dB\nFilter 1: ON PK Fc 100 Hz Gain 3 dB Q 1"
+            .parse()
+            .unwrap();
+        // Only JSON keeps the tilt
+        profile.tilt_db_per_oct = 0.2;
+
+        let json = dir.path().join("profile.JSON");
+        profile.save(&json).unwrap();
+        assert!(std::fs::read_to_string(&json).unwrap().starts_with('{'));
+        assert_eq!(EqProfile::load(&json).unwrap(), profile);
+
+        let text = dir.path().join("profile.txt");
+        profile.save(&text).unwrap();
+        let loaded = EqProfile::load(&text).unwrap();
+        assert_eq!(loaded.filters, profile.filters);
+        assert_eq!(loaded.preamp_db, -2.0);
+
+        assert!(
+            profile
+                .save(&dir.path().join("missing/profile.txt"))
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_parser_units_and_exponents() {
+        let config = "
+Preamp: -2.5dB
+Filter 1: ON PK Fc 1.05e3 Hz Gain -3dB Q 1.5
+Filter 2: ON LSC Fc 80Hz Gain 2.5E0 dB Q 7.1e-1
+Filter 3: ON PK Fc 4000 Hz Gain 1 dB BW Oct 1.5
+Filter 4: ON PK Fc 500 Hz Gain -1 dB BW 0.5oct
+";
+        let profile: EqProfile = config.parse().unwrap();
+        assert_eq!(profile.preamp_db, -2.5);
+        assert_eq!(profile.filters[0].frequency, 1050.0);
+        assert_eq!(profile.filters[0].gain, -3.0);
+        assert_eq!(profile.filters[0].q_factor, 1.5);
+        assert_eq!(profile.filters[1].frequency, 80.0);
+        assert_eq!(profile.filters[1].gain, 2.5);
+        assert_eq!(profile.filters[1].q_factor, 0.71);
+        assert_eq!(profile.filters[2].bandwidth, Some(1.5));
+        assert_eq!(profile.filters[3].bandwidth, Some(0.5));
+
+        assert!(
+            "Filter 1: ON PK Fc 100 Hz Gain 3dBs Q 1"
+                .parse::<EqProfile>()
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_parser_bom_crlf_and_comments() {
+        let config = "\u{feff}Preamp: -4.5 dB\r\nFilter 1: ON PK Fc 100 Hz Gain 2 dB Q 1.2\r\n";
+        let profile: EqProfile = config.parse().unwrap();
+        assert_eq!(profile.preamp_db, -4.5);
+        assert_eq!(profile.filters.len(), 1);
+        assert_eq!(profile.filters[0].q_factor, 1.2);
+        assert_eq!(EqProfile::parse(config).unwrap(), profile);
+
+        let config = "; exported by some tool\r\n\
+Preamp: -1 dB ; headroom\r\n\
+;Filter 1: ON PK Fc 50 Hz Gain 9 dB Q 1\r\n\
+Filter 2: ON HSC Fc 8000 Hz Gain 1.5 dB Q 0.7;bright\r\n\
+Filter 3: OFF PK Fc 3000 Hz Gain -2 dB Q 4\r";
+        let profile: EqProfile = config.parse().unwrap();
+        assert_eq!(profile.preamp_db, -1.0);
+        assert_eq!(profile.filters.len(), 2);
+        assert_eq!(profile.filters[0].filter_type, FilterType::HighShelf);
+        assert_eq!(profile.filters[0].q_factor, 0.7);
+        assert_eq!(profile.filters[1].q_factor, 4.0);
+
+        // JSON with a byte order mark
+        let json = format!("\u{feff}{}", profile.to_json().unwrap());
+        assert_eq!(EqProfile::parse(&json).unwrap(), profile);
+    }
+
+    #[test]
+    fn test_camilladsp_export() {
+        let config = "
+Preamp: -3.0 dB
+Filter 1: ON PK Fc 100 Hz Gain 2.5 dB Q 1.41
+Filter 2: OFF LSC Fc 80 Hz Gain -2.0 dB Q 0.71
+Filter 3: ON HSC Fc 10000 Hz Gain 1.5 dB Q 0.71
+Filter 4: ON HP Fc 20 Hz Q 0.71
+";
+        let profile: EqProfile = config.parse().unwrap();
+        let yaml = profile.to_camilladsp_yaml(48000.0);
+        let doc: serde_yaml::Value = serde_yaml::from_str(&yaml).unwrap();
+
+        let filters = doc["filters"].as_mapping().unwrap();
+        // preamp + one per enabled band
+        assert_eq!(filters.len(), 4);
+        assert_eq!(doc["filters"]["preamp"]["parameters"]["gain"], -3.0);
+        assert_eq!(doc["filters"]["band_1"]["parameters"]["type"], "Peaking");
+        assert_eq!(doc["filters"]["band_3"]["parameters"]["type"], "Highshelf");
+        assert_eq!(doc["filters"]["band_4"]["parameters"]["freq"], 20.0);
+        assert!(doc["filters"]["band_4"]["parameters"]["gain"].is_null());
+
+        let names: Vec<_> = doc["pipeline"][0]["names"]
+            .as_sequence()
+            .unwrap()
+            .iter()
+            .map(|v| v.as_str().unwrap())
+            .collect();
+        assert_eq!(names, ["preamp", "band_1", "band_3", "band_4"]);
+
+        // The tilt comes after the bands as its two shelves
+        let profile = EqProfile {
+            tilt_db_per_oct: 0.5,
+            ..profile
+        };
+        let yaml = profile.to_camilladsp_yaml(48000.0);
+        let doc: serde_yaml::Value = serde_yaml::from_str(&yaml).unwrap();
+        let tilt = profile.tilt_filters();
+        assert_eq!(doc["filters"]["tilt_low"]["parameters"]["type"], "Lowshelf");
+        assert_eq!(
+            doc["filters"]["tilt_low"]["parameters"]["gain"],
+            tilt[0].gain
+        );
+        assert_eq!(
+            doc["filters"]["tilt_high"]["parameters"]["gain"],
+            tilt[1].gain
+        );
+        assert_eq!(doc["pipeline"][0]["names"][5], "tilt_high");
+    }
+
+    #[test]
+    fn test_json_round_trip() {
+        let profile = EqProfile {
+            preamp_db: -4.5,
+            filters: vec![
+                Filter {
+                    enabled: false,
+                    filter_type: FilterType::HighShelf,
+                    frequency: 8000.0,
+                    gain: -1.25,
+                    q_factor: 0.5,
+                    bandwidth: Some(2.0),
+                    locked: true,
+                    solo: false,
+                },
+                Filter::default(),
+            ],
+            tilt_db_per_oct: 0.25,
+            metadata: ProfileMetadata {
+                name: Some("HD600".to_string()),
+                ..Default::default()
+            },
+        };
+        let json = profile.to_json().unwrap();
+        assert_eq!(EqProfile::from_json(&json).unwrap(), profile);
+        assert_eq!(EqProfile::parse(&json).unwrap(), profile);
+        // Left out when there is none
+        let json = EqProfile::default().to_json().unwrap();
+        assert!(!json.contains("metadata"), "{}", json);
+    }
+
+    #[test]
+    fn test_metadata_round_trip() {
+        let profile = EqProfile {
+            preamp_db: -3.0,
+            filters: vec![Filter::default()],
+            metadata: ProfileMetadata {
+                name: Some("HD600 AutoEQ, tweaked bass".to_string()),
+                source: Some("oratory1990 measurement".to_string()),
+                notes: Some("Bass +1 dB over AutoEQ\n\nkeeps ; and # as written".to_string()),
+            },
+            ..Default::default()
+        };
+        let text = profile.to_string();
+        assert!(
+            text.starts_with(
+                "# Name: HD600 AutoEQ, tweaked bass\n\
+                 # Source: oratory1990 measurement\n\
+                 # Notes: Bass +1 dB over AutoEQ\n\
+                 # Notes:\n\
+                 # Notes: keeps ; and # as written\n\
+                 Preamp: -3 dB\n"
+            ),
+            "{}",
+            text
+        );
+        assert_eq!(text.parse::<EqProfile>().unwrap(), profile);
+
+        // Through a file, where an include's own header is left out
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("base.txt"), "# Name: Base\nPreamp: -1 dB\n").unwrap();
+        let path = dir.path().join("profile.txt");
+        std::fs::write(&path, format!("{}Include: base.txt\n", text)).unwrap();
+        let (loaded, _) = EqProfile::load_apo(&path).unwrap();
+        assert_eq!(loaded.metadata, profile.metadata);
+        assert_eq!(loaded.preamp_db, -4.0);
+
+        // Other comments stay comments; keys ignore case
+        let parsed: EqProfile = "# Generated by AutoEQ\n#name:  Quiet \nPreamp: 0 dB"
+            .parse()
+            .unwrap();
+        assert_eq!(parsed.metadata.name.as_deref(), Some("Quiet"));
+        assert_eq!(parsed.metadata.source, None);
+        assert!(EqProfile::default().to_string().starts_with("Preamp"));
+    }
+
+    mod round_trip {
+        use proptest::prelude::*;
+
+        use super::*;
+
+        /// Gains in dB, with the values most likely to be written oddly.
+        fn db() -> impl Strategy<Value = f64> {
+            prop_oneof![
+                -30.0..30.0f64,
+                Just(0.0),
+                Just(-0.0),
+                Just(1e-7),
+                Just(-1e20)
+            ]
+        }
+
+        fn filter() -> impl Strategy<Value = Filter> {
+            (
+                any::<bool>(),
+                proptest::sample::select(FilterType::ALL.to_vec()),
+                prop_oneof![1.0..24000.0f64, Just(0.5), Just(96000.0)],
+                db(),
+                0.01..20.0f64,
+                proptest::option::of(0.01..5.0f64),
+            )
+                .prop_map(
+                    |(enabled, filter_type, frequency, gain, q_factor, bandwidth)| Filter {
+                        enabled,
+                        filter_type,
+                        frequency,
+                        gain,
+                        q_factor,
+                        bandwidth,
+                        ..Default::default()
+                    },
+                )
+        }
+
+        fn profile() -> impl Strategy<Value = EqProfile> {
+            (db(), proptest::collection::vec(filter(), 0..12)).prop_map(|(preamp_db, filters)| {
+                EqProfile {
+                    preamp_db,
+                    filters,
+                    ..Default::default()
+                }
+            })
+        }
+
+        /// What APO text can hold: a band set in octaves loses its Q, low
+        /// and high pass their gain.
+        fn apo_fields(profile: &EqProfile) -> EqProfile {
+            let filters = profile
+                .filters
+                .iter()
+                .map(|f| Filter {
+                    gain: if f.filter_type.has_gain() {
+                        f.gain
+                    } else {
+                        0.0
+                    },
+                    q_factor: match f.bandwidth {
+                        Some(_) => Filter::default().q_factor,
+                        None => f.q_factor,
+                    },
+                    locked: false,
+                    solo: false,
+                    ..f.clone()
+                })
+                .collect();
+            EqProfile {
+                preamp_db: profile.preamp_db,
+                filters,
+                tilt_db_per_oct: 0.0,
+                metadata: profile.metadata.clone(),
+            }
+        }
+
+        /// Relative difference allowed between a number and the same number
+        /// read back from APO text. Numbers are written in full, so they come
+        /// back exactly today; this is the most a change of format may cost.
+        const TOLERANCE: f64 = 1e-9;
+
+        fn close(a: f64, b: f64) -> bool {
+            (a - b).abs() <= TOLERANCE * a.abs().max(b.abs()).max(1.0)
+        }
+
+        fn same_within(a: &EqProfile, b: &EqProfile) -> bool {
+            close(a.preamp_db, b.preamp_db)
+                && a.filters.len() == b.filters.len()
+                && a.filters.iter().zip(&b.filters).all(|(a, b)| {
+                    a.enabled == b.enabled
+                        && a.filter_type == b.filter_type
+                        && close(a.frequency, b.frequency)
+                        && close(a.gain, b.gain)
+                        && close(a.q_factor, b.q_factor)
+                        && match (a.bandwidth, b.bandwidth) {
+                            (Some(a), Some(b)) => close(a, b),
+                            (a, b) => a == b,
+                        }
+                })
+        }
+
+        proptest! {
+            #[test]
+            fn test_apo_text_round_trip(profile in profile()) {
+                let text = profile.to_string();
+                let parsed: EqProfile = text.parse().unwrap();
+                let expected = apo_fields(&profile);
+                prop_assert!(same_within(&expected, &parsed), "{}\n{:?}", text, parsed);
+            }
+        }
+
+        #[test]
+        fn test_pass_filters_keep_their_q() {
+            // Equalizer APO would run "LP ... Q 2" at its fixed Q
+            let profile: EqProfile = "Filter 1: ON LPQ Fc 500 Hz Q 2".parse().unwrap();
+            assert_eq!(profile.filters[0].filter_type, FilterType::LowPass);
+            assert_eq!(profile.filters[0].q_factor, 2.0);
+            assert!(profile.to_string().contains("ON LPQ Fc 500 Hz Q 2"));
+        }
+    }
+
+    #[test]
+    fn test_filter_type_names_round_trip() {
+        for filter_type in FilterType::ALL {
+            let parsed: FilterType = filter_type.to_string().parse().unwrap();
+            assert_eq!(parsed, filter_type);
+            let parsed: FilterType = filter_type.apo_name().parse().unwrap();
+            assert_eq!(parsed, filter_type);
+        }
+    }
+
+    #[test]
+    fn test_mid_side_side_boost_widens() {
+        let side_boost: EqProfile = "Preamp: 6 dB".parse().unwrap();
+        let mut ms = MidSide::from_profiles(&EqProfile::default(), &side_boost, 48000.0);
+        // Partially correlated: mostly mid with some side
+        let mut data: Vec<f32> = (0..4800)
+            .flat_map(|i| {
+                let x = (2.0 * PI * 440.0 * i as f32 / 48000.0).sin();
+                [x, 0.5 * x]
+            })
+            .collect();
+        let width = |data: &[f32]| {
+            let (mut mid, mut side) = (0.0, 0.0);
+            for frame in data.chunks_exact(2) {
+                mid += ((frame[0] + frame[1]) * 0.5).powi(2);
+                side += ((frame[0] - frame[1]) * 0.5).powi(2);
+            }
+            (side / mid).sqrt()
+        };
+        let width_before = width(&data);
+        let mid_before: Vec<f32> = data.chunks_exact(2).map(|f| f[0] + f[1]).collect();
+        ms.process_buffer(&mut data);
+
+        // +6 dB on side doubles the side/mid ratio and leaves mid alone
+        let ratio = width(&data) / width_before;
+        assert!((ratio - 2.0).abs() < 0.01, "ratio {}", ratio);
+        for (frame, before) in data.chunks_exact(2).zip(mid_before) {
+            assert!((frame[0] + frame[1] - before).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn test_solo_bypasses_other_bands() {
+        let band = |frequency| Filter {
+            frequency,
+            ..Default::default()
+        };
+        let mut profile = EqProfile {
+            preamp_db: 0.0,
+            filters: vec![band(100.0), band(1000.0), band(10000.0)],
+            ..Default::default()
+        };
+        profile.filters[2].enabled = false;
+        let active = |profile: &EqProfile| -> Vec<f64> {
+            profile.active_filters().map(|f| f.frequency).collect()
+        };
+        assert_eq!(active(&profile), [100.0, 1000.0]);
+        profile.filters[1].solo = true;
+        assert_eq!(active(&profile), [1000.0]);
+        assert!(profile.filters[0].enabled);
+        // Solo is not saved
+        let json = profile.to_json().unwrap();
+        assert_eq!(
+            active(&EqProfile::from_json(&json).unwrap()),
+            [100.0, 1000.0]
+        );
+        profile.filters[1].solo = false;
+        assert_eq!(active(&profile), [100.0, 1000.0]);
+    }
+
+    #[test]
+    fn test_bandwidth_q_conversion() {
+        assert!((bandwidth_to_q(1.0) - std::f64::consts::SQRT_2).abs() < 1e-9);
+        assert!((bandwidth_to_q(2.0) - 2.0 / 3.0).abs() < 1e-9);
+        assert!((q_to_bandwidth(std::f64::consts::SQRT_2) - 1.0).abs() < 1e-9);
+        for q in [0.1, 0.707, 1.0, 4.3, 10.0] {
+            assert!((bandwidth_to_q(q_to_bandwidth(q)) - q).abs() < 1e-9);
+        }
+        let profile: EqProfile = "Filter 1: ON PK Fc 1000 Hz Gain 3 dB BW Oct 2"
+            .parse()
+            .unwrap();
+        assert_eq!(profile.filters[0].bandwidth, Some(2.0));
+        assert!((profile.filters[0].effective_q() - 2.0 / 3.0).abs() < 1e-9);
+        let reparsed: EqProfile = profile.to_string().parse().unwrap();
+        assert_eq!(reparsed, profile);
+    }
+
+    #[test]
+    fn test_stability_check() {
+        let stable = BiquadCoeffs::calculate(FilterType::Peaking, 1000.0, 1.0, 6.0, 48000.0);
+        assert!(stable.is_stable());
+        let unstable = BiquadCoeffs {
+            b0: 1.0,
+            b1: 0.0,
+            b2: 0.0,
+            a1: -1.9,
+            a2: 1.05,
+        };
+        assert!(!unstable.is_stable());
+
+        // Centred past Nyquist, the RBJ formulas put a pole outside the unit
+        // circle; the EQ bypasses that band instead of blowing up
+        let filter = Filter {
+            frequency: 30000.0,
+            q_factor: 0.5,
+            ..Default::default()
+        };
+        assert!(filter.is_stable(96000.0));
+        assert!(!filter.is_stable(48000.0));
+        let mut eq = ParametricEq::new(48000.0, 1);
+        eq.add_band(FilterType::Peaking, 30000.0, 0.5, 0.0);
+        let mut data: Vec<f32> = (0..4800).map(|i| (i as f32 * 0.1).sin()).collect();
+        let input = data.clone();
+        eq.process_block(&mut data);
+        assert_eq!(data, input);
+        eq.update_band(0, FilterType::Peaking, 1000.0, 1.0, 6.0);
+        eq.process_block(&mut data);
+        assert!(data.iter().all(|s| s.abs() < 4.0));
+    }
+
+    #[test]
+    fn test_band_chain_editing() {
+        let mut eq = ParametricEq::new(48000.0, 1);
+        assert!(eq.is_empty());
+        eq.add_band(FilterType::Peaking, 100.0, 1.0, 6.0);
+        eq.add_band(FilterType::HighShelf, 8000.0, 0.7, -3.0);
+        eq.add_band(FilterType::Peaking, 1000.0, 2.0, 4.0);
+        assert_eq!(eq.len(), 3);
+
+        let tone = |eq: &mut ParametricEq| {
+            let mut data: Vec<f32> = (0..9600)
+                .map(|i| (i as f32 * 1000.0 / 48000.0 * std::f32::consts::TAU).sin())
+                .collect();
+            eq.process_block(&mut data);
+            data[4800..].iter().fold(0.0f32, |m, s| m.max(s.abs()))
+        };
+        // Dropping the shelf leaves the 1 kHz band, now second, in place
+        eq.remove_band(1);
+        eq.remove_band(5);
+        assert_eq!(eq.len(), 2);
+        let mut expected = ParametricEq::new(48000.0, 1);
+        expected.add_band(FilterType::Peaking, 100.0, 1.0, 6.0);
+        expected.add_band(FilterType::Peaking, 1000.0, 2.0, 4.0);
+        assert!((tone(&mut eq) - tone(&mut expected)).abs() < 1e-4);
+        eq.update_band(1, FilterType::Peaking, 1000.0, 2.0, 0.0);
+        eq.remove_band(0);
+        assert!((tone(&mut eq) - 1.0).abs() < 0.01);
+
+        eq.clear();
+        assert!(eq.is_empty());
+        assert_eq!(eq.len(), 0);
+    }
+
+    #[test]
+    fn test_zero_band_eq_applies_preamp() {
+        let mut profile = EqProfile {
+            preamp_db: -6.0,
+            filters: vec![Filter {
+                enabled: false,
+                gain: 6.0,
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let gain = 10.0f32.powf(-6.0 / 20.0);
+        for channels in [1, 2, 6] {
+            let mut eq = ParametricEq::from_profile(&profile, 48000.0, channels);
+            assert!(eq.is_empty());
+            let input: Vec<f32> = (0..channels * 480)
+                .map(|i| (i as f32 * 0.1).sin())
+                .collect();
+            let mut data = input.clone();
+            eq.process_block(&mut data);
+            for (out, inp) in data.iter().zip(&input) {
+                assert!((out - inp * gain).abs() < 1e-6);
+            }
+        }
+        // Without a preamp it passes audio through untouched
+        profile.preamp_db = 0.0;
+        let mut eq = ParametricEq::from_profile(&profile, 48000.0, 2);
+        let input: Vec<f32> = (0..960).map(|i| (i as f32 * 0.1).sin()).collect();
+        let mut data = input.clone();
+        eq.process_block(&mut data);
+        assert_eq!(data, input);
+        assert!((eq.to_fir(31)[15] - 1.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_impulse_response_per_channel() {
+        let profile = EqProfile::parse("Filter 1: ON PK Fc 1000 Hz Gain 6 dB Q 1").unwrap();
+        let impulse = |eq: &mut ParametricEq| {
+            let mut data = vec![0.0; 64];
+            data[0] = 1.0;
+            eq.process_block(&mut data);
+            data
+        };
+        let mut eq = ParametricEq::from_profile(&profile, 48000.0, 1);
+        let response = impulse(&mut eq);
+        assert!(response[0] > 1.0);
+        assert!(response[1..].iter().any(|s| *s != 0.0));
+
+        // Only the channel that got the impulse rings
+        let left_impulse = |eq: &mut ParametricEq| {
+            let mut stereo = vec![0.0; 128];
+            stereo[0] = 1.0;
+            eq.process_block(&mut stereo);
+            assert!(stereo.iter().skip(1).step_by(2).all(|s| *s == 0.0));
+            stereo.iter().step_by(2).copied().collect::<Vec<f32>>()
+        };
+        let mut eq = ParametricEq::from_profile(&profile, 48000.0, 2);
+        assert_eq!(left_impulse(&mut eq), response);
+
+        // Its tail carries over into the next call unless reset
+        let mut tail = vec![0.0; 4];
+        eq.process_block(&mut tail);
+        assert_ne!(tail[0], 0.0);
+        eq.reset();
+        let mut data = vec![0.0; 4];
+        eq.process_block(&mut data);
+        assert_eq!(data, [0.0; 4]);
+        assert_eq!(left_impulse(&mut eq), response);
+
+        // Same band count: updated in place; otherwise rebuilt
+        let louder = EqProfile::parse("Filter 1: ON PK Fc 1000 Hz Gain 9 dB Q 1").unwrap();
+        eq.update(&louder);
+        eq.reset();
+        assert_eq!(
+            left_impulse(&mut eq),
+            impulse(&mut ParametricEq::from_profile(&louder, 48000.0, 1))
+        );
+        eq.update(&EqProfile::default());
+        assert!(eq.is_empty());
+    }
+
+    #[test]
+    fn test_impulse_response_magnitude() {
+        let profile = EqProfile::parse(
+            "Preamp: -4 dB
+Filter 1: ON LSC Fc 120 Hz Gain 5 dB Q 0.7
+Filter 2: ON PK Fc 1500 Hz Gain -6 dB Q 2
+Filter 3: ON HSC Fc 8000 Hz Gain 3 dB Q 0.7",
+        )
+        .unwrap();
+        let fs = 48000.0;
+        let size = 1 << 16;
+        let freqs = [60.0, 120.0, 500.0, 1500.0, 4000.0, 12000.0];
+        let expected = profile.magnitude_response(&freqs, fs);
+        for minimum_phase in [false, true] {
+            let ir = profile.to_impulse_response(4096, fs, minimum_phase);
+            let mut spectrum: Vec<Complex<f64>> = ir
+                .iter()
+                .map(|s| Complex::new(*s as f64, 0.0))
+                .chain(std::iter::repeat(Complex::new(0.0, 0.0)))
+                .take(size)
+                .collect();
+            FftPlanner::new()
+                .plan_fft_forward(size)
+                .process(&mut spectrum);
+            for (freq, db) in freqs.iter().zip(&expected) {
+                let bin = (freq / fs * size as f64).round() as usize;
+                let measured = 20.0 * spectrum[bin].norm().log10();
+                assert!(
+                    (measured - (db - 4.0)).abs() < 0.3,
+                    "{} Hz, minimum phase {}: {} vs {}",
+                    freq,
+                    minimum_phase,
+                    measured,
+                    db - 4.0
+                );
+            }
+            // The minimum-phase kernel front-loads its energy
+            let peak = ir
+                .iter()
+                .enumerate()
+                .max_by(|a, b| a.1.abs().total_cmp(&b.1.abs()))
+                .unwrap()
+                .0;
+            if minimum_phase {
+                assert!(peak < 8, "{}", peak);
+            } else {
+                assert_eq!(peak, 2048);
+            }
+        }
+    }
+
+    #[test]
+    fn test_block_matches_per_sample() {
+        let profile = EqProfile::parse(
+            "Preamp: -3.0 dB
+Filter 1: ON PK Fc 200 Hz Gain 6.0 dB Q 1.000
+Filter 2: ON HSC Fc 6000 Hz Gain -4.0 dB Q 0.700",
+        )
+        .unwrap();
+        // Six channels, so the SIMD path needs a second group of lanes
+        let channels = 6;
+        let block: Vec<f32> = (0..4800 * channels)
+            .map(|i| {
+                let (frame, channel) = (i / channels, i % channels);
+                (frame as f32 * 0.01 * (channel + 1) as f32).sin()
+            })
+            .collect();
+        let mut processed = block.clone();
+        let mut eq = ParametricEq::from_profile(&profile, 48000.0, channels);
+        let mut scalar = eq.to_scalar();
+        eq.process_block(&mut processed);
+        // The scalar kernel agrees with whichever one this platform runs
+        let mut scalar_processed = block.clone();
+        scalar.process_block(&mut scalar_processed);
+        for (a, b) in processed.iter().zip(&scalar_processed) {
+            assert!((a - b).abs() < 1e-5);
+        }
+
+        for channel in 0..channels {
+            let mut mono = ParametricEq::from_profile(&profile, 48000.0, 1);
+            for (frame, expected) in block
+                .chunks_exact(channels)
+                .zip(processed.chunks_exact(channels))
+            {
+                let mut sample = [frame[channel]];
+                mono.process_block(&mut sample);
+                assert!(
+                    (sample[0] - expected[channel]).abs() < 1e-5,
+                    "channel {} differs",
+                    channel
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_fit_single_bump() {
+        let target: Vec<(f64, f64)> = (0..=200)
+            .map(|i| {
+                let freq = 20.0 * 1000.0f64.powf(i as f64 / 200.0);
+                let octaves = (freq / 2000.0).log2();
+                (freq, 6.0 * (-octaves * octaves / 0.5).exp())
+            })
+            .collect();
+        let profile = EqProfile::fit_to_target(&target, 4, 48000.0);
+        let first = &profile.filters[0];
+        assert!(
+            (first.frequency - 2000.0).abs() < 200.0,
+            "at {} Hz",
+            first.frequency
+        );
+        assert!((first.gain - 6.0).abs() < 0.5, "gain {}", first.gain);
+        assert!(profile.filters.len() <= 4);
+        let responses: Vec<_> = profile
+            .filters
+            .iter()
+            .map(|f| BiquadResponse::calc(f, 48000.0))
+            .collect();
+        for (freq, db) in target {
+            let fitted: f64 = responses
+                .iter()
+                .map(|r| r.calc_magnitude_db(freq, 48000.0))
+                .sum();
+            assert!(
+                (fitted - db).abs() < 1.0,
+                "{} dB off at {} Hz",
+                fitted - db,
+                freq
+            );
+        }
+    }
+
+    #[test]
+    fn test_flatten_keeps_bands() {
+        let mut profile: EqProfile = "Preamp: -4 dB
+Filter 1: ON PK Fc 100 Hz Gain 3 dB Q 1
+Filter 2: ON HSC Fc 8000 Hz Gain -2.5 dB BW Oct 1
+"
+        .parse()
+        .unwrap();
+        profile.tilt_db_per_oct = 0.3;
+        let before = profile.clone();
+        profile.flatten();
+        assert_eq!(profile.preamp_db, 0.0);
+        assert_eq!(profile.tilt_db_per_oct, 0.0);
+        for (flat, band) in profile.filters.iter().zip(&before.filters) {
+            assert_eq!(flat.gain, 0.0);
+            assert_eq!(
+                Filter {
+                    gain: band.gain,
+                    ..flat.clone()
+                },
+                *band
+            );
+        }
+        let response = profile.magnitude_response(&[100.0, 8000.0], 48000.0);
+        assert!(response.iter().all(|db| db.abs() < 1e-9), "{:?}", response);
+    }
+
+    #[test]
+    fn test_invert_twice_is_identity() {
+        let config = "Preamp: -4 dB
+Filter 1: ON PK Fc 100 Hz Gain 3 dB Q 1
+Filter 2: OFF LSC Fc 60 Hz Gain 0 dB Q 0.7
+Filter 3: ON HSC Fc 8000 Hz Gain -2.5 dB BW Oct 1
+";
+        let mut profile: EqProfile = config.parse().unwrap();
+        profile.tilt_db_per_oct = 0.3;
+        let inverted = profile.inverted();
+        assert_eq!(inverted.preamp_db, 4.0);
+        assert_eq!(inverted.tilt_db_per_oct, -0.3);
+        let gains: Vec<f64> = inverted.filters.iter().map(|f| f.gain).collect();
+        assert_eq!(gains, [-3.0, 0.0, 2.5]);
+        assert!(!inverted.to_string().contains("-0 dB"));
+        assert_eq!(inverted.inverted(), profile);
+
+        // Together they are flat
+        let freqs = [50.0, 100.0, 1000.0, 8000.0];
+        let both = profile.magnitude_response(&freqs, 48000.0);
+        let back = inverted.magnitude_response(&freqs, 48000.0);
+        for (a, b) in both.iter().zip(back) {
+            assert!((a + b).abs() < 1e-9, "{} + {}", a, b);
+        }
+
+        assert!(profile.is_invertible());
+        profile.filters.push(Filter {
+            filter_type: FilterType::HighPass,
+            frequency: 30.0,
+            ..Default::default()
+        });
+        assert!(!profile.is_invertible());
+        assert_eq!(profile.inverted().filters[3], profile.filters[3]);
+        assert_eq!(profile.inverted().inverted(), profile);
+    }
+
+    mod impulse_responses {
+        use super::*;
+        use crate::response::BiquadResponse;
+
+        struct Case {
+            filter_type: FilterType,
+            freq: f64,
+            q: f64,
+            gain_db: f64,
+            sample_rate: f64,
+            impulse: [f64; 16],
+        }
+
+        /// The first 16 samples of each band's response to a unit impulse.
+        /// Generated apart from this crate, in Python with double precision:
+        /// the RBJ Audio EQ Cookbook coefficients (shelves taking Q as their
+        /// slope S), normalized by a0 and run as direct form I.
+        const CASES: [Case; 6] = [
+            Case {
+                filter_type: FilterType::Peaking,
+                freq: 1000.0,
+                q: 1.41,
+                gain_db: 6.0,
+                sample_rate: 48000.0,
+                impulse: [
+                    1.0315779, 0.0606288, 0.0552539, 0.0493047, 0.0429161, 0.0362219, 0.0293524,
+                    0.0224325, 0.0155800, 0.0089043, 0.0025047, -0.0035303, -0.0091239, -0.0142113,
+                    -0.0187405, -0.0226718,
+                ],
+            },
+            Case {
+                filter_type: FilterType::Peaking,
+                freq: 3000.0,
+                q: 4.0,
+                gain_db: -9.0,
+                sample_rate: 44100.0,
+                impulse: [
+                    0.9483670, -0.0864550, -0.0497596, -0.0107008, 0.0238777, 0.0489692, 0.0619388,
+                    0.0625798, 0.0527593, 0.0357774, 0.0155913, -0.0039447, -0.0197009, -0.0296742,
+                    -0.0331391, -0.0305640,
+                ],
+            },
+            Case {
+                filter_type: FilterType::LowShelf,
+                freq: 105.0,
+                q: 0.7,
+                gain_db: 5.5,
+                sample_rate: 48000.0,
+                impulse: [
+                    1.0037143, 0.0074147, 0.0073870, 0.0073587, 0.0073301, 0.0073009, 0.0072714,
+                    0.0072415, 0.0072112, 0.0071804, 0.0071494, 0.0071179, 0.0070861, 0.0070540,
+                    0.0070215, 0.0069888,
+                ],
+            },
+            Case {
+                filter_type: FilterType::HighShelf,
+                freq: 8000.0,
+                q: 0.7,
+                gain_db: -4.0,
+                sample_rate: 48000.0,
+                impulse: [
+                    0.7433866, 0.1687307, 0.0744341, 0.0202932, 0.0003257, -0.0034923, -0.0024615,
+                    -0.0010534, -0.0002737, 0.0000047, 0.0000533, 0.0000358, 0.0000149, 0.0000037,
+                    -0.0000002, -0.0000008,
+                ],
+            },
+            Case {
+                filter_type: FilterType::LowPass,
+                freq: 2000.0,
+                q: 0.707,
+                gain_db: 0.0,
+                sample_rate: 48000.0,
+                impulse: [
+                    0.0144011, 0.0523186, 0.0898901, 0.1106575, 0.1186242, 0.1172924, 0.1096162,
+                    0.0980009, 0.0843346, 0.0700391, 0.0561327, 0.0432960, 0.0319375, 0.0222540,
+                    0.0142850, 0.0079591,
+                ],
+            },
+            Case {
+                filter_type: FilterType::HighPass,
+                freq: 80.0,
+                q: 0.707,
+                gain_db: 0.0,
+                sample_rate: 44100.0,
+                impulse: [
+                    0.9919715, -0.0159919, -0.0158620, -0.0157320, -0.0156021, -0.0154723,
+                    -0.0153426, -0.0152129, -0.0150834, -0.0149540, -0.0148248, -0.0146957,
+                    -0.0145667, -0.0144380, -0.0143094, -0.0141811,
+                ],
+            },
+        ];
+
+        /// Loose enough for single precision, tight enough for any sign
+        /// or normalization mistake.
+        const TOLERANCE: f64 = 1e-5;
+
+        fn assert_matches(what: &str, case: &Case, impulse: &[f64]) {
+            for (n, (got, expected)) in impulse.iter().zip(&case.impulse).enumerate() {
+                assert!(
+                    (got - expected).abs() < TOLERANCE,
+                    "{} {} at {} Hz, sample {}: {} instead of {}",
+                    what,
+                    case.filter_type,
+                    case.freq,
+                    n,
+                    got,
+                    expected
+                );
+            }
+        }
+
+        #[test]
+        fn test_impulse_responses() {
+            for case in &CASES {
+                let mut eq = ParametricEq::new(case.sample_rate as f32, 1);
+                eq.add_band(
+                    case.filter_type,
+                    case.freq as f32,
+                    case.q as f32,
+                    case.gain_db as f32,
+                );
+                let mut data = [0.0f32; 16];
+                data[0] = 1.0;
+                eq.process_block(&mut data);
+                let impulse: Vec<f64> = data.iter().map(|s| *s as f64).collect();
+                assert_matches("ParametricEq", case, &impulse);
+
+                // The graph's double-precision copy of the formulas
+                let band = Filter {
+                    filter_type: case.filter_type,
+                    frequency: case.freq,
+                    q_factor: case.q,
+                    gain: case.gain_db,
+                    ..Default::default()
+                };
+                let response = BiquadResponse::calc(&band, case.sample_rate);
+                assert_matches("BiquadResponse", case, &response.impulse_response(16));
+            }
+        }
+    }
 }
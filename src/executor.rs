@@ -1,168 +1,1044 @@
 use crate::{
+    command::{AbSlot, DeviceList, SetDevice, State},
     eq::EqProfile,
-    run::{run, run_realtime},
+    run::{
+        DeviceLost, Input, StreamOptions, device_id, find_device, resolve_host, run, run_realtime,
+        stream_options,
+    },
     settings::Settings,
-    ui::command::{SetDevice, SetRealtime, State},
 };
-use cpal::{
-    Device,
-    traits::{DeviceTrait, HostTrait},
+use cpal::{Device, Host, traits::DeviceTrait};
+use tracing::{debug, error, info, warn};
+
+use crate::{
+    command::Command,
+    config::{
+        ChannelMode, Config, InstanceKey, MAIN_INSTANCE, OverflowPolicy, RangeConfig, clamp_finite,
+        config_dir,
+    },
+    midi::BandParam,
+    osc,
+    watcher::{FileWatcher, watch},
+};
+use std::{
+    collections::BTreeMap,
+    ops::ControlFlow,
+    sync::{
+        Arc, Mutex,
+        atomic::Ordering,
+        mpsc::{Receiver, RecvTimeoutError, Sender, SyncSender, channel},
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
 };
-use tracing::debug;
 
-use crate::{config::Config, ui::command::Command};
-use std::sync::{atomic::Ordering, mpsc::Receiver};
+/// Handle to the audio runner thread. Starting a runner first stops the
+/// previous one and waits for it, so two runners never hold a device at once.
+#[derive(Default)]
+struct Runner {
+    handle: Option<JoinHandle<()>>,
+}
 
-pub struct Executor {
-    receiver: Receiver<Command>,
-    config: Config,
+impl Runner {
+    /// Spawns `body` with the instance id it should run under.
+    fn start(&mut self, settings: &Settings, body: impl FnOnce(usize) + Send + 'static) {
+        self.stop(settings);
+        let instance_id = settings.instance_id.load(Ordering::Relaxed);
+        self.handle = Some(std::thread::spawn(move || body(instance_id)));
+    }
+
+    fn stop(&mut self, settings: &Settings) {
+        settings.instance_id.fetch_add(1, Ordering::Relaxed);
+        if let Some(handle) = self.handle.take() {
+            handle.join().ok();
+        }
+    }
+
+    fn is_running(&self) -> bool {
+        self.handle.as_ref().is_some_and(|h| !h.is_finished())
+    }
+}
+
+/// A profile from a control surface with its values as the editor would
+/// take them, or None when any of them isn't a finite number.
+fn checked_profile(mut profile: EqProfile, ranges: &RangeConfig) -> Option<EqProfile> {
+    profile.preamp_db = clamp_finite(profile.preamp_db, ranges.preamp())?;
+    profile.tilt_db_per_oct = clamp_finite(profile.tilt_db_per_oct, ranges.tilt())?;
+    for filter in &mut profile.filters {
+        filter.frequency = BandParam::Frequency.clamp(filter.frequency, ranges)?;
+        filter.gain = BandParam::Gain.clamp(filter.gain, ranges)?;
+        filter.q_factor = BandParam::Q.clamp(filter.q_factor, ranges)?;
+        if let Some(bandwidth) = filter.bandwidth {
+            filter.bandwidth = Some(clamp_finite(bandwidth, ranges.bandwidth())?);
+        }
+    }
+    Some(profile)
+}
+
+/// Wait before reconnection attempt `attempt`, doubling up to 10 s.
+fn reconnect_delay(attempt: u32) -> Duration {
+    Duration::from_millis(500)
+        .saturating_mul(1 << attempt.min(5))
+        .min(Duration::from_secs(10))
+}
+
+/// The profiles of `config` that differ from the ones last seen for the same
+/// pairs, which become the last seen ones. Pairs not in `last` are skipped.
+fn changed_profiles(
+    last: &mut BTreeMap<InstanceKey, EqProfile>,
+    config: &Config,
+) -> Vec<(InstanceKey, EqProfile)> {
+    let mut changed = Vec::new();
+    for (key, last_profile) in last.iter_mut() {
+        let Some(instance) = config.instance(key) else {
+            continue;
+        };
+        if instance.eq_profile != *last_profile {
+            *last_profile = instance.eq_profile.clone();
+            changed.push((key.clone(), instance.eq_profile));
+        }
+    }
+    changed
+}
+
+/// The parts of the config the running engine was built from. Changing any
+/// of them needs new streams; everything else is pushed to the live engine.
+#[derive(PartialEq, Debug)]
+struct EngineKey {
+    host: Option<String>,
+    input_dev_name: Option<String>,
+    output_dev_name: Option<String>,
+    loopback: bool,
+    sample_rate: Option<u32>,
+    buffer_size: Option<u32>,
+    linear_phase: bool,
+    overflow: OverflowPolicy,
+    side_profile: Option<EqProfile>,
+}
+
+/// One input/output pair with its own engine, settings and instance id.
+struct Instance {
     settings: Settings,
+    /// Whether the pair should be running, also while it waits for a device
+    /// to come back
+    running: bool,
     input_device: Option<Device>,
     output_device: Option<Device>,
+    runner: Runner,
+    engine_key: Option<EngineKey>,
+    profile_sender: Option<Sender<EqProfile>>,
+    // attempt number and due time of the next reconnect after a device loss
+    reconnect: Option<(u32, Instant)>,
+}
+
+impl Instance {
+    fn new(settings: Settings) -> Self {
+        Self {
+            settings,
+            running: true,
+            input_device: None,
+            output_device: None,
+            runner: Runner::default(),
+            engine_key: None,
+            profile_sender: None,
+            reconnect: None,
+        }
+    }
+
+    fn devices_missing(&self) -> bool {
+        self.input_device.is_none() || self.output_device.is_none()
+    }
+
+    fn stop(&mut self) {
+        self.reconnect = None;
+        self.runner.stop(&self.settings);
+        self.engine_key = None;
+        self.profile_sender = None;
+    }
+}
+
+pub struct Executor {
+    receiver: Receiver<Command>,
+    sender: SyncSender<Command>,
+    config: Config,
+    /// The main pair under [`MAIN_INSTANCE`], plus one per `config.instances`
+    instances: BTreeMap<InstanceKey, Instance>,
     state: State,
+    /// The A/B slot the main profile belongs to, and the other slot's profile
+    ab_slot: AbSlot,
+    ab_other: EqProfile,
+    /// The profile of each pair as last seen in config.toml, shared with the
+    /// config watcher; pairs started later are added
+    watched_profiles: Arc<Mutex<BTreeMap<InstanceKey, EqProfile>>>,
 }
 
 impl Executor {
-    pub fn new(receiver: Receiver<Command>, config: Config, settings: Settings) -> Self {
+    pub fn new(
+        receiver: Receiver<Command>,
+        sender: SyncSender<Command>,
+        config: Config,
+        settings: Settings,
+    ) -> Self {
+        let mut instances = BTreeMap::new();
+        for (key, instance) in &config.instances {
+            if key == MAIN_INSTANCE {
+                warn!("Ignoring instance \"{}\", the name is reserved", key);
+                continue;
+            }
+            instances.insert(
+                key.clone(),
+                Instance::new(Settings::from(&instance.settings)),
+            );
+        }
+        instances.insert(MAIN_INSTANCE.to_string(), Instance::new(settings));
+        let watched_profiles = instances
+            .keys()
+            .filter_map(|key| Some((key.clone(), config.instance(key)?.eq_profile)))
+            .collect();
         Executor {
             receiver,
+            sender,
+            ab_slot: AbSlot::default(),
+            ab_other: config.eq_profile.clone(),
             config,
-            settings,
-            input_device: None,
-            output_device: None,
+            instances,
             state: State::default(),
+            watched_profiles: Arc::new(Mutex::new(watched_profiles)),
         }
     }
 
     pub fn run(&mut self) {
-        let host = cpal::default_host();
-        if let Some(input_device_name) = self.config.input_dev_name.as_ref() {
-            for device in host.devices().unwrap() {
-                if device.description().unwrap().name() == input_device_name {
-                    self.input_device = Some(device);
-                    break;
-                }
-            }
+        let mut host = resolve_host(self.config.host.as_deref());
+        for key in self.keys() {
+            self.load_devices(&host, &key);
+            self.flag_missing_devices(&key);
         }
-        if let Some(output_device_name) = self.config.output_dev_name.as_ref() {
-            for device in host.devices().unwrap() {
-                if device.description().unwrap().name() == output_device_name {
-                    self.output_device = Some(device);
-                    break;
-                }
-            }
+        if self.main().devices_missing() {
+            self.main_mut().running = false;
         }
-        if self.input_device.is_none() || self.output_device.is_none() {
-            self.state.running = false;
-        } else {
-            self.start_proc();
+        for key in self.keys() {
+            self.start_proc(&key);
         }
-        while let Ok(command) = self.receiver.recv() {
+        let _watcher = self.watch_config();
+        if self.config.osc.enabled
+            && let Err(e) = osc::serve(&self.config.osc, self.sender.clone())
+        {
+            error!("Failed to start the OSC server: {:?}", e);
+            self.main().settings.status.set_error(format!("{:#}", e));
+        }
+        self.serve_http();
+        loop {
+            let command = match self.next_reconnect() {
+                Some(due) => match self
+                    .receiver
+                    .recv_timeout(due.saturating_duration_since(Instant::now()))
+                {
+                    Ok(command) => command,
+                    Err(RecvTimeoutError::Timeout) => {
+                        self.reconnect_due(&host);
+                        continue;
+                    }
+                    Err(RecvTimeoutError::Disconnected) => break,
+                },
+                None => match self.receiver.recv() {
+                    Ok(command) => command,
+                    Err(_) => break,
+                },
+            };
             debug!("New command: {:?}", command);
-            match command {
-                Command::SetState(new_state) => {
-                    if self.state.running != new_state.running {
-                        self.state.running = new_state.running;
-                        if self.state.running {
-                            self.state.running = new_state.running;
-                            self.start_proc();
-                        } else {
-                            self.settings.instance_id.fetch_add(1, Ordering::Relaxed);
-                        }
+            if self.handle(command, &mut host).is_break() {
+                return;
+            }
+        }
+    }
+
+    fn handle(&mut self, command: Command, host: &mut Host) -> ControlFlow<()> {
+        match command {
+            Command::SetState(new_state) => {
+                if self.main().running != new_state.running {
+                    self.main_mut().running = new_state.running;
+                    if new_state.running {
+                        self.start_proc(MAIN_INSTANCE);
+                    } else {
+                        self.stop_proc(MAIN_INSTANCE);
                     }
-                    if self.state.enabled != new_state.enabled {
-                        self.state.enabled = new_state.enabled;
-                        self.settings
-                            .enable_eq
-                            .store(self.state.enabled, Ordering::Relaxed);
+                }
+                self.set_enabled(new_state.enabled);
+            }
+            Command::SetEnabled(enabled) => self.set_enabled(enabled),
+            Command::UpdateSettings(new_settings) => {
+                self.main_mut().settings = new_settings.clone();
+            }
+            Command::UpdateProfile(new_profile) => self.update_profile(MAIN_INSTANCE, new_profile),
+            Command::Restart => {
+                if self.main().runner.is_running()
+                    && self.main().engine_key == self.current_engine_key(MAIN_INSTANCE)
+                {
+                    debug!("Engine config unchanged, skipping restart");
+                } else {
+                    self.start_proc(MAIN_INSTANCE);
+                }
+            }
+            Command::Save(settings, profile) => {
+                self.config.settings = settings.persistent();
+                self.main_mut().settings = settings.clone();
+                self.config.eq_profile = profile;
+                self.save_config();
+            }
+            Command::GetState(oneshot) => {
+                oneshot.send(State {
+                    running: self.main().running,
+                    ..self.state
+                });
+            }
+            Command::GetProfile(oneshot) => oneshot.send(self.config.eq_profile.clone()),
+            Command::RefreshDevices(oneshot) => {
+                let devices = DeviceList::from_host(host);
+                for key in self.keys() {
+                    let was_missing = self.instances[&key].devices_missing();
+                    self.load_devices(host, &key);
+                    self.flag_missing_devices(&key);
+                    // Resume once a device that was unplugged comes back
+                    if was_missing {
+                        self.start_proc(&key);
                     }
                 }
-                Command::UpdateSettings(new_settings) => {
-                    self.settings = new_settings.clone();
+                oneshot.send(devices);
+            }
+            Command::GetStreamOptions(oneshot) => {
+                let options = match (
+                    self.input(MAIN_INSTANCE),
+                    self.main().output_device.as_ref(),
+                ) {
+                    (Some(input), Some(output)) => stream_options(&input, output)
+                        .inspect_err(|e| warn!("Failed to query stream options: {:?}", e))
+                        .unwrap_or_default(),
+                    _ => StreamOptions::default(),
+                };
+                oneshot.send(options);
+            }
+            Command::SetHost(name) => {
+                *host = resolve_host(Some(&name));
+                self.config.host = Some(name);
+                // Devices belong to a host; look the configured names up again
+                for key in self.keys() {
+                    self.load_devices(host, &key);
                 }
-                Command::UpdateProfile(new_profile) => {
-                    self.config.eq_profile = new_profile;
+                self.save_config();
+            }
+            Command::SetDevice(set_device, name) => {
+                let dev = find_device(host, &name, None);
+                let id = dev.as_ref().and_then(device_id);
+                match set_device {
+                    SetDevice::Input => {
+                        self.main_mut().input_device = dev;
+                        self.config.input_dev_name = Some(name);
+                        self.config.input_dev_id = id;
+                        self.config.loopback = false;
+                    }
+                    SetDevice::Loopback => {
+                        self.main_mut().input_device = dev;
+                        self.config.input_dev_name = Some(name);
+                        self.config.input_dev_id = id;
+                        self.config.loopback = true;
+                    }
+                    SetDevice::Output => {
+                        self.main_mut().output_device = dev;
+                        self.config.output_dev_name = Some(name);
+                        self.config.output_dev_id = id;
+                    }
                 }
-                Command::Restart => {
-                    self.settings.instance_id.fetch_add(1, Ordering::Relaxed);
-                    self.start_proc();
+                self.save_config();
+            }
+            Command::SetRealtime(realtime) => {
+                self.state.realtime = realtime;
+                if realtime || self.state.enabled {
+                    self.start_proc(MAIN_INSTANCE);
                 }
-                Command::Save(settings, profile) => {
-                    self.settings = settings.clone();
-                    self.config.eq_profile = profile;
-                    self.config.save().unwrap();
+            }
+            Command::SavePreset(name, profile) => {
+                self.config.save_preset(name, profile);
+                self.save_config();
+            }
+            Command::LoadPreset(name) => match self.config.preset(&name).cloned() {
+                Some(profile) => self.update_profile(MAIN_INSTANCE, profile),
+                None => warn!("No preset named {}", name),
+            },
+            Command::DeletePreset(name) => {
+                self.config.delete_preset(&name);
+                self.save_config();
+            }
+            Command::SaveSetup(setup) => {
+                self.config.save_setup(setup);
+                self.save_config();
+            }
+            Command::ApplySetup(name) => self.apply_setup(host, &name),
+            Command::DeleteSetup(name) => {
+                self.config.delete_setup(&name);
+                self.save_config();
+            }
+            // Both take effect on the next Restart
+            Command::SetChannelMode(channel_mode) => self.config.channel_mode = channel_mode,
+            Command::UpdateSideProfile(side_profile) => self.config.side_profile = side_profile,
+            Command::SwapAB(profile) => {
+                // In realtime mode the UI pushes the swapped profile itself
+                let next = std::mem::replace(&mut self.ab_other, profile);
+                self.ab_slot = self.ab_slot.other();
+                info!("Switched to profile {}", self.ab_slot);
+                self.update_profile(MAIN_INSTANCE, next);
+            }
+            Command::SaveWindow(window) => {
+                self.config.window = window;
+                self.save_config();
+            }
+            Command::SetLang(lang) => {
+                self.config.lang = lang;
+                self.save_config();
+            }
+            Command::SetMidi(midi) => {
+                self.config.midi = midi;
+                self.save_config();
+            }
+            Command::SetPreamp(db) => {
+                let Some(db) = clamp_finite(db, self.config.ranges.sanitized().preamp()) else {
+                    warn!("Ignoring preamp of {} dB", db);
+                    return ControlFlow::Continue(());
+                };
+                let mut profile = self.config.eq_profile.clone();
+                profile.preamp_db = db;
+                self.external_profile(MAIN_INSTANCE, profile);
+                self.save_config();
+            }
+            Command::SetBand(band, param, value) => {
+                let mut profile = self.config.eq_profile.clone();
+                let Some(filter) = profile.filters.get_mut(band) else {
+                    warn!("No band {}", band + 1);
+                    return ControlFlow::Continue(());
+                };
+                if !param.set(filter, value, &self.config.ranges.sanitized()) {
+                    warn!("Ignoring {} of {} for band {}", param, value, band + 1);
+                    return ControlFlow::Continue(());
                 }
-                Command::GetState(oneshot) => {
-                    oneshot.send(self.state);
+                self.external_profile(MAIN_INSTANCE, profile);
+                self.save_config();
+            }
+            Command::SetProfile(profile) => {
+                let Some(profile) = checked_profile(profile, &self.config.ranges.sanitized())
+                else {
+                    warn!("Ignoring a profile with values that aren't numbers");
+                    return ControlFlow::Continue(());
+                };
+                self.external_profile(MAIN_INSTANCE, profile);
+                self.save_config();
+            }
+            Command::StartInstance(key) => {
+                if !self.instances.contains_key(&key) {
+                    let Some(instance) = self.config.instance(&key) else {
+                        warn!("No instance named {}", key);
+                        return ControlFlow::Continue(());
+                    };
+                    let settings = Settings::from(&instance.settings);
+                    self.instances.insert(key.clone(), Instance::new(settings));
+                    self.watched_profiles
+                        .lock()
+                        .unwrap()
+                        .entry(key.clone())
+                        .or_insert(instance.eq_profile);
                 }
-                Command::SetDevice(set_device, name) => {
-                    let mut dev = None;
-                    for device in host.devices().unwrap() {
-                        if device.description().unwrap().name() == name.as_str() {
-                            dev = Some(device);
-                            break;
-                        }
-                    }
-                    match set_device {
-                        SetDevice::Input => {
-                            self.input_device = dev;
-                            self.config.input_dev_name = Some(name)
-                        }
-                        SetDevice::Output => {
-                            self.output_device = dev;
-                            self.config.output_dev_name = Some(name)
-                        }
-                    }
-                    self.config.save().unwrap();
+                self.load_devices(host, &key);
+                self.flag_missing_devices(&key);
+                self.instances.get_mut(&key).unwrap().running = true;
+                self.start_proc(&key);
+            }
+            Command::StopInstance(key) => match self.instances.get_mut(&key) {
+                Some(instance) => {
+                    instance.running = false;
+                    instance.stop();
                 }
-                Command::SetRealtime(set_realtime) => match set_realtime {
-                    SetRealtime::Off => {
-                        self.state.realtime = false;
-                        if self.state.enabled {
-                            self.start_proc();
-                        }
-                    }
-                    SetRealtime::On(receiver) => {
-                        self.state.realtime = true;
-                        self.settings.instance_id.fetch_add(1, Ordering::Relaxed);
-                        self.start_proc_realtime(receiver);
-                    }
-                },
-                Command::Shutdown => {
-                    self.settings.instance_id.fetch_add(1, Ordering::Relaxed);
-                    return;
+                None => warn!("No instance named {}", key),
+            },
+            Command::UpdateInstanceProfile(key, profile) => self.external_profile(&key, profile),
+            Command::DeviceLost(key, instance_id) => {
+                // A stop or restart since then makes the report stale
+                if let Some(instance) = self.instances.get(&key)
+                    && instance_id == instance.settings.instance_id.load(Ordering::Relaxed)
+                    && instance.running
+                {
+                    self.schedule_reconnect(&key, 0);
+                }
+            }
+            Command::Shutdown => {
+                for instance in self.instances.values_mut() {
+                    instance.stop();
                 }
+                return ControlFlow::Break(());
             }
         }
+        ControlFlow::Continue(())
+    }
+
+    fn keys(&self) -> Vec<InstanceKey> {
+        self.instances.keys().cloned().collect()
+    }
+
+    fn main(&self) -> &Instance {
+        &self.instances[MAIN_INSTANCE]
+    }
+
+    fn main_mut(&mut self) -> &mut Instance {
+        self.instances
+            .get_mut(MAIN_INSTANCE)
+            .expect("the main instance is never removed")
+    }
+
+    /// Pushes the profiles to the engines when config.toml is edited by hand.
+    /// Profiles only come from the config here; the CLI's EQ file is
+    /// reloaded with its `reload` command.
+    fn watch_config(&self) -> Option<FileWatcher> {
+        let sender = self.sender.clone();
+        let watched_profiles = self.watched_profiles.clone();
+        watch(
+            vec![config_dir()],
+            Duration::from_millis(300),
+            move |path| match Config::read(path) {
+                Ok(config) => {
+                    let changed = changed_profiles(&mut watched_profiles.lock().unwrap(), &config);
+                    // Sent with the lock released: the executor may be
+                    // waiting for it while the channel is full
+                    for (key, profile) in changed {
+                        info!("{} changed, reloading profile of {}", path.display(), key);
+                        sender
+                            .send(Command::UpdateInstanceProfile(key, profile))
+                            .ok();
+                    }
+                }
+                Err(e) => warn!("Ignoring config change: {:?}", e),
+            },
+        )
+        .inspect_err(|e| warn!("Failed to watch config: {:?}", e))
+        .ok()
     }
 
-    fn start_proc(&self) {
-        if self.state.running
-            && let Some(input) = self.input_device.clone()
-            && let Some(output) = self.output_device.clone()
+    #[cfg(feature = "http")]
+    fn serve_http(&self) {
+        if self.config.http.enabled
+            && let Err(e) = crate::http::serve(&self.config.http, self.sender.clone())
         {
-            let settings = self.settings.clone();
-            let profile = self.config.eq_profile.clone();
-            std::thread::spawn(move || {
-                run(input, output, settings, profile)
-                    .inspect_err(|e| println!("{:?}", e))
-                    .ok();
-            });
+            error!("Failed to start the HTTP server: {:?}", e);
+            self.main().settings.status.set_error(format!("{:#}", e));
+        }
+    }
+
+    #[cfg(not(feature = "http"))]
+    fn serve_http(&self) {
+        if self.config.http.enabled {
+            warn!("The HTTP server is enabled in the config, but this build has no `http` feature");
+        }
+    }
+
+    fn set_enabled(&mut self, enabled: bool) {
+        if self.state.enabled != enabled {
+            self.state.enabled = enabled;
+            self.main()
+                .settings
+                .enable_eq
+                .store(enabled, Ordering::Relaxed);
+        }
+    }
+
+    fn update_profile(&mut self, key: &str, profile: EqProfile) {
+        let Some(instance) = self.instances.get(key) else {
+            warn!("No instance named {}", key);
+            return;
+        };
+        if let Some(sender) = instance.profile_sender.as_ref() {
+            sender.send(profile.clone()).ok();
+        }
+        if key == MAIN_INSTANCE {
+            self.config.eq_profile = profile;
+        } else if let Some(instance) = self.config.instances.get_mut(key) {
+            instance.eq_profile = profile;
         }
     }
 
-    fn start_proc_realtime(&self, receiver: Receiver<EqProfile>) {
-        if self.state.running
-            && let Some(input) = self.input_device.clone()
-            && let Some(output) = self.output_device.clone()
+    /// Like `update_profile`, for a profile changed outside the GUI, which
+    /// gets the main one so its next Apply or realtime edit doesn't revert
+    /// it. Our own saves come back from the config watcher and are skipped.
+    fn external_profile(&mut self, key: &str, profile: EqProfile) {
+        if self
+            .config
+            .instance(key)
+            .is_some_and(|config| config.eq_profile == profile)
         {
-            let settings = self.settings.clone();
-            let profile = self.config.eq_profile.clone();
-            std::thread::spawn(move || {
-                run_realtime(input, output, settings, profile, receiver)
-                    .inspect_err(|e| println!("{:?}", e))
+            return;
+        }
+        if key == MAIN_INSTANCE {
+            self.main().settings.status.push_profile(profile.clone());
+        }
+        self.update_profile(key, profile);
+    }
+
+    fn apply_setup(&mut self, host: &Host, name: &str) {
+        let Some(setup) = self.config.setup(name).cloned() else {
+            warn!("No setup named {}", name);
+            return;
+        };
+        // Setups only have names; the ids are filled in once found
+        self.config.input_dev_name = setup.input;
+        self.config.input_dev_id = None;
+        self.config.loopback = setup.loopback;
+        self.config.output_dev_name = setup.output;
+        self.config.output_dev_id = None;
+        self.config.settings.latency = setup.latency;
+        self.config.eq_profile = setup.profile;
+        self.main_mut()
+            .settings
+            .latency
+            .store(setup.latency, Ordering::Relaxed);
+        self.load_devices(host, MAIN_INSTANCE);
+        self.flag_missing_devices(MAIN_INSTANCE);
+        self.save_config();
+        self.start_proc(MAIN_INSTANCE);
+    }
+
+    fn save_config(&self) {
+        if let Err(e) = self.config.save() {
+            error!("Failed to save config: {:?}", e);
+            self.main().settings.status.set_error(format!("{:#}", e));
+        }
+    }
+
+    fn current_engine_key(&self, key: &str) -> Option<EngineKey> {
+        let config = self.config.instance(key)?;
+        let settings = &self.instances.get(key)?.settings;
+        Some(EngineKey {
+            host: self.config.host.clone(),
+            input_dev_name: config.input_dev_name,
+            output_dev_name: config.output_dev_name,
+            loopback: config.loopback,
+            sample_rate: settings.sample_rate,
+            buffer_size: settings.buffer_size,
+            linear_phase: settings.linear_phase,
+            overflow: settings.overflow,
+            side_profile: self.side_profile(key),
+        })
+    }
+
+    /// The side profile when the main pair runs mid/side, `None` for plain
+    /// stereo.
+    fn side_profile(&self, key: &str) -> Option<EqProfile> {
+        (key == MAIN_INSTANCE && self.config.channel_mode == ChannelMode::MidSide)
+            .then(|| self.config.side_profile.clone())
+    }
+
+    fn load_devices(&mut self, host: &Host, key: &str) {
+        let (Some(config), Some(instance)) =
+            (self.config.instance(key), self.instances.get_mut(key))
+        else {
+            return;
+        };
+        let find = |name: &Option<String>, id: &Option<String>| {
+            name.as_ref()
+                .and_then(|name| find_device(host, name, id.as_deref()))
+        };
+        instance.input_device = find(&config.input_dev_name, &config.input_dev_id);
+        instance.output_device = find(&config.output_dev_name, &config.output_dev_id);
+        let found = [&instance.input_device, &instance.output_device].map(|device| {
+            device.as_ref().map(|device| {
+                let name = device.description().ok().map(|d| d.name().to_string());
+                (name, device_id(device))
+            })
+        });
+        self.remember_devices(key, found);
+    }
+
+    /// Saves the current names and ids of the input and output found for
+    /// `key`, so a renamed device keeps being found and shown under its new
+    /// name.
+    fn remember_devices(
+        &mut self,
+        key: &str,
+        found: [Option<(Option<String>, Option<String>)>; 2],
+    ) {
+        let fields = if key == MAIN_INSTANCE {
+            let c = &mut self.config;
+            [
+                (&mut c.input_dev_name, &mut c.input_dev_id),
+                (&mut c.output_dev_name, &mut c.output_dev_id),
+            ]
+        } else if let Some(c) = self.config.instances.get_mut(key) {
+            [
+                (&mut c.input_dev_name, &mut c.input_dev_id),
+                (&mut c.output_dev_name, &mut c.output_dev_id),
+            ]
+        } else {
+            return;
+        };
+        let mut changed = false;
+        for ((saved_name, saved_id), found) in fields.into_iter().zip(found) {
+            let Some((name, id)) = found else {
+                continue;
+            };
+            if let Some(name) = name
+                && saved_name.as_ref() != Some(&name)
+            {
+                info!(
+                    "Device \"{}\" is now called \"{}\"",
+                    saved_name.as_deref().unwrap_or_default(),
+                    name
+                );
+                *saved_name = Some(name);
+                changed = true;
+            }
+            if id.is_some() && *saved_id != id {
+                *saved_id = id;
+                changed = true;
+            }
+        }
+        if changed {
+            self.save_config();
+        }
+    }
+
+    /// Reports configured devices the host doesn't have (anymore).
+    fn flag_missing_devices(&self, key: &str) {
+        let (Some(config), Some(instance)) = (self.config.instance(key), self.instances.get(key))
+        else {
+            return;
+        };
+        let missing = [
+            ("Input", &config.input_dev_name, &instance.input_device),
+            ("Output", &config.output_dev_name, &instance.output_device),
+        ]
+        .into_iter()
+        .filter_map(|(kind, name, device)| match (name, device) {
+            (Some(name), None) => Some(format!("{} device \"{}\" is not available", kind, name)),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+        if !missing.is_empty() {
+            warn!("{}: {}", key, missing.join(", "));
+            instance.settings.status.set_error(missing.join(", "));
+        }
+    }
+
+    fn input(&self, key: &str) -> Option<Input> {
+        let device = self.instances.get(key)?.input_device.clone()?;
+        Some(if self.config.instance(key)?.loopback {
+            Input::Loopback(device)
+        } else {
+            Input::Device(device)
+        })
+    }
+
+    /// Due time of the earliest pending reconnect.
+    fn next_reconnect(&self) -> Option<Instant> {
+        self.instances
+            .values()
+            .filter_map(|instance| instance.reconnect.map(|(_, due)| due))
+            .min()
+    }
+
+    fn reconnect_due(&mut self, host: &Host) {
+        let now = Instant::now();
+        for key in self.keys() {
+            if self.instances[&key]
+                .reconnect
+                .is_some_and(|(_, due)| due <= now)
+            {
+                self.try_reconnect(host, &key);
+            }
+        }
+    }
+
+    fn schedule_reconnect(&mut self, key: &str, attempt: u32) {
+        let Some(instance) = self.instances.get_mut(key) else {
+            return;
+        };
+        let delay = reconnect_delay(attempt);
+        let message = format!(
+            "Audio device disconnected, reconnecting in {:.1} s (attempt {})",
+            delay.as_secs_f32(),
+            attempt + 1
+        );
+        warn!("{}: {}", key, message);
+        instance.settings.status.set_error(message);
+        instance.reconnect = Some((attempt, Instant::now() + delay));
+    }
+
+    /// Looks the pair's devices up by name again and restarts its engine once
+    /// both are back.
+    fn try_reconnect(&mut self, host: &Host, key: &str) {
+        let Some((attempt, _)) = self
+            .instances
+            .get_mut(key)
+            .and_then(|instance| instance.reconnect.take())
+        else {
+            return;
+        };
+        self.load_devices(host, key);
+        if self.instances[key].devices_missing() {
+            self.schedule_reconnect(key, attempt + 1);
+        } else {
+            info!("Devices of {} are back, restarting engine", key);
+            self.start_proc(key);
+        }
+    }
+
+    fn stop_proc(&mut self, key: &str) {
+        if let Some(instance) = self.instances.get_mut(key) {
+            instance.stop();
+        }
+    }
+
+    fn start_proc(&mut self, key: &str) {
+        let input = self.input(key);
+        let engine_key = self.current_engine_key(key);
+        let Some(config) = self.config.instance(key) else {
+            return;
+        };
+        let side_profile = self.side_profile(key);
+        // Realtime edits always go to the main profile
+        let engine = if key == MAIN_INSTANCE && self.state.realtime {
+            run_realtime
+        } else {
+            run
+        };
+        let Some(instance) = self.instances.get_mut(key) else {
+            return;
+        };
+        if instance.running
+            && let Some(input) = input
+            && let Some(output) = instance.output_device.clone()
+        {
+            let settings = instance.settings.clone();
+            let profile = config.eq_profile;
+            let (sender, receiver) = channel();
+            let executor = self.sender.clone();
+            let key = key.to_string();
+            instance.reconnect = None;
+            instance.settings.status.clear_error();
+            instance
+                .runner
+                .start(&instance.settings, move |instance_id| {
+                    let status = settings.status.clone();
+                    engine(
+                        input,
+                        output,
+                        settings,
+                        profile,
+                        side_profile,
+                        receiver,
+                        instance_id,
+                    )
+                    .inspect_err(|e| {
+                        error!("Audio processing failed: {:?}", e);
+                        status.set_error(format!("{:#}", e));
+                        if e.is::<DeviceLost>() {
+                            executor.send(Command::DeviceLost(key, instance_id)).ok();
+                        }
+                    })
                     .ok();
+                });
+            instance.engine_key = engine_key;
+            instance.profile_sender = Some(sender);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::mpsc::sync_channel, time::Duration};
+
+    use super::*;
+    use crate::{config::InstanceConfig, settings::PersistentSettings, testing::MockEngine};
+
+    #[test]
+    fn test_runner_rapid_toggle() {
+        let settings = Settings::from(&PersistentSettings::default());
+        let engine = MockEngine::default();
+        let mut runner = Runner::default();
+        for i in 0..200 {
+            let settings_cloned = settings.clone();
+            let engine = engine.clone();
+            let (_, receiver) = channel();
+            runner.start(&settings, move |instance_id| {
+                engine.run(&settings_cloned, instance_id, receiver);
             });
+            if i % 2 == 0 {
+                runner.stop(&settings);
+            }
+        }
+        runner.stop(&settings);
+        assert_eq!(engine.active(), 0);
+        assert_eq!(engine.peak(), 1);
+    }
+
+    #[test]
+    fn test_instances_are_independent() {
+        let (sender, receiver) = sync_channel(8);
+        let config = Config {
+            instances: [("headphones".to_string(), InstanceConfig::default())].into(),
+            ..Default::default()
+        };
+        let settings = Settings::from(&PersistentSettings::default());
+        let mut executor = Executor::new(receiver, sender, config, settings);
+        let mut host = cpal::default_host();
+        let engines = [MockEngine::default(), MockEngine::default()];
+        for (key, engine) in [MAIN_INSTANCE, "headphones"].into_iter().zip(&engines) {
+            let instance = executor.instances.get_mut(key).unwrap();
+            let settings = instance.settings.clone();
+            let engine = engine.clone();
+            let (sender, receiver) = channel();
+            instance
+                .runner
+                .start(&instance.settings, move |instance_id| {
+                    engine.run(&settings, instance_id, receiver);
+                });
+            instance.profile_sender = Some(sender);
         }
+        let [main_engine, headphones_engine] = &engines;
+        let running = |executor: &Executor, key| executor.instances[key].runner.is_running();
+
+        let _ = executor.handle(Command::StopInstance(MAIN_INSTANCE.to_string()), &mut host);
+        assert!(!running(&executor, MAIN_INSTANCE));
+        assert!(running(&executor, "headphones"));
+
+        let profile = EqProfile {
+            preamp_db: -2.0,
+            ..Default::default()
+        };
+        let _ = executor.handle(
+            Command::UpdateInstanceProfile("headphones".to_string(), profile.clone()),
+            &mut host,
+        );
+        assert_eq!(headphones_engine.next_profile(), Some(profile.clone()));
+        assert_eq!(executor.config.instances["headphones"].eq_profile, profile);
+        assert_eq!(executor.config.eq_profile, EqProfile::default());
+
+        // Unknown pairs are ignored
+        let _ = executor.handle(Command::StopInstance("speakers".to_string()), &mut host);
+        assert!(running(&executor, "headphones"));
+        let _ = executor.handle(Command::Shutdown, &mut host);
+        assert!(!running(&executor, "headphones"));
+        assert!(main_engine.try_profile().is_none());
+        assert!(headphones_engine.try_profile().is_none());
+    }
+
+    #[test]
+    fn test_swap_ab() {
+        let (sender, receiver) = sync_channel(8);
+        let a = EqProfile {
+            preamp_db: -1.0,
+            ..Default::default()
+        };
+        let config = Config {
+            eq_profile: a.clone(),
+            ..Default::default()
+        };
+        let settings = Settings::from(&PersistentSettings::default());
+        let mut executor = Executor::new(receiver, sender, config, settings);
+        let mut host = cpal::default_host();
+        let (profile_sender, applied) = channel();
+        executor.main_mut().profile_sender = Some(profile_sender);
+
+        // B starts as a copy of A, and takes the tweaks made while it is active
+        let _ = executor.handle(Command::SwapAB(a.clone()), &mut host);
+        assert_eq!(applied.try_recv().unwrap(), a);
+        assert_eq!(executor.ab_slot, AbSlot::B);
+        let b = EqProfile {
+            preamp_db: -3.0,
+            ..Default::default()
+        };
+        let _ = executor.handle(Command::SwapAB(b.clone()), &mut host);
+        assert_eq!(applied.try_recv().unwrap(), a);
+        assert_eq!(executor.ab_slot, AbSlot::A);
+        let _ = executor.handle(Command::SwapAB(a.clone()), &mut host);
+        assert_eq!(applied.try_recv().unwrap(), b);
+        assert_eq!(executor.config.eq_profile, b);
+        assert_eq!(executor.ab_other, a);
+        // Pushed to the running engine, not restarted
+        assert!(executor.main().engine_key.is_none());
+    }
+
+    #[test]
+    fn test_external_profile_reaches_gui() {
+        let (sender, receiver) = sync_channel(8);
+        let settings = Settings::from(&PersistentSettings::default());
+        let status = settings.status.clone();
+        let mut executor = Executor::new(receiver, sender, Config::default(), settings);
+        let mut host = cpal::default_host();
+        let (profile_sender, applied) = channel();
+        executor.main_mut().profile_sender = Some(profile_sender);
+
+        let profile = EqProfile {
+            preamp_db: -4.0,
+            ..Default::default()
+        };
+        let command = Command::UpdateInstanceProfile(MAIN_INSTANCE.to_string(), profile.clone());
+        let _ = executor.handle(command, &mut host);
+        assert_eq!(applied.try_recv().unwrap(), profile);
+        assert_eq!(executor.config.eq_profile, profile);
+        assert_eq!(status.take_profile(), Some(profile));
+        assert_eq!(status.take_profile(), None);
+        // Profiles from the GUI aren't sent back to it
+        let _ = executor.handle(Command::UpdateProfile(EqProfile::default()), &mut host);
+        assert_eq!(status.take_profile(), None);
+    }
+
+    #[test]
+    fn test_changed_profiles() {
+        let louder = EqProfile {
+            preamp_db: 3.0,
+            ..Default::default()
+        };
+        let mut config = Config {
+            instances: [("headphones".to_string(), InstanceConfig::default())].into(),
+            ..Default::default()
+        };
+        let mut last: BTreeMap<_, _> = [(MAIN_INSTANCE.to_string(), EqProfile::default())].into();
+        assert!(changed_profiles(&mut last, &config).is_empty());
+        config.eq_profile = louder.clone();
+        config.instances.get_mut("headphones").unwrap().eq_profile = louder.clone();
+        // Only the pairs watched so far
+        assert_eq!(
+            changed_profiles(&mut last, &config),
+            [(MAIN_INSTANCE.to_string(), louder.clone())]
+        );
+        assert!(changed_profiles(&mut last, &config).is_empty());
+        last.insert("headphones".to_string(), EqProfile::default());
+        assert_eq!(
+            changed_profiles(&mut last, &config),
+            [("headphones".to_string(), louder)]
+        );
+    }
+
+    #[test]
+    fn test_control_values_checked() {
+        let ranges = RangeConfig::default().sanitized();
+        assert_eq!(clamp_finite(200.0, ranges.preamp()), Some(12.0));
+        assert_eq!(clamp_finite(f64::NAN, ranges.preamp()), None);
+        assert_eq!(clamp_finite(f64::INFINITY, ranges.preamp()), None);
+        assert_eq!(BandParam::Gain.clamp(-30.0, &ranges), Some(-12.0));
+        assert_eq!(BandParam::Frequency.clamp(5.0, &ranges), Some(20.0));
+        assert_eq!(BandParam::Q.clamp(f64::NAN, &ranges), None);
+
+        let mut profile =
+            EqProfile::parse("Preamp: 40 dB\nFilter 1: ON PK Fc 1000 Hz Gain 50 dB Q 100").unwrap();
+        let checked = checked_profile(profile.clone(), &ranges).unwrap();
+        assert_eq!(checked.preamp_db, 12.0);
+        assert_eq!(checked.filters[0].gain, 12.0);
+        assert_eq!(checked.filters[0].q_factor, 10.0);
+        profile.filters[0].frequency = f64::NAN;
+        assert!(checked_profile(profile, &ranges).is_none());
+    }
+
+    #[test]
+    fn test_reconnect_delay() {
+        assert_eq!(reconnect_delay(0), Duration::from_millis(500));
+        assert_eq!(reconnect_delay(1), Duration::from_secs(1));
+        assert_eq!(reconnect_delay(4), Duration::from_secs(8));
+        assert_eq!(reconnect_delay(5), Duration::from_secs(10));
+        assert_eq!(reconnect_delay(100), Duration::from_secs(10));
     }
 }
@@ -0,0 +1,269 @@
+//! Linear-phase EQ: the profile's magnitude response as an FIR kernel (see
+//! [`ParametricEq::to_fir`]), applied by FFT convolution with overlap-add.
+
+use std::{collections::VecDeque, sync::Arc};
+
+use rustfft::{Fft, FftPlanner, num_complex::Complex};
+
+use crate::eq::{EqProfile, ParametricEq};
+
+/// Kernel length. At 48 kHz this resolves the response down to about 12 Hz.
+pub const LINEAR_PHASE_TAPS: usize = 4095;
+
+/// Samples the linear-phase EQ delays the signal by: a block of input is
+/// gathered before it is convolved, and the kernel is centred on half its
+/// length. With [`LINEAR_PHASE_TAPS`] this is 6143 samples, 128 ms at 48 kHz.
+pub fn latency_frames(taps: usize) -> usize {
+    block_size(taps) + (taps | 1) / 2
+}
+
+fn block_size(taps: usize) -> usize {
+    (taps | 1).next_power_of_two()
+}
+
+struct ChannelState {
+    // input gathered towards the next block
+    input: Vec<f32>,
+    // tail of the last block's result, added to the next one
+    overlap: Vec<f32>,
+    // convolved samples ready to go out
+    output: VecDeque<f32>,
+    // the input, delayed as much as the convolved signal
+    dry: VecDeque<f32>,
+}
+
+/// Convolves interleaved audio with an FIR kernel, each channel on its own.
+/// Buffers are allocated up front, so `process` doesn't allocate.
+pub struct Convolver {
+    block: usize,
+    kernel: Vec<Complex<f32>>,
+    fft: Arc<dyn Fft<f32>>,
+    ifft: Arc<dyn Fft<f32>>,
+    buffer: Vec<Complex<f32>>,
+    scratch: Vec<Complex<f32>>,
+    channels: Vec<ChannelState>,
+}
+
+impl Convolver {
+    pub fn new(kernel: &[f32], channels: usize) -> Self {
+        let block = block_size(kernel.len());
+        let latency = latency_frames(kernel.len());
+        let mut planner = FftPlanner::new();
+        let fft = planner.plan_fft_forward(block * 2);
+        let ifft = planner.plan_fft_inverse(block * 2);
+        let scratch_len = fft
+            .get_inplace_scratch_len()
+            .max(ifft.get_inplace_scratch_len());
+        let mut convolver = Self {
+            block,
+            kernel: Vec::new(),
+            fft,
+            ifft,
+            buffer: vec![Complex::default(); block * 2],
+            scratch: vec![Complex::default(); scratch_len],
+            channels: (0..channels.max(1))
+                .map(|_| ChannelState {
+                    input: Vec::with_capacity(block),
+                    overlap: vec![0.0; block],
+                    // a block of silence covers the first one being gathered
+                    output: VecDeque::from(vec![0.0; block]),
+                    dry: VecDeque::from(vec![0.0; latency]),
+                })
+                .collect(),
+        };
+        convolver.set_kernel(kernel);
+        convolver
+    }
+
+    /// Linear-phase version of a profile, at `sample_rate`.
+    pub fn from_profile(profile: &EqProfile, sample_rate: f32, channels: usize) -> Self {
+        let kernel = ParametricEq::from_profile(profile, sample_rate, 1).to_fir(LINEAR_PHASE_TAPS);
+        Self::new(&kernel, channels)
+    }
+
+    /// Swaps in a kernel of the same length, keeping the signal in flight.
+    pub fn set_kernel(&mut self, kernel: &[f32]) {
+        let mut spectrum: Vec<Complex<f32>> = kernel
+            .iter()
+            .map(|s| Complex::new(*s, 0.0))
+            .chain(std::iter::repeat(Complex::default()))
+            .take(self.block * 2)
+            .collect();
+        self.fft.process(&mut spectrum);
+        // folds in the inverse FFT's scaling
+        let scale = 1.0 / (self.block * 2) as f32;
+        self.kernel = spectrum.into_iter().map(|c| c * scale).collect();
+    }
+
+    /// Swaps kernels with another convolver built for the same length, so
+    /// the old one is freed along with `other`.
+    pub fn swap_kernel(&mut self, other: &mut Convolver) {
+        if other.block == self.block {
+            std::mem::swap(&mut self.kernel, &mut other.kernel);
+        }
+    }
+
+    /// Processes interleaved samples in place.
+    pub fn process_buffer(&mut self, data: &mut [f32]) {
+        self.process(data, None);
+    }
+
+    /// Passes the input through at `gain`, delayed like the convolved
+    /// signal, while still convolving it: switching between the two is
+    /// seamless both ways.
+    pub fn process_bypassed(&mut self, data: &mut [f32], gain: f32) {
+        self.process(data, Some(gain));
+    }
+
+    fn process(&mut self, data: &mut [f32], dry_gain: Option<f32>) {
+        let channels = self.channels.len();
+        for frame in data.chunks_exact_mut(channels) {
+            for (index, sample) in frame.iter_mut().enumerate() {
+                let state = &mut self.channels[index];
+                state.input.push(*sample);
+                state.dry.push_back(*sample);
+                let wet = state.output.pop_front().unwrap_or(0.0);
+                let dry = state.dry.pop_front().unwrap_or(0.0);
+                *sample = dry_gain.map_or(wet, |gain| dry * gain);
+                if state.input.len() == self.block {
+                    self.convolve_block(index);
+                }
+            }
+        }
+    }
+
+    fn convolve_block(&mut self, index: usize) {
+        let block = self.block;
+        let state = &mut self.channels[index];
+        for (slot, sample) in self.buffer.iter_mut().zip(
+            state
+                .input
+                .drain(..)
+                .chain(std::iter::repeat(0.0))
+                .take(block * 2),
+        ) {
+            *slot = Complex::new(sample, 0.0);
+        }
+        self.fft
+            .process_with_scratch(&mut self.buffer, &mut self.scratch);
+        for (bin, kernel) in self.buffer.iter_mut().zip(self.kernel.iter()) {
+            *bin *= kernel;
+        }
+        self.ifft
+            .process_with_scratch(&mut self.buffer, &mut self.scratch);
+        let (head, tail) = self.buffer.split_at(block);
+        for (value, overlap) in head.iter().zip(state.overlap.iter()) {
+            state.output.push_back(value.re + overlap);
+        }
+        for (overlap, value) in state.overlap.iter_mut().zip(tail.iter()) {
+            *overlap = value.re;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eq::{Filter, FilterType};
+
+    fn profile() -> EqProfile {
+        let band = |filter_type, frequency, gain| Filter {
+            filter_type,
+            frequency,
+            gain,
+            q_factor: 1.0,
+            ..Default::default()
+        };
+        EqProfile {
+            preamp_db: -3.0,
+            filters: vec![
+                band(FilterType::LowShelf, 100.0, 4.0),
+                band(FilterType::Peaking, 1000.0, 6.0),
+                band(FilterType::Peaking, 6000.0, -5.0),
+            ],
+            ..Default::default()
+        }
+    }
+
+    /// Gain of `kernel` at `freq`, ignoring its phase.
+    fn fir_gain_db(kernel: &[f32], freq: f64, sample_rate: f64) -> f64 {
+        let w = std::f64::consts::TAU * freq / sample_rate;
+        let sum: Complex<f64> = kernel
+            .iter()
+            .enumerate()
+            .map(|(n, s)| Complex::from_polar(*s as f64, -w * n as f64))
+            .sum();
+        20.0 * sum.norm().log10()
+    }
+
+    #[test]
+    fn test_fir_matches_iir_magnitude() {
+        let eq = ParametricEq::from_profile(&profile(), 48000.0, 1);
+        let kernel = eq.to_fir(LINEAR_PHASE_TAPS);
+        assert_eq!(kernel.len(), LINEAR_PHASE_TAPS);
+        for freq in [50.0, 100.0, 300.0, 1000.0, 3000.0, 6000.0, 15000.0] {
+            let iir = 20.0 * eq.magnitude(std::f64::consts::TAU * freq / 48000.0).log10();
+            let fir = fir_gain_db(&kernel, freq, 48000.0);
+            assert!(
+                (fir - iir).abs() < 0.25,
+                "{} Hz: FIR {} dB, IIR {} dB",
+                freq,
+                fir,
+                iir
+            );
+        }
+        // Linear phase: the kernel is symmetric
+        let half = kernel.len() / 2;
+        for i in 0..half {
+            assert!((kernel[i] - kernel[kernel.len() - 1 - i]).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn test_convolver_delays_by_latency() {
+        let kernel = [0.0, 0.0, 0.5, 0.0, 0.0];
+        let mut convolver = Convolver::new(&kernel, 2);
+        let latency = latency_frames(kernel.len());
+        let mut data = vec![0.0; (latency + 8) * 2];
+        data[0] = 1.0;
+        data[3] = -1.0;
+        // Odd-sized callbacks, like a real device may give
+        for chunk in data.chunks_mut(6) {
+            convolver.process_buffer(chunk);
+        }
+        for (i, sample) in data.iter().enumerate() {
+            let expected = match i {
+                i if i == latency * 2 => 0.5,
+                i if i == (latency + 1) * 2 + 1 => -0.5,
+                _ => 0.0,
+            };
+            assert!((sample - expected).abs() < 1e-6, "sample {}: {}", i, sample);
+        }
+    }
+
+    #[test]
+    fn test_bypass_keeps_convolving() {
+        let kernel = [0.0, 0.0, 0.5, 0.0, 0.0];
+        let mut convolver = Convolver::new(&kernel, 2);
+        let latency = latency_frames(kernel.len());
+        let input: Vec<f32> = (0..(latency * 4) * 2).map(|i| (i / 2 + 1) as f32).collect();
+        let mut data = input.clone();
+        // Bypassed, then on, then bypassed again
+        for (n, chunk) in data.chunks_mut(latency * 2).enumerate() {
+            if n.is_multiple_of(3) {
+                convolver.process_bypassed(chunk, 2.0);
+            } else {
+                convolver.process_buffer(chunk);
+            }
+        }
+        for (i, sample) in data.iter().enumerate().skip(latency * 2) {
+            let gain = if (i / (latency * 2)).is_multiple_of(3) {
+                2.0
+            } else {
+                0.5
+            };
+            let expected = input[i - latency * 2] * gain;
+            assert!((sample - expected).abs() < 1e-2, "sample {}: {}", i, sample);
+        }
+    }
+}
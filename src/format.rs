@@ -0,0 +1,218 @@
+//! Device sample formats. Streams are opened in a format the device offers
+//! natively and converted to and from the f32 the processing chain runs on.
+
+use cpal::{
+    BuildStreamError, Device, FromSample, I24, InputCallbackInfo, OutputCallbackInfo, Sample,
+    SampleFormat, SizedSample, Stream, StreamConfig, StreamError, SupportedStreamConfigRange,
+    traits::DeviceTrait,
+};
+
+/// Formats streams are opened in, most precise first.
+const FORMATS: [SampleFormat; 5] = [
+    SampleFormat::F32,
+    SampleFormat::I32,
+    SampleFormat::I24,
+    SampleFormat::I16,
+    SampleFormat::U16,
+];
+
+/// The best of [`FORMATS`] that `configs` offer at `config`'s rate, f32 when
+/// none of them is listed. The channel count isn't compared, since devices
+/// often list their formats only for other channel counts than the stream's.
+pub fn choose_format(
+    configs: &[SupportedStreamConfigRange],
+    config: &StreamConfig,
+) -> SampleFormat {
+    FORMATS
+        .into_iter()
+        .find(|format| {
+            configs.iter().any(|range| {
+                range.sample_format() == *format
+                    && (range.min_sample_rate()..=range.max_sample_rate())
+                        .contains(&config.sample_rate)
+            })
+        })
+        .unwrap_or(SampleFormat::F32)
+}
+
+/// Converts device samples into `out`, reusing its allocation.
+pub fn to_f32<T: Sample>(data: &[T], out: &mut Vec<f32>)
+where
+    f32: FromSample<T>,
+{
+    out.clear();
+    out.extend(data.iter().map(|sample| sample.to_sample::<f32>()));
+}
+
+/// Converts f32 samples into the device's buffer; both are the same length.
+pub fn from_f32<T: Sample + FromSample<f32>>(data: &[f32], out: &mut [T]) {
+    for (out, sample) in out.iter_mut().zip(data) {
+        *out = sample.to_sample::<T>();
+    }
+}
+
+/// Opens an input stream in `format` whose callback sees f32 samples.
+pub fn build_input_stream<D, E>(
+    device: &Device,
+    config: &StreamConfig,
+    format: SampleFormat,
+    callback: D,
+    error_callback: E,
+) -> Result<Stream, BuildStreamError>
+where
+    D: FnMut(&[f32], &InputCallbackInfo) + Send + 'static,
+    E: FnMut(StreamError) + Send + 'static,
+{
+    match format {
+        SampleFormat::I32 => converted_input::<i32, _, _>(device, config, callback, error_callback),
+        SampleFormat::I24 => converted_input::<I24, _, _>(device, config, callback, error_callback),
+        SampleFormat::I16 => converted_input::<i16, _, _>(device, config, callback, error_callback),
+        SampleFormat::U16 => converted_input::<u16, _, _>(device, config, callback, error_callback),
+        _ => device.build_input_stream(config, callback, error_callback, None),
+    }
+}
+
+/// Opens an output stream in `format` whose callback fills f32 samples.
+pub fn build_output_stream<D, E>(
+    device: &Device,
+    config: &StreamConfig,
+    format: SampleFormat,
+    callback: D,
+    error_callback: E,
+) -> Result<Stream, BuildStreamError>
+where
+    D: FnMut(&mut [f32], &OutputCallbackInfo) + Send + 'static,
+    E: FnMut(StreamError) + Send + 'static,
+{
+    match format {
+        SampleFormat::I32 => {
+            converted_output::<i32, _, _>(device, config, callback, error_callback)
+        }
+        SampleFormat::I24 => {
+            converted_output::<I24, _, _>(device, config, callback, error_callback)
+        }
+        SampleFormat::I16 => {
+            converted_output::<i16, _, _>(device, config, callback, error_callback)
+        }
+        SampleFormat::U16 => {
+            converted_output::<u16, _, _>(device, config, callback, error_callback)
+        }
+        _ => device.build_output_stream(config, callback, error_callback, None),
+    }
+}
+
+fn converted_input<T, D, E>(
+    device: &Device,
+    config: &StreamConfig,
+    mut callback: D,
+    error_callback: E,
+) -> Result<Stream, BuildStreamError>
+where
+    T: SizedSample,
+    f32: FromSample<T>,
+    D: FnMut(&[f32], &InputCallbackInfo) + Send + 'static,
+    E: FnMut(StreamError) + Send + 'static,
+{
+    // grows to the largest buffer in the first callbacks, then stays
+    let mut buffer = Vec::new();
+    device.build_input_stream(
+        config,
+        move |data: &[T], info: &InputCallbackInfo| {
+            to_f32(data, &mut buffer);
+            callback(&buffer, info);
+        },
+        error_callback,
+        None,
+    )
+}
+
+fn converted_output<T, D, E>(
+    device: &Device,
+    config: &StreamConfig,
+    mut callback: D,
+    error_callback: E,
+) -> Result<Stream, BuildStreamError>
+where
+    T: SizedSample + FromSample<f32>,
+    D: FnMut(&mut [f32], &OutputCallbackInfo) + Send + 'static,
+    E: FnMut(StreamError) + Send + 'static,
+{
+    let mut buffer = Vec::new();
+    device.build_output_stream(
+        config,
+        move |data: &mut [T], info: &OutputCallbackInfo| {
+            buffer.resize(data.len(), 0.0);
+            callback(&mut buffer, info);
+            from_f32(&buffer, data);
+        },
+        error_callback,
+        None,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use cpal::SupportedBufferSize;
+
+    use super::*;
+
+    #[test]
+    fn test_i16_round_trip() {
+        let samples: Vec<i16> = (i16::MIN..=i16::MAX).collect();
+        let mut floats = Vec::new();
+        to_f32(&samples, &mut floats);
+        assert_eq!(floats[0], -1.0);
+        assert_eq!(floats[samples.len() / 2], 0.0);
+        assert!(floats.iter().all(|s| (-1.0..1.0).contains(s)));
+        let mut back = vec![0i16; samples.len()];
+        from_f32(&floats, &mut back);
+        assert_eq!(back, samples);
+
+        // Overs clip instead of wrapping around
+        let mut clipped = [0i16; 2];
+        from_f32(&[1.5, -1.5], &mut clipped);
+        assert_eq!(clipped, [i16::MAX, i16::MIN]);
+    }
+
+    #[test]
+    fn test_choose_format() {
+        let ranges = |channels, format| {
+            SupportedStreamConfigRange::new(
+                channels,
+                44100,
+                48000,
+                SupportedBufferSize::Unknown,
+                format,
+            )
+        };
+        let range = |format| ranges(2, format);
+        let config = StreamConfig {
+            channels: 2,
+            sample_rate: 48000,
+            buffer_size: cpal::BufferSize::Default,
+        };
+        let configs = [
+            range(SampleFormat::I16),
+            range(SampleFormat::I24),
+            range(SampleFormat::I32),
+        ];
+        assert_eq!(choose_format(&configs, &config), SampleFormat::I32);
+        assert_eq!(choose_format(&configs[..2], &config), SampleFormat::I24);
+        assert_eq!(choose_format(&configs[..1], &config), SampleFormat::I16);
+        // A device listing another channel count still gets its own format
+        assert_eq!(
+            choose_format(&[ranges(8, SampleFormat::I24)], &config),
+            SampleFormat::I24
+        );
+        // Nothing we convert, or nothing at this rate: leave it to the host
+        assert_eq!(
+            choose_format(&[range(SampleFormat::U8)], &config),
+            SampleFormat::F32
+        );
+        let config = StreamConfig {
+            sample_rate: 96000,
+            ..config
+        };
+        assert_eq!(choose_format(&configs, &config), SampleFormat::F32);
+    }
+}
@@ -0,0 +1,152 @@
+//! Equalizer APO `GraphicEQ:` lines: a gain per frequency, for players that
+//! take no parametric bands.
+
+use std::str::FromStr;
+
+use anyhow::{Context, bail};
+
+use crate::{eq::EqProfile, response::log_freq};
+
+/// The ISO 1/3-octave centres from 20 Hz to 20 kHz.
+pub const STANDARD_FREQS: [f64; 31] = [
+    20.0, 25.0, 31.5, 40.0, 50.0, 63.0, 80.0, 100.0, 125.0, 160.0, 200.0, 250.0, 315.0, 400.0,
+    500.0, 630.0, 800.0, 1000.0, 1250.0, 1600.0, 2000.0, 2500.0, 3150.0, 4000.0, 5000.0, 6300.0,
+    8000.0, 10000.0, 12500.0, 16000.0, 20000.0,
+];
+
+/// Most peaking filters an imported curve is fitted with.
+const IMPORT_BANDS: usize = 20;
+
+const PREFIX: &str = "GRAPHICEQ:";
+
+/// (frequency, dB) points, sorted by frequency.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct GraphicEq {
+    pub points: Vec<(f64, f64)>,
+}
+
+impl GraphicEq {
+    /// `count` frequencies spaced evenly on a log scale from 20 Hz to 20 kHz.
+    pub fn log_freqs(count: usize) -> Vec<f64> {
+        let steps = count.saturating_sub(1).max(1);
+        (0..count)
+            .map(|i| (log_freq(i as f64 / steps as f64) * 10.0).round() / 10.0)
+            .collect()
+    }
+
+    /// Samples the response of `profile`, preamp included, at `freqs`.
+    /// Frequencies at or above Nyquist are left out.
+    pub fn from_profile(profile: &EqProfile, freqs: &[f64], fs: f64) -> Self {
+        let freqs: Vec<f64> = freqs
+            .iter()
+            .copied()
+            .filter(|freq| *freq < fs / 2.0)
+            .collect();
+        let gains = profile.magnitude_response(&freqs, fs);
+        let points = freqs
+            .into_iter()
+            .zip(gains)
+            .map(|(freq, db)| (freq, ((db + profile.preamp_db) * 100.0).round() / 100.0))
+            .collect();
+        Self { points }
+    }
+
+    /// Approximates the curve with peaking filters. The median gain becomes
+    /// the preamp, so a curve shifted as a whole doesn't spend bands on it.
+    pub fn to_profile(&self, fs: f64) -> EqProfile {
+        let mut gains: Vec<f64> = self.points.iter().map(|(_, db)| *db).collect();
+        gains.sort_by(f64::total_cmp);
+        let preamp_db = gains.get(gains.len() / 2).copied().unwrap_or_default();
+        let points: Vec<(f64, f64)> = self
+            .points
+            .iter()
+            .map(|(freq, db)| (*freq, db - preamp_db))
+            .collect();
+        let mut profile = EqProfile::fit_to_target(&points, IMPORT_BANDS, fs);
+        profile.preamp_db = preamp_db;
+        profile
+    }
+}
+
+/// A `GraphicEQ: f1 g1; f2 g2; ...` line. Other lines, e.g. a `Preamp`
+/// exported next to it, are skipped.
+impl FromStr for GraphicEq {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some(values) = s.lines().map(str::trim).find_map(|line| {
+            line.get(..PREFIX.len())
+                .filter(|prefix| prefix.eq_ignore_ascii_case(PREFIX))
+                .map(|_| &line[PREFIX.len()..])
+        }) else {
+            bail!("No GraphicEQ line");
+        };
+        let mut points = Vec::new();
+        for pair in values.split(';').map(str::trim).filter(|p| !p.is_empty()) {
+            let mut fields = pair.split_whitespace().map(str::parse::<f64>);
+            match (fields.next(), fields.next(), fields.next()) {
+                (Some(freq), Some(db), None) => points.push((
+                    freq.with_context(|| format!("Bad frequency in \"{}\"", pair))?,
+                    db.with_context(|| format!("Bad gain in \"{}\"", pair))?,
+                )),
+                _ => bail!("Expected a frequency and a gain: \"{}\"", pair),
+            }
+        }
+        if points.is_empty() {
+            bail!("No points in the GraphicEQ line");
+        }
+        points.sort_by(|a, b| a.0.total_cmp(&b.0));
+        Ok(Self { points })
+    }
+}
+
+impl std::fmt::Display for GraphicEq {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let points: Vec<String> = self
+            .points
+            .iter()
+            .map(|(freq, db)| format!("{} {}", freq, db))
+            .collect();
+        writeln!(f, "GraphicEQ: {}", points.join("; "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        // A bass shelf and a presence dip, 2 dB down overall
+        let line = "GraphicEQ: 20 3.5; 25 3.5; 31.5 3.4; 40 3.3; 50 3; 63 2.4; 80 1.5; \
+                    100 0.6; 125 -0.2; 160 -1; 200 -1.5; 250 -1.8; 315 -2; 400 -2; 500 -2; \
+                    630 -2; 800 -2; 1000 -2; 1250 -2; 1600 -2.2; 2000 -2.6; 2500 -3.3; \
+                    3150 -4; 4000 -4; 5000 -3.3; 6300 -2.6; 8000 -2.2; 10000 -2; 12500 -2; \
+                    16000 -2; 20000 -2";
+        let graphic: GraphicEq = line.parse().unwrap();
+        assert_eq!(graphic.points.len(), STANDARD_FREQS.len());
+        assert_eq!(graphic.to_string().parse::<GraphicEq>().unwrap(), graphic);
+
+        let profile = graphic.to_profile(48000.0);
+        assert_eq!(profile.preamp_db, -2.0);
+        let again = GraphicEq::from_profile(&profile, &STANDARD_FREQS, 48000.0);
+        let freqs: Vec<f64> = again.points.iter().map(|(freq, _)| *freq).collect();
+        assert_eq!(freqs, STANDARD_FREQS);
+        for ((freq, want), (_, got)) in graphic.points.iter().zip(&again.points) {
+            assert!((want - got).abs() < 1.0, "{} Hz: {} vs {}", freq, want, got);
+        }
+    }
+
+    #[test]
+    fn test_sampling_includes_preamp() {
+        let profile =
+            EqProfile::parse("Preamp: -3 dB\nFilter 1: ON PK Fc 1000 Hz Gain 6 dB Q 1").unwrap();
+        let graphic = GraphicEq::from_profile(&profile, &GraphicEq::log_freqs(4), 32000.0);
+        // 20 kHz is above Nyquist
+        assert_eq!(graphic.points.len(), 3);
+        assert_eq!(graphic.points[0], (20.0, -3.0));
+        assert!(graphic.to_string().starts_with("GraphicEQ: 20 -3; 200 "));
+        assert!("Preamp: -1 dB".parse::<GraphicEq>().is_err());
+        assert!("GraphicEQ: 20 1; 40".parse::<GraphicEq>().is_err());
+    }
+}
@@ -0,0 +1,327 @@
+//! REST API for scripts and web front ends, translated into the commands
+//! the GUI sends:
+//! - `GET /state`: whether the EQ is enabled and running, and the profile
+//! - `POST /profile`: runs the Equalizer APO or JSON profile in the body
+//! - `POST /enable`: `true`/`false`, `1`/`0` or `on`/`off` in the body
+//! - `GET /response.csv`: the profile's response, `?fs=` and `?points=`
+//!   as in `response`
+//!
+//! Requests from web pages are refused: ones with an `Origin` header, and
+//! ones addressed to another host than the one listened on, as a page that
+//! rebinds its own name to 127.0.0.1 would send.
+
+use std::{io::Read, net::SocketAddr, sync::mpsc::SyncSender};
+
+use anyhow::{Context, Result, anyhow, bail};
+use serde_json::json;
+use tiny_http::{Header, Method, Request, Response, Server};
+use tracing::{info, warn};
+
+use crate::{
+    cli::response::{ResponseFormat, render, response_points},
+    command::{Command, State},
+    config::HttpConfig,
+    eq::EqProfile,
+    utils::OneShot,
+};
+
+const DEFAULT_FS: f64 = 48000.0;
+const DEFAULT_POINTS: usize = 256;
+/// Keeps `points` from turning a request into minutes of work.
+const MAX_POINTS: usize = 10000;
+/// Largest request body read; profiles are far smaller.
+const MAX_BODY: u64 = 256 * 1024;
+
+/// A reply's status code, content type and body.
+type Reply = (u16, &'static str, String);
+
+fn ask<T: Clone>(
+    sender: &SyncSender<Command>,
+    command: impl FnOnce(OneShot<T>) -> Command,
+) -> Result<T> {
+    let oneshot = OneShot::new();
+    sender
+        .send(command(oneshot.clone()))
+        .map_err(|_| anyhow!("The engine has shut down"))?;
+    Ok(oneshot.recv())
+}
+
+fn parse_enable(body: &str) -> Result<bool> {
+    match body.trim().to_lowercase().as_str() {
+        "true" | "1" | "on" => Ok(true),
+        "false" | "0" | "off" => Ok(false),
+        other => bail!("Expected true or false, got \"{}\"", other),
+    }
+}
+
+/// `fs` and `points` from a query string like `fs=44100&points=64`.
+fn response_query(query: &str) -> Result<(f64, usize)> {
+    let (mut fs, mut points) = (DEFAULT_FS, DEFAULT_POINTS);
+    for pair in query.split('&').filter(|p| !p.is_empty()) {
+        match pair.split_once('=') {
+            Some(("fs", value)) => fs = value.parse().context("Invalid fs")?,
+            Some(("points", value)) => points = value.parse().context("Invalid points")?,
+            _ => bail!("Unknown parameter \"{}\"", pair),
+        }
+    }
+    if fs.is_nan() || fs <= 0.0 || !(1..=MAX_POINTS).contains(&points) {
+        bail!("fs must be positive and points within 1 - {}", MAX_POINTS);
+    }
+    Ok((fs, points))
+}
+
+/// Whether a `Host` header names the address the server listens on: its IP
+/// and port, or `localhost` with the port when listening on loopback. On an
+/// unspecified address any IP with the port does. Names other than
+/// `localhost` are refused, as they could be rebound.
+fn host_allowed(host: &str, bound: SocketAddr) -> bool {
+    if let Ok(address) = host.parse::<SocketAddr>() {
+        return address.port() == bound.port()
+            && (address.ip() == bound.ip() || bound.ip().is_unspecified());
+    }
+    match host.rsplit_once(':') {
+        Some((name, port)) => {
+            name.eq_ignore_ascii_case("localhost")
+                && port.parse() == Ok(bound.port())
+                && (bound.ip().is_loopback() || bound.ip().is_unspecified())
+        }
+        None => false,
+    }
+}
+
+fn header<'a>(request: &'a Request, name: &'static str) -> Option<&'a str> {
+    request
+        .headers()
+        .iter()
+        .find(|h| h.field.equiv(name))
+        .map(|h| h.value.as_str())
+}
+
+/// Why a request is refused before it is routed, if it is.
+fn refusal(request: &Request, bound: SocketAddr) -> Option<Reply> {
+    if header(request, "Origin").is_some() {
+        return Some((
+            403,
+            "text/plain",
+            "Cross-origin requests are refused".to_string(),
+        ));
+    }
+    if !header(request, "Host").is_some_and(|host| host_allowed(host, bound)) {
+        return Some((403, "text/plain", "Unexpected Host".to_string()));
+    }
+    None
+}
+
+fn route(method: &Method, url: &str, body: &str, sender: &SyncSender<Command>) -> Result<Reply> {
+    let (path, query) = url.split_once('?').unwrap_or((url, ""));
+    Ok(match (method, path) {
+        (Method::Get, "/state") => {
+            let state: State = ask(sender, Command::GetState)?;
+            let profile: EqProfile = ask(sender, Command::GetProfile)?;
+            let body = json!({
+                "enabled": state.enabled,
+                "running": state.running,
+                "profile": profile,
+            });
+            (200, "application/json", body.to_string())
+        }
+        (Method::Post, "/profile") => match EqProfile::parse(body) {
+            Ok(profile) => {
+                sender.send(Command::SetProfile(profile)).ok();
+                (204, "text/plain", String::new())
+            }
+            Err(e) => (400, "text/plain", format!("{:#}", e)),
+        },
+        (Method::Post, "/enable") => match parse_enable(body) {
+            Ok(enabled) => {
+                sender.send(Command::SetEnabled(enabled)).ok();
+                (204, "text/plain", String::new())
+            }
+            Err(e) => (400, "text/plain", format!("{:#}", e)),
+        },
+        (Method::Get, "/response.csv") => match response_query(query) {
+            Ok((fs, points)) => {
+                let profile: EqProfile = ask(sender, Command::GetProfile)?;
+                let points = response_points(&profile, fs, points);
+                (200, "text/csv", render(&points, fs, ResponseFormat::Csv))
+            }
+            Err(e) => (400, "text/plain", format!("{:#}", e)),
+        },
+        (_, "/state" | "/profile" | "/enable" | "/response.csv") => {
+            (405, "text/plain", "Method not allowed".to_string())
+        }
+        _ => (404, "text/plain", "Not found".to_string()),
+    })
+}
+
+fn respond(mut request: Request, sender: &SyncSender<Command>, bound: SocketAddr) {
+    let mut body = String::new();
+    let reply = match refusal(&request, bound) {
+        Some(reply) => Ok(reply),
+        None => match request
+            .as_reader()
+            .take(MAX_BODY + 1)
+            .read_to_string(&mut body)
+        {
+            Ok(read) if read as u64 > MAX_BODY => Ok((
+                413,
+                "text/plain",
+                format!("Body is over {} bytes", MAX_BODY),
+            )),
+            Ok(_) => route(request.method(), request.url(), &body, sender),
+            Err(e) => Ok((400, "text/plain", format!("Unreadable body: {}", e))),
+        },
+    };
+    let (status, content_type, body) =
+        reply.unwrap_or_else(|e| (503, "text/plain", format!("{:#}", e)));
+    let header = Header::from_bytes("Content-Type", content_type).expect("valid header");
+    let response = Response::from_string(body)
+        .with_status_code(status)
+        .with_header(header);
+    if let Err(e) = request.respond(response) {
+        warn!("Failed to reply to HTTP client: {}", e);
+    }
+}
+
+/// Serves requests on a thread of its own, forwarding them to `sender`.
+/// Returns the address bound, which has the actual port when 0 is asked for.
+pub fn serve(config: &HttpConfig, sender: SyncSender<Command>) -> Result<SocketAddr> {
+    let address = SocketAddr::new(config.address, config.port);
+    let server =
+        Server::http(address).map_err(|e| anyhow!("Failed to listen on {}: {}", address, e))?;
+    let address = server.server_addr().to_ip().unwrap_or(address);
+    info!("HTTP server listening on {}", address);
+    std::thread::spawn(move || {
+        for request in server.incoming_requests() {
+            respond(request, &sender, address);
+        }
+    });
+    Ok(address)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Read, Write},
+        net::{IpAddr, Ipv4Addr, TcpStream},
+        sync::mpsc::sync_channel,
+    };
+
+    use super::*;
+
+    /// Sends a request and returns the status line and the body.
+    fn request(address: SocketAddr, method: &str, path: &str, body: &str) -> (String, String) {
+        let host = format!("Host: {}", address);
+        request_with(address, method, path, &[&host], body)
+    }
+
+    fn request_with(
+        address: SocketAddr,
+        method: &str,
+        path: &str,
+        headers: &[&str],
+        body: &str,
+    ) -> (String, String) {
+        let mut stream = TcpStream::connect(address).unwrap();
+        write!(
+            stream,
+            "{} {} HTTP/1.1\r\n{}\r\nConnection: close\r\nContent-Length: {}\r\n\r\n{}",
+            method,
+            path,
+            headers.join("\r\n"),
+            body.len(),
+            body
+        )
+        .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        (head.lines().next().unwrap().to_string(), body.to_string())
+    }
+
+    #[test]
+    fn test_state_and_profile() {
+        let (sender, receiver) = sync_channel(16);
+        // Stands in for the executor, keeping the profile and the switch
+        std::thread::spawn(move || {
+            let mut profile = EqProfile::default();
+            let mut enabled = true;
+            for command in receiver {
+                match command {
+                    Command::GetState(oneshot) => oneshot.send(State {
+                        enabled,
+                        ..Default::default()
+                    }),
+                    Command::GetProfile(oneshot) => oneshot.send(profile.clone()),
+                    Command::SetProfile(new_profile) => profile = new_profile,
+                    Command::SetEnabled(new_enabled) => enabled = new_enabled,
+                    _ => {}
+                }
+            }
+        });
+        let config = HttpConfig {
+            enabled: true,
+            address: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            port: 0,
+        };
+        let address = serve(&config, sender).unwrap();
+
+        let (status, body) = request(address, "GET", "/state", "");
+        assert_eq!(status, "HTTP/1.1 200 OK");
+        let state: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(state["enabled"], true);
+        assert_eq!(state["profile"]["preamp_db"], 0.0);
+
+        let apo = "Preamp: -2 dB\nFilter 1: ON PK Fc 1000 Hz Gain 4 dB Q 1";
+        let (status, _) = request(address, "POST", "/profile", apo);
+        assert!(status.starts_with("HTTP/1.1 204"), "{}", status);
+        let (status, body) = request(address, "POST", "/profile", "{ not json");
+        assert!(status.starts_with("HTTP/1.1 400"), "{}: {}", status, body);
+        let (status, _) = request(address, "POST", "/enable", "off");
+        assert!(status.starts_with("HTTP/1.1 204"), "{}", status);
+
+        let (_, body) = request(address, "GET", "/state", "");
+        let state: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(state["enabled"], false);
+        assert_eq!(state["profile"]["preamp_db"], -2.0);
+        assert_eq!(state["profile"]["filters"][0]["gain"], 4.0);
+
+        let (status, body) = request(address, "GET", "/response.csv?points=2", "");
+        assert_eq!(status, "HTTP/1.1 200 OK");
+        assert_eq!(body.lines().next(), Some("frequency_hz,gain_db"));
+        assert_eq!(body.lines().count(), 3);
+        assert!(request(address, "DELETE", "/state", "").0.contains("405"));
+        assert!(request(address, "GET", "/nope", "").0.contains("404"));
+
+        // A web page's requests, and oversized bodies, are refused
+        let host = format!("Host: {}", address);
+        let from_page = ["Origin: https://example.com", &host];
+        let (status, _) = request_with(address, "POST", "/enable", &from_page, "on");
+        assert!(status.starts_with("HTTP/1.1 403"), "{}", status);
+        let rebound = ["Host: attacker.example"];
+        let (status, _) = request_with(address, "POST", "/enable", &rebound, "on");
+        assert!(status.starts_with("HTTP/1.1 403"), "{}", status);
+        let localhost = format!("Host: localhost:{}", address.port());
+        let (status, _) = request_with(address, "GET", "/state", &[&localhost], "");
+        assert_eq!(status, "HTTP/1.1 200 OK");
+        let huge = " ".repeat(MAX_BODY as usize + 1);
+        let (status, _) = request(address, "POST", "/profile", &huge);
+        assert!(status.starts_with("HTTP/1.1 413"), "{}", status);
+        let (_, body) = request(address, "GET", "/state", "");
+        let state: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(state["enabled"], false);
+    }
+
+    #[test]
+    fn test_host_allowed() {
+        let loopback: SocketAddr = "127.0.0.1:8080".parse().unwrap();
+        assert!(host_allowed("127.0.0.1:8080", loopback));
+        assert!(host_allowed("localhost:8080", loopback));
+        assert!(!host_allowed("localhost:9090", loopback));
+        assert!(!host_allowed("localhost", loopback));
+        assert!(!host_allowed("evil.example:8080", loopback));
+        let any: SocketAddr = "0.0.0.0:8080".parse().unwrap();
+        assert!(host_allowed("192.168.1.5:8080", any));
+        assert!(!host_allowed("evil.example:8080", any));
+    }
+}
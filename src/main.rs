@@ -1,27 +1,34 @@
-use std::sync::Arc;
+use tracing::{error, info};
 
-use cpal::traits::{DeviceTrait, HostTrait};
-use eframe::egui;
-use font_kit::{family_name::FamilyName, properties::Properties, source::SystemSource};
-use settings::Settings;
-use tracing::{error, info, warn};
-use ui::App;
-
-use crate::{
-    config::{Config, config_dir},
-    executor::Executor,
-    ui::command::Info,
-    utils::OneShot,
-};
+use crate::config::{Config, config_dir};
+mod bench;
+mod cli;
+mod command;
 mod config;
+mod dsp;
 mod eq;
 mod executor;
-#[cfg(target_os = "macos")]
+mod fir;
+mod format;
+mod graphic_eq;
+#[cfg(feature = "http")]
+mod http;
+#[cfg(all(target_os = "macos", feature = "gui"))]
 mod macos;
+mod midi;
+mod osc;
+mod presets;
+mod response;
 mod run;
 mod settings;
+mod svg;
+#[cfg(test)]
+mod testing;
+#[cfg(feature = "gui")]
 mod ui;
 mod utils;
+mod watcher;
+mod wav;
 
 fn main() {
     tracing_subscriber::fmt()
@@ -29,113 +36,40 @@ fn main() {
             tracing_subscriber::EnvFilter::try_from_default_env().unwrap_or_else(|_| "info".into()),
         )
         .init();
+    // Any command-line argument selects the CLI (e.g. `eq_layer --list`);
+    // a bare launch opens the GUI, or runs headless in a build without it.
+    if std::env::args_os().len() > 1 {
+        if let Err(e) = cli::cli_main() {
+            error!("{:?}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
     info!(
         "Starting Eq Layer with config directory: {}",
         config_dir().to_string_lossy()
     );
-    let options = eframe::NativeOptions {
-        viewport: egui::ViewportBuilder::default().with_inner_size([1000.0, 450.0]),
-        ..Default::default()
-    };
-    let (sender, receiver) = std::sync::mpsc::sync_channel(1024);
-
-    let config_dir = dirs::config_dir().unwrap().join("eq_layer");
-    std::fs::create_dir_all(&config_dir).unwrap();
-    let config_path = config_dir.join("config.toml");
+    // The directory is created on the first save
+    let config_path = config_dir();
     let config = if config_path.exists()
-        && let Ok(config_contents) =
-            std::fs::read_to_string(&config_path).inspect_err(|e| error!("Error: {:?}", e))
-        && let Ok(config) =
-            toml::from_str(config_contents.as_str()).inspect_err(|e| error!("Error: {:?}", e))
+        && let Ok(config) = Config::load(&config_path).inspect_err(|e| error!("Error: {:?}", e))
     {
         config
     } else {
         Config::default()
     };
-    let settings = Settings {
-        enable_eq: std::sync::Arc::new(std::sync::atomic::AtomicBool::new(true)),
-        instance_id: std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0)),
-    };
-    let settings_cloned = settings.clone();
-    let config_cloned = config.clone();
-    std::thread::spawn(move || {
-        Executor::new(receiver, config_cloned, settings_cloned).run();
-    });
-    let oneshot = OneShot::new();
-    sender
-        .send(ui::command::Command::GetState(oneshot.clone()))
-        .unwrap();
-    let state = oneshot.recv();
-    let dev_names = cpal::Host::default()
-        .devices()
-        .unwrap()
-        .map(|v| v.description().map(|v| v.name().to_string()))
-        .filter_map(|v| v.ok())
-        .collect();
-    let info = Info {
-        device_names: dev_names,
-        input_dev: config.input_dev_name.clone().unwrap_or(String::new()),
-        output_dev: config.output_dev_name.clone().unwrap_or(String::new()),
-    };
-    let app = App::new(settings, config.eq_profile, sender, state, info);
-
-    eframe::run_native(
-        "Eq Layer",
-        options,
-        Box::new(|ctx| {
-            load_font(&ctx.egui_ctx);
-            #[cfg(target_os = "macos")]
-            let _dock_observer = macos::setup_dock_observer(ctx.egui_ctx.clone());
-            Ok(Box::new(app))
-        }),
-    )
-    .unwrap();
+    #[cfg(feature = "gui")]
+    ui::launch::run(config);
+    #[cfg(not(feature = "gui"))]
+    run_headless(config);
 }
 
-fn load_font(ctx: &egui::Context) {
-    let source = SystemSource::new();
-
-    let font_families = [
-        FamilyName::Title("Microsoft YaHei".to_string()), // Windows SC
-        FamilyName::Title("PingFang SC".to_string()),     // macOS SC
-        FamilyName::Title("Noto Sans CJK SC".to_string()), // Linux SC
-        FamilyName::Title("Arial".to_string()),           // Common English
-        FamilyName::SansSerif,                            // Fallback
-    ];
-
-    // 3. 尝试查找字体
-    let mut font_data_bytes: Option<Vec<u8>> = None;
-
-    for family in &font_families {
-        let properties = Properties::new();
-
-        if let Ok(handle) = source.select_best_match(&[family.clone()], &properties) {
-            if let Ok(font) = handle.load() {
-                if let Some(data) = font.copy_font_data() {
-                    font_data_bytes = Some(data.to_vec());
-                    warn!("Found font: {:?}", family);
-                    break;
-                }
-            }
-        }
-    }
-
-    if let Some(font_data) = font_data_bytes {
-        let mut fonts = egui::FontDefinitions::default();
-
-        fonts.font_data.insert(
-            "system_font".to_owned(),
-            Arc::new(egui::FontData::from_owned(font_data)),
-        );
-
-        if let Some(family) = fonts.families.get_mut(&egui::FontFamily::Proportional) {
-            family.insert(0, "system_font".to_owned());
-        }
-
-        if let Some(family) = fonts.families.get_mut(&egui::FontFamily::Monospace) {
-            family.push("system_font".to_owned());
-        }
-
-        ctx.set_fonts(fonts);
-    }
+/// The saved setup without a window: the device pairs, the OSC and HTTP
+/// servers and the config watcher, until the process is stopped.
+#[cfg(not(feature = "gui"))]
+fn run_headless(config: Config) {
+    let (sender, receiver) = std::sync::mpsc::sync_channel(1024);
+    let settings = settings::Settings::from(&config.settings);
+    info!("Running headless; stop with Ctrl+C");
+    executor::Executor::new(receiver, sender, config, settings).run();
 }
@@ -0,0 +1,302 @@
+//! MIDI control surfaces: controller (CC) knobs bound to band parameters,
+//! and a note that turns the EQ on and off.
+
+use anyhow::{Context, Result, anyhow};
+use midir::{MidiInput, MidiInputConnection};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::{
+    config::{RangeConfig, clamp_finite},
+    eq::{Filter, q_to_bandwidth},
+    response::log_freq_between,
+};
+
+/// Span of the Q a knob sweeps, on a log scale.
+const MIN_Q: f64 = 0.1;
+const MAX_Q: f64 = 10.0;
+
+/// Band parameters a knob can drive.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum BandParam {
+    Frequency,
+    Gain,
+    Q,
+}
+
+impl BandParam {
+    /// The value a CC value of 0..=127 sets: the editor's frequency and gain
+    /// `ranges`, and Q 0.1 - 10. Frequency and Q are on a log scale, so every
+    /// step of the knob moves them by the same ratio.
+    #[cfg_attr(not(feature = "gui"), allow(dead_code))]
+    pub fn value(self, cc_value: u8, ranges: &RangeConfig) -> f64 {
+        let t = cc_value.min(127) as f64 / 127.0;
+        match self {
+            Self::Frequency => log_freq_between(t, ranges.min_freq_hz, ranges.max_freq_hz),
+            Self::Gain => (2.0 * t - 1.0) * ranges.max_gain_db,
+            Self::Q => MIN_Q * (MAX_Q / MIN_Q).powf(t),
+        }
+    }
+
+    /// `value` as the editor would take it for this parameter: clamped to
+    /// `ranges`, or None when it isn't a finite number.
+    pub fn clamp(self, value: f64, ranges: &RangeConfig) -> Option<f64> {
+        let range = match self {
+            Self::Frequency => ranges.freq(),
+            Self::Gain => ranges.gain(),
+            Self::Q => ranges.q(),
+        };
+        clamp_finite(value, range)
+    }
+
+    /// Sets this parameter of `filter`, clamped as [`clamp`](Self::clamp)
+    /// does; a value that isn't a number leaves it alone and returns false.
+    /// A band edited in octaves gets the bandwidth of the new Q.
+    pub fn set(self, filter: &mut Filter, value: f64, ranges: &RangeConfig) -> bool {
+        let Some(value) = self.clamp(value, ranges) else {
+            return false;
+        };
+        match self {
+            Self::Frequency => filter.frequency = value,
+            Self::Gain => filter.gain = value,
+            Self::Q => match &mut filter.bandwidth {
+                Some(bw) => *bw = q_to_bandwidth(value),
+                None => filter.q_factor = value,
+            },
+        }
+        true
+    }
+
+    /// The parameter that differs between two versions of a band, if any.
+    #[cfg_attr(not(feature = "gui"), allow(dead_code))]
+    pub fn changed(before: &Filter, after: &Filter) -> Option<Self> {
+        if before.frequency != after.frequency {
+            Some(Self::Frequency)
+        } else if before.gain != after.gain {
+            Some(Self::Gain)
+        } else if before.q_factor != after.q_factor || before.bandwidth != after.bandwidth {
+            Some(Self::Q)
+        } else {
+            None
+        }
+    }
+}
+
+impl std::fmt::Display for BandParam {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Frequency => "frequency",
+            Self::Gain => "gain",
+            Self::Q => "Q",
+        })
+    }
+}
+
+/// A controller number on a channel, driving one parameter of a band.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct MidiBinding {
+    /// 0..=15
+    pub channel: u8,
+    pub cc: u8,
+    /// Index into the profile's filters
+    pub band: usize,
+    pub param: BandParam,
+}
+
+/// Under `[midi]` in the config.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Default)]
+#[serde(default)]
+pub struct MidiConfig {
+    /// Off unless set; the GUI then listens on `port`
+    pub enabled: bool,
+    /// Input port name, or part of it; the first port when unset
+    pub port: Option<String>,
+    pub bindings: Vec<MidiBinding>,
+    /// Note, on any channel, that toggles the EQ
+    pub toggle_note: Option<u8>,
+}
+
+#[cfg_attr(not(feature = "gui"), allow(dead_code))]
+impl MidiConfig {
+    /// Adds `binding`, replacing the ones on the same controller or for the
+    /// same band parameter, so a knob only ever drives one thing.
+    pub fn bind(&mut self, binding: MidiBinding) {
+        self.bindings.retain(|b| {
+            (b.channel, b.cc) != (binding.channel, binding.cc)
+                && (b.band, b.param) != (binding.band, binding.param)
+        });
+        self.bindings.push(binding);
+    }
+
+    /// Bindings of the controller `cc` on `channel`.
+    pub fn bound(&self, channel: u8, cc: u8) -> impl Iterator<Item = &MidiBinding> {
+        self.bindings
+            .iter()
+            .filter(move |b| b.channel == channel && b.cc == cc)
+    }
+}
+
+/// The messages acted on; the rest are dropped when parsed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(not(feature = "gui"), allow(dead_code))]
+pub enum MidiEvent {
+    Control { channel: u8, cc: u8, value: u8 },
+    NoteOn { channel: u8, note: u8 },
+}
+
+#[cfg_attr(not(feature = "gui"), allow(dead_code))]
+impl MidiEvent {
+    pub fn parse(message: &[u8]) -> Option<Self> {
+        let [status, data1, data2, ..] = *message else {
+            return None;
+        };
+        let channel = status & 0x0f;
+        match status & 0xf0 {
+            0xb0 => Some(Self::Control {
+                channel,
+                cc: data1,
+                value: data2,
+            }),
+            // Velocity 0 is a note off
+            0x90 if data2 > 0 => Some(Self::NoteOn {
+                channel,
+                note: data1,
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Keeps listening until dropped.
+#[cfg_attr(not(feature = "gui"), allow(dead_code))]
+pub struct MidiConnection {
+    _connection: MidiInputConnection<()>,
+}
+
+/// Calls `on_event` for every control change and note on arriving at the
+/// first input port whose name contains `port`, or the first port at all.
+#[cfg_attr(not(feature = "gui"), allow(dead_code))]
+pub fn connect(
+    port: Option<&str>,
+    mut on_event: impl FnMut(MidiEvent) + Send + 'static,
+) -> Result<MidiConnection> {
+    let input = MidiInput::new("eq_layer").context("MIDI is unavailable")?;
+    let ports = input.ports();
+    let (port, name) = ports
+        .iter()
+        .filter_map(|p| Some((p, input.port_name(p).ok()?)))
+        .find(|(_, name)| port.is_none_or(|wanted| name.contains(wanted)))
+        .with_context(|| match port {
+            Some(wanted) => format!("No MIDI input port matching \"{}\"", wanted),
+            None => "No MIDI input port".to_string(),
+        })?;
+    let connection = input
+        .connect(
+            port,
+            "eq_layer-control",
+            move |_, message, _| {
+                if let Some(event) = MidiEvent::parse(message) {
+                    on_event(event);
+                }
+            },
+            (),
+        )
+        .map_err(|e| anyhow!("Failed to open MIDI port {}: {}", name, e))?;
+    info!("Listening to MIDI port {}", name);
+    Ok(MidiConnection {
+        _connection: connection,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cc_mapping() {
+        let ranges = RangeConfig::default();
+        assert!((BandParam::Frequency.value(0, &ranges) - 20.0).abs() < 1e-9);
+        assert!((BandParam::Frequency.value(127, &ranges) - 20000.0).abs() < 1e-6);
+        // 64 is just past the middle of the log scale, 632 Hz
+        let mid = BandParam::Frequency.value(64, &ranges);
+        assert!((mid - 650.0).abs() < 5.0, "{}", mid);
+        assert_eq!(BandParam::Gain.value(0, &ranges), -12.0);
+        assert_eq!(BandParam::Gain.value(127, &ranges), 12.0);
+        assert!(BandParam::Gain.value(64, &ranges).abs() < 0.1);
+        assert!((BandParam::Q.value(0, &ranges) - 0.1).abs() < 1e-9);
+        assert!((BandParam::Q.value(127, &ranges) - 10.0).abs() < 1e-9);
+        // Out-of-range data bytes are clamped
+        assert_eq!(BandParam::Gain.value(200, &ranges), 12.0);
+        // Knobs follow the configured ranges
+        let subwoofer = RangeConfig {
+            min_freq_hz: 10.0,
+            max_freq_hz: 200.0,
+            max_gain_db: 18.0,
+        };
+        assert!((BandParam::Frequency.value(0, &subwoofer) - 10.0).abs() < 1e-9);
+        assert!((BandParam::Frequency.value(127, &subwoofer) - 200.0).abs() < 1e-6);
+        assert_eq!(BandParam::Gain.value(0, &subwoofer), -18.0);
+
+        let mut band = Filter {
+            bandwidth: Some(1.0),
+            ..Default::default()
+        };
+        assert!(BandParam::Q.set(&mut band, BandParam::Q.value(127, &ranges), &ranges));
+        assert_eq!(band.q_factor, Filter::default().q_factor);
+        assert_eq!(band.bandwidth, Some(q_to_bandwidth(10.0)));
+        let before = band.clone();
+        assert!(BandParam::Gain.set(&mut band, BandParam::Gain.value(0, &ranges), &ranges));
+        assert_eq!(band.gain, -12.0);
+        assert_eq!(BandParam::changed(&before, &band), Some(BandParam::Gain));
+        assert_eq!(BandParam::changed(&band, &band), None);
+        // Values from outside the knobs' span are clamped, non-numbers ignored
+        assert!(BandParam::Gain.set(&mut band, 40.0, &ranges));
+        assert_eq!(band.gain, 12.0);
+        assert!(!BandParam::Frequency.set(&mut band, f64::NAN, &ranges));
+        assert_eq!(band.frequency, Filter::default().frequency);
+    }
+
+    #[test]
+    fn test_parse_and_bind() {
+        assert_eq!(
+            MidiEvent::parse(&[0xb3, 7, 100]),
+            Some(MidiEvent::Control {
+                channel: 3,
+                cc: 7,
+                value: 100
+            })
+        );
+        assert_eq!(
+            MidiEvent::parse(&[0x90, 60, 90]),
+            Some(MidiEvent::NoteOn {
+                channel: 0,
+                note: 60
+            })
+        );
+        assert_eq!(MidiEvent::parse(&[0x90, 60, 0]), None);
+        assert_eq!(MidiEvent::parse(&[0x80, 60, 90]), None);
+        assert_eq!(MidiEvent::parse(&[0xf8]), None);
+
+        let mut config = MidiConfig::default();
+        let binding = |cc, band, param| MidiBinding {
+            channel: 0,
+            cc,
+            band,
+            param,
+        };
+        config.bind(binding(1, 0, BandParam::Gain));
+        config.bind(binding(2, 0, BandParam::Frequency));
+        // Rebinding the knob moves it, and the parameter loses its old knob
+        config.bind(binding(1, 1, BandParam::Gain));
+        config.bind(binding(3, 0, BandParam::Frequency));
+        assert_eq!(
+            config.bindings,
+            [
+                binding(1, 1, BandParam::Gain),
+                binding(3, 0, BandParam::Frequency)
+            ]
+        );
+        assert_eq!(config.bound(0, 3).count(), 1);
+        assert_eq!(config.bound(1, 3).count(), 0);
+    }
+}
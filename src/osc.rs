@@ -0,0 +1,185 @@
+//! OSC remote control: UDP messages turned into the commands the GUI sends,
+//! for show-control and streaming tools.
+
+use std::{
+    net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket},
+    sync::mpsc::SyncSender,
+};
+
+use anyhow::{Context, Result, anyhow, bail};
+use rosc::{OscMessage, OscPacket, OscType};
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::{command::Command, midi::BandParam};
+
+/// Under `[osc]` in the config.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct OscConfig {
+    pub enabled: bool,
+    /// Interface to listen on; only this machine by default, `0.0.0.0` for
+    /// every network
+    pub address: IpAddr,
+    pub port: u16,
+}
+
+impl Default for OscConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            address: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            port: 9000,
+        }
+    }
+}
+
+/// The single number a message carries, whatever its OSC type.
+fn number(message: &OscMessage) -> Result<f64> {
+    match message.args.as_slice() {
+        [OscType::Float(v)] => Ok(*v as f64),
+        [OscType::Double(v)] => Ok(*v),
+        [OscType::Int(v)] => Ok(*v as f64),
+        [OscType::Long(v)] => Ok(*v as f64),
+        [OscType::Bool(v)] => Ok(if *v { 1.0 } else { 0.0 }),
+        _ => bail!("{} takes one number", message.addr),
+    }
+}
+
+/// - `/eq/enable <0|1>`
+/// - `/eq/preamp <dB>`
+/// - `/eq/band/<n>/<freq|gain|q> <value>`, bands counted from 1
+/// - `/eq/instance/<name>/running <0|1>`
+fn command(message: &OscMessage) -> Result<Command> {
+    let path: Vec<&str> = message.addr.trim_start_matches('/').split('/').collect();
+    let value = number(message)?;
+    Ok(match path.as_slice() {
+        ["eq", "enable"] => Command::SetEnabled(value != 0.0),
+        ["eq", "preamp"] => Command::SetPreamp(value),
+        ["eq", "band", index, field] => {
+            let band = index
+                .parse::<usize>()
+                .ok()
+                .and_then(|n| n.checked_sub(1))
+                .with_context(|| format!("Invalid band number in {}", message.addr))?;
+            let param = match *field {
+                "freq" | "fc" => BandParam::Frequency,
+                "gain" => BandParam::Gain,
+                "q" => BandParam::Q,
+                _ => bail!("Unknown band field in {}", message.addr),
+            };
+            Command::SetBand(band, param, value)
+        }
+        ["eq", "instance", key, "running"] if value != 0.0 => {
+            Command::StartInstance(key.to_string())
+        }
+        ["eq", "instance", key, "running"] => Command::StopInstance(key.to_string()),
+        _ => bail!("Unknown address {}", message.addr),
+    })
+}
+
+/// The commands in a UDP packet; a bundle can hold several. Messages that
+/// don't translate are skipped with a warning.
+pub fn decode(packet: &[u8]) -> Result<Vec<Command>> {
+    let (_, packet) = rosc::decoder::decode_udp(packet).map_err(|e| anyhow!("{}", e))?;
+    let mut pending = vec![packet];
+    let mut commands = Vec::new();
+    while let Some(packet) = pending.pop() {
+        match packet {
+            OscPacket::Message(message) => match command(&message) {
+                Ok(command) => commands.push(command),
+                Err(e) => warn!("Ignoring OSC message: {:#}", e),
+            },
+            // Contents in order; time tags are ignored
+            OscPacket::Bundle(bundle) => pending.extend(bundle.content.into_iter().rev()),
+        }
+    }
+    Ok(commands)
+}
+
+/// Listens on a thread of its own and forwards the commands to `sender`
+/// until the receiving end is gone.
+pub fn serve(config: &OscConfig, sender: SyncSender<Command>) -> Result<()> {
+    let address = SocketAddr::new(config.address, config.port);
+    let socket =
+        UdpSocket::bind(address).with_context(|| format!("Failed to listen on {}", address))?;
+    info!("OSC server listening on {}", address);
+    std::thread::spawn(move || {
+        let mut buffer = [0; rosc::decoder::MTU];
+        loop {
+            let (len, from) = match socket.recv_from(&mut buffer) {
+                Ok(received) => received,
+                Err(e) => {
+                    warn!("OSC receive failed: {}", e);
+                    continue;
+                }
+            };
+            let commands = match decode(&buffer[..len]) {
+                Ok(commands) => commands,
+                Err(e) => {
+                    warn!("Ignoring OSC packet from {}: {:#}", from, e);
+                    continue;
+                }
+            };
+            for command in commands {
+                if sender.send(command).is_err() {
+                    return;
+                }
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(addr: &str, arg: OscType) -> OscPacket {
+        OscPacket::Message(OscMessage {
+            addr: addr.to_string(),
+            args: vec![arg],
+        })
+    }
+
+    #[test]
+    fn test_decode_packet() {
+        let packet =
+            rosc::encoder::encode(&message("/eq/band/3/gain", OscType::Float(2.5))).unwrap();
+        let commands = decode(&packet).unwrap();
+        assert!(matches!(
+            commands.as_slice(),
+            [Command::SetBand(2, BandParam::Gain, 2.5)]
+        ));
+
+        let bundle = OscPacket::Bundle(rosc::OscBundle {
+            timetag: (0, 1).into(),
+            content: vec![
+                message("/eq/enable", OscType::Int(1)),
+                message("/eq/preamp", OscType::Double(-3.0)),
+                // Skipped: bands count from 1, and an unknown field
+                message("/eq/band/0/gain", OscType::Float(1.0)),
+                message("/eq/band/1/width", OscType::Float(1.0)),
+                message("/eq/band/1/freq", OscType::Int(120)),
+                message("/eq/instance/headphones/running", OscType::Bool(true)),
+                message("/eq/instance/main/running", OscType::Int(0)),
+            ],
+        });
+        let commands = decode(&rosc::encoder::encode(&bundle).unwrap()).unwrap();
+        assert!(
+            matches!(
+                commands.as_slice(),
+                [
+                    Command::SetEnabled(true),
+                    Command::SetPreamp(-3.0),
+                    Command::SetBand(0, BandParam::Frequency, 120.0),
+                    Command::StartInstance(headphones),
+                    Command::StopInstance(main),
+                ] if headphones == "headphones" && main == "main"
+            ),
+            "{:?}",
+            commands
+        );
+        assert!(decode(b"not osc").is_err());
+    }
+}
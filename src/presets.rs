@@ -0,0 +1,149 @@
+//! Profiles generated from parameters rather than edited by hand.
+
+use crate::eq::{EqProfile, Filter, FilterType};
+
+const LOUDNESS_BASS_HZ: f64 = 100.0;
+const LOUDNESS_TREBLE_HZ: f64 = 10000.0;
+/// Boost per dB of volume below 0 dB. Equal-loudness contours flatten out as
+/// the level rises, and bass loses much more than treble at low levels.
+const LOUDNESS_BASS_RATIO: f64 = 0.3;
+const LOUDNESS_TREBLE_RATIO: f64 = 0.1;
+const LOUDNESS_MAX_BASS_DB: f64 = 12.0;
+const LOUDNESS_MAX_TREBLE_DB: f64 = 6.0;
+
+const BASS_BOOST_HZ: f64 = 105.0;
+const TREBLE_BOOST_HZ: f64 = 8000.0;
+const PRESENCE_HZ: f64 = 3000.0;
+const PRESENCE_DB: f64 = 3.0;
+const PRESENCE_Q: f64 = 1.0;
+/// Butterworth slope, steep without a bump next to the shelf
+const SHELF_Q: f64 = 0.707;
+
+fn shelf(filter_type: FilterType, frequency: f64, gain: f64) -> Filter {
+    Filter {
+        filter_type,
+        frequency,
+        gain,
+        q_factor: SHELF_Q,
+        ..Default::default()
+    }
+}
+
+/// A single band, with the preamp lowered by its boost so it can't clip.
+fn single_band(filter: Filter) -> EqProfile {
+    EqProfile {
+        preamp_db: -filter.gain.max(0.0),
+        filters: vec![filter],
+        ..Default::default()
+    }
+}
+
+/// A low shelf lifting (or cutting) everything below about 100 Hz by `db`.
+#[cfg_attr(not(feature = "gui"), allow(dead_code))]
+pub fn bass_boost(db: f64) -> EqProfile {
+    single_band(shelf(FilterType::LowShelf, BASS_BOOST_HZ, db))
+}
+
+/// A high shelf lifting (or cutting) everything above about 8 kHz by `db`.
+#[cfg_attr(not(feature = "gui"), allow(dead_code))]
+pub fn treble_boost(db: f64) -> EqProfile {
+    single_band(shelf(FilterType::HighShelf, TREBLE_BOOST_HZ, db))
+}
+
+/// A broad presence peak around 3 kHz that brings voices forward.
+#[cfg_attr(not(feature = "gui"), allow(dead_code))]
+pub fn vocal_clarity() -> EqProfile {
+    single_band(Filter {
+        filter_type: FilterType::Peaking,
+        frequency: PRESENCE_HZ,
+        gain: PRESENCE_DB,
+        q_factor: PRESENCE_Q,
+        ..Default::default()
+    })
+}
+
+/// Loudness compensation for listening at `volume_db` below full volume:
+/// a bass and a treble shelf that make up for the ear losing sensitivity at
+/// the extremes, roughly following the ISO 226 contours. Flat at 0 dB and
+/// above.
+pub fn loudness(volume_db: f64) -> EqProfile {
+    EqProfile {
+        filters: loudness_shelves(volume_db).to_vec(),
+        ..Default::default()
+    }
+}
+
+/// The bands of [`loudness`], without allocating, for the audio callback.
+pub fn loudness_shelves(volume_db: f64) -> [Filter; 2] {
+    let below = (-volume_db).max(0.0);
+    [
+        shelf(
+            FilterType::LowShelf,
+            LOUDNESS_BASS_HZ,
+            (below * LOUDNESS_BASS_RATIO).min(LOUDNESS_MAX_BASS_DB),
+        ),
+        shelf(
+            FilterType::HighShelf,
+            LOUDNESS_TREBLE_HZ,
+            (below * LOUDNESS_TREBLE_RATIO).min(LOUDNESS_MAX_TREBLE_DB),
+        ),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_loudness_grows_as_volume_drops() {
+        let gains = |volume_db| -> Vec<f64> {
+            loudness(volume_db).filters.iter().map(|f| f.gain).collect()
+        };
+        assert_eq!(gains(0.0), [0.0, 0.0]);
+        assert_eq!(gains(6.0), [0.0, 0.0]);
+        let quiet = gains(-20.0);
+        let quieter = gains(-30.0);
+        assert!(quiet[0] > 0.0 && quiet[1] > 0.0);
+        assert!(quieter[0] > quiet[0] && quieter[1] > quiet[1]);
+        // Bass needs more help than treble
+        assert!(quiet[0] > quiet[1]);
+        assert_eq!(
+            gains(-100.0),
+            [LOUDNESS_MAX_BASS_DB, LOUDNESS_MAX_TREBLE_DB]
+        );
+    }
+
+    #[test]
+    fn test_quick_presets() {
+        let bass = bass_boost(6.0);
+        let band = &bass.filters[0];
+        assert_eq!(bass.filters.len(), 1);
+        assert_eq!(band.filter_type, FilterType::LowShelf);
+        assert_eq!(
+            (band.frequency, band.gain, band.q_factor),
+            (105.0, 6.0, 0.707)
+        );
+        assert_eq!(bass.preamp_db, -6.0);
+
+        let treble = treble_boost(-3.0);
+        let band = &treble.filters[0];
+        assert_eq!(band.filter_type, FilterType::HighShelf);
+        assert_eq!(
+            (band.frequency, band.gain, band.q_factor),
+            (8000.0, -3.0, 0.707)
+        );
+        // A cut needs no headroom
+        assert_eq!(treble.preamp_db, 0.0);
+
+        let vocal = vocal_clarity();
+        let band = &vocal.filters[0];
+        assert_eq!(band.filter_type, FilterType::Peaking);
+        assert_eq!(
+            (band.frequency, band.gain, band.q_factor),
+            (3000.0, 3.0, 1.0)
+        );
+        // Never clips
+        assert!(vocal.peak_db(48000.0) <= 0.0);
+        assert!(bass.peak_db(48000.0) <= 0.0);
+    }
+}
@@ -0,0 +1,175 @@
+//! Frequency response of the bands, for the graph and for fitting filters.
+
+use std::f64::consts::{PI, SQRT_2};
+
+use crate::eq::{Filter, FilterType};
+
+/// Frequency `t` of the way along the 20 Hz - 20 kHz log scale.
+pub fn log_freq(t: f64) -> f64 {
+    log_freq_between(t, 20.0, 20000.0)
+}
+
+/// Frequency `t` of the way along the `min` - `max` log scale.
+pub fn log_freq_between(t: f64, min: f64, max: f64) -> f64 {
+    let log_min = min.ln();
+    let log_max = max.ln();
+    (log_min + t * (log_max - log_min)).exp()
+}
+
+/// Biquad coefficients in double precision, for computing the response of
+/// a band rather than running audio through it.
+pub struct BiquadResponse {
+    b0: f64,
+    b1: f64,
+    b2: f64,
+    a0: f64,
+    a1: f64,
+    a2: f64,
+}
+
+impl BiquadResponse {
+    pub fn calc(band: &Filter, fs: f64) -> BiquadResponse {
+        let w0 = 2.0 * PI * band.frequency / fs;
+        let cos_w0 = w0.cos();
+        let sin_w0 = w0.sin();
+        let q = band.effective_q();
+        let a = 10.0_f64.powf(band.gain / 40.0);
+        let alpha = match band.filter_type {
+            FilterType::LowShelf | FilterType::HighShelf => {
+                sin_w0 / 2.0 * ((a + 1.0 / a) * (1.0 / q - 1.0) + 2.0).sqrt()
+            }
+            _ => sin_w0 / (2.0 * q),
+        };
+
+        let (b0, b1, b2, a0, a1, a2) = match band.filter_type {
+            FilterType::Peaking => (
+                1.0 + alpha * a,
+                -2.0 * cos_w0,
+                1.0 - alpha * a,
+                1.0 + alpha / a,
+                -2.0 * cos_w0,
+                1.0 - alpha / a,
+            ),
+            FilterType::LowShelf => {
+                let sqrt_a = a.sqrt();
+                (
+                    a * ((a + 1.0) - (a - 1.0) * cos_w0 + 2.0 * sqrt_a * alpha),
+                    2.0 * a * ((a - 1.0) - (a + 1.0) * cos_w0),
+                    a * ((a + 1.0) - (a - 1.0) * cos_w0 - 2.0 * sqrt_a * alpha),
+                    (a + 1.0) + (a - 1.0) * cos_w0 + 2.0 * sqrt_a * alpha,
+                    -2.0 * ((a - 1.0) + (a + 1.0) * cos_w0),
+                    (a + 1.0) + (a - 1.0) * cos_w0 - 2.0 * sqrt_a * alpha,
+                )
+            }
+            FilterType::HighShelf => {
+                let sqrt_a = a.sqrt();
+                (
+                    a * ((a + 1.0) + (a - 1.0) * cos_w0 + 2.0 * sqrt_a * alpha),
+                    -2.0 * a * ((a - 1.0) + (a + 1.0) * cos_w0),
+                    a * ((a + 1.0) + (a - 1.0) * cos_w0 - 2.0 * sqrt_a * alpha),
+                    (a + 1.0) - (a - 1.0) * cos_w0 + 2.0 * sqrt_a * alpha,
+                    2.0 * ((a - 1.0) - (a + 1.0) * cos_w0),
+                    (a + 1.0) - (a - 1.0) * cos_w0 - 2.0 * sqrt_a * alpha,
+                )
+            }
+            FilterType::LowPass => (
+                (1.0 - cos_w0) / 2.0,
+                1.0 - cos_w0,
+                (1.0 - cos_w0) / 2.0,
+                1.0 + alpha,
+                -2.0 * cos_w0,
+                1.0 - alpha,
+            ),
+            FilterType::HighPass => (
+                (1.0 + cos_w0) / 2.0,
+                -(1.0 + cos_w0),
+                (1.0 + cos_w0) / 2.0,
+                1.0 + alpha,
+                -2.0 * cos_w0,
+                1.0 - alpha,
+            ),
+        };
+
+        BiquadResponse {
+            b0,
+            b1,
+            b2,
+            a0,
+            a1,
+            a2,
+        }
+    }
+
+    pub fn calc_magnitude_db(&self, freq: f64, fs: f64) -> f64 {
+        let w = 2.0 * PI * freq / fs;
+        let cw = w.cos();
+        let c2w = (2.0 * w).cos();
+        let sw = w.sin();
+        let s2w = (2.0 * w).sin();
+
+        // H(z) = (b0 + b1*z^-1 + b2*z^-2) / (a0 + a1*z^-1 + a2*z^-2)
+        // 实际上是复数除法，这里分别计算分子分母的实部和虚部
+
+        let num_re = self.b0 + self.b1 * cw + self.b2 * c2w;
+        let num_im = -(self.b1 * sw + self.b2 * s2w);
+
+        let den_re = self.a0 + self.a1 * cw + self.a2 * c2w;
+        let den_im = -(self.a1 * sw + self.a2 * s2w);
+
+        let mag_sq = (num_re * num_re + num_im * num_im) / (den_re * den_re + den_im * den_im);
+
+        10.0 * mag_sq.log10() // 20 * log10(mag) = 10 * log10(mag^2)
+    }
+
+    /// The first `len` samples of the band's response to a unit impulse.
+    #[cfg(test)]
+    pub fn impulse_response(&self, len: usize) -> Vec<f64> {
+        let (mut x1, mut x2, mut y1, mut y2) = (0.0, 0.0, 0.0, 0.0);
+        (0..len)
+            .map(|n| {
+                let x = if n == 0 { 1.0 } else { 0.0 };
+                let y = (self.b0 * x + self.b1 * x1 + self.b2 * x2 - self.a1 * y1 - self.a2 * y2)
+                    / self.a0;
+                (x2, x1, y2, y1) = (x1, x, y1, y);
+                y
+            })
+            .collect()
+    }
+}
+
+/// How steeply the band's gain changes around its frequency, in dB per
+/// octave: the difference across the octave centred on it.
+#[cfg_attr(not(feature = "gui"), allow(dead_code))]
+pub fn slope_db_per_oct(band: &Filter, fs: f64) -> f64 {
+    let coeffs = BiquadResponse::calc(band, fs);
+    let low = coeffs.calc_magnitude_db(band.frequency / SQRT_2, fs);
+    let high = coeffs.calc_magnitude_db(band.frequency * SQRT_2, fs);
+    (high - low).abs()
+}
+
+/// Peak above the passband of a second-order low or high pass, in dB. Only
+/// a Q above 1/√2 (Butterworth) has one.
+#[cfg_attr(not(feature = "gui"), allow(dead_code))]
+pub fn resonance_db(q: f64) -> Option<f64> {
+    (q > 1.0 / SQRT_2).then(|| 20.0 * (q / (1.0 - 1.0 / (4.0 * q * q)).sqrt()).log10())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pass_filter_response() {
+        let band = |filter_type| Filter {
+            filter_type,
+            frequency: 1000.0,
+            ..Default::default()
+        };
+        let low_pass = BiquadResponse::calc(&band(FilterType::LowPass), 48000.0);
+        assert!(low_pass.calc_magnitude_db(50.0, 48000.0).abs() < 0.1);
+        assert!(low_pass.calc_magnitude_db(10000.0, 48000.0) < -30.0);
+        let high_pass = BiquadResponse::calc(&band(FilterType::HighPass), 48000.0);
+        assert!(high_pass.calc_magnitude_db(50.0, 48000.0) < -30.0);
+        assert!(high_pass.calc_magnitude_db(10000.0, 48000.0).abs() < 0.1);
+    }
+}
@@ -1,115 +1,867 @@
 use std::{
     cmp::Ordering,
-    sync::{Arc, Mutex, mpsc::Receiver},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, AtomicU32},
+        mpsc::{Receiver, RecvTimeoutError},
+    },
     thread::sleep,
     time::Duration,
 };
 
-use anyhow::Result;
+use anyhow::{Context, Result, bail};
 use cpal::{
-    Device,
+    Device, Host,
     SupportedBufferSize::{Range, Unknown},
+    SupportedStreamConfig, SupportedStreamConfigRange,
 };
 use ringbuf::{
-    HeapRb,
+    HeapCons, HeapProd, HeapRb,
     traits::{Consumer, Producer, Split},
 };
-use tracing::{debug, error, info};
+use tracing::{debug, error, info, warn};
 
 use crate::{
-    eq::{EqProfile, ParametricEq},
-    settings::Settings,
+    config::OverflowPolicy,
+    dsp::{self, DcBlocker, Dither, OutputStage},
+    eq::{EqProfile, MidSide, ParametricEq},
+    fir::{self, Convolver, LINEAR_PHASE_TAPS},
+    format::{self, choose_format},
+    settings::{RingFill, Settings, Status},
 };
 use cpal::{
     StreamConfig,
-    traits::{DeviceTrait, StreamTrait},
+    traits::{DeviceTrait, HostTrait, StreamTrait},
 };
 
-pub fn run(
-    input_device: Device,
-    output_device: Device,
-    settings: Settings,
-    profile: EqProfile,
-) -> Result<()> {
-    let input_config_range = input_device
-        .supported_input_configs()?
-        .into_iter()
-        .min_by(|a, b| match (a.buffer_size(), b.buffer_size()) {
-            (Range { min: a_min, max: _ }, Range { min: b_min, max: _ }) => a_min.cmp(b_min),
-            (Range { min: _, max: _ }, Unknown) => Ordering::Less,
-            (Unknown, Range { min: _, max: _ }) => Ordering::Greater,
-            (Unknown, Unknown) => Ordering::Equal,
+/// Resolves a host by its `HostId` name (e.g. "ASIO", "Jack"), falling back
+/// to the default host when the name is unknown, not compiled in, or the
+/// backend can't be reached.
+pub fn resolve_host(name: Option<&str>) -> Host {
+    let Some(name) = name else {
+        return cpal::default_host();
+    };
+    match name.parse().and_then(cpal::host_from_id) {
+        Ok(host) => host,
+        Err(_) => {
+            warn!("Audio host {} is unavailable, using the default host", name);
+            cpal::default_host()
+        }
+    }
+}
+
+/// A device's cpal id, as saved in the config; some hosts have none.
+pub fn device_id(device: &Device) -> Option<String> {
+    device.id().ok().map(|id| id.to_string())
+}
+
+/// Index of the saved device among `(id, name)` pairs: the one with its id,
+/// or else the first with its name.
+fn pick_device(
+    devices: &[(Option<String>, Option<String>)],
+    name: &str,
+    id: Option<&str>,
+) -> Option<usize> {
+    id.and_then(|id| devices.iter().position(|(d, _)| d.as_deref() == Some(id)))
+        .or_else(|| devices.iter().position(|(_, n)| n.as_deref() == Some(name)))
+}
+
+/// The device saved as `name` and `id`.
+pub fn find_device(host: &Host, name: &str, id: Option<&str>) -> Option<Device> {
+    // Devices can vanish mid-enumeration when unplugged, so skip errors
+    let devices: Vec<Device> = host.devices().ok()?.collect();
+    let keys: Vec<_> = devices
+        .iter()
+        .map(|d| {
+            (
+                device_id(d),
+                d.description().ok().map(|d| d.name().to_string()),
+            )
         })
-        .expect("Can not find supported input config");
-    let output_config_range = output_device
-        .supported_output_configs()?
-        .into_iter()
-        .min_by(|a, b| match (a.buffer_size(), b.buffer_size()) {
-            (Range { min: a_min, max: _ }, Range { min: b_min, max: _ }) => a_min.cmp(b_min),
-            (Range { min: _, max: _ }, Unknown) => Ordering::Less,
-            (Unknown, Range { min: _, max: _ }) => Ordering::Greater,
-            (Unknown, Unknown) => Ordering::Equal,
+        .collect();
+    let index = pick_device(&keys, name, id)?;
+    devices.into_iter().nth(index)
+}
+
+/// The name the device saved as `name` and `id` goes by now, which differs
+/// when it was renamed; `name` when it isn't there.
+#[cfg_attr(not(feature = "gui"), allow(dead_code))]
+pub fn current_device_name(host: &Host, name: &str, id: Option<&str>) -> String {
+    find_device(host, name, id)
+        .and_then(|device| device.description().ok())
+        .map_or_else(|| name.to_string(), |d| d.name().to_string())
+}
+
+/// Where the EQ reads from. `Loopback` records what an output device is
+/// playing; cpal does this when an input stream is built on an output device
+/// under WASAPI and CoreAudio.
+#[derive(Clone)]
+pub enum Input {
+    Device(Device),
+    Loopback(Device),
+}
+
+impl Input {
+    fn device(&self) -> &Device {
+        match self {
+            Self::Device(device) | Self::Loopback(device) => device,
+        }
+    }
+
+    fn supported_configs(&self) -> Result<Vec<SupportedStreamConfigRange>> {
+        Ok(match self {
+            Self::Device(device) => device.supported_input_configs()?.collect(),
+            Self::Loopback(device) => device.supported_output_configs()?.collect(),
         })
-        .expect("Can not find supported output config");
-    let sample_rate = input_config_range
-        .min_sample_rate()
-        .max(output_config_range.min_sample_rate());
-    let buffer_size = match (
-        input_config_range.buffer_size(),
-        output_config_range.buffer_size(),
-    ) {
-        (Range { min: a_min, max: _ }, Range { min: b_min, max: _ }) => {
-            cpal::BufferSize::Fixed(*a_min.max(b_min))
-        }
-        (Range { min: _, max: _ }, Unknown) => cpal::BufferSize::Default,
-        (Unknown, Range { min: _, max: _ }) => cpal::BufferSize::Default,
-        (Unknown, Unknown) => cpal::BufferSize::Default,
+    }
+
+    fn default_config(&self) -> Result<SupportedStreamConfig> {
+        Ok(match self {
+            Self::Device(device) => device.default_input_config()?,
+            Self::Loopback(device) => device.default_output_config()?,
+        })
+    }
+}
+
+/// The EQ stage: one equalizer over L/R, separate mid and side ones, or a
+/// linear-phase FIR version of the profile. The FIR one keeps the
+/// profile's preamp as a gain besides the kernel, for the level-matched
+/// bypass.
+enum Processor {
+    Stereo(ParametricEq),
+    MidSide(MidSide),
+    LinearPhase(Convolver, f32),
+}
+
+impl Processor {
+    fn new(
+        profile: &EqProfile,
+        side_profile: Option<&EqProfile>,
+        sample_rate: f32,
+        settings: &Settings,
+        channels: u16,
+    ) -> Self {
+        match side_profile {
+            Some(side_profile) => {
+                if settings.linear_phase {
+                    warn!("Linear phase is not available in mid/side mode");
+                }
+                Self::MidSide(MidSide::from_profiles(profile, side_profile, sample_rate))
+            }
+            None if settings.linear_phase => Self::LinearPhase(
+                Convolver::from_profile(profile, sample_rate, channels as usize),
+                10.0f32.powf(profile.preamp_db as f32 / 20.0),
+            ),
+            None => Self::Stereo(ParametricEq::from_profile(
+                profile,
+                sample_rate,
+                channels as usize,
+            )),
+        }
+    }
+
+    /// Takes over a processor built for an edited profile. The linear-phase
+    /// EQ only swaps its kernel so the signal in flight isn't dropped, and
+    /// the biquads keep their state while the band count stays the same.
+    /// Returns what is left over, for the caller to free off the audio
+    /// thread.
+    fn replace(&mut self, mut new: Processor) -> Processor {
+        match (&mut *self, &mut new) {
+            (Self::LinearPhase(current, gain), Self::LinearPhase(new, new_gain)) => {
+                current.swap_kernel(new);
+                std::mem::swap(gain, new_gain);
+            }
+            (Self::Stereo(current), Self::Stereo(new)) => current.swap_coeffs(new),
+            (Self::MidSide(current), Self::MidSide(new)) => current.swap_coeffs(new),
+            _ => std::mem::swap(self, &mut new),
+        }
+        new
+    }
+
+    /// Frames the EQ delays the signal by.
+    fn latency_frames(&self) -> usize {
+        match self {
+            Self::LinearPhase(..) => fir::latency_frames(LINEAR_PHASE_TAPS),
+            Self::Stereo(_) | Self::MidSide(_) => 0,
+        }
+    }
+
+    fn process_buffer(&mut self, data: &mut [f32]) {
+        match self {
+            Self::Stereo(eq) => eq.process_block(data),
+            Self::MidSide(ms) => ms.process_buffer(data),
+            Self::LinearPhase(convolver, _) => convolver.process_buffer(data),
+        }
+    }
+
+    /// Stands in for [`process_buffer`](Self::process_buffer) while the EQ
+    /// is off. A level-matched bypass keeps the profile's preamp, so
+    /// switching compares the bands at the same loudness. The linear-phase
+    /// EQ keeps convolving and delays the bypassed signal as much, so
+    /// neither switch jumps in time or replays old audio.
+    fn process_bypassed(&mut self, data: &mut [f32], matched: bool) {
+        let gain = match self {
+            Self::LinearPhase(convolver, gain) => {
+                return convolver.process_bypassed(data, if matched { *gain } else { 1.0 });
+            }
+            _ if !matched => return,
+            Self::Stereo(eq) => eq.preamp_gain(),
+            Self::MidSide(ms) => return ms.process_preamps(data),
+        };
+        for sample in data {
+            *sample *= gain;
+        }
+    }
+}
+
+/// Orders config ranges by their smallest supported buffer, unknown last.
+fn by_min_buffer(a: &&SupportedStreamConfigRange, b: &&SupportedStreamConfigRange) -> Ordering {
+    match (a.buffer_size(), b.buffer_size()) {
+        (Range { min: a_min, max: _ }, Range { min: b_min, max: _ }) => a_min.cmp(b_min),
+        (Range { min: _, max: _ }, Unknown) => Ordering::Less,
+        (Unknown, Range { min: _, max: _ }) => Ordering::Greater,
+        (Unknown, Unknown) => Ordering::Equal,
+    }
+}
+
+fn supports_rate(range: &SupportedStreamConfigRange, sample_rate: u32) -> bool {
+    (range.min_sample_rate()..=range.max_sample_rate()).contains(&sample_rate)
+}
+
+/// A config range for error messages, e.g. "44100-48000 Hz, 2 ch, f32".
+fn describe_range(range: &SupportedStreamConfigRange) -> String {
+    let rates = if range.min_sample_rate() == range.max_sample_rate() {
+        range.min_sample_rate().to_string()
+    } else {
+        format!("{}-{}", range.min_sample_rate(), range.max_sample_rate())
     };
-    let channels = input_config_range
-        .channels()
-        .min(output_config_range.channels());
-    let stream_config = StreamConfig {
-        channels,
+    format!(
+        "{} Hz, {} ch, {}",
+        rates,
+        range.channels(),
+        range.sample_format()
+    )
+}
+
+fn describe_ranges(ranges: &[SupportedStreamConfigRange]) -> String {
+    if ranges.is_empty() {
+        return "nothing".to_string();
+    }
+    ranges
+        .iter()
+        .map(describe_range)
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+/// The first input and output ranges, smallest buffers first, that share a
+/// sample rate, and the lowest rate they share.
+fn compatible_ranges<'a>(
+    input_configs: &'a [SupportedStreamConfigRange],
+    output_configs: &'a [SupportedStreamConfigRange],
+) -> Result<(
+    &'a SupportedStreamConfigRange,
+    &'a SupportedStreamConfigRange,
+    u32,
+)> {
+    let mut inputs: Vec<_> = input_configs.iter().collect();
+    let mut outputs: Vec<_> = output_configs.iter().collect();
+    inputs.sort_by(by_min_buffer);
+    outputs.sort_by(by_min_buffer);
+    inputs
+        .iter()
+        .flat_map(|input| outputs.iter().map(move |output| (*input, *output)))
+        .find_map(|(input, output)| {
+            let rate = input.min_sample_rate().max(output.min_sample_rate());
+            let max = input.max_sample_rate().min(output.max_sample_rate());
+            (rate <= max).then_some((input, output, rate))
+        })
+        .with_context(|| {
+            format!(
+                "No stream config suits both devices. Input offers {}; output offers {}",
+                describe_ranges(input_configs),
+                describe_ranges(output_configs)
+            )
+        })
+}
+
+/// The input device's default config when the output can run at its rate and
+/// channel count; otherwise, or when there is no default, a config both
+/// devices support.
+fn default_stream_config(
+    default: Result<SupportedStreamConfig>,
+    input_configs: &[SupportedStreamConfigRange],
+    output_configs: &[SupportedStreamConfigRange],
+) -> Result<StreamConfig> {
+    match default {
+        Ok(default)
+            if output_configs.iter().any(|r| {
+                supports_rate(r, default.sample_rate()) && r.channels() >= default.channels()
+            }) =>
+        {
+            return Ok(default.into());
+        }
+        Ok(default) => warn!(
+            "The output can't run the input's default {} Hz, {} ch, looking for another config",
+            default.sample_rate(),
+            default.channels()
+        ),
+        Err(e) => warn!("No default input config ({:#}), looking for another", e),
+    }
+    choose_stream_config(input_configs, output_configs, None, None)
+}
+
+/// Picks a stream config both devices support. A requested sample rate or
+/// buffer size is used when both sides accept it; otherwise it is ignored
+/// with a warning and chosen automatically, preferring the smallest buffer.
+fn choose_stream_config(
+    input_configs: &[SupportedStreamConfigRange],
+    output_configs: &[SupportedStreamConfigRange],
+    sample_rate: Option<u32>,
+    buffer_size: Option<u32>,
+) -> Result<StreamConfig> {
+    let requested = sample_rate.and_then(|rate| {
+        let input = input_configs
+            .iter()
+            .filter(|r| supports_rate(r, rate))
+            .min_by(by_min_buffer)?;
+        let output = output_configs
+            .iter()
+            .filter(|r| supports_rate(r, rate))
+            .min_by(by_min_buffer)?;
+        Some((input, output, rate))
+    });
+    let (input_range, output_range, sample_rate) = match (requested, sample_rate) {
+        (Some(requested), _) => requested,
+        (None, requested_rate) => {
+            if let Some(rate) = requested_rate {
+                warn!(
+                    "{} Hz isn't supported by both devices, choosing automatically",
+                    rate
+                );
+            }
+            compatible_ranges(input_configs, output_configs)?
+        }
+    };
+    let buffer_size = match (input_range.buffer_size(), output_range.buffer_size()) {
+        (
+            Range {
+                min: a_min,
+                max: a_max,
+            },
+            Range {
+                min: b_min,
+                max: b_max,
+            },
+        ) => {
+            let min = *a_min.max(b_min);
+            match buffer_size {
+                Some(frames) if (min..=*a_max.min(b_max)).contains(&frames) => {
+                    cpal::BufferSize::Fixed(frames)
+                }
+                Some(frames) => {
+                    warn!("Buffer size {} isn't supported by both devices", frames);
+                    cpal::BufferSize::Fixed(min)
+                }
+                None => cpal::BufferSize::Fixed(min),
+            }
+        }
+        _ => {
+            if let Some(frames) = buffer_size {
+                warn!("Buffer size {} can't be set on these devices", frames);
+            }
+            cpal::BufferSize::Default
+        }
+    };
+    Ok(StreamConfig {
+        channels: input_range.channels().min(output_range.channels()),
         sample_rate,
         buffer_size,
+    })
+}
+
+/// Turns a latency target in ms into ring buffer samples for one stream.
+#[derive(Debug, Clone, Copy)]
+pub struct LatencyTarget {
+    pub sample_rate: u32,
+    /// Frames per device callback
+    pub buffer_frames: usize,
+    pub channels: u16,
+    /// Fraction of the target the ring buffer starts filled with, see
+    /// [`Self::primed`]
+    pub priming: Option<f32>,
+    /// Ring buffer capacity as a multiple of the target, see
+    /// [`Self::capacity`]
+    pub ring_factor: Option<f32>,
+}
+
+impl LatencyTarget {
+    /// Without a fixed buffer size the device's is unknown; 1 ms is assumed.
+    pub fn new(config: &StreamConfig, priming: Option<f32>, ring_factor: Option<f32>) -> Self {
+        let buffer_frames = match config.buffer_size {
+            cpal::BufferSize::Fixed(frames) => frames as usize,
+            cpal::BufferSize::Default => config.sample_rate as usize / 1000,
+        };
+        Self {
+            sample_rate: config.sample_rate,
+            buffer_frames,
+            channels: config.channels,
+            priming,
+            ring_factor,
+        }
+    }
+
+    /// Samples held back for `latency_ms`: whole frames, and at least one
+    /// device buffer so the output isn't starved on every callback.
+    pub fn samples(&self, latency_ms: u32) -> usize {
+        let frames = self.sample_rate as usize * latency_ms as usize / 1000;
+        frames.max(self.buffer_frames) * self.channels as usize
+    }
+
+    /// Silence a ring buffer for `target` samples starts with: `priming`
+    /// of the target, all of it when unset. Less lowers the latency, at the
+    /// risk of a gap if the input is slow to start.
+    /// Whole frames, and never more than the buffer holds.
+    pub fn primed(&self, target: usize) -> usize {
+        let fraction = self.priming.unwrap_or(1.0).max(0.0) as f64;
+        let samples = ((target as f64 * fraction) as usize).min(self.capacity(target));
+        samples - samples % self.channels.max(1) as usize
+    }
+
+    /// Estimated delay from input to output: the `ring_samples` the ring
+    /// buffer holds back, a device buffer on either side, and the
+    /// `processing_frames` the EQ itself delays by.
+    pub fn estimate_ms(&self, ring_samples: usize, processing_frames: usize) -> f32 {
+        let frames = ring_samples / self.channels.max(1) as usize
+            + 2 * self.buffer_frames
+            + processing_frames;
+        frames as f32 * 1000.0 / self.sample_rate as f32
+    }
+
+    /// Samples a ring buffer for `target` holds: `ring_factor` times the
+    /// target, twice when unset, leaving room for the input to run ahead.
+    /// Never less than the target itself, and rounded up to whole frames.
+    pub fn capacity(&self, target: usize) -> usize {
+        let factor = self
+            .ring_factor
+            .filter(|f| f.is_finite())
+            .unwrap_or(DEFAULT_RING_FACTOR)
+            .max(1.0);
+        let samples = (target as f64 * factor as f64).ceil() as usize;
+        samples
+            .next_multiple_of(self.channels.max(1) as usize)
+            .max(1)
+    }
+}
+
+const DEFAULT_RING_FACTOR: f32 = 2.0;
+
+/// A ring buffer of `capacity` samples, primed with `primed` samples of
+/// silence so the output starts that far behind the input.
+fn latency_ring(capacity: usize, primed: usize) -> (HeapProd<f32>, HeapCons<f32>) {
+    let (mut producer, consumer) = HeapRb::<f32>::new(capacity).split();
+    producer.push_iter(std::iter::repeat_n(0.0, primed));
+    (producer, consumer)
+}
+
+/// A ring buffer built for a new latency target, waiting for the stream
+/// callbacks to pick up their halves, and the EQ built for the latest
+/// profile, waiting for the output callback. The streams keep running; the
+/// audio in the old buffer is dropped, so a latency change is a short gap.
+/// What the callbacks replace goes back the other way, to be freed by the
+/// control loop: freeing memory in a callback can block.
+struct Handover {
+    producer: Mutex<Option<HeapProd<f32>>>,
+    consumer: Mutex<Option<(HeapCons<f32>, usize)>>,
+    eq: Mutex<Option<Processor>>,
+    retired: Mutex<Vec<Retired>>,
+}
+
+/// Something a stream callback replaced, waiting to be freed. Only ever
+/// dropped, never read.
+#[allow(dead_code)]
+enum Retired {
+    Producer(HeapProd<f32>),
+    Consumer(HeapCons<f32>),
+    Eq(Processor),
+}
+
+/// Replaced objects the callbacks can queue between two control loop
+/// passes; more than that are freed in the callback after all.
+const RETIRED_CAPACITY: usize = 16;
+
+impl Default for Handover {
+    fn default() -> Self {
+        Self {
+            producer: Mutex::default(),
+            consumer: Mutex::default(),
+            eq: Mutex::default(),
+            retired: Mutex::new(Vec::with_capacity(RETIRED_CAPACITY)),
+        }
+    }
+}
+
+impl Handover {
+    /// Queues a new ring buffer when `latency` moved away from `*current`,
+    /// returning the samples of silence it is primed with. Called by the runners'
+    /// control loop, for both the GUI and the CLI.
+    fn follow(
+        &self,
+        latency: &AtomicU32,
+        current: &mut u32,
+        target: LatencyTarget,
+    ) -> Option<usize> {
+        let latency_ms = latency.load(std::sync::atomic::Ordering::Relaxed);
+        if latency_ms == *current {
+            return None;
+        }
+        *current = latency_ms;
+        let samples = target.samples(latency_ms);
+        info!("Latency target now {} ms ({} samples)", latency_ms, samples);
+        let primed = target.primed(samples);
+        let (producer, consumer) = latency_ring(target.capacity(samples), primed);
+        if let Ok(mut slot) = self.producer.lock() {
+            *slot = Some(producer);
+        }
+        if let Ok(mut slot) = self.consumer.lock() {
+            *slot = Some((consumer, samples));
+        }
+        Some(primed)
+    }
+
+    /// For the input callback; never blocks.
+    fn take_producer(&self, producer: &mut HeapProd<f32>) {
+        if let Ok(mut slot) = self.producer.try_lock()
+            && let Some(new) = slot.take()
+        {
+            self.retire(Retired::Producer(std::mem::replace(producer, new)));
+        }
+    }
+
+    /// Queues an EQ for the output callback, replacing one it hasn't taken
+    /// yet.
+    fn offer_eq(&self, eq: Processor) {
+        if let Ok(mut slot) = self.eq.lock() {
+            *slot = Some(eq);
+        }
+    }
+
+    /// For the output callback, once per buffer; never blocks.
+    fn take_eq(&self, eq: &mut Processor) {
+        if let Ok(mut slot) = self.eq.try_lock()
+            && let Some(new) = slot.take()
+        {
+            self.retire(Retired::Eq(eq.replace(new)));
+        }
+    }
+
+    /// For the output callback, with the target the new buffer is primed for.
+    fn take_consumer(&self, consumer: &mut HeapCons<f32>, target: &mut usize) {
+        if let Ok(mut slot) = self.consumer.try_lock()
+            && let Some((new, new_target)) = slot.take()
+        {
+            self.retire(Retired::Consumer(std::mem::replace(consumer, new)));
+            *target = new_target;
+        }
+    }
+
+    /// For the callbacks; never blocks or allocates. When the queue is busy
+    /// or full, `retired` is freed here after all.
+    fn retire(&self, retired: Retired) {
+        if let Ok(mut queue) = self.retired.try_lock()
+            && queue.len() < queue.capacity()
+        {
+            queue.push(retired);
+        }
+    }
+
+    /// Frees what the callbacks retired. For the control loop.
+    fn free_retired(&self) {
+        let retired: Vec<Retired> = match self.retired.lock() {
+            Ok(mut queue) => queue.drain(..).collect(),
+            Err(_) => return,
+        };
+        drop(retired);
+    }
+}
+
+/// Waits up to `timeout` for a profile and returns the newest one queued, so
+/// a burst of edits such as a realtime drag is built and applied once.
+fn next_profile<T>(receiver: &Receiver<T>, timeout: Duration) -> Result<T, RecvTimeoutError> {
+    let first = receiver.recv_timeout(timeout)?;
+    Ok(receiver.try_iter().last().unwrap_or(first))
+}
+
+const COMMON_SAMPLE_RATES: [u32; 6] = [44100, 48000, 88200, 96000, 176400, 192000];
+
+/// What can be requested from a device pair, for the UI dropdowns.
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(not(feature = "gui"), allow(dead_code))]
+pub struct StreamOptions {
+    /// Common rates both devices accept
+    pub sample_rates: Vec<u32>,
+    /// Buffer sizes in frames both devices accept, when they report a range
+    pub buffer_sizes: Option<(u32, u32)>,
+}
+
+pub fn stream_options(input: &Input, output_device: &Device) -> Result<StreamOptions> {
+    let input_configs = input.supported_configs()?;
+    let output_configs: Vec<_> = output_device.supported_output_configs()?.collect();
+    let sample_rates = COMMON_SAMPLE_RATES
+        .into_iter()
+        .filter(|rate| {
+            input_configs.iter().any(|r| supports_rate(r, *rate))
+                && output_configs.iter().any(|r| supports_rate(r, *rate))
+        })
+        .collect();
+    let buffer_range = |configs: &[SupportedStreamConfigRange]| {
+        configs
+            .iter()
+            .filter_map(|r| match r.buffer_size() {
+                Range { min, max } => Some((*min, *max)),
+                Unknown => None,
+            })
+            .reduce(|(a_min, a_max), (b_min, b_max)| (a_min.min(b_min), a_max.max(b_max)))
     };
-    info!("Selected stream config: {stream_config:?}");
+    let buffer_sizes = match (buffer_range(&input_configs), buffer_range(&output_configs)) {
+        (Some((a_min, a_max)), Some((b_min, b_max))) if a_min.max(b_min) <= a_max.min(b_max) => {
+            Some((a_min.max(b_min), a_max.min(b_max)))
+        }
+        _ => None,
+    };
+    Ok(StreamOptions {
+        sample_rates,
+        buffer_sizes,
+    })
+}
+
+/// Runs until `settings.instance_id` moves away from `instance_id`, which the
+/// caller snapshots before spawning so an early stop can't be missed.
+/// Profiles sent on `receiver` replace the EQ without restarting the streams.
+/// With a `side_profile` the stream is processed as mid/side, `profile`
+/// being the mid; that needs a stereo stream. Fails with [`DeviceLost`] when
+/// either device disappears.
+pub fn run(
+    input: Input,
+    output_device: Device,
+    settings: Settings,
+    profile: EqProfile,
+    side_profile: Option<EqProfile>,
+    receiver: Receiver<EqProfile>,
+    instance_id: usize,
+) -> Result<()> {
+    run_engine(
+        input,
+        output_device,
+        settings,
+        profile,
+        side_profile,
+        receiver,
+        instance_id,
+        false,
+    )
+}
+
+/// Like [`run`], for realtime editing: without a configured rate or buffer
+/// size it opens the input's default config, and it stops once the
+/// profile sender is dropped.
+pub fn run_realtime(
+    input: Input,
+    output_device: Device,
+    settings: Settings,
+    profile: EqProfile,
+    side_profile: Option<EqProfile>,
+    receiver: Receiver<EqProfile>,
+    instance_id: usize,
+) -> Result<()> {
+    run_engine(
+        input,
+        output_device,
+        settings,
+        profile,
+        side_profile,
+        receiver,
+        instance_id,
+        true,
+    )
+}
 
-    let mut eq = ParametricEq::from_profile(&profile, sample_rate as f32);
-    let ring_buffer = HeapRb::<f32>::new(
-        if let cpal::BufferSize::Fixed(size) = stream_config.buffer_size {
-            size as usize
+#[allow(clippy::too_many_arguments)]
+fn run_engine(
+    input: Input,
+    output_device: Device,
+    settings: Settings,
+    profile: EqProfile,
+    side_profile: Option<EqProfile>,
+    receiver: Receiver<EqProfile>,
+    instance_id: usize,
+    realtime: bool,
+) -> Result<()> {
+    let input_configs = input.supported_configs()?;
+    let output_configs: Vec<_> = output_device.supported_output_configs()?.collect();
+    let stream_config =
+        if realtime && settings.sample_rate.is_none() && settings.buffer_size.is_none() {
+            default_stream_config(input.default_config(), &input_configs, &output_configs)?
         } else {
-            sample_rate as usize / 1000
-        } * channels as usize
-            * 2,
-    ); // stereo
-    let (mut producer, mut consumer) = ring_buffer.split();
+            choose_stream_config(
+                &input_configs,
+                &output_configs,
+                settings.sample_rate,
+                settings.buffer_size,
+            )?
+        };
+    let StreamConfig {
+        channels,
+        sample_rate,
+        ..
+    } = stream_config;
+    let input_format = choose_format(&input_configs, &stream_config);
+    let output_format = choose_format(&output_configs, &stream_config);
+    info!("Selected stream config: {stream_config:?}, {input_format} in, {output_format} out");
+    if side_profile.is_some() && channels != 2 {
+        bail!(
+            "Mid/side mode needs a stereo stream, got {} channels",
+            channels
+        );
+    }
+
+    let mut eq = Processor::new(
+        &profile,
+        side_profile.as_ref(),
+        sample_rate as f32,
+        &settings,
+        channels,
+    );
+    if let Processor::LinearPhase(..) = eq {
+        info!(
+            "Linear phase EQ adds {} frames of latency",
+            fir::latency_frames(LINEAR_PHASE_TAPS)
+        );
+    }
+    let latency_target = LatencyTarget::new(&stream_config, settings.priming, settings.ring_factor);
+    let mut latency_ms = settings.latency.load(std::sync::atomic::Ordering::Relaxed);
+    let mut target = latency_target.samples(latency_ms);
+    let primed = latency_target.primed(target);
+    let (mut producer, mut consumer) = latency_ring(latency_target.capacity(target), primed);
+    let handover = Arc::new(Handover::default());
+    let processing_frames = eq.latency_frames();
+    report_latency(
+        &settings.status,
+        latency_target.estimate_ms(primed, processing_frames),
+    );
 
+    let status = settings.status.clone();
+    status
+        .meters
+        .channels
+        .store(channels as usize, std::sync::atomic::Ordering::Relaxed);
+    let input_handover = handover.clone();
     let input_data_fn = move |data: &[f32], _: &cpal::InputCallbackInfo| {
-        producer.push_slice(data);
+        input_handover.take_producer(&mut producer);
+        status.meters.input.record(data, channels as usize);
+        push_input(&mut producer, data, &status);
     };
+    let overflow = settings.overflow;
+    let output_handover = handover.clone();
     let settings_cloned = settings.clone();
+    let mut output_stage = OutputStage::new(settings.clone(), channels, sample_rate);
+    let mut dc_blocker = DcBlocker::new(channels, sample_rate);
+    let mut analyzer = analyzer_tap(&settings.status, sample_rate);
+    let mut fill = FillWindow::new(sample_rate);
+    let bits = dsp::dither_bits(output_format);
+    let mut dither = Dither::new(channels);
     let output_data_fn = move |data: &mut [f32], _: &cpal::OutputCallbackInfo| {
+        output_handover.take_consumer(&mut consumer, &mut target);
+        output_handover.take_eq(&mut eq);
         let eq_enabled = settings_cloned
             .enable_eq
             .load(std::sync::atomic::Ordering::Relaxed);
-        consumer.pop_slice(data);
+        fill.record(
+            &consumer,
+            data.len() / channels as usize,
+            &settings_cloned.status.ring_fill,
+        );
+        pop_output(
+            &mut consumer,
+            data,
+            overflow,
+            target,
+            channels as usize,
+            &settings_cloned.status,
+        );
+        if settings_cloned
+            .dc_blocker
+            .load(std::sync::atomic::Ordering::Relaxed)
+        {
+            dc_blocker.process(data);
+        } else {
+            dc_blocker.reset();
+        }
         if eq_enabled {
             eq.process_buffer(data);
+        } else {
+            eq.process_bypassed(
+                data,
+                settings_cloned
+                    .matched_bypass
+                    .load(std::sync::atomic::Ordering::Relaxed),
+            );
+        }
+        output_stage.process(data);
+        let status = &settings_cloned.status;
+        status.meters.output.record(data, channels as usize);
+        feed_analyzer(status, &mut analyzer, data, channels as usize);
+        if let Some(bits) = bits {
+            dither.process(data, bits, &settings_cloned.dither);
         }
     };
-    let input_stream =
-        input_device.build_input_stream(&stream_config, input_data_fn, err_fn, None)?;
-    let output_stream =
-        output_device.build_output_stream(&stream_config, output_data_fn, err_fn, None)?;
+    let lost = Arc::new(AtomicBool::new(false));
+    let input_stream = format::build_input_stream(
+        input.device(),
+        &stream_config,
+        input_format,
+        input_data_fn,
+        err_fn(settings.status.clone(), lost.clone()),
+    )?;
+    let output_stream = format::build_output_stream(
+        &output_device,
+        &stream_config,
+        output_format,
+        output_data_fn,
+        err_fn(settings.status.clone(), lost.clone()),
+    )?;
     input_stream.play()?;
     output_stream.play()?;
-    let instance_id = settings
-        .instance_id
-        .load(std::sync::atomic::Ordering::Relaxed);
     loop {
-        sleep(Duration::from_millis(100 as u64));
+        match next_profile(&receiver, Duration::from_millis(100)) {
+            // Profile edits swap the EQ in place; the streams keep running
+            Ok(profile) => {
+                let new_eq = Processor::new(
+                    &profile,
+                    side_profile.as_ref(),
+                    sample_rate as f32,
+                    &settings,
+                    channels,
+                );
+                handover.offer_eq(new_eq);
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            // Realtime editing ended
+            Err(RecvTimeoutError::Disconnected) if realtime => break,
+            // Nobody sends live updates (e.g. the CLI); just poll instance_id
+            Err(RecvTimeoutError::Disconnected) => sleep(Duration::from_millis(100)),
+        }
+        handover.free_retired();
+        if let Some(primed) = handover.follow(&settings.latency, &mut latency_ms, latency_target) {
+            report_latency(
+                &settings.status,
+                latency_target.estimate_ms(primed, processing_frames),
+            );
+        }
+        if lost.load(std::sync::atomic::Ordering::Relaxed) {
+            stop_streams(&input_stream, &output_stream);
+            return Err(DeviceLost.into());
+        }
         if instance_id
             != settings
                 .instance_id
@@ -118,59 +870,613 @@ pub fn run(
             break;
         }
     }
+    stop_streams(&input_stream, &output_stream);
+    settings.status.meters.clear();
+    settings.status.latency_ms.store(0.0);
+    settings.status.ring_fill.clear();
+    debug!("Engine exited");
     Ok(())
 }
 
-pub fn run_realtime(
-    input_device: Device,
-    output_device: Device,
-    settings: Settings,
-    profile: EqProfile,
-    receiver: Receiver<EqProfile>,
-) -> Result<()> {
-    let stream_config: StreamConfig = input_device.default_input_config()?.into();
+fn report_latency(status: &Status, latency_ms: f32) {
+    info!("Estimated latency {:.1} ms", latency_ms);
+    status.latency_ms.store(latency_ms);
+}
 
-    let eq = ParametricEq::from_profile(&profile, stream_config.sample_rate as f32);
-    let latency_frames = stream_config.sample_rate as u32 / 1000;
-    let ring_buffer = HeapRb::<f32>::new(latency_frames as usize * 2 * 2); // stereo
-    let (mut producer, mut consumer) = ring_buffer.split();
-    for _ in 0..latency_frames {
-        producer.try_push(0.0).unwrap()
+/// Room for a few UI frames of analyzer samples; the rest is dropped.
+const ANALYZER_CAPACITY: usize = 16384;
+
+/// Hands a fresh analyzer buffer to the UI and returns the producer half.
+fn analyzer_tap(status: &Status, sample_rate: u32) -> HeapProd<f32> {
+    let (producer, consumer) = HeapRb::<f32>::new(ANALYZER_CAPACITY).split();
+    status
+        .analyzer
+        .sample_rate
+        .store(sample_rate, std::sync::atomic::Ordering::Relaxed);
+    if let Ok(mut samples) = status.analyzer.samples.lock() {
+        *samples = Some(consumer);
     }
+    producer
+}
 
-    let input_data_fn = move |data: &[f32], _: &cpal::InputCallbackInfo| {
-        for &sample in data {
-            producer.try_push(sample).ok();
+/// Feeds the analyzer a mono downmix of `data` while it is shown.
+fn feed_analyzer(status: &Status, producer: &mut HeapProd<f32>, data: &[f32], channels: usize) {
+    if !status
+        .analyzer
+        .enabled
+        .load(std::sync::atomic::Ordering::Relaxed)
+    {
+        return;
+    }
+    let scale = 1.0 / channels as f32;
+    for frame in data.chunks_exact(channels) {
+        if producer
+            .try_push(frame.iter().sum::<f32>() * scale)
+            .is_err()
+        {
+            break;
         }
-    };
-    let settings_cloned = settings.clone();
-    let eq = Arc::new(Mutex::new(eq));
-    let eq_cloned = eq.clone();
-    let output_data_fn = move |data: &mut [f32], _: &cpal::OutputCallbackInfo| {
-        let eq_enabled = settings_cloned
-            .enable_eq
-            .load(std::sync::atomic::Ordering::Relaxed);
-        let mut eq = eq_cloned.try_lock();
-        consumer.pop_slice(data);
-        if eq_enabled && let Ok(eq) = eq.as_mut() {
-            eq.process_buffer(data);
+    }
+}
+
+/// Pauses output before input so the device stops pulling from the ring
+/// buffer first, then lets both streams drop on return.
+fn stop_streams(input_stream: &cpal::Stream, output_stream: &cpal::Stream) {
+    output_stream
+        .pause()
+        .inspect_err(|e| error!("Failed to pause output stream: {e}"))
+        .ok();
+    input_stream
+        .pause()
+        .inspect_err(|e| error!("Failed to pause input stream: {e}"))
+        .ok();
+}
+
+/// Returned by the runners when a stream's device went away, so the caller
+/// can wait for it to come back.
+#[derive(Debug)]
+pub struct DeviceLost;
+
+impl std::fmt::Display for DeviceLost {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Audio device disconnected")
+    }
+}
+
+impl std::error::Error for DeviceLost {}
+
+/// The lowest and highest ring buffer fill the output callback finds,
+/// published to [`RingFill`] once per second of output.
+struct FillWindow {
+    window_frames: usize,
+    frames: usize,
+    min: usize,
+    max: usize,
+}
+
+impl FillWindow {
+    fn new(sample_rate: u32) -> Self {
+        Self {
+            window_frames: sample_rate as usize,
+            frames: 0,
+            min: usize::MAX,
+            max: 0,
         }
-    };
-    let input_stream =
-        input_device.build_input_stream(&stream_config, input_data_fn, err_fn, None)?;
-    let output_stream =
-        output_device.build_output_stream(&stream_config, output_data_fn, err_fn, None)?;
-    input_stream.play()?;
-    output_stream.play()?;
-    while let Ok(profile) = receiver.recv() {
-        if let Ok(mut eq) = eq.try_lock() {
-            *eq = ParametricEq::from_profile(&profile, stream_config.sample_rate as f32);
+    }
+
+    /// Called before the output callback takes its `frames`.
+    fn record(&mut self, consumer: &impl Consumer<Item = f32>, frames: usize, fill: &RingFill) {
+        let occupied = consumer.occupied_len();
+        self.min = self.min.min(occupied);
+        self.max = self.max.max(occupied);
+        self.frames += frames;
+        if self.frames >= self.window_frames {
+            fill.capacity.store(
+                consumer.capacity().get(),
+                std::sync::atomic::Ordering::Relaxed,
+            );
+            fill.min
+                .store(self.min, std::sync::atomic::Ordering::Relaxed);
+            fill.max
+                .store(self.max, std::sync::atomic::Ordering::Relaxed);
+            *self = Self::new(self.window_frames as u32);
         }
     }
-    debug!("run_realtime exited");
-    Ok(())
 }
 
-fn err_fn(err: cpal::StreamError) {
-    error!("an error occurred on stream: {err}");
+/// Queues input for the output callback. Whatever doesn't fit is lost and
+/// counted as an overrun.
+fn push_input(producer: &mut impl Producer<Item = f32>, data: &[f32], status: &Status) {
+    if producer.push_slice(data) < data.len() {
+        status.record_overrun();
+    }
+}
+
+/// Fills `data` from the ring buffer, padding with silence on an underrun.
+/// With [`OverflowPolicy::DropOldest`], a buffer more than three quarters
+/// full is first skipped ahead in whole frames, so `target` samples are left
+/// after this callback, before the input has to drop anything.
+fn pop_output(
+    consumer: &mut impl Consumer<Item = f32>,
+    data: &mut [f32],
+    overflow: OverflowPolicy,
+    target: usize,
+    channels: usize,
+    status: &Status,
+) {
+    if overflow == OverflowPolicy::DropOldest
+        && consumer.occupied_len() > consumer.capacity().get() / 4 * 3
+    {
+        let excess = consumer.occupied_len().saturating_sub(target + data.len());
+        if consumer.skip(excess - excess % channels.max(1)) > 0 {
+            status.record_overrun();
+        }
+    }
+    let popped = consumer.pop_slice(data);
+    if popped < data.len() {
+        data[popped..].fill(0.0);
+        status.record_underrun();
+    }
+}
+
+/// Stream errors are logged and surfaced in the UI status line. A device
+/// that is gone also sets `lost`, which ends the runner's loop.
+fn err_fn(
+    status: Arc<Status>,
+    lost: Arc<AtomicBool>,
+) -> impl FnMut(cpal::StreamError) + Send + 'static {
+    move |err| {
+        error!("an error occurred on stream: {err}");
+        if let cpal::StreamError::DeviceNotAvailable = err {
+            lost.store(true, std::sync::atomic::Ordering::Relaxed);
+        }
+        status.set_error(err);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cpal::{SampleFormat, SupportedBufferSize};
+    use ringbuf::traits::Observer;
+
+    use super::*;
+    use crate::settings::PersistentSettings;
+
+    fn range(
+        min_rate: u32,
+        max_rate: u32,
+        buffer: Option<(u32, u32)>,
+    ) -> SupportedStreamConfigRange {
+        let buffer_size = match buffer {
+            Some((min, max)) => SupportedBufferSize::Range { min, max },
+            None => SupportedBufferSize::Unknown,
+        };
+        SupportedStreamConfigRange::new(2, min_rate, max_rate, buffer_size, SampleFormat::F32)
+    }
+
+    #[test]
+    fn test_overflow_policies() {
+        // Stereo, room for 8 frames; the latency target is 2 frames
+        let input: Vec<f32> = (0..24).map(|i| i as f32).collect();
+        let mut out = [0.0; 4];
+
+        let status = Status::default();
+        let (mut producer, mut consumer) = HeapRb::<f32>::new(16).split();
+        push_input(&mut producer, &input[..14], &status);
+        pop_output(
+            &mut consumer,
+            &mut out,
+            OverflowPolicy::DropNewest,
+            4,
+            2,
+            &status,
+        );
+        assert_eq!(out, [0.0, 1.0, 2.0, 3.0]);
+        // Only 6 of the next 10 samples fit
+        push_input(&mut producer, &input[14..], &status);
+        assert_eq!(
+            status.overruns.load(std::sync::atomic::Ordering::Relaxed),
+            1
+        );
+        assert_eq!(consumer.occupied_len(), 16);
+        assert_eq!(consumer.pop_iter().last(), Some(19.0));
+
+        let status = Status::default();
+        let (mut producer, mut consumer) = HeapRb::<f32>::new(16).split();
+        push_input(&mut producer, &input[..14], &status);
+        // Skips ahead so 2 frames are left after this callback
+        pop_output(
+            &mut consumer,
+            &mut out,
+            OverflowPolicy::DropOldest,
+            4,
+            2,
+            &status,
+        );
+        assert_eq!(out, [6.0, 7.0, 8.0, 9.0]);
+        assert_eq!(consumer.occupied_len(), 4);
+        push_input(&mut producer, &input[14..], &status);
+        assert_eq!(
+            status.overruns.load(std::sync::atomic::Ordering::Relaxed),
+            1
+        );
+        assert_eq!(consumer.pop_iter().last(), Some(23.0));
+
+        // Running dry pads with silence either way
+        pop_output(
+            &mut consumer,
+            &mut out,
+            OverflowPolicy::DropOldest,
+            4,
+            2,
+            &status,
+        );
+        assert_eq!(out, [0.0; 4]);
+        assert_eq!(
+            status.underruns.load(std::sync::atomic::Ordering::Relaxed),
+            1
+        );
+    }
+
+    #[test]
+    fn test_pick_device() {
+        let device = |id: Option<&str>, name: &str| (id.map(String::from), Some(name.to_string()));
+        let devices = [
+            device(Some("alsa:hw:1"), "USB DAC"),
+            device(Some("alsa:hw:2"), "USB DAC"),
+            device(None, "Speakers"),
+        ];
+        // The id tells identical devices apart, and survives a rename
+        assert_eq!(pick_device(&devices, "USB DAC", Some("alsa:hw:2")), Some(1));
+        assert_eq!(
+            pick_device(&devices, "Old name", Some("alsa:hw:2")),
+            Some(1)
+        );
+        // Without a known id the name decides
+        assert_eq!(pick_device(&devices, "USB DAC", None), Some(0));
+        assert_eq!(
+            pick_device(&devices, "Speakers", Some("alsa:hw:9")),
+            Some(2)
+        );
+        assert_eq!(pick_device(&devices, "Headphones", Some("alsa:hw:9")), None);
+    }
+
+    #[test]
+    fn test_choose_stream_config() {
+        let input = [
+            range(44100, 48000, Some((64, 2048))),
+            range(96000, 96000, Some((256, 4096))),
+        ];
+        let output = [range(44100, 96000, Some((128, 1024)))];
+
+        let config = choose_stream_config(&input, &output, Some(48000), Some(256)).unwrap();
+        assert_eq!(config.sample_rate, 48000);
+        assert_eq!(config.buffer_size, cpal::BufferSize::Fixed(256));
+
+        let config = choose_stream_config(&input, &output, Some(96000), Some(128)).unwrap();
+        assert_eq!(config.sample_rate, 96000);
+        // 128 is below the 96 kHz input range, fall back to the smallest both accept
+        assert_eq!(config.buffer_size, cpal::BufferSize::Fixed(256));
+
+        // Unsupported rate falls back to the automatic choice
+        let config = choose_stream_config(&input, &output, Some(192000), None).unwrap();
+        assert_eq!(config.sample_rate, 44100);
+        assert_eq!(config.buffer_size, cpal::BufferSize::Fixed(128));
+
+        let config =
+            choose_stream_config(&input, &[range(48000, 48000, None)], None, Some(64)).unwrap();
+        assert_eq!(config.sample_rate, 48000);
+        assert_eq!(config.buffer_size, cpal::BufferSize::Default);
+        assert!(choose_stream_config(&[], &output, None, None).is_err());
+
+        // The smallest buffers are at rates the other side lacks
+        let input = [
+            range(44100, 48000, Some((32, 2048))),
+            range(88200, 96000, Some((256, 2048))),
+        ];
+        let output = [
+            range(96000, 96000, Some((16, 1024))),
+            range(48000, 48000, Some((512, 1024))),
+        ];
+        let config = choose_stream_config(&input, &output, None, None).unwrap();
+        assert_eq!(config.sample_rate, 48000);
+        assert_eq!(config.buffer_size, cpal::BufferSize::Fixed(512));
+
+        let error = choose_stream_config(&input[..1], &output[..1], None, None)
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("44100-48000 Hz, 2 ch, f32"), "{}", error);
+        assert!(
+            error.contains("output offers 96000 Hz, 2 ch, f32"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn test_default_stream_config_fallback() {
+        let input = [range(44100, 96000, Some((64, 2048)))];
+        let output = [range(48000, 48000, Some((128, 1024)))];
+        let default = |rate| Ok(input[0].with_sample_rate(rate));
+
+        let config = default_stream_config(default(48000), &input, &output).unwrap();
+        assert_eq!(config.sample_rate, 48000);
+        assert_eq!(config.buffer_size, cpal::BufferSize::Default);
+        // The output can't follow the default; the shared rate is used
+        let config = default_stream_config(default(44100), &input, &output).unwrap();
+        assert_eq!(config.sample_rate, 48000);
+        assert_eq!(config.buffer_size, cpal::BufferSize::Fixed(128));
+        // Neither can a virtual device without a default
+        let config =
+            default_stream_config(Err(anyhow::anyhow!("no default")), &input, &output).unwrap();
+        assert_eq!(config.sample_rate, 48000);
+        assert!(default_stream_config(default(48000), &input, &[]).is_err());
+    }
+
+    #[test]
+    fn test_latency_samples() {
+        let target = |sample_rate, buffer_frames, channels| LatencyTarget {
+            sample_rate,
+            buffer_frames,
+            channels,
+            priming: None,
+            ring_factor: None,
+        };
+        assert_eq!(target(48000, 256, 2).samples(10), 960);
+        assert_eq!(target(44100, 256, 2).samples(10), 882);
+        assert_eq!(target(48000, 64, 6).samples(20), 5760);
+        // Never less than a device buffer
+        assert_eq!(target(96000, 256, 1).samples(1), 256);
+        assert_eq!(target(44100, 512, 2).samples(0), 1024);
+
+        let config = StreamConfig {
+            channels: 2,
+            sample_rate: 44100,
+            buffer_size: cpal::BufferSize::Default,
+        };
+        assert_eq!(LatencyTarget::new(&config, None, None).samples(0), 88);
+    }
+
+    #[test]
+    fn test_priming_within_capacity() {
+        let target = |priming| LatencyTarget {
+            sample_rate: 48000,
+            buffer_frames: 64,
+            channels: 2,
+            priming,
+            ring_factor: None,
+        };
+        let samples = target(None).samples(10);
+        assert_eq!(target(None).primed(samples), 960);
+        assert_eq!(target(Some(0.5)).primed(samples), 480);
+        assert_eq!(target(Some(0.0)).primed(samples), 0);
+        // Whole frames only
+        assert_eq!(target(Some(0.001)).primed(samples), 0);
+        assert_eq!(target(Some(0.25)).primed(samples), 240);
+        // Never beyond what the ring buffer holds
+        assert_eq!(target(Some(5.0)).primed(samples), 1920);
+        assert_eq!(target(Some(-1.0)).primed(samples), 0);
+        assert_eq!(target(Some(f32::NAN)).primed(samples), 0);
+        let (_, consumer) = latency_ring(1920, target(Some(5.0)).primed(samples));
+        assert_eq!(consumer.occupied_len(), consumer.capacity().get());
+    }
+
+    #[test]
+    fn test_ring_capacity() {
+        let target = |channels, ring_factor| LatencyTarget {
+            sample_rate: 48000,
+            buffer_frames: 64,
+            channels,
+            priming: None,
+            ring_factor,
+        };
+        for (latency_ms, ring_factor, capacity) in [
+            (10, None, 1920),
+            (10, Some(2.0), 1920),
+            (10, Some(1.5), 1440),
+            (10, Some(3.0), 2880),
+            (100, None, 19200),
+            (100, Some(4.0), 38400),
+            // Never less than the target, and unusable factors are unset
+            (10, Some(0.5), 960),
+            (10, Some(-2.0), 960),
+            (10, Some(f32::NAN), 1920),
+            (10, Some(f32::INFINITY), 1920),
+        ] {
+            let target = target(2, ring_factor);
+            assert_eq!(
+                target.capacity(target.samples(latency_ms)),
+                capacity,
+                "{} ms, factor {:?}",
+                latency_ms,
+                ring_factor
+            );
+        }
+        // Rounded up to whole frames
+        assert_eq!(target(2, Some(1.01)).capacity(960), 970);
+        assert_eq!(target(6, Some(1.001)).capacity(2880), 2886);
+        let (_, consumer) = latency_ring(target(2, Some(3.0)).capacity(960), 0);
+        assert_eq!(consumer.capacity().get(), 2880);
+    }
+
+    #[test]
+    fn test_fill_window() {
+        let fill = RingFill::default();
+        assert_eq!(fill.percent(), None);
+        let (mut producer, mut consumer) = HeapRb::<f32>::new(100).split();
+        // A window of 1000 frames, 400 per callback
+        let mut window = FillWindow::new(1000);
+        producer.push_iter(std::iter::repeat_n(0.0, 50));
+        window.record(&consumer, 400, &fill);
+        consumer.skip(30);
+        window.record(&consumer, 400, &fill);
+        assert_eq!(fill.percent(), None, "published before the window is up");
+        producer.push_iter(std::iter::repeat_n(0.0, 60));
+        window.record(&consumer, 400, &fill);
+        assert_eq!(fill.percent(), Some((20.0, 80.0)));
+        // The next window starts over
+        consumer.skip(80);
+        window.record(&consumer, 1000, &fill);
+        assert_eq!(fill.percent(), Some((0.0, 0.0)));
+        fill.clear();
+        assert_eq!(fill.percent(), None);
+    }
+
+    #[test]
+    fn test_estimate_latency() {
+        let target = LatencyTarget {
+            sample_rate: 48000,
+            buffer_frames: 240,
+            channels: 2,
+            priming: None,
+            ring_factor: None,
+        };
+        // 10 ms held back plus 5 ms of device buffers each way
+        assert_eq!(target.estimate_ms(target.samples(10), 0), 20.0);
+        let fir = fir::latency_frames(LINEAR_PHASE_TAPS);
+        let with_fir = target.estimate_ms(target.samples(10), fir);
+        assert!((with_fir - (20.0 + fir as f32 / 48.0)).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_profile_burst_applied_once() {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        for gain in 1..=5 {
+            sender.send(gain).unwrap();
+        }
+        let timeout = Duration::from_millis(1);
+        assert_eq!(next_profile(&receiver, timeout), Ok(5));
+        assert_eq!(
+            next_profile(&receiver, timeout),
+            Err(RecvTimeoutError::Timeout)
+        );
+
+        // The callback takes only the last EQ offered
+        let eq = |gain| {
+            let profile = EqProfile::parse(&format!("Preamp: {} dB", gain)).unwrap();
+            Processor::Stereo(ParametricEq::from_profile(&profile, 48000.0, 2))
+        };
+        let handover = Handover::default();
+        handover.offer_eq(eq(-6.0));
+        handover.offer_eq(eq(-20.0));
+        let mut current = eq(0.0);
+        handover.take_eq(&mut current);
+        let mut data = [1.0, 1.0];
+        current.process_buffer(&mut data);
+        assert!((data[0] - 0.1).abs() < 1e-6, "{:?}", data);
+        assert!(handover.eq.lock().unwrap().is_none());
+        // The replaced coefficients wait for the control loop to free them
+        assert_eq!(handover.retired.lock().unwrap().len(), 1);
+        handover.free_retired();
+        assert!(handover.retired.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_mid_side_replace_keeps_state() {
+        let mid = EqProfile::parse("Filter 1: ON PK Fc 100 Hz Gain 6 dB Q 1").unwrap();
+        let side = EqProfile::parse("Filter 1: ON PK Fc 5000 Hz Gain -6 dB Q 1").unwrap();
+        let eq = || Processor::MidSide(MidSide::from_profiles(&mid, &side, 48000.0));
+        let mut impulse = vec![0.0; 256];
+        impulse[0] = 1.0;
+        let (mut kept, mut replaced) = (eq(), eq());
+        let (mut a, mut b) = (impulse.clone(), impulse);
+        kept.process_buffer(&mut a);
+        replaced.process_buffer(&mut b);
+        // Same bands: the ringing carries on as if nothing was swapped
+        let retired = replaced.replace(eq());
+        assert!(matches!(retired, Processor::MidSide(_)));
+        let (mut a, mut b) = (vec![0.0; 256], vec![0.0; 256]);
+        kept.process_buffer(&mut a);
+        replaced.process_buffer(&mut b);
+        assert_ne!(a[0], 0.0);
+        assert_eq!(a, b);
+    }
+
+    #[test]
+    fn test_matched_bypass_level() {
+        // The preamp makes room for a treble boost the test tone is far from
+        let profile =
+            EqProfile::parse("Preamp: -6 dB\nFilter 1: ON PK Fc 8000 Hz Gain 6 dB Q 2").unwrap();
+        let tone: Vec<f32> = (0..19200)
+            .flat_map(|i| {
+                let s = (i as f32 * 200.0 / 48000.0 * std::f32::consts::TAU).sin() * 0.5;
+                [s, s]
+            })
+            .collect();
+        // The second half, past the FIR's delay and the filters settling
+        let rms_db = |data: &[f32]| {
+            let tail = &data[data.len() / 2..];
+            let power = tail.iter().map(|s| s * s).sum::<f32>() / tail.len() as f32;
+            10.0 * power.log10()
+        };
+        let dry_db = rms_db(&tone);
+        for (linear_phase, side) in [(false, None), (false, Some(&profile)), (true, None)] {
+            let settings = Settings::from(&PersistentSettings {
+                linear_phase,
+                ..Default::default()
+            });
+            let mut eq = Processor::new(&profile, side, 48000.0, &settings, 2);
+            let mut wet = tone.clone();
+            eq.process_buffer(&mut wet);
+            let mut bypassed = tone.clone();
+            eq.process_bypassed(&mut bypassed, true);
+            let (wet_db, bypassed_db) = (rms_db(&wet), rms_db(&bypassed));
+            assert!(
+                (wet_db - bypassed_db).abs() < 0.1,
+                "{} {}",
+                wet_db,
+                bypassed_db
+            );
+            // A plain bypass is louder by the preamp
+            assert!((dry_db - bypassed_db - 6.0).abs() < 0.1, "{}", bypassed_db);
+        }
+    }
+
+    #[test]
+    fn test_latency_handover() {
+        let latency = AtomicU32::new(10);
+        let target = LatencyTarget {
+            sample_rate: 48000,
+            buffer_frames: 64,
+            channels: 2,
+            priming: None,
+            ring_factor: None,
+        };
+        let mut current = 10;
+        let samples = target.samples(current);
+        let (mut producer, mut consumer) = latency_ring(target.capacity(samples), samples);
+        assert_eq!(consumer.occupied_len(), 960);
+        assert!(consumer.pop_iter().all(|sample| sample == 0.0));
+
+        let handover = Handover::default();
+        handover.follow(&latency, &mut current, target);
+        let mut samples = 960;
+        handover.take_consumer(&mut consumer, &mut samples);
+        assert_eq!(consumer.occupied_len(), 0, "nothing queued while unchanged");
+
+        latency.store(20, std::sync::atomic::Ordering::Relaxed);
+        handover.follow(&latency, &mut current, target);
+        assert_eq!(current, 20);
+        handover.take_producer(&mut producer);
+        handover.take_consumer(&mut consumer, &mut samples);
+        assert_eq!(samples, 1920);
+        assert_eq!(consumer.occupied_len(), 1920);
+        assert_eq!(producer.vacant_len(), 1920);
+    }
+
+    #[test]
+    fn test_err_fn_flags_lost_device() {
+        let status = Arc::new(Status::default());
+        let lost = Arc::new(AtomicBool::new(false));
+        let mut on_error = err_fn(status.clone(), lost.clone());
+        on_error(cpal::StreamError::BackendSpecific {
+            err: cpal::BackendSpecificError {
+                description: "glitch".to_string(),
+            },
+        });
+        assert!(!lost.load(std::sync::atomic::Ordering::Relaxed));
+        assert!(status.last_error().unwrap().contains("glitch"));
+        on_error(cpal::StreamError::DeviceNotAvailable);
+        assert!(lost.load(std::sync::atomic::Ordering::Relaxed));
+    }
 }
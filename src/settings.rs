@@ -1,11 +1,539 @@
 use std::sync::{
-    Arc,
-    atomic::{AtomicBool, AtomicUsize},
+    Arc, Mutex, OnceLock,
+    atomic::{AtomicBool, AtomicU8, AtomicU32, AtomicU64, AtomicUsize, Ordering},
 };
 
+use ringbuf::HeapCons;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::{
+        CrossfeedConfig, DcBlockerConfig, DitherConfig, LimiterConfig, LimiterMode, OverflowPolicy,
+    },
+    eq::EqProfile,
+    utils::AtomicF32,
+};
+
+/// The part of [`Settings`] kept in the config, as plain values. Fields
+/// missing from an older config take their defaults.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Default)]
+#[serde(default)]
+pub struct PersistentSettings {
+    pub latency: u32,
+    /// Fraction of the latency target the ring buffer starts filled with;
+    /// all of it when unset
+    pub priming: Option<f32>,
+    /// Ring buffer capacity as a multiple of the latency target; 2 when
+    /// unset
+    pub ring_factor: Option<f32>,
+    pub sample_rate: Option<u32>,
+    pub buffer_size: Option<u32>,
+    pub linear_phase: bool,
+    pub overflow: OverflowPolicy,
+    pub output_gain_db: f32,
+    pub balance: f32,
+    pub swap_channels: bool,
+    /// Loudness compensation following `output_gain_db`, see `presets::loudness`
+    pub loudness: bool,
+    /// The profile's preamp still applies while the EQ is disabled
+    pub matched_bypass: bool,
+    pub crossfeed: CrossfeedConfig,
+    pub dc_blocker: DcBlockerConfig,
+    pub limiter: LimiterConfig,
+    pub dither: DitherConfig,
+}
+
 // use atomic var to reduce the runner thread to restart
 #[derive(Clone, Debug)]
 pub struct Settings {
+    // latency target in ms, sizes the ring buffer; a running engine resizes
+    // it without reopening the streams, see `run::Handover`
+    pub latency: Arc<AtomicU32>,
+    // silence the ring buffer starts with, as a fraction of the latency target
+    pub priming: Option<f32>,
+    // ring buffer capacity as a multiple of the latency target, the room the
+    // input has to run ahead; see `run::LatencyTarget::capacity`
+    pub ring_factor: Option<f32>,
+    // requested stream format, validated against the devices; None chooses automatically
+    pub sample_rate: Option<u32>,
+    pub buffer_size: Option<u32>,
+    // FIR instead of biquads for the EQ, see `fir`; adds latency
+    pub linear_phase: bool,
+    // what the input callback does when the ring buffer is full
+    pub overflow: OverflowPolicy,
     pub enable_eq: Arc<AtomicBool>,
+    // applies the profile's preamp while the EQ is disabled, so the bypass
+    // isn't louder only because the preamp makes room for boosts
+    pub matched_bypass: Arc<AtomicBool>,
+    // master volume applied after the EQ, independent of the profile's preamp
+    pub output_gain_db: Arc<AtomicF32>,
+    // boosts bass and treble as the master volume goes down
+    pub loudness: Arc<AtomicBool>,
+    // stereo only: -1.0 is full left, 1.0 full right
+    pub balance: Arc<AtomicF32>,
+    pub swap_channels: Arc<AtomicBool>,
+    pub crossfeed: Arc<CrossfeedControls>,
+    pub dc_blocker: Arc<AtomicBool>,
+    pub limiter: Arc<LimiterControls>,
+    pub dither: Arc<DitherControls>,
     pub instance_id: Arc<AtomicUsize>,
+    pub status: Arc<Status>,
+}
+
+/// Fresh live controls holding the persisted values; the EQ starts enabled.
+impl From<&PersistentSettings> for Settings {
+    fn from(persistent: &PersistentSettings) -> Self {
+        Self {
+            latency: Arc::new(AtomicU32::new(persistent.latency)),
+            priming: persistent.priming,
+            ring_factor: persistent.ring_factor,
+            sample_rate: persistent.sample_rate,
+            buffer_size: persistent.buffer_size,
+            linear_phase: persistent.linear_phase,
+            overflow: persistent.overflow,
+            enable_eq: Arc::new(AtomicBool::new(true)),
+            matched_bypass: Arc::new(AtomicBool::new(persistent.matched_bypass)),
+            output_gain_db: Arc::new(AtomicF32::new(persistent.output_gain_db)),
+            loudness: Arc::new(AtomicBool::new(persistent.loudness)),
+            balance: Arc::new(AtomicF32::new(persistent.balance)),
+            swap_channels: Arc::new(AtomicBool::new(persistent.swap_channels)),
+            crossfeed: Arc::new(persistent.crossfeed.into()),
+            dc_blocker: Arc::new(AtomicBool::new(persistent.dc_blocker.enabled)),
+            limiter: Arc::new(persistent.limiter.into()),
+            dither: Arc::new(persistent.dither.into()),
+            instance_id: Arc::new(AtomicUsize::new(0)),
+            status: Default::default(),
+        }
+    }
+}
+
+impl Settings {
+    /// Current values of everything [`PersistentSettings`] keeps.
+    pub fn persistent(&self) -> PersistentSettings {
+        PersistentSettings {
+            latency: self.latency.load(Ordering::Relaxed),
+            priming: self.priming,
+            ring_factor: self.ring_factor,
+            sample_rate: self.sample_rate,
+            buffer_size: self.buffer_size,
+            linear_phase: self.linear_phase,
+            overflow: self.overflow,
+            output_gain_db: self.output_gain_db.load(),
+            balance: self.balance.load(),
+            swap_channels: self.swap_channels.load(Ordering::Relaxed),
+            loudness: self.loudness.load(Ordering::Relaxed),
+            matched_bypass: self.matched_bypass.load(Ordering::Relaxed),
+            crossfeed: self.crossfeed.config(),
+            dc_blocker: DcBlockerConfig {
+                enabled: self.dc_blocker.load(Ordering::Relaxed),
+            },
+            limiter: self.limiter.config(),
+            dither: self.dither.config(),
+        }
+    }
+}
+
+/// Live crossfeed parameters, mirrored from [`CrossfeedConfig`].
+#[derive(Debug)]
+pub struct CrossfeedControls {
+    pub enabled: AtomicBool,
+    pub amount: AtomicF32,
+    pub cutoff_hz: AtomicF32,
+}
+
+impl From<CrossfeedConfig> for CrossfeedControls {
+    fn from(config: CrossfeedConfig) -> Self {
+        Self {
+            enabled: AtomicBool::new(config.enabled),
+            amount: AtomicF32::new(config.amount),
+            cutoff_hz: AtomicF32::new(config.cutoff_hz),
+        }
+    }
+}
+
+impl Default for CrossfeedControls {
+    fn default() -> Self {
+        CrossfeedConfig::default().into()
+    }
+}
+
+impl CrossfeedControls {
+    pub fn config(&self) -> CrossfeedConfig {
+        CrossfeedConfig {
+            enabled: self.enabled.load(Ordering::Relaxed),
+            amount: self.amount.load(),
+            cutoff_hz: self.cutoff_hz.load(),
+        }
+    }
+}
+
+/// Live dither switches, mirrored from [`DitherConfig`].
+#[derive(Debug)]
+pub struct DitherControls {
+    pub enabled: AtomicBool,
+    pub noise_shaping: AtomicBool,
+}
+
+impl From<DitherConfig> for DitherControls {
+    fn from(config: DitherConfig) -> Self {
+        Self {
+            enabled: AtomicBool::new(config.enabled),
+            noise_shaping: AtomicBool::new(config.noise_shaping),
+        }
+    }
+}
+
+impl Default for DitherControls {
+    fn default() -> Self {
+        DitherConfig::default().into()
+    }
+}
+
+impl DitherControls {
+    pub fn config(&self) -> DitherConfig {
+        DitherConfig {
+            enabled: self.enabled.load(Ordering::Relaxed),
+            noise_shaping: self.noise_shaping.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Live limiter parameters, mirrored from [`LimiterConfig`], plus the gain
+/// reduction the output callback reports back.
+#[derive(Debug)]
+pub struct LimiterControls {
+    mode: AtomicU8,
+    pub ceiling_db: AtomicF32,
+    pub release_ms: AtomicF32,
+    oversampling: AtomicU8,
+    /// Largest reduction in the last output buffer, in positive dB
+    pub gain_reduction_db: AtomicF32,
+}
+
+const LIMITER_MODES: [LimiterMode; 3] = [
+    LimiterMode::Off,
+    LimiterMode::SoftClip,
+    LimiterMode::Brickwall,
+];
+
+/// Oversampling factors the limiter supports; 1 is off.
+pub const OVERSAMPLING_FACTORS: [u8; 3] = [1, 2, 4];
+
+impl From<LimiterConfig> for LimiterControls {
+    fn from(config: LimiterConfig) -> Self {
+        let controls = Self {
+            mode: AtomicU8::new(0),
+            ceiling_db: AtomicF32::new(config.ceiling_db),
+            release_ms: AtomicF32::new(config.release_ms),
+            oversampling: AtomicU8::new(1),
+            gain_reduction_db: AtomicF32::new(0.0),
+        };
+        controls.set_mode(config.mode);
+        controls.set_oversampling(config.oversampling);
+        controls
+    }
+}
+
+impl Default for LimiterControls {
+    fn default() -> Self {
+        LimiterConfig::default().into()
+    }
+}
+
+impl LimiterControls {
+    pub fn mode(&self) -> LimiterMode {
+        LIMITER_MODES[self.mode.load(Ordering::Relaxed) as usize]
+    }
+
+    pub fn set_mode(&self, mode: LimiterMode) {
+        let index = LIMITER_MODES.iter().position(|m| *m == mode).unwrap_or(0);
+        self.mode.store(index as u8, Ordering::Relaxed);
+    }
+
+    pub fn oversampling(&self) -> u8 {
+        self.oversampling.load(Ordering::Relaxed)
+    }
+
+    /// Anything but a supported factor turns oversampling off.
+    pub fn set_oversampling(&self, factor: u8) {
+        let factor = if OVERSAMPLING_FACTORS.contains(&factor) {
+            factor
+        } else {
+            1
+        };
+        self.oversampling.store(factor, Ordering::Relaxed);
+    }
+
+    pub fn config(&self) -> LimiterConfig {
+        LimiterConfig {
+            mode: self.mode(),
+            ceiling_db: self.ceiling_db.load(),
+            release_ms: self.release_ms.load(),
+            oversampling: self.oversampling(),
+        }
+    }
+}
+
+/// Written by the audio callbacks and the executor, read by the UI.
+#[derive(Debug, Default)]
+pub struct Status {
+    /// Output callbacks that found the ring buffer short and padded with silence
+    pub underruns: AtomicU64,
+    /// Times input was dropped because the ring buffer was full, see
+    /// [`OverflowPolicy`]
+    pub overruns: AtomicU64,
+    /// Estimated input-to-output delay of the running engine in ms, see
+    /// `run::LatencyTarget::estimate_ms`; 0 when stopped
+    pub latency_ms: AtomicF32,
+    pub ring_fill: RingFill,
+    pub last_error: Mutex<Option<String>>,
+    pub meters: Meters,
+    pub analyzer: AnalyzerTap,
+    /// The profile last changed from outside the GUI, e.g. in config.toml,
+    /// until the GUI takes it
+    external_profile: Mutex<Option<EqProfile>>,
+    on_change: OnChange,
+}
+
+/// How full the ring buffer ran over the last second, in samples, for
+/// tuning the latency target: a minimum near empty risks underruns, a
+/// maximum near the capacity overruns. Published by the output callback;
+/// the capacity is 0 while stopped.
+#[derive(Debug, Default)]
+pub struct RingFill {
+    pub capacity: AtomicUsize,
+    pub min: AtomicUsize,
+    pub max: AtomicUsize,
+}
+
+impl RingFill {
+    /// The lowest and highest fill in percent of the capacity, once there
+    /// is one.
+    pub fn percent(&self) -> Option<(f32, f32)> {
+        let capacity = self.capacity.load(Ordering::Relaxed);
+        if capacity == 0 {
+            return None;
+        }
+        let percent =
+            |fill: &AtomicUsize| fill.load(Ordering::Relaxed) as f32 * 100.0 / capacity as f32;
+        Some((percent(&self.min), percent(&self.max)))
+    }
+
+    pub fn clear(&self) {
+        self.capacity.store(0, Ordering::Relaxed);
+    }
+}
+
+/// Output samples for the spectrum analyzer, downmixed to mono. The runner
+/// puts a fresh consumer here per stream and only feeds it while `enabled`.
+#[derive(Default)]
+pub struct AnalyzerTap {
+    pub enabled: AtomicBool,
+    pub sample_rate: AtomicU32,
+    pub samples: Mutex<Option<HeapCons<f32>>>,
+}
+
+impl std::fmt::Debug for AnalyzerTap {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AnalyzerTap")
+            .field("enabled", &self.enabled)
+            .field("sample_rate", &self.sample_rate)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Called when the error changes, so the UI can redraw without polling.
+#[derive(Default)]
+struct OnChange(OnceLock<Box<dyn Fn() + Send + Sync>>);
+
+impl std::fmt::Debug for OnChange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("OnChange")
+    }
+}
+
+/// Channels shown by the level meters; further channels aren't measured.
+pub const METER_CHANNELS: usize = 2;
+
+/// Linear peak and RMS of the last buffer, per channel.
+#[derive(Debug, Default)]
+pub struct Levels {
+    pub peak: [AtomicF32; METER_CHANNELS],
+    pub rms: [AtomicF32; METER_CHANNELS],
+}
+
+impl Levels {
+    /// Measures interleaved samples; doesn't allocate, so it is safe to call
+    /// from the audio callbacks.
+    pub fn record(&self, data: &[f32], channels: usize) {
+        let channels = channels.max(1);
+        let frames = data.len() / channels;
+        if frames == 0 {
+            return;
+        }
+        for channel in 0..channels.min(METER_CHANNELS) {
+            let (mut peak, mut sum) = (0.0f32, 0.0f32);
+            for sample in data.iter().skip(channel).step_by(channels) {
+                peak = peak.max(sample.abs());
+                sum += sample * sample;
+            }
+            self.peak[channel].store(peak);
+            self.rms[channel].store((sum / frames as f32).sqrt());
+        }
+    }
+
+    pub fn clear(&self) {
+        for level in self.peak.iter().chain(self.rms.iter()) {
+            level.store(0.0);
+        }
+    }
+}
+
+/// Levels before and after processing, for the UI meters.
+#[derive(Debug, Default)]
+pub struct Meters {
+    pub input: Levels,
+    pub output: Levels,
+    /// Channel count of the running stream
+    pub channels: AtomicUsize,
+}
+
+impl Meters {
+    pub fn clear(&self) {
+        self.input.clear();
+        self.output.clear();
+    }
+}
+
+impl Status {
+    pub fn record_underrun(&self) {
+        self.underruns.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_overrun(&self) {
+        self.overruns.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn set_error(&self, error: impl ToString) {
+        if let Ok(mut last_error) = self.last_error.lock() {
+            *last_error = Some(error.to_string());
+        }
+        self.changed();
+    }
+
+    pub fn clear_error(&self) {
+        if let Ok(mut last_error) = self.last_error.lock() {
+            *last_error = None;
+        }
+        self.changed();
+    }
+
+    /// Registers the UI's repaint; only the first call has an effect.
+    #[cfg_attr(not(feature = "gui"), allow(dead_code))]
+    pub fn on_change(&self, callback: impl Fn() + Send + Sync + 'static) {
+        self.on_change.0.set(Box::new(callback)).ok();
+    }
+
+    fn changed(&self) {
+        if let Some(callback) = self.on_change.0.get() {
+            callback();
+        }
+    }
+
+    #[cfg_attr(not(feature = "gui"), allow(dead_code))]
+    pub fn last_error(&self) -> Option<String> {
+        self.last_error.lock().ok()?.clone()
+    }
+
+    /// Hands a profile changed outside the GUI over to it.
+    pub fn push_profile(&self, profile: EqProfile) {
+        if let Ok(mut external_profile) = self.external_profile.lock() {
+            *external_profile = Some(profile);
+        }
+        self.changed();
+    }
+
+    #[cfg_attr(not(feature = "gui"), allow(dead_code))]
+    pub fn take_profile(&self) -> Option<EqProfile> {
+        self.external_profile.lock().ok()?.take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_levels_of_sine() {
+        let levels = Levels::default();
+        // Left: full period sine of amplitude 0.5, right: constant -0.25
+        let data: Vec<f32> = (0..4800)
+            .flat_map(|i| {
+                let phase = i as f32 / 48.0 * std::f32::consts::TAU;
+                [phase.sin() * 0.5, -0.25]
+            })
+            .collect();
+        levels.record(&data, 2);
+        assert!((levels.peak[0].load() - 0.5).abs() < 1e-3);
+        assert!((levels.rms[0].load() - 0.5 / 2.0f32.sqrt()).abs() < 1e-3);
+        assert_eq!(levels.peak[1].load(), 0.25);
+        assert!((levels.rms[1].load() - 0.25).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_persistent_round_trip() {
+        let persistent = PersistentSettings {
+            latency: 25,
+            priming: Some(0.5),
+            ring_factor: Some(3.0),
+            sample_rate: Some(96000),
+            buffer_size: Some(256),
+            linear_phase: true,
+            overflow: OverflowPolicy::DropOldest,
+            output_gain_db: -3.5,
+            balance: 0.25,
+            swap_channels: true,
+            loudness: true,
+            matched_bypass: true,
+            crossfeed: CrossfeedConfig {
+                enabled: true,
+                ..Default::default()
+            },
+            dc_blocker: DcBlockerConfig { enabled: false },
+            limiter: LimiterConfig {
+                mode: LimiterMode::Brickwall,
+                oversampling: 4,
+                ..Default::default()
+            },
+            dither: DitherConfig {
+                enabled: false,
+                noise_shaping: true,
+            },
+        };
+        // Through the live atomics and back
+        let settings = Settings::from(&persistent);
+        assert!(settings.enable_eq.load(Ordering::Relaxed));
+        assert_eq!(settings.persistent(), persistent);
+
+        // Through serde, on its own and flattened into the config
+        let toml = toml::to_string(&persistent).unwrap();
+        assert_eq!(
+            toml::from_str::<PersistentSettings>(&toml).unwrap(),
+            persistent
+        );
+        let config = crate::config::Config {
+            settings: persistent.clone(),
+            ..Default::default()
+        };
+        let toml = toml::to_string(&config).unwrap();
+        assert!(toml.starts_with("latency = 25") || toml.contains("\nlatency = 25"));
+        let loaded: crate::config::Config = toml::from_str(&toml).unwrap();
+        assert_eq!(loaded.settings, persistent);
+
+        // Older configs missing some fields load the rest
+        let loaded: crate::config::Config =
+            toml::from_str(&toml.replace("linear_phase = true", "")).unwrap();
+        assert!(!loaded.settings.linear_phase);
+        assert_eq!(loaded.settings.latency, 25);
+    }
 }
@@ -0,0 +1,176 @@
+//! Response curves drawn to SVG for reports, by the graph's export and by
+//! `response --format svg`.
+
+use std::fmt::Write;
+
+const WIDTH: f64 = 800.0;
+const HEIGHT: f64 = 400.0;
+// Room for the tick labels and axis titles
+const LEFT: f64 = 60.0;
+const RIGHT: f64 = 20.0;
+const TOP: f64 = 40.0;
+const BOTTOM: f64 = 50.0;
+
+const MIN_FREQ: f64 = 20.0;
+const MAX_FREQ: f64 = 20000.0;
+const FREQ_TICKS: [f64; 10] = [
+    20.0, 50.0, 100.0, 200.0, 500.0, 1000.0, 2000.0, 5000.0, 10000.0, 20000.0,
+];
+/// Curve colors, in order.
+const COLORS: [&str; 4] = ["#3a7bd5", "#e07b39", "#3aa655", "#a64ca6"];
+
+fn x(freq: f64) -> f64 {
+    let t = (freq / MIN_FREQ).log10() / (MAX_FREQ / MIN_FREQ).log10();
+    LEFT + t * (WIDTH - LEFT - RIGHT)
+}
+
+fn y(db: f64, range: f64) -> f64 {
+    TOP + (range - db) / (2.0 * range) * (HEIGHT - TOP - BOTTOM)
+}
+
+fn freq_label(freq: f64) -> String {
+    if freq >= 1000.0 {
+        format!("{}k", freq / 1000.0)
+    } else {
+        format!("{}", freq)
+    }
+}
+
+/// A plot of labeled (frequency, dB) curves on a log frequency axis from
+/// 20 Hz to 20 kHz, with a grid and `fs` in the title. The gain axis is
+/// symmetric around 0 dB, at least ±6 dB and in steps of 6 dB.
+pub fn response_svg(curves: &[(&str, &[(f64, f64)])], fs: f64) -> String {
+    let peak = curves
+        .iter()
+        .flat_map(|(_, points)| points.iter().map(|(_, db)| db.abs()))
+        .filter(|db| db.is_finite())
+        .fold(0.0, f64::max);
+    let range = ((peak / 6.0).ceil() * 6.0).max(6.0);
+    let step = if range <= 12.0 { 3.0 } else { 6.0 };
+
+    let mut out = String::new();
+    writeln!(
+        out,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{w}" height="{h}" viewBox="0 0 {w} {h}" font-family="sans-serif" font-size="12">"#,
+        w = WIDTH,
+        h = HEIGHT
+    )
+    .unwrap();
+    writeln!(
+        out,
+        r#"<rect width="{}" height="{}" fill="white"/>"#,
+        WIDTH, HEIGHT
+    )
+    .unwrap();
+    writeln!(
+        out,
+        r#"<text x="{}" y="24" text-anchor="middle">Frequency response, fs = {} Hz</text>"#,
+        WIDTH / 2.0,
+        fs
+    )
+    .unwrap();
+
+    for freq in FREQ_TICKS {
+        let x = x(freq);
+        writeln!(
+            out,
+            r##"<line x1="{x:.1}" y1="{}" x2="{x:.1}" y2="{}" stroke="#ddd"/>"##,
+            TOP,
+            HEIGHT - BOTTOM
+        )
+        .unwrap();
+        writeln!(
+            out,
+            r#"<text x="{x:.1}" y="{}" text-anchor="middle">{}</text>"#,
+            HEIGHT - BOTTOM + 16.0,
+            freq_label(freq)
+        )
+        .unwrap();
+    }
+    let steps = (2.0 * range / step) as i32;
+    for i in 0..=steps {
+        let db = -range + i as f64 * step;
+        let y = y(db, range);
+        let stroke = if db == 0.0 { "#999" } else { "#ddd" };
+        writeln!(
+            out,
+            r#"<line x1="{}" y1="{y:.1}" x2="{}" y2="{y:.1}" stroke="{}"/>"#,
+            LEFT,
+            WIDTH - RIGHT,
+            stroke
+        )
+        .unwrap();
+        writeln!(
+            out,
+            r#"<text x="{}" y="{:.1}" text-anchor="end">{:+}</text>"#,
+            LEFT - 6.0,
+            y + 4.0,
+            db
+        )
+        .unwrap();
+    }
+    writeln!(
+        out,
+        r#"<text x="{}" y="{}" text-anchor="middle">Frequency (Hz)</text>"#,
+        (LEFT + WIDTH - RIGHT) / 2.0,
+        HEIGHT - 12.0
+    )
+    .unwrap();
+    writeln!(
+        out,
+        r#"<text x="16" y="{y}" text-anchor="middle" transform="rotate(-90 16 {y})">Gain (dB)</text>"#,
+        y = (TOP + HEIGHT - BOTTOM) / 2.0
+    )
+    .unwrap();
+
+    for (i, (label, points)) in curves.iter().enumerate() {
+        let color = COLORS[i % COLORS.len()];
+        let coords: Vec<String> = points
+            .iter()
+            .filter(|(freq, db)| (MIN_FREQ..=MAX_FREQ).contains(freq) && db.is_finite())
+            .map(|(freq, db)| format!("{:.1},{:.1}", x(*freq), y(*db, range)))
+            .collect();
+        writeln!(
+            out,
+            r#"<polyline points="{}" fill="none" stroke="{}" stroke-width="2"/>"#,
+            coords.join(" "),
+            color
+        )
+        .unwrap();
+        // Legend, top right
+        writeln!(
+            out,
+            r#"<text x="{}" y="{}" text-anchor="end" fill="{}">{}</text>"#,
+            WIDTH - RIGHT - 4.0,
+            TOP + 16.0 * (i + 1) as f64,
+            color,
+            label
+        )
+        .unwrap();
+    }
+    out.push_str("</svg>\n");
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_response_svg() {
+        let points = [(20.0, 0.0), (1000.0, 7.0), (20000.0, 0.0), (30000.0, 1.0)];
+        let svg = response_svg(&[("EQ", &points)], 96000.0);
+        assert!(svg.starts_with("<svg "));
+        assert!(svg.ends_with("</svg>\n"));
+        assert!(svg.contains("fs = 96000 Hz"));
+        // 7 dB rounds the scale up to ±12 dB, gridded every 3 dB
+        assert!(svg.contains(">+12</text>"));
+        assert!(svg.contains(">-9</text>"));
+        assert!(!svg.contains(">+15</text>"));
+        assert!(svg.contains(">1k</text>"));
+        // Ends of the axes; 30 kHz is off the plot
+        assert!(svg.contains(r#"<polyline points="60.0,195.0 "#));
+        assert!(svg.contains(r#" 780.0,195.0" fill="none""#));
+        assert!(svg.contains(">EQ</text>"));
+    }
+}
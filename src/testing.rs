@@ -0,0 +1,95 @@
+//! Test doubles shared by the executor's and the CLI's tests.
+
+use std::{
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{Receiver, RecvTimeoutError, Sender, channel},
+    },
+    thread,
+    time::Duration,
+};
+
+use crate::{cli::session::Spawn, eq::EqProfile, settings::Settings};
+
+/// Stands in for `run::run` without a device: runs until
+/// `settings.instance_id` moves away from the id it started under, and
+/// reports the profiles it is sent. Clones share the counts.
+#[derive(Clone)]
+pub struct MockEngine {
+    active: Arc<AtomicUsize>,
+    peak: Arc<AtomicUsize>,
+    sender: Sender<EqProfile>,
+    received: Arc<Mutex<Receiver<EqProfile>>>,
+}
+
+impl Default for MockEngine {
+    fn default() -> Self {
+        let (sender, received) = channel();
+        Self {
+            active: Arc::default(),
+            peak: Arc::default(),
+            sender,
+            received: Arc::new(Mutex::new(received)),
+        }
+    }
+}
+
+impl MockEngine {
+    /// Engines running now
+    pub fn active(&self) -> usize {
+        self.active.load(Ordering::SeqCst)
+    }
+
+    /// Most engines that ran at the same time
+    pub fn peak(&self) -> usize {
+        self.peak.load(Ordering::SeqCst)
+    }
+
+    /// The next profile an engine was sent, waiting a while for it.
+    pub fn next_profile(&self) -> Option<EqProfile> {
+        let received = self.received.lock().unwrap();
+        received.recv_timeout(Duration::from_secs(5)).ok()
+    }
+
+    /// A profile an engine was sent, without waiting.
+    pub fn try_profile(&self) -> Option<EqProfile> {
+        self.received.lock().unwrap().try_recv().ok()
+    }
+
+    /// Runs on the calling thread, as a `Runner` body.
+    pub fn run(&self, settings: &Settings, instance_id: usize, receiver: Receiver<EqProfile>) {
+        self.enter();
+        self.wait(settings, instance_id, receiver);
+    }
+
+    /// A session spawn that runs the engine on a thread. It counts as
+    /// running once spawned, so a reply to `start` already sees it.
+    pub fn spawn(&self) -> Spawn {
+        let engine = self.clone();
+        Box::new(move |settings: Settings, _, receiver| {
+            engine.enter();
+            let engine = engine.clone();
+            let instance_id = settings.instance_id.load(Ordering::Relaxed);
+            thread::spawn(move || engine.wait(&settings, instance_id, receiver))
+        })
+    }
+
+    fn enter(&self) {
+        let now = self.active.fetch_add(1, Ordering::SeqCst) + 1;
+        self.peak.fetch_max(now, Ordering::SeqCst);
+    }
+
+    fn wait(&self, settings: &Settings, instance_id: usize, receiver: Receiver<EqProfile>) {
+        while settings.instance_id.load(Ordering::Relaxed) == instance_id {
+            match receiver.recv_timeout(Duration::from_millis(1)) {
+                Ok(profile) => {
+                    self.sender.send(profile).ok();
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => thread::sleep(Duration::from_millis(1)),
+            }
+        }
+        self.active.fetch_sub(1, Ordering::SeqCst);
+    }
+}
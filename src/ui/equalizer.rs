@@ -1,61 +1,215 @@
+use std::sync::atomic::Ordering;
+
 use eframe::egui::{
-    ComboBox, DragValue, ScrollArea, Slider, TextWrapMode, Ui, Widget,
-    scroll_area::ScrollBarVisibility,
+    Button, Color32, ComboBox, CursorIcon, DragValue, Id, ScrollArea, Slider, StrokeKind,
+    TextWrapMode, Ui, Widget, scroll_area::ScrollBarVisibility,
 };
 
 use crate::{
-    eq::{Filter, FilterType},
-    ui::App,
+    config::{Lang, RangeConfig},
+    eq::{Filter, FilterType, bandwidth_to_q, q_to_bandwidth},
+    midi::BandParam,
+    response::{resonance_db, slope_db_per_oct},
+    ui::{App, i18n::Text, midi::LearnTarget},
 };
 
-fn band_ui(index: usize, band: &mut Filter, ui: &mut Ui, remove: &mut bool) {
-    ui.vertical(|ui| {
-        ui.label("Type");
-        ComboBox::new(format!("FilterType_{}", index), "")
-            .selected_text(band.filter_type.to_string())
-            .show_ui(ui, |ui| {
-                ui.selectable_value(
-                    &mut band.filter_type,
-                    FilterType::Peaking,
-                    FilterType::Peaking.to_string(),
-                );
-                ui.selectable_value(
-                    &mut band.filter_type,
-                    FilterType::HighShelf,
-                    FilterType::HighShelf.to_string(),
-                );
-                ui.selectable_value(
-                    &mut band.filter_type,
-                    FilterType::LowShelf,
-                    FilterType::LowShelf.to_string(),
-                );
+/// Moves the item at `from` to `to`, shifting the ones in between.
+fn move_item<T>(items: &mut Vec<T>, from: usize, to: usize) {
+    if from != to && from < items.len() && to < items.len() {
+        let item = items.remove(from);
+        items.insert(to, item);
+    }
+}
+
+fn filter_type_text(filter_type: FilterType) -> Text {
+    match filter_type {
+        FilterType::Peaking => Text::Peak,
+        FilterType::LowShelf => Text::LowShelf,
+        FilterType::HighShelf => Text::HighShelf,
+        FilterType::LowPass => Text::LowPass,
+        FilterType::HighPass => Text::HighPass,
+    }
+}
+
+/// Low and high passes are second-order biquads.
+const PASS_SLOPE_DB_PER_OCT: f64 = 12.0;
+
+/// What the band's Q amounts to, for its type: the bandwidth of a peak, the
+/// steepness of a shelf at its frequency (the Q of a shelf is the cookbook's
+/// shelf slope S), and the slope of a low or high pass with the peak its
+/// resonance adds.
+fn width_text(band: &Filter, sample_rate: f32) -> String {
+    let q = band.effective_q();
+    match band.filter_type {
+        FilterType::Peaking => format!("{:.2} oct", q_to_bandwidth(q)),
+        FilterType::LowShelf | FilterType::HighShelf => {
+            format!("{:.1} dB/oct", slope_db_per_oct(band, sample_rate as f64))
+        }
+        FilterType::LowPass | FilterType::HighPass => match resonance_db(q) {
+            Some(peak) => format!("{} dB/oct, +{:.1} dB", PASS_SLOPE_DB_PER_OCT, peak),
+            None => format!("{} dB/oct", PASS_SLOPE_DB_PER_OCT),
+        },
+    }
+}
+
+/// Undoes this frame's edits of a band that was locked at its start, on top
+/// of its widgets being read-only; only the lock, solo and mute change.
+fn keep_locked(before: &Filter, band: &mut Filter) {
+    if before.locked {
+        *band = Filter {
+            enabled: band.enabled,
+            locked: band.locked,
+            solo: band.solo,
+            ..before.clone()
+        };
+    }
+}
+
+/// Returns the index of a band dropped onto this one.
+fn band_ui(
+    index: usize,
+    band: &mut Filter,
+    sample_rate: f32,
+    ranges: &RangeConfig,
+    lang: Lang,
+    ui: &mut Ui,
+    remove: &mut bool,
+) -> Option<usize> {
+    let response = ui.vertical(|ui| {
+        ui.dnd_drag_source(Id::new(("band_drag", index)), index, |ui| {
+            ui.label(lang.tr(Text::Move));
+        })
+        .response
+        .on_hover_cursor(CursorIcon::Grab);
+        // Read-only while locked; the band is still heard and drawn
+        ui.add_enabled_ui(!band.locked, |ui| {
+            ui.label(lang.tr(Text::Type));
+            ComboBox::new(format!("FilterType_{}", index), "")
+                .selected_text(lang.tr(filter_type_text(band.filter_type)))
+                .show_ui(ui, |ui| {
+                    for filter_type in FilterType::ALL {
+                        ui.selectable_value(
+                            &mut band.filter_type,
+                            filter_type,
+                            lang.tr(filter_type_text(filter_type)),
+                        );
+                    }
+                });
+            ui.label(lang.tr(Text::Freq));
+            // The slider is for coarse moves; exact values are typed below it
+            Slider::new(&mut band.frequency, ranges.freq())
+                .vertical()
+                .logarithmic(true)
+                .show_value(false)
+                .ui(ui);
+            let speed = band.frequency * 0.005;
+            DragValue::new(&mut band.frequency)
+                .speed(speed)
+                .range(ranges.freq())
+                .max_decimals(1)
+                .suffix(" Hz")
+                .ui(ui);
+            ui.horizontal(|ui| {
+                ui.label(if band.bandwidth.is_some() { "BW" } else { "Q" });
+                let mut octaves = band.bandwidth.is_some();
+                if ui
+                    .toggle_value(&mut octaves, "Oct")
+                    .on_hover_text(lang.tr(Text::OctavesHover))
+                    .changed()
+                {
+                    // Convert so the switch leaves the response unchanged
+                    match band.bandwidth.take() {
+                        Some(bw) => band.q_factor = bandwidth_to_q(bw),
+                        None => band.bandwidth = Some(q_to_bandwidth(band.q_factor)),
+                    }
+                }
             });
-        ui.label("Freq");
-        Slider::new(&mut band.frequency, 20.0..=20000.0)
-            .vertical()
-            .logarithmic(true)
-            .suffix(" Hz")
-            .show_value(true)
-            .ui(ui);
-        ui.label("Q");
-        DragValue::new(&mut band.q_factor)
-            .speed(0.1)
-            .range(0.01..=10.0)
-            .ui(ui);
-        ui.label("Gain");
-        DragValue::new(&mut band.gain)
-            .speed(0.1)
-            .range(-12.0..=12.0)
-            .ui(ui);
-        ui.checkbox(&mut band.enabled, format!("Band {}", index + 1));
-        if ui.button("Remove").clicked() {
+            match &mut band.bandwidth {
+                Some(bw) => {
+                    DragValue::new(bw)
+                        .speed(0.01)
+                        .range(ranges.bandwidth())
+                        .max_decimals(3)
+                        .suffix(" oct")
+                        .ui(ui);
+                    ui.weak(format!("Q {:.3}", bandwidth_to_q(*bw)));
+                }
+                None => {
+                    DragValue::new(&mut band.q_factor)
+                        .speed(0.1)
+                        .range(ranges.q())
+                        .max_decimals(3)
+                        .ui(ui);
+                }
+            }
+            // A peak set in octaves already shows its width
+            if band.bandwidth.is_none() || band.filter_type != FilterType::Peaking {
+                ui.weak(width_text(band, sample_rate));
+            }
+            ui.label(lang.tr(Text::Gain));
+            // Low and high pass ignore the gain
+            ui.add_enabled(
+                band.filter_type.has_gain(),
+                DragValue::new(&mut band.gain)
+                    .speed(0.1)
+                    .range(ranges.gain()),
+            );
+        });
+        ui.label(format!("{} {}", lang.tr(Text::Band), index + 1));
+        if !band.is_stable(sample_rate) {
+            ui.colored_label(Color32::RED, lang.tr(Text::Unstable))
+                .on_hover_text(lang.fill(Text::UnstableHover, &[("rate", &sample_rate)]));
+        }
+        ui.horizontal(|ui| {
+            ui.toggle_value(&mut band.solo, lang.tr(Text::Solo))
+                .on_hover_text(lang.tr(Text::SoloHover));
+            let mut muted = !band.enabled;
+            if ui.toggle_value(&mut muted, lang.tr(Text::Mute)).changed() {
+                band.enabled = !muted;
+            }
+            ui.toggle_value(&mut band.locked, lang.tr(Text::Lock))
+                .on_hover_text(lang.tr(Text::LockHover));
+        });
+        if ui
+            .add_enabled(!band.locked, Button::new(lang.tr(Text::Remove)))
+            .clicked()
+        {
             *remove = true;
         }
     });
+    let response = response.response;
+    if response.dnd_hover_payload::<usize>().is_some() {
+        ui.painter().rect_stroke(
+            response.rect,
+            2.0,
+            ui.visuals().selection.stroke,
+            StrokeKind::Outside,
+        );
+    }
+    response.dnd_release_payload::<usize>().map(|from| *from)
 }
 
 impl App {
+    /// Rate of the running stream, else the one that will be requested.
+    fn sample_rate(&self) -> f32 {
+        match self
+            .eq_settings
+            .status
+            .analyzer
+            .sample_rate
+            .load(Ordering::Relaxed)
+        {
+            0 => self.eq_settings.sample_rate.unwrap_or(48000) as f32,
+            rate => rate as f32,
+        }
+    }
+
     pub fn equalizer_ui(&mut self, ui: &mut Ui) {
+        let sample_rate = self.sample_rate();
+        let lang = self.info.lang;
+        let ranges = self.info.ranges;
+        let learning = self.midi.learning;
+        let mut touched = None;
         let profile = &mut self.eq_profile;
         ScrollArea::horizontal()
             .scroll_bar_visibility(ScrollBarVisibility::VisibleWhenNeeded)
@@ -63,17 +217,138 @@ impl App {
                 ui.style_mut().wrap_mode = Some(TextWrapMode::Extend);
                 ui.horizontal(|ui| {
                     let mut remove_index = None;
+                    let mut moved = None;
                     for (i, band) in profile.filters.iter_mut().enumerate() {
                         let mut remove = false;
-                        band_ui(i, band, ui, &mut remove);
+                        let before = band.clone();
+                        if let Some(from) =
+                            band_ui(i, band, sample_rate, &ranges, lang, ui, &mut remove)
+                        {
+                            moved = Some((from, i));
+                        }
+                        keep_locked(&before, band);
+                        if let Some(param) = BandParam::changed(&before, band)
+                            && learning
+                        {
+                            touched = Some(LearnTarget::Band(i, param));
+                        }
                         if remove {
                             remove_index = Some(i);
                         }
                     }
                     if let Some(i) = remove_index {
                         profile.filters.remove(i);
+                    } else if let Some((from, to)) = moved {
+                        // One mutation after all bands are drawn, so a realtime
+                        // engine only ever receives the fully reordered profile
+                        move_item(&mut profile.filters, from, to);
                     }
                 });
             });
+        if let Some(target) = touched {
+            self.learn(target);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_move_item() {
+        let mut items = vec![1, 2, 3, 4];
+        move_item(&mut items, 0, 2);
+        assert_eq!(items, [2, 3, 1, 4]);
+        move_item(&mut items, 3, 0);
+        assert_eq!(items, [4, 2, 3, 1]);
+        move_item(&mut items, 1, 1);
+        move_item(&mut items, 1, 9);
+        assert_eq!(items, [4, 2, 3, 1]);
+    }
+
+    /// The band after a frame of its editor; the widgets clamp what they show.
+    fn edited(mut band: Filter, ranges: &RangeConfig) -> Filter {
+        let ctx = eframe::egui::Context::default();
+        let _ = ctx.run_ui(Default::default(), |ui| {
+            band_ui(0, &mut band, 48000.0, ranges, Lang::En, ui, &mut false);
+        });
+        band
+    }
+
+    #[test]
+    fn test_configured_ranges() {
+        let band = Filter {
+            frequency: 10.0,
+            gain: 18.0,
+            ..Default::default()
+        };
+        let clamped = edited(band.clone(), &RangeConfig::default());
+        assert_eq!((clamped.frequency, clamped.gain), (20.0, 12.0));
+        let wide = RangeConfig {
+            min_freq_hz: 10.0,
+            max_gain_db: 18.0,
+            ..Default::default()
+        }
+        .sanitized();
+        let kept = edited(band, &wide);
+        assert_eq!((kept.frequency, kept.gain), (10.0, 18.0));
+        assert_eq!(*wide.preamp().start(), -18.0);
+    }
+
+    #[test]
+    fn test_width_text() {
+        let band = |filter_type, q_factor, gain| Filter {
+            filter_type,
+            q_factor,
+            gain,
+            ..Default::default()
+        };
+        let text = |band: Filter| width_text(&band, 48000.0);
+        assert_eq!(text(band(FilterType::Peaking, 1.414, 3.0)), "1.00 oct");
+        assert_eq!(text(band(FilterType::Peaking, 2.0 / 3.0, 3.0)), "2.00 oct");
+        assert_eq!(text(band(FilterType::LowPass, 0.707, 0.0)), "12 dB/oct");
+        assert_eq!(text(band(FilterType::HighPass, 0.5, 0.0)), "12 dB/oct");
+        // Q 2 peaks 6.3 dB above the passband
+        assert_eq!(
+            text(band(FilterType::LowPass, 2.0, 0.0)),
+            "12 dB/oct, +6.3 dB"
+        );
+        // A steeper shelf slope, and more gain, make the transition steeper
+        let shelf = |q, gain| slope_db_per_oct(&band(FilterType::LowShelf, q, gain), 48000.0);
+        assert!(shelf(1.0, 12.0) > shelf(0.5, 12.0));
+        assert!(shelf(1.0, 12.0) > shelf(1.0, 6.0));
+        assert_eq!(text(band(FilterType::HighShelf, 1.0, 0.0)), "0.0 dB/oct");
+        let steep = text(band(FilterType::HighShelf, 1.0, 12.0));
+        assert_eq!(steep, format!("{:.1} dB/oct", shelf(1.0, 12.0)));
+    }
+
+    #[test]
+    fn test_locked_band_keeps_settings() {
+        let locked = Filter {
+            gain: 3.0,
+            locked: true,
+            ..Default::default()
+        };
+        let mut band = locked.clone();
+        band.gain = 5.0;
+        band.frequency = 200.0;
+        band.filter_type = FilterType::HighShelf;
+        band.enabled = false;
+        keep_locked(&locked, &mut band);
+        assert_eq!(band.gain, 3.0);
+        assert_eq!(band.frequency, locked.frequency);
+        assert_eq!(band.filter_type, FilterType::Peaking);
+        // Muting still works
+        assert!(!band.enabled);
+
+        // Unlocking takes effect, and the next frame's edits stay
+        band.locked = false;
+        keep_locked(&locked, &mut band);
+        assert!(!band.locked);
+        let unlocked = band.clone();
+        band.gain = 5.0;
+        keep_locked(&unlocked, &mut band);
+        assert_eq!(band.gain, 5.0);
     }
 }
@@ -1,213 +1,492 @@
-use std::f32::consts::PI;
+use std::{sync::atomic::Ordering, time::Duration};
 
-use eframe::egui::{Color32, Response, Ui};
-use egui_plot::{GridInput, GridMark, Line, Plot, PlotPoints};
+use eframe::egui::{Color32, Response, Slider, Ui};
+use egui_plot::{GridInput, GridMark, Line, LineStyle, Plot, PlotPoints};
 
 use crate::{
-    eq::{Filter, FilterType},
-    ui::App,
+    config::RangeConfig,
+    eq::EqProfile,
+    response::log_freq_between,
+    svg::response_svg,
+    ui::{
+        App,
+        i18n::Text,
+        reference::{Reference, difference, max_deviation},
+        spectrum::FLOOR_DB,
+    },
 };
 
-struct BiquadCoeffs {
-    b0: f64,
-    b1: f64,
-    b2: f64,
-    a0: f64,
-    a1: f64,
-    a2: f64,
-}
+/// Sample rate the curve and the clip check are computed at.
+pub const PREVIEW_FS: f64 = 44000.0;
 
-impl BiquadCoeffs {
-    pub fn calc(band: &Filter, fs: f64) -> BiquadCoeffs {
-        let w0 = 2.0 * PI as f64 * band.frequency / fs;
-        let cos_w0 = w0.cos();
-        let sin_w0 = w0.sin();
-        let q = band.effective_q();
-        let a = 10.0_f64.powf(band.gain / 40.0);
-        let alpha = match band.filter_type {
-            FilterType::LowShelf | FilterType::HighShelf => {
-                sin_w0 / 2.0 * ((a + 1.0 / a) * (1.0 / q - 1.0) + 2.0).sqrt()
-            }
-            _ => sin_w0 / (2.0 * q),
-        };
+/// Response below this much overshoot is not reported as clipping.
+const CLIP_THRESHOLD_DB: f64 = 0.05;
 
-        let (b0, b1, b2, a0, a1, a2) = match band.filter_type {
-            FilterType::Peaking => (
-                1.0 + alpha * a,
-                -2.0 * cos_w0,
-                1.0 - alpha * a,
-                1.0 + alpha / a,
-                -2.0 * cos_w0,
-                1.0 - alpha / a,
-            ),
-            FilterType::LowShelf => {
-                let sqrt_a = a.sqrt();
-                (
-                    a * ((a + 1.0) - (a - 1.0) * cos_w0 + 2.0 * sqrt_a * alpha),
-                    2.0 * a * ((a - 1.0) - (a + 1.0) * cos_w0),
-                    a * ((a + 1.0) - (a - 1.0) * cos_w0 - 2.0 * sqrt_a * alpha),
-                    (a + 1.0) + (a - 1.0) * cos_w0 + 2.0 * sqrt_a * alpha,
-                    -2.0 * ((a - 1.0) + (a + 1.0) * cos_w0),
-                    (a + 1.0) + (a - 1.0) * cos_w0 - 2.0 * sqrt_a * alpha,
-                )
-            }
-            FilterType::HighShelf => {
-                let sqrt_a = a.sqrt();
-                (
-                    a * ((a + 1.0) + (a - 1.0) * cos_w0 + 2.0 * sqrt_a * alpha),
-                    -2.0 * a * ((a - 1.0) + (a + 1.0) * cos_w0),
-                    a * ((a + 1.0) + (a - 1.0) * cos_w0 - 2.0 * sqrt_a * alpha),
-                    (a + 1.0) - (a - 1.0) * cos_w0 + 2.0 * sqrt_a * alpha,
-                    2.0 * ((a - 1.0) - (a + 1.0) * cos_w0),
-                    (a + 1.0) - (a - 1.0) * cos_w0 - 2.0 * sqrt_a * alpha,
-                )
-            }
-            _ => unimplemented!(),
-        };
+/// `count + 1` frequencies spanning the graph, evenly on its log scale.
+fn graph_freqs(count: usize, ranges: &RangeConfig) -> Vec<f64> {
+    (0..=count)
+        .map(|i| {
+            log_freq_between(
+                i as f64 / count as f64,
+                ranges.min_freq_hz,
+                ranges.max_freq_hz,
+            )
+        })
+        .collect()
+}
+
+/// The plotted curve, kept until the profile changes or the graph is
+/// resized enough to need a different number of points.
+#[derive(Default)]
+pub struct CurveCache {
+    // profile, span and point count the points were computed for
+    key: Option<(EqProfile, RangeConfig, usize)>,
+    points: Vec<[f64; 2]>,
+}
 
-        BiquadCoeffs {
-            b0,
-            b1,
-            b2,
-            a0,
-            a1,
-            a2,
+impl CurveCache {
+    /// Recomputes the curve if it is stale and returns whether it did. A
+    /// width change of under a tenth keeps the old points.
+    fn update(&mut self, profile: &EqProfile, ranges: &RangeConfig, point_count: usize) -> bool {
+        if let Some((cached, cached_ranges, count)) = &self.key
+            && cached == profile
+            && cached_ranges == ranges
+            && count.abs_diff(point_count) * 10 <= *count
+        {
+            return false;
         }
+        let freqs = graph_freqs(point_count, ranges);
+        let gains = profile.magnitude_response(&freqs, PREVIEW_FS);
+        self.points = freqs
+            .into_iter()
+            .zip(gains)
+            .map(|(freq, db)| [freq, db])
+            .collect();
+        self.key = Some((profile.clone(), *ranges, point_count));
+        true
     }
+}
 
-    pub fn calc_magnitude_db(&self, freq: f64, fs: f64) -> f64 {
-        let w = 2.0 * PI as f64 * freq / fs;
-        let cw = w.cos();
-        let c2w = (2.0 * w).cos();
-        let sw = w.sin();
-        let s2w = (2.0 * w).sin();
-
-        // H(z) = (b0 + b1*z^-1 + b2*z^-2) / (a0 + a1*z^-1 + a2*z^-2)
-        // 实际上是复数除法，这里分别计算分子分母的实部和虚部
-
-        let num_re = self.b0 + self.b1 * cw + self.b2 * c2w;
-        let num_im = -(self.b1 * sw + self.b2 * s2w);
+/// How far above 0 dB the response peaks with the preamp applied, if it
+/// does within the graph's span; a full-scale signal at that frequency would
+/// clip.
+pub fn clip_db(profile: &EqProfile, ranges: &RangeConfig) -> Option<f64> {
+    let over = profile.peak_db_between(PREVIEW_FS, ranges.min_freq_hz, ranges.max_freq_hz);
+    (over > CLIP_THRESHOLD_DB).then_some(over)
+}
 
-        let den_re = self.a0 + self.a1 * cw + self.a2 * c2w;
-        let den_im = -(self.a1 * sw + self.a2 * s2w);
+const SPECTRUM_REFRESH: Duration = Duration::from_millis(33);
 
-        let mag_sq = (num_re * num_re + num_im * num_im) / (den_re * den_re + den_im * den_im);
+/// Points per curve in an exported graph.
+const EXPORT_POINTS: usize = 400;
 
-        10.0 * mag_sq.log10() // 20 * log10(mag) = 10 * log10(mag^2)
-    }
+/// The spectrum has its own scale on the gain axis: 0 dBFS at +24 and the
+/// analyzer floor at -24.
+fn spectrum_y(db: f32) -> f64 {
+    24.0 + (db / -FLOOR_DB * 48.0) as f64
 }
 
-fn audio_grid_spacer(input: GridInput) -> Vec<GridMark> {
-    let mut marks = Vec::new();
+/// Frequencies below this get no gridlines, so a view zoomed or panned to
+/// 0 Hz and below still has a bounded number of decades.
+const GRID_MIN_HZ: f64 = 1.0;
 
-    // input.bounds 包含了当前视图的最小值和最大值 (真实频率值，非 log 值)
+/// Gridlines at every integer multiple of each decade in view, in three
+/// weights: the decades themselves (10, 100, 1k, 10k) are drawn strongest
+/// and always labeled, the 2× and 5× multiples come next and the rest are
+/// faintest. The same bounds always give the same marks, whatever the zoom.
+fn audio_grid_spacer(input: GridInput) -> Vec<GridMark> {
     let (min, max) = input.bounds;
+    let min = min.max(GRID_MIN_HZ);
+    if max.is_nan() || max < min {
+        return Vec::new();
+    }
+    // egui_plot weighs and labels a mark by how far `step_size` is on
+    // screen; the whole view is always far enough
+    let span = max - min;
+    let mut marks = Vec::new();
+    for power in min.log10().floor() as i32..=max.log10().floor() as i32 {
+        let decade = 10f64.powi(power);
+        for k in 1..10 {
+            let value = k as f64 * decade;
+            if value < min || value > max {
+                continue;
+            }
+            let step_size = match k {
+                1 => span,
+                2 | 5 => decade,
+                _ => decade / 2.0,
+            };
+            marks.push(GridMark { value, step_size });
+        }
+    }
+    marks
+}
 
-    // 计算 10 的幂次范围
-    let min_log = min.log10().floor() as i32;
-    let max_log = max.log10().ceil() as i32;
-
-    for power in min_log..=max_log {
-        let base = 10.0_f64.powi(power);
+/// Linearly interpolates the curve at `x`. The points must be sorted by x;
+/// outside their range there is no value.
+pub fn curve_value(points: &[[f64; 2]], x: f64) -> Option<f64> {
+    let next = points.partition_point(|p| p[0] < x);
+    let [x1, y1] = *points.get(next)?;
+    if next == 0 {
+        return (x == x1).then_some(y1);
+    }
+    let [x0, y0] = points[next - 1];
+    Some(y0 + (y1 - y0) * (x - x0) / (x1 - x0))
+}
 
-        // 我们通常希望显示 1, 2, 5 序列 (例如 100, 200, 500)
-        // 或者如果缩放得够大，显示 1, 2, 3, 4...
+/// Hover text for a point of the curve, e.g. "1.2 kHz: +3.4 dB".
+fn readout(freq: f64, db: f64) -> String {
+    if freq >= 1000.0 {
+        format!("{:.1} kHz: {:+.1} dB", freq / 1000.0, db)
+    } else {
+        format!("{:.0} Hz: {:+.1} dB", freq, db)
+    }
+}
 
-        // 1. 主刻度 (10^n): 10, 100, 1k, 10k
-        if base >= min && base <= max {
-            marks.push(GridMark {
-                value: base,
-                step_size: base, // 这一项帮助 egui 决定是否显示 label
-            });
+impl App {
+    /// Feeds the analyzer while it is visible and returns the spectrum
+    /// outline to draw, if any.
+    fn spectrum_points(&mut self, ui: &mut Ui) -> Option<PlotPoints<'static>> {
+        let tap = &self.eq_settings.status.analyzer;
+        let active = self.info.window.show_spectrum && self.state.running;
+        tap.enabled.store(active, Ordering::Relaxed);
+        if !active {
+            return None;
         }
+        ui.horizontal(|ui| {
+            ui.label(self.info.lang.tr(Text::SpectrumAveraging));
+            ui.add(Slider::new(&mut self.spectrum.averaging, 0.0..=0.95));
+        });
+        self.spectrum.update(tap);
+        if !self.window_hidden {
+            ui.ctx().request_repaint_after(SPECTRUM_REFRESH);
+        }
+        Some(
+            self.spectrum
+                .points()
+                .filter(|(freq, _)| self.info.ranges.freq().contains(&(*freq as f64)))
+                .map(|(freq, db)| [freq as f64, spectrum_y(db)])
+                .collect(),
+        )
+    }
 
-        // 2. 次级刻度: 2, 3, 4 ... 9 * base
-        for k in 2..10 {
-            let val = base * k as f64;
-            if val > max {
-                break;
-            }
-            if val < min {
-                continue;
-            }
-
-            // 简单的细节剔除逻辑：
-            // 只有当两个主刻度（比如 100 和 1000）在屏幕上的距离足够宽时，才显示中间的刻度
-            // 这里我们用一个近似的启发式方法：
-            // 如果视图范围涵盖了太多的数量级，就不显示次级刻度
-
-            let range_magnitude = (max / min).log10();
-
-            // 如果显示的范围跨度小于 4 个数量级 (比如 20Hz - 20kHz 是 3 个数量级)，显示详细网格
-            if range_magnitude < 4.0 {
-                // 音频常用: 2, 5 总是比较重要
-                if k == 2 || k == 5 {
-                    marks.push(GridMark {
-                        value: val,
-                        step_size: base,
-                    });
-                } else if range_magnitude < 2.5 {
-                    // 只有放大得比较大时，才显示 3, 4, 6, 7, 8, 9
-                    marks.push(GridMark {
-                        value: val,
-                        step_size: base,
-                    });
-                }
-            }
+    /// Saves the curve, plus the preamp applied when there is one, to an SVG
+    /// file the user picks.
+    pub fn export_graph(&mut self) {
+        let Some(path) = rfd::FileDialog::new()
+            .add_filter("SVG", &["svg"])
+            .set_file_name("response.svg")
+            .save_file()
+        else {
+            return;
+        };
+        let freqs = graph_freqs(EXPORT_POINTS, &self.info.ranges);
+        let gains = self.eq_profile.magnitude_response(&freqs, PREVIEW_FS);
+        let bands: Vec<(f64, f64)> = freqs.into_iter().zip(gains).collect();
+        let preamp_db = self.eq_profile.preamp_db;
+        let with_preamp: Vec<(f64, f64)> = bands
+            .iter()
+            .map(|(freq, db)| (*freq, db + preamp_db))
+            .collect();
+        let mut curves = vec![("EQ", bands.as_slice())];
+        if preamp_db != 0.0 {
+            curves.push(("With preamp", with_preamp.as_slice()));
+        }
+        match std::fs::write(&path, response_svg(&curves, PREVIEW_FS)) {
+            Ok(()) => self.notify(
+                self.info
+                    .lang
+                    .fill(Text::ExportedGraph, &[("path", &path.display())]),
+            ),
+            Err(e) => self.notify(format!("{} {}", self.info.lang.tr(Text::ExportFailed), e)),
         }
     }
 
-    marks
-}
+    /// Loads the curve the graph compares the profile with.
+    pub fn load_reference(&mut self) {
+        let Some(path) = rfd::FileDialog::new().pick_file() else {
+            return;
+        };
+        match Reference::load(&path) {
+            Ok(reference) => self.reference = Some(reference),
+            Err(e) => self.notify(format!(
+                "{} {:#}",
+                self.info.lang.tr(Text::LoadReferenceFailed),
+                e
+            )),
+        }
+    }
 
-impl App {
-    pub fn graph_ui(&self, ui: &mut Ui) -> Response {
-        let fs = 44000.0;
-        let coeffs: Vec<_> = self
-            .eq_profile
-            .filters
+    /// The reference's toggle and how far the profile strays from it, and
+    /// the reference and difference curves to draw while it is shown.
+    fn reference_ui(
+        &mut self,
+        ui: &mut Ui,
+        curve_points: &[[f64; 2]],
+    ) -> Option<[Vec<[f64; 2]>; 2]> {
+        let reference = self.reference.as_mut()?;
+        let values = reference.values(curve_points);
+        let reference_points: Vec<[f64; 2]> = curve_points
             .iter()
-            .filter(|f| f.enabled)
-            .map(|f| BiquadCoeffs::calc(f, fs))
+            .zip(values)
+            .filter_map(|([freq, _], db)| Some([*freq, (*db)?]))
             .collect();
+        let difference = difference(curve_points, values);
+        let lang = self.info.lang;
+        let mut remove = false;
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut reference.shown, lang.tr(Text::Reference))
+                .on_hover_text(&reference.name);
+            if let Some([freq, db]) = max_deviation(&difference) {
+                ui.weak(format!(
+                    "{} {}",
+                    lang.tr(Text::LargestDifference),
+                    readout(freq, db)
+                ));
+            }
+            remove = ui
+                .small_button("✖")
+                .on_hover_text(lang.tr(Text::RemoveReference))
+                .clicked();
+        });
+        if remove {
+            self.reference = None;
+            return None;
+        }
+        reference.shown.then_some([reference_points, difference])
+    }
+
+    pub fn graph_ui(&mut self, ui: &mut Ui) -> Response {
+        let spectrum = self.spectrum_points(ui);
         let width = ui.available_width();
-        let point_count = width as usize * 2;
-        let log_min = 20.0f64.ln();
-        let log_max = 20000.0f64.ln();
-        let log_range = log_max - log_min;
-
-        let curve_points: PlotPoints = (0..=point_count)
-            .map(|i| {
-                let t = i as f64 / point_count as f64;
-                // 将线性索引 t 映射到对数频率域
-                let freq = (log_min + t * log_range).exp();
-
-                // 总响应是所有滤波器 dB 值的累加
-                let mut total_db = 0.0;
-                for coeffs in &coeffs {
-                    total_db += coeffs.calc_magnitude_db(freq, fs);
-                }
-                [freq, total_db]
-            })
+        let point_count = (width as usize * 2).max(1);
+        self.curve
+            .update(&self.eq_profile, &self.info.ranges, point_count);
+        let curve_points = self.curve.points.clone();
+        // Drawn as in the exported graph; with no band active it is all that
+        // is left of the EQ
+        let preamp_db = self.eq_profile.preamp_db;
+        let preamp_points: Vec<[f64; 2]> = curve_points
+            .iter()
+            .map(|[freq, db]| [*freq, db + preamp_db])
             .collect();
+        let reference = self.reference_ui(ui, &curve_points);
 
-        let plot = Plot::new("Graph");
-        plot.x_axis_label("Frequency (Hz)")
-            .y_axis_label("Gain (dB)")
+        let lang = self.info.lang;
+        let plot = Plot::new("Graph")
+            .x_axis_label(lang.tr(Text::FrequencyAxis))
+            .y_axis_label(lang.tr(Text::GainAxis))
             .allow_drag(false)
             .allow_scroll(false)
             .allow_zoom(false)
             .allow_axis_zoom_drag(false)
             .allow_boxed_zoom(false)
             .x_grid_spacer(audio_grid_spacer)
-            .default_x_bounds(20.0, 20000.0)
+            .default_x_bounds(self.info.ranges.min_freq_hz, self.info.ranges.max_freq_hz)
+            // The readout below replaces the plot's own coordinate label
+            .show_x(false)
+            .show_y(false)
             .show(ui, |ui| {
+                if let Some(spectrum) = spectrum {
+                    ui.line(
+                        Line::new("Spectrum", spectrum)
+                            .fill(spectrum_y(FLOOR_DB) as f32)
+                            .color(Color32::from_gray(120).gamma_multiply(0.5)),
+                    );
+                }
+                let hovered = ui.pointer_coordinate().and_then(|pointer| {
+                    curve_value(&curve_points, pointer.x).map(|db| readout(pointer.x, db))
+                });
+                if let Some([reference, difference]) = reference {
+                    ui.line(
+                        Line::new("Reference", PlotPoints::from(reference))
+                            .style(LineStyle::dashed_loose())
+                            .color(Color32::GRAY),
+                    );
+                    ui.line(
+                        Line::new("Difference", PlotPoints::from(difference))
+                            .color(Color32::from_rgb(230, 150, 60)),
+                    );
+                }
+                if preamp_db != 0.0 {
+                    ui.line(
+                        Line::new("With preamp", PlotPoints::from(preamp_points))
+                            .color(Color32::LIGHT_BLUE.gamma_multiply(0.5)),
+                    );
+                }
                 ui.line(
-                    Line::new("Line", curve_points)
+                    Line::new("Line", PlotPoints::from(curve_points))
                         .width(2.0)
                         .color(Color32::LIGHT_BLUE),
                 );
-            })
-            .response
+                hovered
+            });
+        match plot.inner {
+            // Only shown while the pointer is over the plot
+            Some(text) => plot.response.on_hover_text_at_pointer(text),
+            None => plot.response,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eq::{Filter, TILT_SPAN_OCTAVES};
+
+    fn grid(min: f64, max: f64) -> Vec<GridMark> {
+        audio_grid_spacer(GridInput {
+            bounds: (min, max),
+            base_step_size: 1.0,
+        })
+    }
+
+    #[test]
+    fn test_grid_marks() {
+        let marks = grid(20.0, 20000.0);
+        let values: Vec<f64> = marks.iter().map(|m| m.value).collect();
+        let mut expected = Vec::new();
+        for decade in [10.0, 100.0, 1000.0, 10000.0] {
+            for k in 1..10 {
+                let value = k as f64 * decade;
+                if (20.0..=20000.0).contains(&value) {
+                    expected.push(value);
+                }
+            }
+        }
+        assert_eq!(values, expected);
+        assert_eq!(marks.len(), 28);
+        let step = |value: f64| marks.iter().find(|m| m.value == value).unwrap().step_size;
+        // Decades get the whole view as their step, so they are always labeled
+        for decade in [100.0, 1000.0, 10000.0] {
+            assert_eq!(step(decade), 19980.0);
+        }
+        assert_eq!(step(20.0), 10.0);
+        assert_eq!(step(500.0), 100.0);
+        assert_eq!(step(20000.0), 10000.0);
+        assert_eq!(step(300.0), 50.0);
+        assert_eq!(step(9000.0), 500.0);
+
+        // Zoomed in, the decade below still counts; zoomed out past 0 Hz the
+        // marks start at 1 Hz
+        let values: Vec<f64> = grid(150.0, 450.0).iter().map(|m| m.value).collect();
+        assert_eq!(values, [200.0, 300.0, 400.0]);
+        let marks = grid(-50.0, 10.0);
+        assert_eq!(marks.len(), 10);
+        assert_eq!(marks[0].value, 1.0);
+        assert!(grid(100.0, 50.0).is_empty());
+        assert!(grid(-10.0, 0.5).is_empty());
+    }
+
+    #[test]
+    fn test_hover_readout() {
+        let points = [[100.0, 0.0], [200.0, 4.0], [1000.0, -2.0]];
+        assert_eq!(curve_value(&points, 150.0), Some(2.0));
+        assert_eq!(curve_value(&points, 600.0), Some(1.0));
+        assert_eq!(curve_value(&points, 100.0), Some(0.0));
+        assert_eq!(curve_value(&points, 1000.0), Some(-2.0));
+        assert_eq!(curve_value(&points, 50.0), None);
+        assert_eq!(curve_value(&points, 2000.0), None);
+        assert_eq!(readout(1234.0, 3.44), "1.2 kHz: +3.4 dB");
+        assert_eq!(readout(85.2, -2.0), "85 Hz: -2.0 dB");
+    }
+
+    #[test]
+    fn test_curve_cache() {
+        let ranges = RangeConfig::default();
+        let mut profile = EqProfile::default();
+        let mut cache = CurveCache::default();
+        assert!(cache.update(&profile, &ranges, 800));
+        assert_eq!(cache.points.len(), 801);
+        assert!(!cache.update(&profile, &ranges, 800));
+        // A few pixels of resize keep the points, a larger one doesn't
+        assert!(!cache.update(&profile, &ranges, 760));
+        assert!(cache.update(&profile, &ranges, 400));
+        assert_eq!(cache.points.len(), 401);
+
+        profile.filters.push(Filter {
+            gain: 6.0,
+            ..Default::default()
+        });
+        assert!(cache.update(&profile, &ranges, 400));
+        let peak = cache.points.iter().map(|p| p[1]).fold(f64::MIN, f64::max);
+        assert!((peak - 6.0).abs() < 0.1, "{}", peak);
+        profile.preamp_db = -3.0;
+        assert!(cache.update(&profile, &ranges, 400));
+        // A wider span is a new curve
+        let wide = RangeConfig {
+            min_freq_hz: 10.0,
+            ..ranges
+        };
+        assert!(cache.update(&profile, &wide, 400));
+        assert!((cache.points[0][0] - 10.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_empty_profile_is_flat() {
+        let ranges = RangeConfig::default();
+        let mut profile = EqProfile {
+            preamp_db: -4.0,
+            ..Default::default()
+        };
+        let mut cache = CurveCache::default();
+        assert!(cache.update(&profile, &ranges, 100));
+        assert_eq!(cache.points.len(), 101);
+        assert!(cache.points.iter().all(|[_, db]| *db == 0.0));
+        // Disabled bands count for nothing
+        profile.filters.push(Filter {
+            enabled: false,
+            gain: 6.0,
+            ..Default::default()
+        });
+        let gains = profile.magnitude_response(&graph_freqs(100, &ranges), PREVIEW_FS);
+        assert!(gains.iter().all(|db| *db == 0.0));
+        // The preamp alone sets the peak
+        assert!((profile.peak_db(PREVIEW_FS) + 4.0).abs() < 1e-9);
+        assert_eq!(clip_db(&profile, &ranges), None);
+    }
+
+    #[test]
+    fn test_clip_detection() {
+        let ranges = RangeConfig::default();
+        let mut profile = EqProfile {
+            preamp_db: 0.0,
+            filters: vec![Filter {
+                gain: 6.0,
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let over = clip_db(&profile, &ranges).unwrap();
+        assert!((over - 6.0).abs() < 0.05, "over by {}", over);
+        // The suggested preamp brings the peak back to 0 dB
+        profile.preamp_db = -over;
+        assert_eq!(clip_db(&profile, &ranges), None);
+        profile.preamp_db = -5.9;
+        assert!(clip_db(&profile, &ranges).is_some());
+        assert_eq!(clip_db(&EqProfile::default(), &ranges), None);
+    }
+
+    #[test]
+    fn test_tilt_response() {
+        for tilt in [0.5, -0.3] {
+            let profile = EqProfile {
+                tilt_db_per_oct: tilt,
+                ..Default::default()
+            };
+            let [low, pivot, high] = profile
+                .magnitude_response(&[20.0, 1000.0, 20000.0], PREVIEW_FS)
+                .try_into()
+                .unwrap();
+            let span = high - low;
+            let expected = tilt * TILT_SPAN_OCTAVES;
+            assert!(
+                (span - expected).abs() < 0.1 * expected.abs(),
+                "{} vs {}",
+                span,
+                expected
+            );
+            assert!(pivot.abs() < 0.1);
+        }
     }
 }
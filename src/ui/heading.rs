@@ -1,134 +1,996 @@
-use std::{ops::DerefMut, str::FromStr, sync::mpsc};
+use std::{
+    ops::DerefMut,
+    sync::atomic::Ordering,
+    time::{Duration, Instant},
+};
 
-use eframe::egui::{self, ComboBox, DragValue, Widget};
+use eframe::egui::{self, Color32, ComboBox, DragValue, Widget};
 use tracing::{debug, error};
 
 use crate::{
+    command::{Command, SetDevice},
+    config::{ChannelMode, Lang, LimiterMode, OverflowPolicy, Setup},
     eq::EqProfile,
-    ui::{
-        App,
-        command::{Command, SetDevice, SetRealtime},
-    },
+    fir::{self, LINEAR_PHASE_TAPS},
+    presets,
+    run::{StreamOptions, resolve_host},
+    settings::OVERSAMPLING_FACTORS,
+    ui::{App, i18n::Text, midi::LearnTarget},
+    utils::OneShot,
 };
 
+const NOTICE_DURATION: Duration = Duration::from_secs(3);
+const DEVICE_POLL_INTERVAL: Duration = Duration::from_secs(3);
+const BUFFER_SIZES: [u32; 8] = [32, 64, 128, 256, 512, 1024, 2048, 4096];
+/// Boost of the quick bass and treble buttons.
+const QUICK_BASS_DB: f64 = 6.0;
+const QUICK_TREBLE_DB: f64 = 4.0;
+
+/// Parses APO text or JSON, rejecting text that contains no profile at all
+/// (the APO parser skips unknown lines).
+fn parse_clipboard(text: &str) -> anyhow::Result<EqProfile> {
+    let profile = EqProfile::parse(text)?;
+    if profile.filters.is_empty() && profile.preamp_db == 0.0 {
+        anyhow::bail!("clipboard doesn't contain an EQ profile");
+    }
+    Ok(profile)
+}
+
+/// Confirmation text for resetting a profile with `bands` bands.
+fn reset_prompt(lang: Lang, bands: usize) -> String {
+    match bands {
+        1 => lang.tr(Text::ResetOne).to_string(),
+        n => lang.fill(Text::ResetMany, &[("bands", &n)]),
+    }
+}
+
+/// Shows a device name in the error color when the device is gone.
+fn missing_text(ui: &egui::Ui, text: String, missing: bool) -> egui::RichText {
+    let text = egui::RichText::new(text);
+    if missing {
+        text.color(ui.visuals().error_fg_color)
+    } else {
+        text
+    }
+}
+
 impl App {
     pub fn heading_ui(&mut self, ui: &mut eframe::egui::Ui) {
+        let lang = self.info.lang;
+        self.handle_paste(ui.ctx());
+        self.poll_devices(ui.ctx());
         ui.horizontal(|ui| {
             if !self.state.realtime
                 && ui
-                    .button(if self.state.running { "Stop" } else { "Start" })
+                    .button(lang.tr(if self.state.running {
+                        Text::Stop
+                    } else {
+                        Text::Start
+                    }))
                     .clicked()
             {
                 self.state.running = !self.state.running;
                 self.sender.send(Command::SetState(self.state)).ok();
             }
             if ui
-                .button(if self.state.enabled {
-                    "Disable EQ"
+                .button(lang.tr(if self.state.enabled {
+                    Text::DisableEq
                 } else {
-                    "Enable EQ"
-                })
+                    Text::EnableEq
+                }))
                 .clicked()
             {
-                self.state.enabled = !self.state.enabled;
-                self.sender.send(Command::SetState(self.state)).ok();
+                self.toggle_eq();
+                self.learn(LearnTarget::EnableEq);
+            }
+            let mut matched = self.eq_settings.matched_bypass.load(Ordering::Relaxed);
+            if ui
+                .checkbox(&mut matched, lang.tr(Text::MatchedBypass))
+                .on_hover_text(lang.tr(Text::MatchedBypassHover))
+                .changed()
+            {
+                self.eq_settings
+                    .matched_bypass
+                    .store(matched, Ordering::Relaxed);
+            }
+            if self.info.host_names.len() > 1 {
+                ui.label(lang.tr(Text::Host));
+                let mut selected = None;
+                ComboBox::new("host", "")
+                    .selected_text(self.info.host.as_str())
+                    .show_ui(ui, |ui| {
+                        for name in self.info.host_names.iter() {
+                            if ui.selectable_label(self.info.host == *name, name).clicked() {
+                                selected = Some(name.clone());
+                            }
+                        }
+                    });
+                if let Some(name) = selected {
+                    self.stream_options = None;
+                    self.info.refresh_devices(&resolve_host(Some(&name)));
+                    self.sender.send(Command::SetHost(name)).ok();
+                }
+            }
+            ui.label(lang.tr(Text::Input));
+            let mut input_text = if self.info.loopback {
+                lang.fill(Text::LoopbackDevice, &[("device", &self.info.input_dev)])
+            } else {
+                self.info.input_dev.clone()
+            };
+            if self.info.input_missing() {
+                input_text = lang.fill(Text::MissingDevice, &[("device", &input_text)]);
             }
-            ui.label("Inp:");
             ComboBox::new("inp_dev", "")
-                .selected_text(self.info.input_dev.as_str())
+                .selected_text(missing_text(ui, input_text, self.info.input_missing()))
                 .show_ui(ui, |ui| {
                     for i in self.info.device_names.iter() {
                         if ui
-                            .selectable_value(&mut self.info.input_dev, i.to_owned(), i)
+                            .selectable_label(!self.info.loopback && self.info.input_dev == *i, i)
                             .clicked()
                         {
                             debug!("Changed");
+                            self.info.input_dev = i.clone();
+                            self.info.loopback = false;
+                            self.stream_options = None;
                             self.sender
                                 .send(Command::SetDevice(SetDevice::Input, i.clone()))
                                 .ok();
                         }
                     }
+                    if !self.info.loopback_names.is_empty() {
+                        ui.separator();
+                    }
+                    for i in self.info.loopback_names.iter() {
+                        if ui
+                            .selectable_label(
+                                self.info.loopback && self.info.input_dev == *i,
+                                lang.fill(Text::LoopbackDevice, &[("device", i)]),
+                            )
+                            .clicked()
+                        {
+                            self.info.input_dev = i.clone();
+                            self.info.loopback = true;
+                            self.stream_options = None;
+                            self.sender
+                                .send(Command::SetDevice(SetDevice::Loopback, i.clone()))
+                                .ok();
+                        }
+                    }
                 });
-            ui.label("Out:");
+            ui.label(lang.tr(Text::Output));
+            let output_text = if self.info.output_missing() {
+                lang.fill(Text::MissingDevice, &[("device", &self.info.output_dev)])
+            } else {
+                self.info.output_dev.clone()
+            };
             ComboBox::new("out_dev", "")
-                .selected_text(self.info.output_dev.as_str())
+                .selected_text(missing_text(ui, output_text, self.info.output_missing()))
                 .show_ui(ui, |ui| {
                     for i in self.info.device_names.iter() {
                         if ui
                             .selectable_value(&mut self.info.output_dev, i.to_owned(), i)
                             .clicked()
                         {
+                            self.stream_options = None;
                             self.sender
                                 .send(Command::SetDevice(SetDevice::Output, i.clone()))
                                 .ok();
                         }
                     }
                 });
-            if ui.button("Load").clicked()
+            if ui.button(lang.tr(Text::Refresh)).clicked() {
+                self.refresh_devices();
+            }
+            self.stream_ui(ui);
+            if ui.button(lang.tr(Text::Load)).clicked()
                 && let Some(path) = rfd::FileDialog::new().pick_file()
-                && let Ok(content) = std::fs::read_to_string(path)
                 && let Ok(profile) =
-                    EqProfile::from_str(content.as_str()).inspect_err(|e| error!("Error: {:?}", e))
+                    EqProfile::load(&path).inspect_err(|e| error!("Error: {:?}", e))
             {
                 *self.eq_profile.deref_mut() = profile;
                 self.sender
                     .send(Command::UpdateSettings(self.eq_settings.clone()))
                     .ok();
             }
-            ui.label("Preamp:");
+            if ui
+                .button(lang.tr(Text::SaveAs))
+                .on_hover_text(lang.tr(Text::SaveAsHover))
+                .clicked()
+            {
+                self.save_profile_as();
+            }
+            if ui.button(lang.tr(Text::Copy)).clicked() {
+                ui.ctx().copy_text(self.eq_profile.to_string());
+                self.notify(lang.tr(Text::CopiedProfile));
+            }
+            if ui.button(lang.tr(Text::Paste)).clicked() {
+                ui.ctx()
+                    .send_viewport_cmd(egui::ViewportCommand::RequestPaste);
+                self.paste_requested = Some(Instant::now());
+            }
+            // Edited through a copy, so the profile only counts as changed
+            // when the text is
+            ui.label(lang.tr(Text::ProfileName));
+            let mut name = self.eq_profile.metadata.name.clone().unwrap_or_default();
+            if egui::TextEdit::singleline(&mut name)
+                .hint_text(lang.tr(Text::Untitled))
+                .desired_width(120.0)
+                .ui(ui)
+                .on_hover_text(lang.tr(Text::ProfileNameHover))
+                .changed()
+            {
+                self.eq_profile.metadata.name = (!name.is_empty()).then_some(name);
+            }
+            ui.label(lang.tr(Text::Preamp));
             DragValue::new(&mut self.eq_profile.preamp_db)
                 .speed(0.1)
-                .range(-12.0..=12.0)
+                .range(self.info.ranges.preamp())
                 .ui(ui);
-            if ui.button("Reset").clicked() {
-                *self.eq_profile.deref_mut() = EqProfile::default();
+            ui.label(lang.tr(Text::Tilt));
+            DragValue::new(&mut self.eq_profile.tilt_db_per_oct)
+                .speed(0.01)
+                .range(self.info.ranges.tilt())
+                .max_decimals(2)
+                .suffix(" dB/oct")
+                .ui(ui)
+                .on_hover_text(lang.tr(Text::TiltHover));
+            if let Some(over) = self.clip_db() {
+                ui.colored_label(
+                    Color32::RED,
+                    format!("{} +{:.1} dB", lang.tr(Text::Clip), over),
+                )
+                .on_hover_text(lang.tr(Text::ClipHover));
+                if ui
+                    .button(lang.tr(Text::Fix))
+                    .on_hover_text(lang.tr(Text::FixHover))
+                    .clicked()
+                {
+                    // Round down to the 0.1 dB the preamp is shown with
+                    self.eq_profile.preamp_db -= (over * 10.0).ceil() / 10.0;
+                }
             }
-            if ui.button("Undo").clicked() {
-                *self.eq_profile.deref_mut() = self.eq_profile_back.clone();
+            // Lives in the settings, so loading a profile leaves it alone
+            ui.label(lang.tr(Text::Volume));
+            let mut gain_db = self.eq_settings.output_gain_db.load();
+            if egui::Slider::new(&mut gain_db, -40.0..=12.0)
+                .suffix(" dB")
+                .ui(ui)
+                .changed()
+            {
+                self.eq_settings.output_gain_db.store(gain_db);
+            }
+            let mut loudness = self.eq_settings.loudness.load(Ordering::Relaxed);
+            if ui
+                .checkbox(&mut loudness, lang.tr(Text::Loudness))
+                .on_hover_text(lang.tr(Text::LoudnessHover))
+                .changed()
+            {
+                self.eq_settings.loudness.store(loudness, Ordering::Relaxed);
+            }
+            if ui.button(lang.tr(Text::Reset)).clicked() {
+                if self.eq_profile.filters.is_empty() {
+                    self.reset_profile();
+                } else {
+                    self.confirm_reset = true;
+                }
+            }
+            if ui
+                .button(lang.tr(Text::Invert))
+                .on_hover_text(lang.tr(Text::InvertHover))
+                .clicked()
+            {
+                let inverted = self.eq_profile.inverted();
+                if !self.eq_profile.is_invertible() {
+                    self.notify(lang.tr(Text::InvertedKeptPasses));
+                }
+                *self.eq_profile.deref_mut() = inverted;
+            }
+            if ui
+                .button(lang.tr(Text::Flatten))
+                .on_hover_text(lang.tr(Text::FlattenHover))
+                .clicked()
+            {
+                self.eq_profile.flatten();
             }
-            if ui.checkbox(&mut self.state.realtime, "Realtime").changed() {
+            // A/B holds main profiles only, like realtime edits
+            let label = if self.ab_blind {
+                "A/B".to_string()
+            } else {
+                format!("A/B: {}", self.ab_slot)
+            };
+            if ui
+                .add_enabled(!self.editing_side, egui::Button::new(label))
+                .on_hover_text(lang.tr(Text::AbHover))
+                .clicked()
+            {
+                self.swap_ab();
+            }
+            ui.checkbox(&mut self.ab_blind, lang.tr(Text::Blind))
+                .on_hover_text(lang.tr(Text::BlindHover));
+            if ui
+                .add_enabled(
+                    self.history.can_undo(),
+                    egui::Button::new(lang.tr(Text::Undo)),
+                )
+                .clicked()
+            {
+                self.undo();
+            }
+            if ui
+                .add_enabled(
+                    self.history.can_redo(),
+                    egui::Button::new(lang.tr(Text::Redo)),
+                )
+                .clicked()
+            {
+                self.redo();
+            }
+            // Realtime edits always go to the main profile
+            if ui
+                .add_enabled(
+                    !self.editing_side,
+                    egui::Checkbox::new(&mut self.state.realtime, lang.tr(Text::Realtime)),
+                )
+                .changed()
+            {
                 if self.state.realtime {
-                    let (sender, receiver) = mpsc::channel();
+                    self.sender.send(Command::SetRealtime(true)).ok();
+                    // Start from what is on screen, not the last applied profile
                     self.sender
-                        .send(Command::SetRealtime(SetRealtime::On(receiver)))
+                        .send(Command::UpdateProfile(self.eq_profile.clone()))
                         .ok();
+                    let sender = self.sender.clone();
                     self.eq_profile.set_callback(move |p| {
-                        sender.send(p.clone()).ok();
+                        sender.send(Command::UpdateProfile(p.clone())).ok();
                     });
                 } else {
-                    self.sender
-                        .send(Command::SetRealtime(SetRealtime::Off))
-                        .ok();
+                    // The restarted engine runs the edits made meanwhile
+                    self.send_snapshot();
+                    self.sender.send(Command::SetRealtime(false)).ok();
                     self.eq_profile.remove_hook();
                 }
             }
-            if !self.state.realtime && ui.button("Apply").clicked() {
-                self.eq_settings_back = self.eq_settings.clone();
-                self.sender
-                    .send(Command::UpdateSettings(self.eq_settings.clone()))
-                    .ok();
-                self.eq_profile_back = self.eq_profile.clone();
+            if !self.state.realtime {
+                let dirty = self.is_dirty();
+                let text = egui::RichText::new(lang.tr(Text::Apply));
+                let button =
+                    egui::Button::new(if dirty { text.strong() } else { text }).selected(dirty);
+                if ui
+                    .add_enabled(dirty, button)
+                    .on_hover_text(lang.tr(Text::ApplyHover))
+                    .on_disabled_hover_text(lang.tr(Text::NothingToApply))
+                    .clicked()
+                {
+                    self.apply();
+                }
+            }
+            if ui.button(lang.tr(Text::Save)).clicked() {
+                let (profile, side_profile) = self.profiles();
                 self.sender
-                    .send(Command::UpdateProfile(self.eq_profile.clone()))
+                    .send(Command::UpdateSideProfile(side_profile))
                     .ok();
-                self.sender.send(Command::Restart).ok();
-            }
-            if ui.button("Save").clicked() {
                 self.sender
-                    .send(Command::Save(
-                        self.eq_settings.clone(),
-                        self.eq_profile.clone(),
-                    ))
+                    .send(Command::Save(self.eq_settings.clone(), profile))
                     .ok();
             }
-            if ui.button("Quit").clicked() {
+            if ui.button(lang.tr(Text::Quit)).clicked() {
+                self.sender.send(Command::SaveWindow(self.info.window)).ok();
                 self.sender.send(Command::Shutdown).ok();
                 self.quitting = true;
                 ui.ctx().send_viewport_cmd(egui::ViewportCommand::Close);
             }
-            if ui.button("Add Band").clicked() {
+            ui.checkbox(&mut self.info.window.show_graph, lang.tr(Text::Graph));
+            ui.checkbox(&mut self.info.window.show_meters, lang.tr(Text::Meters));
+            if self.info.window.show_graph {
+                ui.checkbox(&mut self.info.window.show_spectrum, lang.tr(Text::Spectrum));
+                if ui.button(lang.tr(Text::ExportGraph)).clicked() {
+                    self.export_graph();
+                }
+                if ui
+                    .button(lang.tr(Text::LoadReference))
+                    .on_hover_text(lang.tr(Text::LoadReferenceHover))
+                    .clicked()
+                {
+                    self.load_reference();
+                }
+            }
+            if ui.button(lang.tr(Text::AddBand)).clicked() {
                 self.eq_profile.filters.push(crate::eq::Filter::default());
             }
+            ui.label(lang.tr(Text::Language));
+            ComboBox::new("lang", "")
+                .selected_text(lang.to_string())
+                .show_ui(ui, |ui| {
+                    for option in Lang::ALL {
+                        if ui
+                            .selectable_value(&mut self.info.lang, option, option.to_string())
+                            .clicked()
+                        {
+                            self.sender.send(Command::SetLang(option)).ok();
+                        }
+                    }
+                });
+            ui.label(lang.tr(Text::Font));
+            ui.weak(self.info.font.as_deref().unwrap_or("egui"))
+                .on_hover_text(lang.tr(Text::FontHover));
+            if self.midi.connected()
+                && ui
+                    .toggle_value(&mut self.midi.learning, lang.tr(Text::MidiLearn))
+                    .on_hover_text(lang.tr(Text::MidiLearnHover))
+                    .changed()
+                && !self.midi.learning
+            {
+                self.stop_learning();
+            }
+        });
+        self.preset_ui(ui);
+        self.output_ui(ui);
+        self.status_ui(ui);
+        self.meter_ui(ui);
+        if self.confirm_reset {
+            self.reset_modal(ui.ctx());
+        }
+    }
+
+    /// Asks before Reset throws the bands away.
+    fn reset_modal(&mut self, ctx: &egui::Context) {
+        let lang = self.info.lang;
+        let prompt = reset_prompt(lang, self.eq_profile.filters.len());
+        let modal = egui::Modal::new(egui::Id::new("confirm_reset")).show(ctx, |ui| {
+            ui.label(prompt);
+            ui.horizontal(|ui| {
+                let reset = ui.button(lang.tr(Text::Reset)).clicked();
+                if reset {
+                    self.reset_profile();
+                }
+                reset || ui.button(lang.tr(Text::Cancel)).clicked()
+            })
+            .inner
         });
+        if modal.inner || modal.should_close() {
+            self.confirm_reset = false;
+        }
+    }
+
+    /// Asks the executor for the current device lists. Hosts don't report
+    /// hotplug events through cpal, so this also runs periodically.
+    fn refresh_devices(&mut self) {
+        if self.device_poll.is_none() {
+            let oneshot = OneShot::new();
+            if self
+                .sender
+                .send(Command::RefreshDevices(oneshot.clone()))
+                .is_ok()
+            {
+                self.device_poll = Some(oneshot);
+            }
+        }
+        self.devices_checked = Instant::now();
+    }
+
+    fn poll_devices(&mut self, ctx: &egui::Context) {
+        if let Some(oneshot) = self.device_poll.as_ref() {
+            match oneshot.try_recv() {
+                Some(devices) => {
+                    self.device_poll = None;
+                    if devices.device_names != self.info.device_names
+                        || devices.loopback_names != self.info.loopback_names
+                    {
+                        debug!("Device list changed");
+                        self.info.set_devices(devices);
+                        self.stream_options = None;
+                    }
+                }
+                None => ctx.request_repaint_after(Duration::from_millis(50)),
+            }
+        } else if !self.window_hidden {
+            let remaining = DEVICE_POLL_INTERVAL.saturating_sub(self.devices_checked.elapsed());
+            if remaining.is_zero() {
+                self.refresh_devices();
+                ctx.request_repaint_after(Duration::from_millis(50));
+            } else {
+                ctx.request_repaint_after(remaining);
+            }
+        }
+    }
+
+    /// Rate and buffer size pickers. The choices are queried from the
+    /// executor the first time a dropdown opens after a device change.
+    fn stream_ui(&mut self, ui: &mut eframe::egui::Ui) {
+        let lang = self.info.lang;
+        let auto = |value: Option<u32>, unit: &str| match value {
+            Some(value) => format!("{} {}", value, unit),
+            None => lang.tr(Text::Auto).to_string(),
+        };
+        ui.label(lang.tr(Text::Rate));
+        ComboBox::new("sample_rate", "")
+            .selected_text(auto(self.eq_settings.sample_rate, "Hz"))
+            .show_ui(ui, |ui| {
+                ui.selectable_value(&mut self.eq_settings.sample_rate, None, lang.tr(Text::Auto));
+                for rate in self.stream_options().sample_rates.clone() {
+                    ui.selectable_value(
+                        &mut self.eq_settings.sample_rate,
+                        Some(rate),
+                        format!("{} Hz", rate),
+                    );
+                }
+            });
+        ui.label(lang.tr(Text::Buffer));
+        ComboBox::new("buffer_size", "")
+            .selected_text(auto(self.eq_settings.buffer_size, lang.tr(Text::Frames)))
+            .show_ui(ui, |ui| {
+                ui.selectable_value(&mut self.eq_settings.buffer_size, None, lang.tr(Text::Auto));
+                let (min, max) = self.stream_options().buffer_sizes.unwrap_or((0, u32::MAX));
+                for size in BUFFER_SIZES.into_iter().filter(|s| (min..=max).contains(s)) {
+                    ui.selectable_value(
+                        &mut self.eq_settings.buffer_size,
+                        Some(size),
+                        format!("{} {}", size, lang.tr(Text::Frames)),
+                    );
+                }
+            });
+        ui.checkbox(
+            &mut self.eq_settings.linear_phase,
+            lang.tr(Text::LinearPhase),
+        )
+        .on_hover_text(lang.fill(
+            Text::LinearPhaseHover,
+            &[("frames", &fir::latency_frames(LINEAR_PHASE_TAPS))],
+        ));
+        ui.label(lang.tr(Text::Overflow));
+        ComboBox::new("overflow", "")
+            .selected_text(self.eq_settings.overflow.to_string())
+            .show_ui(ui, |ui| {
+                for policy in [OverflowPolicy::DropNewest, OverflowPolicy::DropOldest] {
+                    ui.selectable_value(&mut self.eq_settings.overflow, policy, policy.to_string());
+                }
+            })
+            .response
+            .on_hover_text(lang.tr(Text::OverflowHover));
+    }
+
+    fn stream_options(&mut self) -> &StreamOptions {
+        self.stream_options.get_or_insert_with(|| {
+            let oneshot = OneShot::new();
+            match self.sender.send(Command::GetStreamOptions(oneshot.clone())) {
+                Ok(()) => oneshot.recv(),
+                Err(_) => StreamOptions::default(),
+            }
+        })
+    }
+
+    /// Controls of the stages after the EQ; all of them apply live.
+    fn output_ui(&mut self, ui: &mut eframe::egui::Ui) {
+        let lang = self.info.lang;
+        ui.horizontal(|ui| {
+            ui.label(lang.tr(Text::Balance));
+            let mut balance = self.eq_settings.balance.load();
+            if egui::Slider::new(&mut balance, -1.0..=1.0).ui(ui).changed() {
+                self.eq_settings.balance.store(balance);
+            }
+            let mut swap = self.eq_settings.swap_channels.load(Ordering::Relaxed);
+            if ui
+                .checkbox(&mut swap, lang.tr(Text::SwapChannels))
+                .changed()
+            {
+                self.eq_settings
+                    .swap_channels
+                    .store(swap, Ordering::Relaxed);
+            }
+            let mut dc_blocker = self.eq_settings.dc_blocker.load(Ordering::Relaxed);
+            if ui
+                .checkbox(&mut dc_blocker, lang.tr(Text::DcFilter))
+                .on_hover_text(lang.tr(Text::DcFilterHover))
+                .changed()
+            {
+                self.eq_settings
+                    .dc_blocker
+                    .store(dc_blocker, Ordering::Relaxed);
+            }
+            let crossfeed = &self.eq_settings.crossfeed;
+            let mut enabled = crossfeed.enabled.load(Ordering::Relaxed);
+            if ui
+                .checkbox(&mut enabled, lang.tr(Text::Crossfeed))
+                .changed()
+            {
+                crossfeed.enabled.store(enabled, Ordering::Relaxed);
+            }
+            if enabled {
+                let mut amount = crossfeed.amount.load();
+                if egui::Slider::new(&mut amount, 0.0..=1.0).ui(ui).changed() {
+                    crossfeed.amount.store(amount);
+                }
+                let mut cutoff_hz = crossfeed.cutoff_hz.load();
+                if egui::Slider::new(&mut cutoff_hz, 300.0..=2000.0)
+                    .logarithmic(true)
+                    .suffix(" Hz")
+                    .ui(ui)
+                    .changed()
+                {
+                    crossfeed.cutoff_hz.store(cutoff_hz);
+                }
+            }
+            let limiter = &self.eq_settings.limiter;
+            let mut mode = limiter.mode();
+            ui.label(lang.tr(Text::Limiter));
+            ComboBox::new("limiter", "")
+                .selected_text(mode.to_string())
+                .show_ui(ui, |ui| {
+                    for m in [
+                        LimiterMode::Off,
+                        LimiterMode::SoftClip,
+                        LimiterMode::Brickwall,
+                    ] {
+                        if ui.selectable_value(&mut mode, m, m.to_string()).clicked() {
+                            limiter.set_mode(m);
+                        }
+                    }
+                });
+            if mode != LimiterMode::Off {
+                let mut ceiling_db = limiter.ceiling_db.load();
+                if DragValue::new(&mut ceiling_db)
+                    .speed(0.1)
+                    .range(-24.0..=0.0)
+                    .suffix(" dBFS")
+                    .ui(ui)
+                    .changed()
+                {
+                    limiter.ceiling_db.store(ceiling_db);
+                }
+                let mut factor = limiter.oversampling();
+                let label = |factor: u8| match factor {
+                    1 => lang.tr(Text::Off).to_string(),
+                    factor => format!("{}x", factor),
+                };
+                ui.label(lang.tr(Text::Oversampling));
+                ComboBox::new("oversampling", "")
+                    .selected_text(label(factor))
+                    .show_ui(ui, |ui| {
+                        for f in OVERSAMPLING_FACTORS {
+                            if ui.selectable_value(&mut factor, f, label(f)).clicked() {
+                                limiter.set_oversampling(f);
+                            }
+                        }
+                    });
+            }
+            let dither = &self.eq_settings.dither;
+            let mut enabled = dither.enabled.load(Ordering::Relaxed);
+            if ui
+                .checkbox(&mut enabled, lang.tr(Text::Dither))
+                .on_hover_text(lang.tr(Text::DitherHover))
+                .changed()
+            {
+                dither.enabled.store(enabled, Ordering::Relaxed);
+            }
+            if enabled {
+                let mut shaping = dither.noise_shaping.load(Ordering::Relaxed);
+                if ui
+                    .checkbox(&mut shaping, lang.tr(Text::NoiseShaping))
+                    .changed()
+                {
+                    dither.noise_shaping.store(shaping, Ordering::Relaxed);
+                }
+            }
+        });
+    }
+
+    fn preset_ui(&mut self, ui: &mut eframe::egui::Ui) {
+        let lang = self.info.lang;
+        ui.horizontal(|ui| {
+            ui.label(lang.tr(Text::Mode));
+            ComboBox::new("channel_mode", "")
+                .selected_text(self.channel_mode.to_string())
+                .show_ui(ui, |ui| {
+                    for mode in [ChannelMode::Stereo, ChannelMode::MidSide] {
+                        if ui
+                            .selectable_value(&mut self.channel_mode, mode, mode.to_string())
+                            .clicked()
+                        {
+                            self.sender.send(Command::SetChannelMode(mode)).ok();
+                        }
+                    }
+                });
+            if self.channel_mode == ChannelMode::MidSide {
+                ui.add_enabled_ui(!self.state.realtime, |ui| {
+                    if ui
+                        .selectable_label(!self.editing_side, lang.tr(Text::Mid))
+                        .clicked()
+                        && self.editing_side
+                    {
+                        self.toggle_side_editing();
+                    }
+                    if ui
+                        .selectable_label(self.editing_side, lang.tr(Text::Side))
+                        .clicked()
+                        && !self.editing_side
+                    {
+                        self.toggle_side_editing();
+                    }
+                });
+            } else if self.editing_side {
+                self.toggle_side_editing();
+            }
+            ui.label(lang.tr(Text::Preset));
+            ComboBox::new("preset", "")
+                .selected_text(self.preset_name.as_str())
+                .show_ui(ui, |ui| {
+                    let mut selected = None;
+                    for (name, profile) in self.info.presets.iter() {
+                        if ui
+                            .selectable_label(self.preset_name == *name, name)
+                            .clicked()
+                        {
+                            selected = Some((name.clone(), profile.clone()));
+                        }
+                    }
+                    if let Some((name, profile)) = selected {
+                        self.load_preset(name, profile);
+                    }
+                });
+            ui.text_edit_singleline(&mut self.preset_name);
+            let name = self.preset_name.trim().to_string();
+            if ui.button(lang.tr(Text::SavePreset)).clicked() && !name.is_empty() {
+                let profile = self.eq_profile.clone();
+                match self.info.presets.iter_mut().find(|(n, _)| *n == name) {
+                    Some((_, preset)) => *preset = profile.clone(),
+                    None => self.info.presets.push((name.clone(), profile.clone())),
+                }
+                self.sender.send(Command::SavePreset(name, profile)).ok();
+            } else if ui.button(lang.tr(Text::DeletePreset)).clicked() {
+                self.info.presets.retain(|(n, _)| *n != name);
+                self.sender.send(Command::DeletePreset(name)).ok();
+                self.preset_name.clear();
+            }
+            // Starting points to tweak, replacing the bands on screen
+            ui.label(lang.tr(Text::Quick));
+            let quick = [
+                (
+                    format!("{} +{}", lang.tr(Text::Bass), QUICK_BASS_DB),
+                    presets::bass_boost(QUICK_BASS_DB),
+                ),
+                (
+                    format!("{} +{}", lang.tr(Text::Treble), QUICK_TREBLE_DB),
+                    presets::treble_boost(QUICK_TREBLE_DB),
+                ),
+                (lang.tr(Text::Vocal).to_string(), presets::vocal_clarity()),
+            ];
+            for (label, profile) in quick {
+                if ui.button(label).clicked() {
+                    *self.eq_profile.deref_mut() = profile;
+                }
+            }
+        });
+        self.setup_ui(ui);
+    }
+
+    /// Devices, profile and latency saved and switched together.
+    fn setup_ui(&mut self, ui: &mut eframe::egui::Ui) {
+        let lang = self.info.lang;
+        ui.horizontal(|ui| {
+            ui.label(lang.tr(Text::Setup));
+            let mut selected = None;
+            ui.add_enabled_ui(!self.state.realtime, |ui| {
+                ComboBox::new("setup", "")
+                    .selected_text(self.setup_name.as_str())
+                    .show_ui(ui, |ui| {
+                        for setup in self.info.setups.iter() {
+                            if ui
+                                .selectable_label(self.setup_name == setup.name, &setup.name)
+                                .clicked()
+                            {
+                                selected = Some(setup.clone());
+                            }
+                        }
+                    });
+            });
+            if let Some(setup) = selected {
+                self.apply_setup(setup);
+            }
+            ui.text_edit_singleline(&mut self.setup_name);
+            let name = self.setup_name.trim().to_string();
+            if ui
+                .button(lang.tr(Text::SaveSetup))
+                .on_hover_text(lang.tr(Text::SaveSetupHover))
+                .clicked()
+                && !name.is_empty()
+            {
+                let setup = Setup {
+                    name,
+                    input: Some(self.info.input_dev.clone()).filter(|n| !n.is_empty()),
+                    loopback: self.info.loopback,
+                    output: Some(self.info.output_dev.clone()).filter(|n| !n.is_empty()),
+                    profile: self.profiles().0,
+                    latency: self.eq_settings.latency.load(Ordering::Relaxed),
+                };
+                match self.info.setups.iter_mut().find(|s| s.name == setup.name) {
+                    Some(existing) => *existing = setup.clone(),
+                    None => self.info.setups.push(setup.clone()),
+                }
+                self.sender.send(Command::SaveSetup(setup)).ok();
+            } else if ui.button(lang.tr(Text::DeleteSetup)).clicked() {
+                self.info.setups.retain(|s| s.name != name);
+                self.sender.send(Command::DeleteSetup(name)).ok();
+                self.setup_name.clear();
+            }
+        });
+    }
+
+    /// Mirrors what the executor switches to for `Command::ApplySetup`.
+    fn apply_setup(&mut self, setup: Setup) {
+        if self.editing_side {
+            self.toggle_side_editing();
+        }
+        self.setup_name = setup.name.clone();
+        self.info.input_dev = setup.input.unwrap_or_default();
+        self.info.loopback = setup.loopback;
+        self.info.output_dev = setup.output.unwrap_or_default();
+        self.eq_settings
+            .latency
+            .store(setup.latency, Ordering::Relaxed);
+        self.stream_options = None;
+        self.applied.profile = setup.profile.clone();
+        *self.eq_profile.deref_mut() = setup.profile;
+        self.sender.send(Command::ApplySetup(setup.name)).ok();
+    }
+
+    /// Writes the edited profile to a file picked in a dialog: Equalizer APO
+    /// text, or JSON when the name ends in `.json`.
+    fn save_profile_as(&mut self) {
+        let lang = self.info.lang;
+        let Some(path) = rfd::FileDialog::new()
+            .add_filter("Equalizer APO", &["txt"])
+            .add_filter("JSON", &["json"])
+            .set_file_name("profile.txt")
+            .save_file()
+        else {
+            return;
+        };
+        match self.eq_profile.save(&path) {
+            Ok(()) => self.notify(lang.fill(Text::SavedProfile, &[("path", &path.display())])),
+            Err(e) => {
+                error!("Error: {:?}", e);
+                self.notify(format!("{} {:#}", lang.tr(Text::SaveFailed), e));
+            }
+        }
+    }
+
+    /// Applies the clipboard text delivered in response to the Paste button.
+    fn handle_paste(&mut self, ctx: &egui::Context) {
+        let lang = self.info.lang;
+        let Some(requested) = self.paste_requested else {
+            return;
+        };
+        let pasted = ctx.input(|i| {
+            i.events.iter().find_map(|e| match e {
+                egui::Event::Paste(text) => Some(text.clone()),
+                _ => None,
+            })
+        });
+        match pasted {
+            Some(text) => {
+                self.paste_requested = None;
+                match parse_clipboard(&text) {
+                    Ok(profile) => {
+                        *self.eq_profile.deref_mut() = profile;
+                        self.notify(lang.tr(Text::PastedProfile));
+                    }
+                    Err(e) => self.notify(format!("{} {}", lang.tr(Text::PasteFailed), e)),
+                }
+            }
+            // Nothing arrives when the clipboard has no text
+            None if requested.elapsed() > Duration::from_secs(1) => {
+                self.paste_requested = None;
+                self.notify(lang.tr(Text::ClipboardEmpty));
+            }
+            None => ctx.request_repaint(),
+        }
+    }
+
+    /// Shows `message` in the status line for a few seconds.
+    /// Turns the EQ on or off, keeping the engine running.
+    pub fn toggle_eq(&mut self) {
+        self.state.enabled = !self.state.enabled;
+        self.sender.send(Command::SetState(self.state)).ok();
+    }
+
+    pub fn notify(&mut self, message: impl Into<String>) {
+        self.notice = Some((message.into(), Instant::now()));
+    }
+
+    /// Underrun count and rate, overruns, plus the last stream error, refreshed once a
+    /// second while the engine runs.
+    fn status_ui(&mut self, ui: &mut eframe::egui::Ui) {
+        let lang = self.info.lang;
+        let status = self.eq_settings.status.clone();
+        let underruns = status.underruns.load(Ordering::Relaxed);
+        let elapsed = self.underruns_checked.elapsed();
+        if elapsed >= Duration::from_secs(1) {
+            let new = underruns.saturating_sub(self.underruns_seen);
+            self.underrun_rate = (new as f64 / elapsed.as_secs_f64()).round() as u64;
+            self.underruns_seen = underruns;
+            self.underruns_checked = Instant::now();
+        }
+        ui.horizontal(|ui| {
+            ui.label(format!(
+                "{} {} ({}/s)",
+                lang.tr(Text::Underruns),
+                underruns,
+                self.underrun_rate
+            ));
+            let latency_ms = status.latency_ms.load();
+            if latency_ms > 0.0 {
+                ui.label(format!(
+                    "{} ~{:.1} ms ({} {} ms)",
+                    lang.tr(Text::Latency),
+                    latency_ms,
+                    lang.tr(Text::LatencyTarget),
+                    self.eq_settings.latency.load(Ordering::Relaxed)
+                ))
+                .on_hover_text(lang.tr(Text::LatencyHover));
+            }
+            if let Some((min, max)) = status.ring_fill.percent() {
+                ui.label(format!(
+                    "{} {:.0}-{:.0}%",
+                    lang.tr(Text::RingFill),
+                    min,
+                    max
+                ))
+                .on_hover_text(lang.tr(Text::RingFillHover));
+            }
+            let overruns = status.overruns.load(Ordering::Relaxed);
+            if overruns > 0 {
+                ui.label(format!("{} {}", lang.tr(Text::Overruns), overruns));
+            }
+            let limiter = &self.eq_settings.limiter;
+            if limiter.mode() != LimiterMode::Off {
+                ui.label(format!(
+                    "{} {:.1} dB",
+                    lang.tr(Text::LimiterReduction),
+                    limiter.gain_reduction_db.load()
+                ));
+            }
+            if let Some(error) = status.last_error() {
+                ui.colored_label(
+                    ui.visuals().error_fg_color,
+                    format!("{} {}", lang.tr(Text::Error), error),
+                );
+            }
+            if let Some((notice, shown)) = self.notice.as_ref() {
+                let remaining = NOTICE_DURATION.saturating_sub(shown.elapsed());
+                if remaining.is_zero() {
+                    self.notice = None;
+                } else {
+                    ui.label(notice);
+                    ui.ctx().request_repaint_after(remaining);
+                }
+            }
+        });
+        if self.state.running && !self.window_hidden {
+            ui.ctx().request_repaint_after(Duration::from_secs(1));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_clipboard() {
+        let profile =
+            EqProfile::parse("Preamp: -3 dB\nFilter 1: ON PK Fc 100 Hz Gain 2 dB Q 1").unwrap();
+        assert_eq!(parse_clipboard(&profile.to_string()).unwrap(), profile);
+        assert_eq!(
+            parse_clipboard(&profile.to_json().unwrap()).unwrap(),
+            profile
+        );
+        assert!(parse_clipboard("hello there").is_err());
+        assert!(parse_clipboard("Filter 1: ON XX Fc 100 Hz").is_err());
+    }
+
+    #[test]
+    fn test_reset_prompt() {
+        assert!(reset_prompt(Lang::En, 1).contains("Its band will be lost"));
+        assert!(reset_prompt(Lang::En, 12).contains("All 12 bands will be lost"));
     }
 }
@@ -0,0 +1,79 @@
+use std::collections::VecDeque;
+
+/// Bounded undo/redo stack of snapshots. The oldest undo step is dropped once
+/// `limit` is reached.
+pub struct History<T> {
+    undo: VecDeque<T>,
+    redo: Vec<T>,
+    limit: usize,
+}
+
+impl<T: Clone + PartialEq> History<T> {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            undo: VecDeque::new(),
+            redo: Vec::new(),
+            limit,
+        }
+    }
+
+    /// Records `state` as the state before an edit. Any redo steps are lost.
+    pub fn push(&mut self, state: T) {
+        if self.undo.back() == Some(&state) {
+            return;
+        }
+        self.undo.push_back(state);
+        if self.undo.len() > self.limit {
+            self.undo.pop_front();
+        }
+        self.redo.clear();
+    }
+
+    /// Returns the state to restore, remembering `current` for redo.
+    pub fn undo(&mut self, current: T) -> Option<T> {
+        let state = self.undo.pop_back()?;
+        self.redo.push(current);
+        Some(state)
+    }
+
+    pub fn redo(&mut self, current: T) -> Option<T> {
+        let state = self.redo.pop()?;
+        self.undo.push_back(current);
+        Some(state)
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_undo_redo() {
+        let mut history = History::new(3);
+        for state in 0..5 {
+            history.push(state);
+        }
+        // Only the last three steps are kept
+        assert_eq!(history.undo(5), Some(4));
+        assert_eq!(history.undo(4), Some(3));
+        assert_eq!(history.undo(3), Some(2));
+        assert_eq!(history.undo(2), None);
+
+        assert_eq!(history.redo(2), Some(3));
+        assert_eq!(history.redo(3), Some(4));
+        assert!(history.can_redo());
+        // Editing 4 into 6 discards the remaining redo step
+        history.push(4);
+        assert!(!history.can_redo());
+        assert_eq!(history.undo(6), Some(4));
+        assert_eq!(history.undo(4), Some(3));
+    }
+}
@@ -0,0 +1,600 @@
+//! Translations of the GUI labels. English is complete; other languages
+//! fall back to it for texts they don't have.
+
+use std::fmt::Display;
+
+use crate::config::Lang;
+
+/// Declares [`Text`] and, for the tests, the list of all its variants, so
+/// the list can't miss one.
+macro_rules! texts {
+    ($($text:ident,)*) => {
+        /// A label shown in the GUI.
+        #[derive(Clone, Copy, Debug, PartialEq)]
+        pub enum Text {
+            $($text,)*
+        }
+
+        impl Text {
+            #[cfg(test)]
+            const ALL: &[Text] = &[$(Text::$text,)*];
+        }
+    };
+}
+
+texts! {
+    Start,
+    Stop,
+    EnableEq,
+    DisableEq,
+    MatchedBypass,
+    Host,
+    Input,
+    Output,
+    Refresh,
+    Load,
+    SaveAs,
+    Copy,
+    Paste,
+    Preamp,
+    ProfileName,
+    Tilt,
+    Fix,
+    Volume,
+    Loudness,
+    Reset,
+    Invert,
+    Flatten,
+    Blind,
+    Undo,
+    Redo,
+    Realtime,
+    Apply,
+    Save,
+    Quit,
+    Graph,
+    Meters,
+    Spectrum,
+    ExportGraph,
+    LoadReference,
+    Reference,
+    AddBand,
+    Cancel,
+    Language,
+    Font,
+    MidiLearn,
+    Rate,
+    Buffer,
+    Auto,
+    LinearPhase,
+    Overflow,
+    Balance,
+    SwapChannels,
+    DcFilter,
+    Crossfeed,
+    Limiter,
+    Oversampling,
+    Dither,
+    NoiseShaping,
+    Mode,
+    Mid,
+    Side,
+    Preset,
+    SavePreset,
+    DeletePreset,
+    Quick,
+    Bass,
+    Treble,
+    Vocal,
+    Setup,
+    SaveSetup,
+    DeleteSetup,
+    Move,
+    Type,
+    Freq,
+    Gain,
+    Band,
+    Unstable,
+    Solo,
+    Mute,
+    Lock,
+    Remove,
+    Peak,
+    LowShelf,
+    HighShelf,
+    LowPass,
+    HighPass,
+    FrequencyAxis,
+    GainAxis,
+    SpectrumAveraging,
+    MatchedBypassHover,
+    Untitled,
+    ProfileNameHover,
+    LoadReferenceHover,
+    FontHover,
+    MidiLearnHover,
+    SaveSetupHover,
+    Underruns,
+    Latency,
+    LatencyTarget,
+    LatencyHover,
+    RingFill,
+    RingFillHover,
+    Overruns,
+    LimiterReduction,
+    Error,
+    LockHover,
+    LargestDifference,
+    RemoveReference,
+    ResetOne,
+    ResetMany,
+    LoopbackDevice,
+    MissingDevice,
+    SaveAsHover,
+    CopiedProfile,
+    TiltHover,
+    Clip,
+    ClipHover,
+    FixHover,
+    LoudnessHover,
+    InvertHover,
+    InvertedKeptPasses,
+    FlattenHover,
+    AbHover,
+    BlindHover,
+    ApplyHover,
+    NothingToApply,
+    Frames,
+    LinearPhaseHover,
+    OverflowHover,
+    DcFilterHover,
+    Off,
+    DitherHover,
+    SavedProfile,
+    SaveFailed,
+    PastedProfile,
+    PasteFailed,
+    ClipboardEmpty,
+    OctavesHover,
+    UnstableHover,
+    SoloHover,
+    ExportedGraph,
+    ExportFailed,
+    LoadReferenceFailed,
+    MidiNoteBound,
+    MidiLearnBand,
+    MidiLearnEnable,
+    MidiBound,
+    ParamFrequency,
+    ParamGain,
+    ParamQ,
+    MeterIn,
+    MeterOut,
+}
+
+impl Text {
+    fn en(self) -> &'static str {
+        match self {
+            Text::Start => "Start",
+            Text::Stop => "Stop",
+            Text::EnableEq => "Enable EQ",
+            Text::DisableEq => "Disable EQ",
+            Text::MatchedBypass => "Match Level",
+            Text::Host => "Host:",
+            Text::Input => "Inp:",
+            Text::Output => "Out:",
+            Text::Refresh => "Refresh",
+            Text::Load => "Load",
+            Text::SaveAs => "Save As",
+            Text::Copy => "Copy",
+            Text::Paste => "Paste",
+            Text::Preamp => "Preamp:",
+            Text::ProfileName => "Name:",
+            Text::Tilt => "Tilt:",
+            Text::Fix => "Fix",
+            Text::Volume => "Volume:",
+            Text::Loudness => "Loudness",
+            Text::Reset => "Reset",
+            Text::Invert => "Invert",
+            Text::Flatten => "Flatten",
+            Text::Blind => "Blind",
+            Text::Undo => "Undo",
+            Text::Redo => "Redo",
+            Text::Realtime => "Realtime",
+            Text::Apply => "Apply",
+            Text::Save => "Save",
+            Text::Quit => "Quit",
+            Text::Graph => "Graph",
+            Text::Meters => "Meters",
+            Text::Spectrum => "Spectrum",
+            Text::ExportGraph => "Export Graph",
+            Text::LoadReference => "Load Reference",
+            Text::Reference => "Reference",
+            Text::AddBand => "Add Band",
+            Text::Cancel => "Cancel",
+            Text::Language => "Language:",
+            Text::Font => "Font:",
+            Text::MidiLearn => "MIDI Learn",
+            Text::Rate => "Rate:",
+            Text::Buffer => "Buffer:",
+            Text::Auto => "Auto",
+            Text::LinearPhase => "Linear Phase",
+            Text::Overflow => "Overflow:",
+            Text::Balance => "Balance:",
+            Text::SwapChannels => "Swap L/R",
+            Text::DcFilter => "DC Filter",
+            Text::Crossfeed => "Crossfeed",
+            Text::Limiter => "Limiter:",
+            Text::Oversampling => "Oversampling:",
+            Text::Dither => "Dither",
+            Text::NoiseShaping => "Noise Shaping",
+            Text::Mode => "Mode:",
+            Text::Mid => "Mid",
+            Text::Side => "Side",
+            Text::Preset => "Preset:",
+            Text::SavePreset => "Save Preset",
+            Text::DeletePreset => "Delete Preset",
+            Text::Quick => "Quick:",
+            Text::Bass => "Bass",
+            Text::Treble => "Treble",
+            Text::Vocal => "Vocal",
+            Text::Setup => "Setup:",
+            Text::SaveSetup => "Save Setup",
+            Text::DeleteSetup => "Delete Setup",
+            Text::Move => "✥ Move",
+            Text::Type => "Type",
+            Text::Freq => "Freq",
+            Text::Gain => "Gain",
+            Text::Band => "Band",
+            Text::Unstable => "Unstable",
+            Text::Solo => "Solo",
+            Text::Mute => "Mute",
+            Text::Lock => "Lock",
+            Text::Remove => "Remove",
+            Text::Peak => "Peak",
+            Text::LowShelf => "LowShelf",
+            Text::HighShelf => "HighShelf",
+            Text::LowPass => "LowPass",
+            Text::HighPass => "HighPass",
+            Text::FrequencyAxis => "Frequency (Hz)",
+            Text::GainAxis => "Gain (dB)",
+            Text::SpectrumAveraging => "Spectrum averaging:",
+            Text::ResetOne => "Reset the profile? Its band will be lost (Undo brings it back).",
+            Text::ResetMany => {
+                "Reset the profile? All {bands} bands will be lost (Undo brings them back)."
+            }
+            Text::LoopbackDevice => "{device} (loopback)",
+            Text::MissingDevice => "{device} (missing)",
+            Text::SaveAsHover => "Write the profile to a file, as JSON for .json names",
+            Text::CopiedProfile => "Copied profile as APO text.",
+            Text::TiltHover => {
+                "Tilts the whole response around 1 kHz: up for brighter, down for warmer"
+            }
+            Text::Clip => "CLIP",
+            Text::ClipHover => {
+                "The EQ boosts some frequencies above 0 dBFS, so loud parts can distort"
+            }
+            Text::FixHover => "Lower the preamp by the overshoot",
+            Text::LoudnessHover => "Boost bass and treble as the volume goes down",
+            Text::InvertHover => "Negate every gain and the preamp, to undo this response",
+            Text::InvertedKeptPasses => "Inverted; low and high passes have no gain and were kept.",
+            Text::FlattenHover => {
+                "Zero every gain and the preamp, keeping the bands; Undo brings them back"
+            }
+            Text::AbHover => "Switch to the other profile while playing, keeping edits to both",
+            Text::BlindHover => "Hide which profile is playing",
+            Text::ApplyHover => "Run the edited settings and profile",
+            Text::NothingToApply => "No unapplied changes",
+            Text::Frames => "frames",
+            Text::LinearPhaseHover => {
+                "FIR EQ without phase shift, at the cost of {frames} frames of latency. Not in mid/side mode"
+            }
+            Text::OverflowHover => {
+                "When the input runs ahead: drop new input, leaving the latency high, or skip old audio, which jumps but keeps the latency low"
+            }
+            Text::DcFilterHover => "Remove DC offset before the EQ",
+            Text::Off => "Off",
+            Text::DitherHover => "Dither when the output device takes 16- or 24-bit integers",
+            Text::SavedProfile => "Saved profile to {path}.",
+            Text::SaveFailed => "Save failed:",
+            Text::PastedProfile => "Pasted profile.",
+            Text::PasteFailed => "Paste failed:",
+            Text::ClipboardEmpty => "Paste failed: clipboard is empty",
+            Text::OctavesHover => "Edit the bandwidth in octaves instead of Q",
+            Text::UnstableHover => "Can't be realized at {rate} Hz; bypassed",
+            Text::SoloHover => "Hear only the soloed bands",
+            Text::ExportedGraph => "Exported graph to {path}.",
+            Text::ExportFailed => "Export failed:",
+            Text::LoadReferenceFailed => "Failed to load reference:",
+            Text::MidiNoteBound => "Note {note} toggles the EQ",
+            Text::MidiLearnBand => "Move a knob to bind it to the {param} of band {band}",
+            Text::MidiLearnEnable => "Press a key to bind it to Enable EQ",
+            Text::MidiBound => "CC {cc} on channel {channel} controls the {param} of band {band}",
+            Text::ParamFrequency => "frequency",
+            Text::ParamGain => "gain",
+            Text::ParamQ => "Q",
+            Text::MeterIn => "In",
+            Text::MeterOut => "Out",
+            Text::MatchedBypassHover => {
+                "Keep the preamp while the EQ is disabled, so both play as loud"
+            }
+            Text::Untitled => "Untitled",
+            Text::ProfileNameHover => "Saved with the profile; source and notes are kept too",
+            Text::LoadReferenceHover => "A profile, or a target curve as CSV, to compare against",
+            Text::FontHover => "Set `font` in config.toml to a family name or a font file",
+            Text::MidiLearnHover => {
+                "Move a band's frequency, gain or Q, or press Enable EQ, then a knob or key to bind it"
+            }
+            Text::SaveSetupHover => "Remember the devices, profile and latency under this name",
+            Text::Underruns => "Underruns:",
+            Text::Latency => "Latency:",
+            Text::LatencyTarget => "target",
+            Text::LatencyHover => {
+                "Estimated from the buffered audio, the device buffers and the linear phase EQ; the devices' own converters add a little more"
+            }
+            Text::RingFill => "Buffer:",
+            Text::RingFillHover => {
+                "Lowest and highest ring buffer fill over the last second. Near 0% the output may run dry: raise the latency. Near 100% input gets dropped: raise the ring factor"
+            }
+            Text::Overruns => "Overruns:",
+            Text::LimiterReduction => "Limiter GR:",
+            Text::Error => "Error:",
+            Text::LockHover => "Keep the band's settings from being changed",
+            Text::LargestDifference => "Largest difference",
+            Text::RemoveReference => "Remove the reference",
+        }
+    }
+}
+
+/// Simplified Chinese. Texts missing here are shown in English.
+const ZH_CN: &[(Text, &str)] = &[
+    (Text::Start, "启动"),
+    (Text::Stop, "停止"),
+    (Text::EnableEq, "启用均衡器"),
+    (Text::DisableEq, "禁用均衡器"),
+    (Text::MatchedBypass, "电平匹配"),
+    (Text::Host, "音频后端："),
+    (Text::Input, "输入："),
+    (Text::Output, "输出："),
+    (Text::Refresh, "刷新"),
+    (Text::Load, "加载"),
+    (Text::SaveAs, "另存为"),
+    (Text::Copy, "复制"),
+    (Text::Paste, "粘贴"),
+    (Text::Preamp, "前级增益："),
+    (Text::ProfileName, "名称："),
+    (Text::Tilt, "倾斜："),
+    (Text::Fix, "修正"),
+    (Text::Volume, "音量："),
+    (Text::Loudness, "响度补偿"),
+    (Text::Reset, "重置"),
+    (Text::Invert, "反转"),
+    (Text::Flatten, "拉平"),
+    (Text::Blind, "盲听"),
+    (Text::Undo, "撤销"),
+    (Text::Redo, "重做"),
+    (Text::Realtime, "实时"),
+    (Text::Apply, "应用"),
+    (Text::Save, "保存"),
+    (Text::Quit, "退出"),
+    (Text::Graph, "曲线"),
+    (Text::Meters, "电平表"),
+    (Text::Spectrum, "频谱"),
+    (Text::ExportGraph, "导出曲线"),
+    (Text::LoadReference, "载入参考曲线"),
+    (Text::Reference, "参考曲线"),
+    (Text::AddBand, "添加频段"),
+    (Text::Cancel, "取消"),
+    (Text::Language, "语言："),
+    (Text::Font, "字体："),
+    (Text::MidiLearn, "MIDI 学习"),
+    (Text::Rate, "采样率："),
+    (Text::Buffer, "缓冲区："),
+    (Text::Auto, "自动"),
+    (Text::LinearPhase, "线性相位"),
+    (Text::Overflow, "溢出："),
+    (Text::Balance, "声道平衡："),
+    (Text::SwapChannels, "交换左右"),
+    (Text::DcFilter, "直流滤波"),
+    (Text::Crossfeed, "交叉馈送"),
+    (Text::Limiter, "限幅器："),
+    (Text::Oversampling, "过采样："),
+    (Text::Dither, "抖动"),
+    (Text::NoiseShaping, "噪声整形"),
+    (Text::Mode, "模式："),
+    (Text::Mid, "中"),
+    (Text::Side, "侧"),
+    (Text::Preset, "预设："),
+    (Text::SavePreset, "保存预设"),
+    (Text::DeletePreset, "删除预设"),
+    (Text::Quick, "快捷："),
+    (Text::Bass, "低音"),
+    (Text::Treble, "高音"),
+    (Text::Vocal, "人声"),
+    (Text::Setup, "方案："),
+    (Text::SaveSetup, "保存方案"),
+    (Text::DeleteSetup, "删除方案"),
+    (Text::Move, "✥ 移动"),
+    (Text::Type, "类型"),
+    (Text::Freq, "频率"),
+    (Text::Gain, "增益"),
+    (Text::Band, "频段"),
+    (Text::Unstable, "不稳定"),
+    (Text::Solo, "独听"),
+    (Text::Mute, "静音"),
+    (Text::Lock, "锁定"),
+    (Text::Remove, "删除"),
+    (Text::Peak, "峰值"),
+    (Text::LowShelf, "低架"),
+    (Text::HighShelf, "高架"),
+    (Text::LowPass, "低通"),
+    (Text::HighPass, "高通"),
+    (Text::FrequencyAxis, "频率 (Hz)"),
+    (Text::GainAxis, "增益 (dB)"),
+    (Text::SpectrumAveraging, "频谱平均："),
+    (Text::ResetOne, "重置配置？该频段将丢失（可撤销恢复）。"),
+    (
+        Text::ResetMany,
+        "重置配置？全部 {bands} 个频段将丢失（可撤销恢复）。",
+    ),
+    (Text::LoopbackDevice, "{device}（环回）"),
+    (Text::MissingDevice, "{device}（已断开）"),
+    (
+        Text::SaveAsHover,
+        "将配置写入文件，文件名以 .json 结尾时保存为 JSON",
+    ),
+    (Text::CopiedProfile, "已将配置复制为 APO 文本。"),
+    (
+        Text::TiltHover,
+        "以 1 kHz 为中心倾斜整个响应：向上更明亮，向下更温暖",
+    ),
+    (Text::Clip, "削波"),
+    (
+        Text::ClipHover,
+        "均衡器将部分频率提升到 0 dBFS 以上，响亮的段落可能失真",
+    ),
+    (Text::FixHover, "按超出量降低前级增益"),
+    (Text::LoudnessHover, "音量降低时提升低音和高音"),
+    (Text::InvertHover, "将所有增益和前级增益取反，以抵消此响应"),
+    (
+        Text::InvertedKeptPasses,
+        "已反转；低通和高通没有增益，保持不变。",
+    ),
+    (
+        Text::FlattenHover,
+        "将所有增益和前级增益归零并保留频段；可撤销恢复",
+    ),
+    (Text::AbHover, "播放时切换到另一个配置，两者的编辑都会保留"),
+    (Text::BlindHover, "隐藏正在播放的配置"),
+    (Text::ApplyHover, "运行编辑后的设置和配置"),
+    (Text::NothingToApply, "没有未应用的更改"),
+    (Text::Frames, "帧"),
+    (
+        Text::LinearPhaseHover,
+        "无相移的 FIR 均衡器，代价是 {frames} 帧延迟。中/侧模式下不可用",
+    ),
+    (
+        Text::OverflowHover,
+        "输入超前时：丢弃新输入，延迟保持较高；或跳过旧音频，会有跳变但延迟保持较低",
+    ),
+    (Text::DcFilterHover, "在均衡器之前去除直流偏移"),
+    (Text::Off, "关"),
+    (Text::DitherHover, "输出设备使用 16 或 24 位整数时进行抖动"),
+    (Text::SavedProfile, "已将配置保存到 {path}。"),
+    (Text::SaveFailed, "保存失败："),
+    (Text::PastedProfile, "已粘贴配置。"),
+    (Text::PasteFailed, "粘贴失败："),
+    (Text::ClipboardEmpty, "粘贴失败：剪贴板为空"),
+    (Text::OctavesHover, "以倍频程而非 Q 值编辑带宽"),
+    (Text::UnstableHover, "无法在 {rate} Hz 下实现，已旁通"),
+    (Text::SoloHover, "只听独听的频段"),
+    (Text::ExportedGraph, "已将曲线导出到 {path}。"),
+    (Text::ExportFailed, "导出失败："),
+    (Text::LoadReferenceFailed, "载入参考曲线失败："),
+    (Text::MidiNoteBound, "音符 {note} 用于切换均衡器"),
+    (
+        Text::MidiLearnBand,
+        "转动旋钮，将其绑定到频段 {band} 的{param}",
+    ),
+    (Text::MidiLearnEnable, "按下按键，将其绑定到启用均衡器"),
+    (
+        Text::MidiBound,
+        "通道 {channel} 上的 CC {cc} 控制频段 {band} 的{param}",
+    ),
+    (Text::ParamFrequency, "频率"),
+    (Text::ParamGain, "增益"),
+    (Text::ParamQ, "Q"),
+    (Text::MeterIn, "入"),
+    (Text::MeterOut, "出"),
+    (
+        Text::MatchedBypassHover,
+        "禁用均衡器时保留前级增益，使两者一样响",
+    ),
+    (Text::Untitled, "未命名"),
+    (Text::ProfileNameHover, "随配置一起保存；来源和备注也会保留"),
+    (Text::LoadReferenceHover, "用于对比的配置或 CSV 目标曲线"),
+    (
+        Text::FontHover,
+        "在 config.toml 中将 `font` 设为字体名称或字体文件",
+    ),
+    (
+        Text::MidiLearnHover,
+        "先调整某个频段的频率、增益或 Q 值，或按下启用均衡器，再转动旋钮或按键完成绑定",
+    ),
+    (Text::SaveSetupHover, "以此名称记住设备、配置和延迟"),
+    (Text::Underruns, "欠载："),
+    (Text::Latency, "延迟："),
+    (Text::LatencyTarget, "目标"),
+    (
+        Text::LatencyHover,
+        "根据已缓冲的音频、设备缓冲区和线性相位均衡器估算；设备自身的转换器还会再增加少许",
+    ),
+    (Text::RingFill, "缓冲占用："),
+    (
+        Text::RingFillHover,
+        "过去一秒内环形缓冲区的最低和最高占用。接近 0% 时输出可能断流：请提高延迟。接近 100% 时会丢弃输入：请提高环形缓冲倍数",
+    ),
+    (Text::Overruns, "溢出次数："),
+    (Text::LimiterReduction, "限幅器增益衰减："),
+    (Text::Error, "错误："),
+    (Text::LockHover, "防止更改此频段的设置"),
+    (Text::LargestDifference, "最大差异"),
+    (Text::RemoveReference, "移除参考曲线"),
+];
+
+impl Lang {
+    pub const ALL: [Lang; 2] = [Lang::En, Lang::ZhCn];
+
+    /// `text` in this language, or in English when it has no translation.
+    pub fn tr(self, text: Text) -> &'static str {
+        let table: &[(Text, &str)] = match self {
+            Lang::En => &[],
+            Lang::ZhCn => ZH_CN,
+        };
+        table
+            .iter()
+            .find(|(t, _)| *t == text)
+            .map_or_else(|| text.en(), |(_, s)| *s)
+    }
+
+    /// `text` in this language, with each `{name}` in it replaced by its
+    /// value.
+    pub fn fill(self, text: Text, values: &[(&str, &dyn Display)]) -> String {
+        values
+            .iter()
+            .fold(self.tr(text).to_string(), |text, (name, value)| {
+                text.replace(&format!("{{{}}}", name), &value.to_string())
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_every_text_is_translated() {
+        for &text in Text::ALL {
+            assert!(!text.en().is_empty(), "{:?}", text);
+            assert_eq!(Lang::En.tr(text), text.en());
+            assert!(
+                ZH_CN.iter().any(|(t, _)| *t == text),
+                "{:?} has no zh-CN text",
+                text
+            );
+        }
+        assert_eq!(Lang::ZhCn.tr(Text::Apply), "应用");
+    }
+
+    #[test]
+    fn test_fill() {
+        assert_eq!(
+            Lang::En.fill(Text::MidiNoteBound, &[("note", &60)]),
+            "Note 60 toggles the EQ"
+        );
+        assert_eq!(
+            Lang::ZhCn.fill(Text::ResetMany, &[("bands", &3)]),
+            "重置配置？全部 3 个频段将丢失（可撤销恢复）。"
+        );
+    }
+}
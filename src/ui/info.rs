@@ -0,0 +1,58 @@
+use cpal::Host;
+
+use crate::{
+    command::DeviceList,
+    config::{ChannelMode, Lang, RangeConfig, Setup, WindowState},
+    eq::EqProfile,
+    midi::MidiConfig,
+};
+
+#[derive(Default)]
+pub struct Info {
+    /// Names of the hosts compiled in and available, e.g. "Alsa", "Jack"
+    pub host_names: Vec<String>,
+    pub host: String,
+    pub device_names: Vec<String>,
+    pub input_dev: String,
+    pub output_dev: String,
+    /// Output devices that can be captured in loopback mode
+    pub loopback_names: Vec<String>,
+    pub loopback: bool,
+    pub presets: Vec<(String, EqProfile)>,
+    pub setups: Vec<Setup>,
+    pub window: WindowState,
+    pub channel_mode: ChannelMode,
+    pub side_profile: EqProfile,
+    pub lang: Lang,
+    /// Name of the font loaded, `None` for egui's own
+    pub font: Option<String>,
+    pub midi: MidiConfig,
+    pub ranges: RangeConfig,
+}
+
+impl Info {
+    /// Re-reads the device lists from `host`.
+    pub fn refresh_devices(&mut self, host: &Host) {
+        self.host = host.id().name().to_string();
+        self.set_devices(DeviceList::from_host(host));
+    }
+
+    pub fn set_devices(&mut self, devices: DeviceList) {
+        self.device_names = devices.device_names;
+        self.loopback_names = devices.loopback_names;
+    }
+
+    /// Whether the selected input is missing from the current device list.
+    pub fn input_missing(&self) -> bool {
+        let names = if self.loopback {
+            &self.loopback_names
+        } else {
+            &self.device_names
+        };
+        !self.input_dev.is_empty() && !names.contains(&self.input_dev)
+    }
+
+    pub fn output_missing(&self) -> bool {
+        !self.output_dev.is_empty() && !self.device_names.contains(&self.output_dev)
+    }
+}
@@ -0,0 +1,183 @@
+//! Opens the window: the executor on a thread of its own and the [`App`]
+//! talking to it.
+
+use std::{path::Path, sync::Arc};
+
+use eframe::egui;
+use font_kit::{
+    family_name::FamilyName, handle::Handle, properties::Properties, source::SystemSource,
+};
+use tracing::{error, info, warn};
+
+use crate::{
+    command::Command,
+    config::Config,
+    executor::Executor,
+    run::{current_device_name, resolve_host},
+    settings::Settings,
+    ui::{App, info::Info},
+    utils::OneShot,
+};
+
+/// Runs until the window is closed.
+pub fn run(config: Config) {
+    let (sender, receiver) = std::sync::mpsc::sync_channel(1024);
+    let mut viewport = egui::ViewportBuilder::default().with_inner_size(config.window.size);
+    if let Some(position) = config.window.position {
+        viewport = viewport.with_position(position);
+    }
+    let options = eframe::NativeOptions {
+        viewport,
+        ..Default::default()
+    };
+    let settings = Settings::from(&config.settings);
+    let settings_cloned = settings.clone();
+    let config_cloned = config.clone();
+    let sender_cloned = sender.clone();
+    std::thread::spawn(move || {
+        Executor::new(receiver, sender_cloned, config_cloned, settings_cloned).run();
+    });
+    let oneshot = OneShot::new();
+    sender.send(Command::GetState(oneshot.clone())).unwrap();
+    let state = oneshot.recv();
+    let host = resolve_host(config.host.as_deref());
+    // The names the saved devices have now, as the executor finds them
+    let device_name = |name: &Option<String>, id: &Option<String>| {
+        name.as_ref().map_or_else(String::new, |name| {
+            current_device_name(&host, name, id.as_deref())
+        })
+    };
+    let mut info = Info {
+        host_names: cpal::available_hosts()
+            .iter()
+            .map(|id| id.name().to_string())
+            .collect(),
+        host: String::new(),
+        device_names: Vec::new(),
+        loopback_names: Vec::new(),
+        loopback: config.loopback,
+        input_dev: device_name(&config.input_dev_name, &config.input_dev_id),
+        output_dev: device_name(&config.output_dev_name, &config.output_dev_id),
+        presets: config.presets.clone(),
+        setups: config.setups.clone(),
+        window: config.window,
+        channel_mode: config.channel_mode,
+        side_profile: config.side_profile.clone(),
+        lang: config.lang,
+        font: None,
+        midi: config.midi.clone(),
+        ranges: config.ranges.sanitized(),
+    };
+    let font = find_font(config.font.as_deref());
+    info.font = font.as_ref().map(|(_, name)| name.clone());
+    if let Some(name) = &info.font {
+        info!("Font: {}", name);
+    }
+    info.refresh_devices(&host);
+    let status = settings.status.clone();
+    let mut app = App::new(settings, config.eq_profile, sender, state, info);
+
+    if let Err(e) = eframe::run_native(
+        "Eq Layer",
+        options,
+        Box::new(|ctx| {
+            if let Some((data, _)) = font {
+                set_font(&ctx.egui_ctx, data);
+            }
+            let egui_ctx = ctx.egui_ctx.clone();
+            status.on_change(move || egui_ctx.request_repaint());
+            app.connect_midi(&ctx.egui_ctx);
+            #[cfg(target_os = "macos")]
+            let _dock_observer = crate::macos::setup_dock_observer(ctx.egui_ctx.clone());
+            Ok(Box::new(app))
+        }),
+    ) {
+        error!("{:?}", e);
+        std::process::exit(1);
+    }
+}
+
+/// Data and full name of the font behind `handle`.
+fn load_handle(handle: Handle) -> Option<(Vec<u8>, String)> {
+    let font = handle.load().ok()?;
+    let data = font.copy_font_data()?;
+    Some((data.to_vec(), font.full_name()))
+}
+
+/// `configured` is a path to a font file, or else a family name.
+fn configured_font(source: &SystemSource, configured: &str) -> Option<(Vec<u8>, String)> {
+    let path = Path::new(configured);
+    let handle = if path.is_file() {
+        Handle::from_path(path.to_path_buf(), 0)
+    } else {
+        source
+            .select_best_match(
+                &[FamilyName::Title(configured.to_string())],
+                &Properties::new(),
+            )
+            .ok()?
+    };
+    load_handle(handle)
+}
+
+/// The configured font, or the first of a list that covers Chinese.
+fn find_font(configured: Option<&str>) -> Option<(Vec<u8>, String)> {
+    let source = SystemSource::new();
+    if let Some(configured) = configured {
+        match configured_font(&source, configured) {
+            Some(font) => return Some(font),
+            None => warn!("Font \"{}\" not found, trying the defaults", configured),
+        }
+    }
+
+    let font_families = [
+        FamilyName::Title("Microsoft YaHei".to_string()), // Windows SC
+        FamilyName::Title("PingFang SC".to_string()),     // macOS SC
+        FamilyName::Title("Noto Sans CJK SC".to_string()), // Linux SC
+        FamilyName::Title("Arial".to_string()),           // Common English
+        FamilyName::SansSerif,                            // Fallback
+    ];
+
+    // 3. 尝试查找字体
+    let font = font_families.iter().find_map(|family| {
+        let handle = source
+            .select_best_match(std::slice::from_ref(family), &Properties::new())
+            .ok()?;
+        load_handle(handle)
+    });
+    if font.is_none() {
+        warn!("No system font found, using egui's own");
+    }
+    font
+}
+
+fn set_font(ctx: &egui::Context, font_data: Vec<u8>) {
+    let mut fonts = egui::FontDefinitions::default();
+
+    fonts.font_data.insert(
+        "system_font".to_owned(),
+        Arc::new(egui::FontData::from_owned(font_data)),
+    );
+
+    if let Some(family) = fonts.families.get_mut(&egui::FontFamily::Proportional) {
+        family.insert(0, "system_font".to_owned());
+    }
+
+    if let Some(family) = fonts.families.get_mut(&egui::FontFamily::Monospace) {
+        family.push("system_font".to_owned());
+    }
+
+    ctx.set_fonts(fonts);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_missing_configured_font() {
+        let source = SystemSource::new();
+        assert!(configured_font(&source, "/no/such/font.ttf").is_none());
+        assert!(configured_font(&source, "No Such Font Family 12345").is_none());
+    }
+}
@@ -0,0 +1,138 @@
+use std::{
+    sync::atomic::Ordering,
+    time::{Duration, Instant},
+};
+
+use eframe::egui::{self, Color32, Rect, Sense, Stroke, Ui, vec2};
+
+use crate::{
+    dsp::gain_to_db,
+    settings::{Levels, METER_CHANNELS},
+    ui::{App, i18n::Text},
+};
+
+/// How long a peak marker takes to fall back to the floor.
+const PEAK_HOLD_SECS: f32 = 1.5;
+const FLOOR_DB: f32 = -60.0;
+const BAR_SIZE: egui::Vec2 = vec2(120.0, 6.0);
+/// Meters redraw at about 30 fps rather than as fast as possible.
+const REFRESH: Duration = Duration::from_millis(33);
+
+/// Peak marker of one meter bar that falls back once the signal drops.
+#[derive(Clone, Copy)]
+pub struct PeakHold {
+    db: f32,
+    since: Instant,
+}
+
+impl Default for PeakHold {
+    fn default() -> Self {
+        Self {
+            db: FLOOR_DB,
+            since: Instant::now(),
+        }
+    }
+}
+
+impl PeakHold {
+    /// Takes the latest peak and returns where the marker is drawn.
+    fn update(&mut self, peak_db: f32) -> f32 {
+        let fall = self.since.elapsed().as_secs_f32() / PEAK_HOLD_SECS;
+        let shown = self.db - (self.db - FLOOR_DB) * fall.min(1.0);
+        if peak_db >= shown {
+            self.db = peak_db;
+            self.since = Instant::now();
+            peak_db
+        } else {
+            shown
+        }
+    }
+}
+
+/// Meter position of a linear level, 0.0 at the floor and 1.0 at 0 dBFS.
+fn fraction(db: f32) -> f32 {
+    ((db - FLOOR_DB) / -FLOOR_DB).clamp(0.0, 1.0)
+}
+
+fn level_bar(ui: &mut Ui, rms: f32, peak: f32, hold_db: f32) {
+    let (rect, _) = ui.allocate_exact_size(BAR_SIZE, Sense::hover());
+    let painter = ui.painter_at(rect);
+    painter.rect_filled(rect, 1.0, ui.visuals().extreme_bg_color);
+    let x = |db: f32| rect.left() + rect.width() * fraction(db);
+    let peak_db = gain_to_db(peak);
+    let color = if peak_db >= 0.0 {
+        Color32::RED
+    } else {
+        Color32::from_rgb(80, 180, 80)
+    };
+    let peak_rect = Rect::from_min_max(rect.min, egui::pos2(x(peak_db), rect.max.y));
+    painter.rect_filled(peak_rect, 1.0, color.gamma_multiply(0.4));
+    let rms_rect = Rect::from_min_max(rect.min, egui::pos2(x(gain_to_db(rms)), rect.max.y));
+    painter.rect_filled(rms_rect, 1.0, color);
+    if hold_db > FLOOR_DB {
+        painter.vline(
+            x(hold_db),
+            rect.y_range(),
+            Stroke::new(1.5, ui.visuals().strong_text_color()),
+        );
+    }
+}
+
+impl App {
+    /// Input and output level bars, one per channel: RMS solid, peak faded,
+    /// and a falling peak-hold marker.
+    pub fn meter_ui(&mut self, ui: &mut Ui) {
+        if !self.state.running || !self.info.window.show_meters {
+            return;
+        }
+        let status = self.eq_settings.status.clone();
+        let channels = status
+            .meters
+            .channels
+            .load(Ordering::Relaxed)
+            .clamp(1, METER_CHANNELS);
+        ui.horizontal(|ui| {
+            for (index, (label, levels)) in [
+                (Text::MeterIn, &status.meters.input),
+                (Text::MeterOut, &status.meters.output),
+            ]
+            .into_iter()
+            .enumerate()
+            {
+                ui.label(self.info.lang.tr(label));
+                ui.vertical(|ui| {
+                    ui.spacing_mut().item_spacing.y = 2.0;
+                    for channel in 0..channels {
+                        self.channel_bar(ui, levels, index, channel);
+                    }
+                });
+            }
+        });
+        if !self.window_hidden {
+            ui.ctx().request_repaint_after(REFRESH);
+        }
+    }
+
+    fn channel_bar(&mut self, ui: &mut Ui, levels: &Levels, index: usize, channel: usize) {
+        let peak = levels.peak[channel].load();
+        let hold_db = self.peak_holds[index][channel].update(gain_to_db(peak));
+        level_bar(ui, levels.rms[channel].load(), peak, hold_db);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_peak_hold_falls_back() {
+        let mut hold = PeakHold::default();
+        assert_eq!(hold.update(-6.0), -6.0);
+        // A quieter peak right after keeps the marker up
+        assert!(hold.update(-20.0) > -7.0);
+        hold.since -= Duration::from_secs_f32(PEAK_HOLD_SECS / 2.0);
+        assert!((hold.update(-40.0) - -33.0).abs() < 0.5);
+        hold.since -= Duration::from_secs_f32(PEAK_HOLD_SECS);
+        assert_eq!(hold.update(-50.0), -50.0);
+    }
+}
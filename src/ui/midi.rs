@@ -0,0 +1,162 @@
+use std::sync::mpsc::{self, Receiver};
+
+use eframe::egui::Context;
+use tracing::{debug, warn};
+
+use crate::{
+    command::Command,
+    midi::{self, BandParam, MidiBinding, MidiConnection, MidiEvent},
+    ui::{App, i18n::Text},
+};
+
+/// How a band parameter is named in the learn notices.
+fn param_text(param: BandParam) -> Text {
+    match param {
+        BandParam::Frequency => Text::ParamFrequency,
+        BandParam::Gain => Text::ParamGain,
+        BandParam::Q => Text::ParamQ,
+    }
+}
+
+/// A control touched in learn mode, bound to the next knob or key.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LearnTarget {
+    Band(usize, BandParam),
+    EnableEq,
+}
+
+/// The GUI's MIDI input. Bindings live in `Info::midi`.
+#[derive(Default)]
+pub struct MidiControl {
+    // open port, kept until the app quits, and the events it receives
+    input: Option<(MidiConnection, Receiver<MidiEvent>)>,
+    pub learning: bool,
+    // last control touched while learning, waiting for a knob or key
+    touched: Option<LearnTarget>,
+}
+
+impl MidiControl {
+    pub fn connected(&self) -> bool {
+        self.input.is_some()
+    }
+}
+
+impl App {
+    /// Starts listening when MIDI is enabled in the config. Events wake the
+    /// GUI up and are handled at the start of the next frame.
+    pub fn connect_midi(&mut self, ctx: &Context) {
+        if !self.info.midi.enabled {
+            return;
+        }
+        let (sender, receiver) = mpsc::channel();
+        let ctx = ctx.clone();
+        match midi::connect(self.info.midi.port.as_deref(), move |event| {
+            sender.send(event).ok();
+            ctx.request_repaint();
+        }) {
+            Ok(connection) => self.midi.input = Some((connection, receiver)),
+            Err(e) => {
+                warn!("{:#}", e);
+                self.notify(format!("{:#}", e));
+            }
+        }
+    }
+
+    /// Remembers a control moved in learn mode.
+    pub fn learn(&mut self, target: LearnTarget) {
+        if !self.midi.learning || self.midi.touched == Some(target) {
+            return;
+        }
+        self.midi.touched = Some(target);
+        let lang = self.info.lang;
+        self.notify(match target {
+            LearnTarget::Band(band, param) => lang.fill(
+                Text::MidiLearnBand,
+                &[
+                    ("param", &lang.tr(param_text(param))),
+                    ("band", &(band + 1)),
+                ],
+            ),
+            LearnTarget::EnableEq => lang.tr(Text::MidiLearnEnable).to_string(),
+        });
+    }
+
+    pub fn stop_learning(&mut self) {
+        self.midi.learning = false;
+        self.midi.touched = None;
+    }
+
+    /// Applies the MIDI events that arrived since the last frame. Band edits
+    /// go to the executor like the OSC server's, so they are heard right
+    /// away and saved; the editor gets the profile back from it.
+    pub fn handle_midi(&mut self) {
+        let Some((_, events)) = &self.midi.input else {
+            return;
+        };
+        let events: Vec<MidiEvent> = events.try_iter().collect();
+        for event in events {
+            match event {
+                MidiEvent::Control { channel, cc, value } => self.midi_control(channel, cc, value),
+                MidiEvent::NoteOn { note, .. } => self.midi_note(note),
+            }
+        }
+    }
+
+    fn midi_control(&mut self, channel: u8, cc: u8, value: u8) {
+        if let Some(LearnTarget::Band(band, param)) = self.midi.touched {
+            self.midi.touched = None;
+            self.info.midi.bind(MidiBinding {
+                channel,
+                cc,
+                band,
+                param,
+            });
+            self.sender
+                .send(Command::SetMidi(self.info.midi.clone()))
+                .ok();
+            let lang = self.info.lang;
+            self.notify(lang.fill(
+                Text::MidiBound,
+                &[
+                    ("cc", &cc),
+                    ("channel", &(channel + 1)),
+                    ("param", &lang.tr(param_text(param))),
+                    ("band", &(band + 1)),
+                ],
+            ));
+        }
+        // Bindings to bands removed since they were learned do nothing
+        let bands = self.profiles().0.filters.len();
+        for binding in self.info.midi.bound(channel, cc) {
+            if binding.band >= bands {
+                debug!(
+                    "CC {} is bound to band {}, which is gone",
+                    cc,
+                    binding.band + 1
+                );
+                continue;
+            }
+            self.sender
+                .send(Command::SetBand(
+                    binding.band,
+                    binding.param,
+                    binding.param.value(value, &self.info.ranges),
+                ))
+                .ok();
+        }
+    }
+
+    fn midi_note(&mut self, note: u8) {
+        if self.midi.touched == Some(LearnTarget::EnableEq) {
+            self.midi.touched = None;
+            self.info.midi.toggle_note = Some(note);
+            self.sender
+                .send(Command::SetMidi(self.info.midi.clone()))
+                .ok();
+            let text = self.info.lang.fill(Text::MidiNoteBound, &[("note", &note)]);
+            self.notify(text);
+        } else if self.info.midi.toggle_note == Some(note) {
+            self.toggle_eq();
+        }
+    }
+}
@@ -1,28 +1,105 @@
 use crate::{
+    command::{AbSlot, Command, DeviceList, State},
+    config::{ChannelMode, OverflowPolicy},
     eq::EqProfile,
-    settings::Settings,
-    ui::command::{Command, Info, State},
-    utils::DerefMutHook,
+    run::StreamOptions,
+    settings::{METER_CHANNELS, Settings},
+    ui::{
+        graph::CurveCache, history::History, info::Info, meter::PeakHold, midi::MidiControl,
+        reference::Reference, spectrum::Spectrum,
+    },
+    utils::{DerefMutHook, OneShot},
 };
 use eframe::egui::{self, CentralPanel};
-use std::sync::mpsc::SyncSender;
+use std::{ops::DerefMut, sync::mpsc::SyncSender, time::Instant};
 use tracing::debug;
 
-pub mod command;
 mod equalizer;
 mod graph;
 mod heading;
+mod history;
+mod i18n;
+pub mod info;
+pub mod launch;
+mod meter;
+mod midi;
+mod reference;
+mod spectrum;
+
+const HISTORY_LIMIT: usize = 100;
+
+/// The stream settings and profiles as last handed to the engine. The editor
+/// has unapplied changes while what it shows differs from this. Settings
+/// behind atomics apply live and aren't part of it.
+#[derive(Debug, Clone, PartialEq)]
+struct Snapshot {
+    sample_rate: Option<u32>,
+    buffer_size: Option<u32>,
+    linear_phase: bool,
+    overflow: OverflowPolicy,
+    profile: EqProfile,
+    side_profile: EqProfile,
+}
+
+impl Snapshot {
+    fn new(settings: &Settings, profile: EqProfile, side_profile: EqProfile) -> Self {
+        Self {
+            sample_rate: settings.sample_rate,
+            buffer_size: settings.buffer_size,
+            linear_phase: settings.linear_phase,
+            overflow: settings.overflow,
+            profile,
+            side_profile,
+        }
+    }
+}
 
 pub struct App {
     eq_settings: Settings,
     eq_profile: DerefMutHook<EqProfile>,
-    eq_settings_back: Settings,
-    eq_profile_back: EqProfile,
+    applied: Snapshot,
+    history: History<EqProfile>,
+    // last profile recorded in `history`; edits are committed once no drag is in progress
+    committed_profile: EqProfile,
     sender: SyncSender<Command>,
     state: State,
     info: Info,
     window_hidden: bool,
+    window_restored: bool,
     quitting: bool,
+    preset_name: String,
+    setup_name: String,
+    channel_mode: ChannelMode,
+    // in mid/side mode, the profile not currently shown in the editor
+    hidden_profile: EqProfile,
+    editing_side: bool,
+    // A/B slot in the editor and the other slot's profile, mirroring the executor
+    ab_slot: AbSlot,
+    ab_other: EqProfile,
+    // hides which slot is playing
+    ab_blind: bool,
+    paste_requested: Option<Instant>,
+    // the Reset confirmation is open
+    confirm_reset: bool,
+    // transient message for the status line
+    notice: Option<(String, Instant)>,
+    underruns_seen: u64,
+    underruns_checked: Instant,
+    underrun_rate: u64,
+    // what the selected devices accept, fetched lazily
+    stream_options: Option<StreamOptions>,
+    // pending device list request and when the last one was sent
+    device_poll: Option<OneShot<DeviceList>>,
+    devices_checked: Instant,
+    // input and output meter markers per channel
+    peak_holds: [[PeakHold; METER_CHANNELS]; 2],
+    spectrum: Spectrum,
+    // profile the clip warning was last computed for, and the overshoot
+    clip_check: Option<(EqProfile, Option<f64>)>,
+    curve: CurveCache,
+    midi: MidiControl,
+    // curve drawn behind the profile's for comparison
+    reference: Option<Reference>,
 }
 
 impl App {
@@ -34,19 +111,55 @@ impl App {
         info: Info,
     ) -> Self {
         Self {
-            eq_settings_back: eq_settings.clone(),
-            eq_profile_back: eq_profile.clone(),
+            applied: Snapshot::new(&eq_settings, eq_profile.clone(), info.side_profile.clone()),
+            history: History::new(HISTORY_LIMIT),
+            committed_profile: eq_profile.clone(),
+            channel_mode: info.channel_mode,
+            hidden_profile: info.side_profile.clone(),
+            ab_other: eq_profile.clone(),
             eq_settings,
             eq_profile: DerefMutHook::new(eq_profile),
             sender,
             state,
             info,
             window_hidden: false,
+            window_restored: false,
             quitting: false,
+            preset_name: String::new(),
+            setup_name: String::new(),
+            editing_side: false,
+            ab_slot: AbSlot::default(),
+            ab_blind: false,
+            paste_requested: None,
+            confirm_reset: false,
+            notice: None,
+            underruns_seen: 0,
+            underruns_checked: Instant::now(),
+            underrun_rate: 0,
+            stream_options: None,
+            device_poll: None,
+            devices_checked: Instant::now(),
+            peak_holds: Default::default(),
+            spectrum: Spectrum::default(),
+            clip_check: None,
+            curve: CurveCache::default(),
+            midi: MidiControl::default(),
+            reference: None,
         }
     }
 }
 
+/// egui only redraws on input or when asked to, so the app stays idle unless
+/// something on screen can change by itself. Repaints are requested by:
+/// - the executor, through `Status::on_change`, when an error is set or cleared
+/// - the level meters and the spectrum, at about 30 fps while running and shown
+/// - the status line, once a second while running (underrun rate) and when a
+///   notice expires
+/// - device polling every few seconds, and while a reply is pending
+/// - a pending paste
+///
+/// Profile edits, including realtime drags, come with pointer or keyboard
+/// input and redraw on their own.
 impl eframe::App for App {
     fn logic(&mut self, ctx: &eframe::egui::Context, _frame: &mut eframe::Frame) {
         debug!("logic tick");
@@ -57,12 +170,18 @@ impl eframe::App for App {
             ctx.send_viewport_cmd(egui::ViewportCommand::Visible(true));
             self.window_hidden = false;
         }
+        if !self.window_hidden {
+            self.track_window(ctx);
+        }
     }
 
     fn ui(&mut self, ui: &mut eframe::egui::Ui, _frame: &mut eframe::Frame) {
         let close_requested = ui.ctx().input(|i| i.viewport().close_requested());
 
         if close_requested && !self.quitting {
+            // Closing only hides the window; the geometry is saved now in
+            // case the app is then ended from outside
+            self.sender.send(Command::SaveWindow(self.info.window)).ok();
             ui.ctx()
                 .send_viewport_cmd(egui::ViewportCommand::CancelClose);
             ui.ctx()
@@ -70,12 +189,316 @@ impl eframe::App for App {
             self.window_hidden = true;
             return;
         }
+        self.take_external_profile();
+        self.handle_midi();
         CentralPanel::default().show_inside(ui, |ui| {
             ui.vertical(|ui| {
                 self.heading_ui(ui);
                 self.equalizer_ui(ui);
-                self.graph_ui(ui);
+                if self.info.window.show_graph {
+                    self.graph_ui(ui);
+                } else {
+                    let analyzer = &self.eq_settings.status.analyzer;
+                    analyzer
+                        .enabled
+                        .store(false, std::sync::atomic::Ordering::Relaxed);
+                }
             })
         });
+        self.commit_history(ui.ctx());
+        self.eq_profile.flush();
+    }
+
+    fn on_exit(&mut self, _gl: Option<&eframe::glow::Context>) {
+        // Quit saved it already
+        if !self.quitting {
+            self.sender.send(Command::SaveWindow(self.info.window)).ok();
+        }
+    }
+}
+
+impl App {
+    /// Follows the window geometry so it can be saved on quit. On the first
+    /// frame, moves a window restored off-screen back onto the monitor.
+    fn track_window(&mut self, ctx: &egui::Context) {
+        let (outer_rect, inner_rect, monitor_size) = ctx.input(|i| {
+            let viewport = i.viewport();
+            (
+                viewport.outer_rect,
+                viewport.inner_rect,
+                viewport.monitor_size,
+            )
+        });
+        let Some(monitor_size) = monitor_size else {
+            return;
+        };
+        let monitor_size = [monitor_size.x, monitor_size.y];
+        let window = &mut self.info.window;
+        if !self.window_restored {
+            self.window_restored = true;
+            if let Some(position) = window.clamped_position(monitor_size)
+                && Some(position) != window.position
+            {
+                ctx.send_viewport_cmd(egui::ViewportCommand::OuterPosition(position.into()));
+                return;
+            }
+        }
+        if let Some(outer_rect) = outer_rect {
+            window.position = Some([outer_rect.min.x, outer_rect.min.y]);
+        }
+        if let Some(inner_rect) = inner_rect {
+            window.size = [inner_rect.width(), inner_rect.height()];
+        }
+        window.monitor_size = Some(monitor_size);
+    }
+
+    /// Records the profile as an undo step once it settles, so a slider drag
+    /// or a value being typed becomes one step rather than one per frame.
+    fn commit_history(&mut self, ctx: &egui::Context) {
+        let settled = !ctx.input(|i| i.pointer.any_down()) && ctx.memory(|m| m.focused().is_none());
+        if settled {
+            self.commit_pending();
+        }
+    }
+
+    fn commit_pending(&mut self) {
+        if *self.eq_profile != self.committed_profile {
+            let previous = std::mem::replace(&mut self.committed_profile, self.eq_profile.clone());
+            self.history.push(previous);
+        }
+    }
+
+    fn undo(&mut self) {
+        self.commit_pending();
+        if let Some(profile) = self.history.undo(self.eq_profile.clone()) {
+            self.restore(profile);
+        }
+    }
+
+    /// Clears the profile as one undo step of its own.
+    fn reset_profile(&mut self) {
+        self.commit_pending();
+        *self.eq_profile.deref_mut() = EqProfile::default();
+        self.commit_pending();
+    }
+
+    /// Shows a main profile changed outside the GUI, e.g. in config.toml, as
+    /// the applied one, so the next Apply or realtime edit doesn't revert
+    /// it. It is one undo step of its own.
+    fn take_external_profile(&mut self) {
+        let Some(profile) = self.eq_settings.status.take_profile() else {
+            return;
+        };
+        self.applied.profile = profile.clone();
+        if self.editing_side {
+            self.hidden_profile = profile;
+        } else {
+            self.commit_pending();
+            *self.eq_profile.deref_mut() = profile;
+            self.commit_pending();
+        }
+    }
+
+    fn redo(&mut self) {
+        if let Some(profile) = self.history.redo(self.eq_profile.clone()) {
+            self.restore(profile);
+        }
+    }
+
+    /// Hands copies of the settings and both profiles to the executor, which
+    /// rebuilds the engine from them. The UI only ever edits its own
+    /// `eq_profile`; the engine gets a profile in one of two ways:
+    /// - normally, here, when Apply is pressed
+    /// - in realtime mode, through the `eq_profile` hook, flushed once at the
+    ///   end of every frame that edited it
+    fn apply(&mut self) {
+        self.send_snapshot();
+        self.sender.send(Command::Restart).ok();
+    }
+
+    /// Hands the settings and profiles to the executor without restarting,
+    /// for a restart that follows anyway.
+    fn send_snapshot(&mut self) {
+        self.sender
+            .send(Command::UpdateSettings(self.eq_settings.clone()))
+            .ok();
+        let (profile, side_profile) = self.profiles();
+        self.sender.send(Command::UpdateProfile(profile)).ok();
+        self.sender
+            .send(Command::UpdateSideProfile(side_profile))
+            .ok();
+        self.applied = self.snapshot();
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        let (profile, side_profile) = self.profiles();
+        Snapshot::new(&self.eq_settings, profile, side_profile)
+    }
+
+    /// Whether the editor shows settings or profiles the engine doesn't run.
+    /// Edits made in realtime mode are live and never count.
+    fn is_dirty(&self) -> bool {
+        !self.state.realtime && self.snapshot() != self.applied
+    }
+
+    /// The (main or mid, side) profiles, whichever one is in the editor.
+    fn profiles(&self) -> (EqProfile, EqProfile) {
+        if self.editing_side {
+            (self.hidden_profile.clone(), self.eq_profile.clone())
+        } else {
+            (self.eq_profile.clone(), self.hidden_profile.clone())
+        }
+    }
+
+    /// Switches the editor between the mid and side profiles. Undo history
+    /// doesn't carry over.
+    fn toggle_side_editing(&mut self) {
+        self.editing_side = !self.editing_side;
+        let shown = std::mem::replace(&mut self.hidden_profile, self.eq_profile.clone());
+        self.history = History::new(HISTORY_LIMIT);
+        self.restore(shown);
+    }
+
+    /// Switches between the A and B profiles, keeping the edits of the one
+    /// left. The engine gets the new one without a restart; in realtime mode
+    /// through the `eq_profile` hook. Undo history doesn't carry over.
+    fn swap_ab(&mut self) {
+        self.sender
+            .send(Command::SwapAB(self.eq_profile.clone()))
+            .ok();
+        self.ab_slot = self.ab_slot.other();
+        let shown = std::mem::replace(&mut self.ab_other, self.eq_profile.clone());
+        self.history = History::new(HISTORY_LIMIT);
+        self.applied.profile = shown.clone();
+        self.restore(shown);
+    }
+
+    /// Overshoot of the edited profile above 0 dB, recomputed when it changes.
+    fn clip_db(&mut self) -> Option<f64> {
+        match &self.clip_check {
+            Some((profile, over)) if profile == &*self.eq_profile => *over,
+            _ => {
+                let over = graph::clip_db(&self.eq_profile, &self.info.ranges);
+                self.clip_check = Some((self.eq_profile.clone(), over));
+                over
+            }
+        }
+    }
+
+    /// Puts a preset into the editor. The main profile runs it at once; the
+    /// side profile, like its other edits, waits for Apply.
+    fn load_preset(&mut self, name: String, profile: EqProfile) {
+        self.preset_name = name.clone();
+        *self.eq_profile.deref_mut() = profile.clone();
+        if !self.editing_side {
+            self.applied.profile = profile;
+            self.sender.send(Command::LoadPreset(name)).ok();
+        }
+    }
+
+    fn restore(&mut self, profile: EqProfile) {
+        self.committed_profile = profile.clone();
+        *self.eq_profile.deref_mut() = profile;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::sync_channel;
+
+    use super::*;
+    use crate::settings::PersistentSettings;
+
+    #[test]
+    fn test_dirty_state() {
+        let (sender, receiver) = sync_channel(64);
+        let settings = Settings::from(&PersistentSettings::default());
+        let applied = EqProfile {
+            preamp_db: -2.0,
+            ..Default::default()
+        };
+        let mut app = App::new(
+            settings,
+            applied.clone(),
+            sender,
+            State::default(),
+            Info::default(),
+        );
+        assert!(!app.is_dirty());
+
+        app.eq_profile.preamp_db = -3.0;
+        assert!(app.is_dirty());
+        // Undoing back to what runs is clean again, redoing isn't
+        app.undo();
+        assert_eq!(*app.eq_profile, applied);
+        assert!(!app.is_dirty());
+        app.redo();
+        assert!(app.is_dirty());
+
+        app.apply();
+        assert!(!app.is_dirty());
+        assert!(matches!(receiver.try_iter().last(), Some(Command::Restart)));
+        // Reset is an edit like any other
+        *app.eq_profile.deref_mut() = EqProfile::default();
+        assert!(app.is_dirty());
+        app.undo();
+        assert!(!app.is_dirty());
+
+        app.eq_settings.buffer_size = Some(256);
+        assert!(app.is_dirty());
+        app.eq_settings.buffer_size = None;
+        assert!(!app.is_dirty());
+        // Live settings apply at once
+        app.eq_settings.output_gain_db.store(-6.0);
+        app.eq_settings
+            .latency
+            .store(50, std::sync::atomic::Ordering::Relaxed);
+        assert!(!app.is_dirty());
+
+        // Reset is a step of its own, even right after an edit
+        app.eq_profile.preamp_db = 2.0;
+        app.reset_profile();
+        assert_eq!(*app.eq_profile, EqProfile::default());
+        app.undo();
+        assert_eq!(app.eq_profile.preamp_db, 2.0);
+
+        // Realtime edits run as they are made
+        app.state.realtime = true;
+        app.eq_profile.preamp_db = 1.0;
+        assert!(!app.is_dirty());
+    }
+
+    #[test]
+    fn test_preset_in_side_editor() {
+        let (sender, receiver) = sync_channel(64);
+        let settings = Settings::from(&PersistentSettings::default());
+        let main = EqProfile {
+            preamp_db: -2.0,
+            ..Default::default()
+        };
+        let preset = EqProfile {
+            preamp_db: -5.0,
+            ..Default::default()
+        };
+        let mut app = App::new(
+            settings,
+            main.clone(),
+            sender,
+            State::default(),
+            Info::default(),
+        );
+        app.toggle_side_editing();
+        app.load_preset("Side".to_string(), preset.clone());
+        assert_eq!(app.profiles(), (main.clone(), preset.clone()));
+        assert_eq!(app.applied.profile, main);
+        // Applied with the next Apply, not as the main profile
+        assert!(receiver.try_recv().is_err());
+        assert!(app.is_dirty());
+
+        app.toggle_side_editing();
+        app.load_preset("Main".to_string(), preset.clone());
+        assert_eq!(app.profiles().0, preset);
+        assert!(matches!(receiver.try_recv(), Ok(Command::LoadPreset(name)) if name == "Main"));
+        assert_eq!(app.applied.profile, preset);
     }
 }
@@ -0,0 +1,138 @@
+use std::path::Path;
+
+use anyhow::{Context, Result};
+
+use crate::{
+    cli::fit::parse_target,
+    eq::EqProfile,
+    ui::graph::{PREVIEW_FS, curve_value},
+};
+
+/// What the profile is compared against.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ReferenceCurve {
+    /// Its bands' response, like the profile's without the preamp
+    Profile(EqProfile),
+    /// Measured or target points, sorted by frequency
+    Target(Vec<[f64; 2]>),
+}
+
+/// A curve drawn dashed behind the profile's, with the difference between
+/// the two.
+pub struct Reference {
+    /// File name, for the toggle's hover text
+    pub name: String,
+    pub shown: bool,
+    curve: ReferenceCurve,
+    // the reference at the frequencies of the last profile curve; None
+    // outside a target's range
+    values: Vec<Option<f64>>,
+}
+
+impl Reference {
+    pub fn new(name: String, curve: ReferenceCurve) -> Self {
+        Self {
+            name,
+            shown: true,
+            curve,
+            values: Vec::new(),
+        }
+    }
+
+    /// A `.csv` file is a target curve as `fit --target` reads it; anything
+    /// else is a profile.
+    pub fn load(path: &Path) -> Result<Self> {
+        let name = path
+            .file_name()
+            .map_or_else(String::new, |n| n.to_string_lossy().into_owned());
+        let is_csv = path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("csv"));
+        let curve = if is_csv {
+            let content = std::fs::read_to_string(path)
+                .with_context(|| format!("Failed to read {}", path.display()))?;
+            let points = parse_target(&content)?;
+            ReferenceCurve::Target(points.into_iter().map(|(f, db)| [f, db]).collect())
+        } else {
+            ReferenceCurve::Profile(EqProfile::load(path)?)
+        };
+        Ok(Self::new(name, curve))
+    }
+
+    /// The reference at the frequencies of `points`, recomputed when their
+    /// number changes; the graph's frequencies only depend on it.
+    pub fn values(&mut self, points: &[[f64; 2]]) -> &[Option<f64>] {
+        if self.values.len() != points.len() {
+            let freqs: Vec<f64> = points.iter().map(|p| p[0]).collect();
+            self.values = match &self.curve {
+                ReferenceCurve::Profile(profile) => profile
+                    .magnitude_response(&freqs, PREVIEW_FS)
+                    .into_iter()
+                    .map(Some)
+                    .collect(),
+                ReferenceCurve::Target(target) => freqs
+                    .iter()
+                    .map(|freq| curve_value(target, *freq))
+                    .collect(),
+            };
+        }
+        &self.values
+    }
+}
+
+/// Profile minus reference wherever the reference has a value.
+pub fn difference(points: &[[f64; 2]], reference: &[Option<f64>]) -> Vec<[f64; 2]> {
+    points
+        .iter()
+        .zip(reference)
+        .filter_map(|([freq, db], r)| Some([*freq, db - (*r)?]))
+        .collect()
+}
+
+/// The point of the difference furthest from 0 dB.
+pub fn max_deviation(difference: &[[f64; 2]]) -> Option<[f64; 2]> {
+    difference
+        .iter()
+        .copied()
+        .max_by(|a, b| a[1].abs().total_cmp(&b[1].abs()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eq::Filter;
+
+    #[test]
+    fn test_difference_to_reference() {
+        let points = [[20.0, 1.0], [100.0, 2.0], [1000.0, 0.0], [10000.0, -1.0]];
+        // A target covering part of the range is only compared there
+        let mut reference = Reference::new(
+            "target.csv".to_string(),
+            ReferenceCurve::Target(vec![[100.0, 0.0], [1000.0, 4.0]]),
+        );
+        let values = reference.values(&points).to_vec();
+        assert_eq!(values, [None, Some(0.0), Some(4.0), None]);
+        let diff = difference(&points, &values);
+        assert_eq!(diff, [[100.0, 2.0], [1000.0, -4.0]]);
+        assert_eq!(max_deviation(&diff), Some([1000.0, -4.0]));
+        assert_eq!(max_deviation(&[]), None);
+
+        // A profile compared with itself doesn't differ anywhere
+        let profile = EqProfile {
+            filters: vec![Filter {
+                gain: 6.0,
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let freqs = [50.0, 1000.0, 5000.0];
+        let gains = profile.magnitude_response(&freqs, PREVIEW_FS);
+        let points: Vec<[f64; 2]> = freqs.iter().zip(gains).map(|(f, g)| [*f, g]).collect();
+        let mut reference =
+            Reference::new("same.txt".to_string(), ReferenceCurve::Profile(profile));
+        let values = reference.values(&points).to_vec();
+        let diff = difference(&points, &values);
+        assert_eq!(diff.len(), 3);
+        assert!(diff.iter().all(|[_, db]| db.abs() < 1e-12));
+    }
+}
@@ -0,0 +1,124 @@
+use std::sync::Arc;
+
+use ringbuf::traits::Consumer;
+use rustfft::{Fft, FftPlanner, num_complex::Complex};
+
+use crate::settings::AnalyzerTap;
+
+const FFT_SIZE: usize = 4096;
+/// Levels below this are drawn at the floor.
+pub const FLOOR_DB: f32 = -96.0;
+
+/// Magnitude spectrum of the output, computed on the UI thread from the
+/// samples the output callback copies into [`AnalyzerTap`].
+pub struct Spectrum {
+    fft: Arc<dyn Fft<f32>>,
+    window: Vec<f32>,
+    // the latest FFT_SIZE samples, oldest first
+    samples: Vec<f32>,
+    scratch: Vec<Complex<f32>>,
+    /// Smoothed level per bin in dBFS, up to Nyquist
+    pub levels: Vec<f32>,
+    pub sample_rate: f32,
+    /// 0.0 follows every frame; towards 1.0 falling levels decay more slowly
+    pub averaging: f32,
+}
+
+impl Default for Spectrum {
+    fn default() -> Self {
+        // Hann window
+        let window = (0..FFT_SIZE)
+            .map(|i| {
+                let phase = i as f32 / FFT_SIZE as f32 * std::f32::consts::TAU;
+                0.5 - 0.5 * phase.cos()
+            })
+            .collect();
+        Self {
+            fft: FftPlanner::new().plan_fft_forward(FFT_SIZE),
+            window,
+            samples: vec![0.0; FFT_SIZE],
+            scratch: vec![Complex::default(); FFT_SIZE],
+            levels: vec![FLOOR_DB; FFT_SIZE / 2],
+            sample_rate: 48000.0,
+            averaging: 0.7,
+        }
+    }
+}
+
+impl Spectrum {
+    /// Drains the tap and recomputes the levels. Returns false when nothing
+    /// new arrived.
+    pub fn update(&mut self, tap: &AnalyzerTap) -> bool {
+        let Ok(mut consumer) = tap.samples.lock() else {
+            return false;
+        };
+        let Some(consumer) = consumer.as_mut() else {
+            return false;
+        };
+        let new: Vec<f32> = consumer.pop_iter().collect();
+        if new.is_empty() {
+            return false;
+        }
+        self.sample_rate = tap.sample_rate.load(std::sync::atomic::Ordering::Relaxed) as f32;
+        self.push(&new);
+        true
+    }
+
+    /// Appends samples and runs the FFT over the latest window.
+    pub fn push(&mut self, new: &[f32]) {
+        let new = &new[new.len().saturating_sub(FFT_SIZE)..];
+        self.samples.drain(..new.len());
+        self.samples.extend_from_slice(new);
+
+        for ((out, sample), window) in self
+            .scratch
+            .iter_mut()
+            .zip(self.samples.iter())
+            .zip(self.window.iter())
+        {
+            *out = Complex::new(sample * window, 0.0);
+        }
+        self.fft.process(&mut self.scratch);
+
+        // A full-scale sine reads 0 dBFS
+        let scale = 2.0 / self.window.iter().sum::<f32>();
+        for (level, bin) in self.levels.iter_mut().zip(self.scratch.iter()) {
+            let db = (20.0 * (bin.norm() * scale).log10()).max(FLOOR_DB);
+            *level = if db >= *level {
+                db
+            } else {
+                *level * self.averaging + db * (1.0 - self.averaging)
+            };
+        }
+    }
+
+    /// (frequency, dBFS) of each bin.
+    pub fn points(&self) -> impl Iterator<Item = (f32, f32)> + '_ {
+        let bin_width = self.sample_rate / FFT_SIZE as f32;
+        self.levels
+            .iter()
+            .enumerate()
+            .map(move |(i, level)| (i as f32 * bin_width, *level))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sine_peaks_at_its_frequency() {
+        let mut spectrum = Spectrum::default();
+        let samples: Vec<f32> = (0..FFT_SIZE)
+            .map(|i| (i as f32 * 1000.0 / 48000.0 * std::f32::consts::TAU).sin() * 0.5)
+            .collect();
+        spectrum.push(&samples);
+        let (freq, level) = spectrum
+            .points()
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+            .unwrap();
+        assert!((freq - 1000.0).abs() < 12.0, "peak at {} Hz", freq);
+        // -6 dBFS, give or take the window's scalloping
+        assert!((level - -6.0).abs() < 1.5, "peak level {}", level);
+    }
+}
@@ -1,4 +1,8 @@
-use std::ops::{Deref, DerefMut};
+use std::{
+    fmt::Debug,
+    ops::{Deref, DerefMut},
+    sync::atomic::{AtomicU32, Ordering},
+};
 
 pub use oneshot::OneShot;
 
@@ -25,6 +29,7 @@ pub mod oneshot {
         }
     }
 
+    #[cfg_attr(not(feature = "gui"), allow(dead_code))]
     impl<T> OneShot<T> {
         pub fn new() -> Self {
             let value = Box::into_raw(Box::new(None));
@@ -43,6 +48,15 @@ pub mod oneshot {
             }
         }
 
+        /// Takes the value if it has been sent, without waiting.
+        pub fn try_recv(&self) -> Option<T> {
+            if self.0.has_value.load(std::sync::atomic::Ordering::Acquire) {
+                unsafe { (*self.0.value).take() }
+            } else {
+                None
+            }
+        }
+
         pub fn recv(&self) -> T {
             while !self.0.has_value.load(std::sync::atomic::Ordering::Acquire) {
                 std::thread::yield_now();
@@ -52,30 +66,84 @@ pub mod oneshot {
     }
 }
 
+/// An `f32` that can be shared with the audio callbacks, stored as its bits.
+#[derive(Default)]
+pub struct AtomicF32(AtomicU32);
+
+impl AtomicF32 {
+    pub fn new(value: f32) -> Self {
+        Self(AtomicU32::new(value.to_bits()))
+    }
+
+    pub fn load(&self) -> f32 {
+        f32::from_bits(self.0.load(Ordering::Relaxed))
+    }
+
+    pub fn store(&self, value: f32) {
+        self.0.store(value.to_bits(), Ordering::Relaxed);
+    }
+}
+
+impl Debug for AtomicF32 {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.load().fmt(f)
+    }
+}
+
+/// Calls back with the data after it was changed. A mutable borrow only
+/// marks the data; `flush` then runs the callback once with the result if it
+/// differs from what the callback saw last, so borrows that only read or
+/// write the same value back don't trigger it.
+#[cfg_attr(not(feature = "gui"), allow(dead_code))]
 pub struct DerefMutHook<T> {
     data: T,
     call: Option<Box<dyn Fn(&T)>>,
+    touched: bool,
+    // the data as last passed to the callback
+    seen: Option<T>,
 }
 
+#[cfg_attr(not(feature = "gui"), allow(dead_code))]
 impl<T> DerefMutHook<T> {
     pub fn new(data: T) -> Self {
-        Self { data, call: None }
+        Self {
+            data,
+            call: None,
+            touched: false,
+            seen: None,
+        }
+    }
+
+    pub fn remove_hook(&mut self) {
+        self.call = None;
+        self.seen = None;
     }
+}
 
+#[cfg_attr(not(feature = "gui"), allow(dead_code))]
+impl<T: PartialEq + Clone> DerefMutHook<T> {
+    /// Reports changes from now on; the current data counts as seen.
     pub fn set_callback(&mut self, callback: impl Fn(&T) + 'static) {
         self.call = Some(Box::new(callback));
+        self.touched = false;
+        self.seen = Some(self.data.clone());
     }
 
-    pub fn remove_hook(&mut self) {
-        self.call = None;
+    /// Runs the callback if the data changed since the last flush.
+    pub fn flush(&mut self) {
+        if !std::mem::take(&mut self.touched) || self.seen.as_ref() == Some(&self.data) {
+            return;
+        }
+        if let Some(call) = self.call.as_ref() {
+            call(&self.data);
+            self.seen = Some(self.data.clone());
+        }
     }
 }
 
 impl<T> DerefMut for DerefMutHook<T> {
     fn deref_mut(&mut self) -> &mut Self::Target {
-        if let Some(call) = self.call.as_ref() {
-            call(&self.data);
-        }
+        self.touched = true;
         &mut self.data
     }
 }
@@ -87,3 +155,56 @@ impl<T> Deref for DerefMutHook<T> {
         &self.data
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use super::*;
+
+    #[test]
+    fn test_hook_sees_each_edit_once() {
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        let mut hook = DerefMutHook::new(0);
+        let log = seen.clone();
+        hook.set_callback(move |value| log.borrow_mut().push(*value));
+        *hook = 1;
+        hook.flush();
+        hook.flush();
+        assert_eq!(*seen.borrow(), [1]);
+        // Several borrows in one frame go out as one update
+        *hook = 2;
+        *hook += 1;
+        hook.flush();
+        assert_eq!(*seen.borrow(), [1, 3]);
+        hook.remove_hook();
+        *hook = 4;
+        hook.flush();
+        assert_eq!(*seen.borrow(), [1, 3]);
+    }
+
+    #[test]
+    fn test_hook_ignores_borrows_without_changes() {
+        let calls = Rc::new(RefCell::new(0));
+        let mut hook = DerefMutHook::new(vec![0; 4]);
+        let counter = calls.clone();
+        hook.set_callback(move |_| *counter.borrow_mut() += 1);
+        for _ in 0..10 {
+            assert_eq!(hook.len(), 4);
+            // Widgets take `&mut` even when nothing is dragged
+            let _ = &mut hook[0];
+            hook.flush();
+        }
+        assert_eq!(*calls.borrow(), 0);
+        for i in 1..=3 {
+            hook[0] = i;
+            hook.flush();
+        }
+        assert_eq!(*calls.borrow(), 3);
+        // A change that is undone before the flush is no change
+        hook[1] = 7;
+        hook[1] = 0;
+        hook.flush();
+        assert_eq!(*calls.borrow(), 3);
+    }
+}
@@ -0,0 +1,88 @@
+//! Hot reload for files edited outside the app.
+
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    sync::mpsc::{RecvTimeoutError, channel},
+    time::Duration,
+};
+
+use anyhow::Result;
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use tracing::warn;
+
+/// Keeps watching until dropped.
+pub struct FileWatcher {
+    _watcher: RecommendedWatcher,
+}
+
+/// Calls `on_change` for each of `paths` that was modified, once writes to it
+/// have been quiet for `debounce`. Parent directories are watched rather than
+/// the files themselves so atomic renames over the target are still seen.
+pub fn watch(
+    paths: Vec<PathBuf>,
+    debounce: Duration,
+    mut on_change: impl FnMut(&Path) + Send + 'static,
+) -> Result<FileWatcher> {
+    let (sender, receiver) = channel();
+    let mut watcher = notify::recommended_watcher(sender)?;
+    let dirs: HashSet<&Path> = paths.iter().filter_map(|p| p.parent()).collect();
+    for dir in dirs {
+        watcher.watch(dir, RecursiveMode::NonRecursive)?;
+    }
+    std::thread::spawn(move || {
+        let mut pending = HashSet::new();
+        loop {
+            let event = if pending.is_empty() {
+                receiver.recv().map_err(|_| RecvTimeoutError::Disconnected)
+            } else {
+                receiver.recv_timeout(debounce)
+            };
+            match event {
+                Ok(Ok(event)) => {
+                    if event.kind.is_access() {
+                        continue;
+                    }
+                    pending.extend(event.paths.into_iter().filter(|p| paths.contains(p)));
+                }
+                Ok(Err(e)) => warn!("File watcher error: {:?}", e),
+                Err(RecvTimeoutError::Timeout) => {
+                    for path in pending.drain() {
+                        on_change(&path);
+                    }
+                }
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+        }
+    });
+    Ok(FileWatcher { _watcher: watcher })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_modify_triggers_reload() {
+        let dir = tempfile::tempdir().unwrap();
+        // Canonical, since watchers report resolved paths (e.g. /private/var on macOS)
+        let path = dir.path().canonicalize().unwrap().join("profile.txt");
+        std::fs::write(&path, "Preamp: 0 dB").unwrap();
+        std::fs::write(dir.path().join("other.txt"), "").unwrap();
+
+        let (sender, receiver) = channel();
+        let _watcher = watch(vec![path.clone()], Duration::from_millis(100), move |p| {
+            sender.send(p.to_path_buf()).ok();
+        })
+        .unwrap();
+        for i in 0..5 {
+            std::fs::write(&path, format!("Preamp: -{} dB", i)).unwrap();
+        }
+        std::fs::write(dir.path().join("other.txt"), "ignored").unwrap();
+
+        let reloaded = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(reloaded, path);
+        // The burst of writes is coalesced into one reload
+        assert!(receiver.recv_timeout(Duration::from_millis(500)).is_err());
+    }
+}
@@ -0,0 +1,226 @@
+//! WAV files: impulse responses written for convolution engines, by
+//! `convert --to wav`, and audio read and written back by `batch`.
+
+use anyhow::{Context, Result, bail};
+
+// WAVE_FORMAT_PCM, WAVE_FORMAT_IEEE_FLOAT and WAVE_FORMAT_EXTENSIBLE
+const PCM: u16 = 1;
+const FLOAT: u16 = 3;
+const EXTENSIBLE: u16 = 0xfffe;
+
+/// How a WAV file stores its samples.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct WavFormat {
+    pub channels: u16,
+    pub sample_rate: u32,
+    /// 8, 16, 24 or 32 for integers, 32 or 64 for floats
+    pub bits: u16,
+    pub float: bool,
+}
+
+/// Interleaved samples scaled to ±1, and the format they came in.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Wav {
+    pub format: WavFormat,
+    pub samples: Vec<f32>,
+}
+
+impl Wav {
+    pub fn frames(&self) -> usize {
+        self.samples.len() / self.format.channels.max(1) as usize
+    }
+
+    /// Reads PCM and float files, plain or `WAVE_FORMAT_EXTENSIBLE`. Chunks
+    /// other than `fmt ` and `data` are skipped.
+    pub fn parse(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() < 12 || &bytes[..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+            bail!("Not a RIFF WAVE file");
+        }
+        let mut format = None;
+        let mut rest = &bytes[12..];
+        while rest.len() >= 8 {
+            let id = &rest[..4];
+            let len = u32::from_le_bytes(rest[4..8].try_into().unwrap()) as usize;
+            // A truncated last chunk is read as far as it goes
+            let body = &rest[8..(8 + len).min(rest.len())];
+            match id {
+                b"fmt " => format = Some(parse_format(body)?),
+                b"data" => {
+                    let format = format.context("The data chunk comes before fmt")?;
+                    return Ok(Self {
+                        format,
+                        samples: decode(body, format),
+                    });
+                }
+                _ => {}
+            }
+            // Chunks are padded to an even length
+            rest = &rest[(8 + len + len % 2).min(rest.len())..];
+        }
+        bail!("No data chunk")
+    }
+
+    /// The file in its own format; integers are rounded and clipped.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let format = self.format;
+        let mut data = Vec::with_capacity(self.samples.len() * (format.bits / 8) as usize);
+        for sample in &self.samples {
+            encode(*sample, format, &mut data);
+        }
+        let mut out = header(format, data.len() as u32);
+        out.extend_from_slice(&data);
+        out
+    }
+}
+
+fn parse_format(body: &[u8]) -> Result<WavFormat> {
+    if body.len() < 16 {
+        bail!("The fmt chunk is too short");
+    }
+    let u16_at = |i: usize| u16::from_le_bytes([body[i], body[i + 1]]);
+    let mut tag = u16_at(0);
+    // The sub-format GUID starts with the format tag
+    if tag == EXTENSIBLE && body.len() >= 26 {
+        tag = u16_at(24);
+    }
+    let format = WavFormat {
+        channels: u16_at(2),
+        sample_rate: u32::from_le_bytes(body[4..8].try_into().unwrap()),
+        bits: u16_at(14),
+        float: tag == FLOAT,
+    };
+    let supported = match tag {
+        PCM => matches!(format.bits, 8 | 16 | 24 | 32),
+        FLOAT => matches!(format.bits, 32 | 64),
+        _ => false,
+    };
+    if !supported {
+        bail!("Unsupported format {} with {} bits", tag, format.bits);
+    }
+    if format.channels == 0 || format.sample_rate == 0 {
+        bail!("No channels or no sample rate");
+    }
+    Ok(format)
+}
+
+fn decode(data: &[u8], format: WavFormat) -> Vec<f32> {
+    let width = (format.bits / 8) as usize;
+    data.chunks_exact(width)
+        .map(|b| match (format.float, format.bits) {
+            (true, 32) => f32::from_le_bytes(b.try_into().unwrap()),
+            (true, _) => f64::from_le_bytes(b.try_into().unwrap()) as f32,
+            // 8-bit samples are unsigned
+            (false, 8) => (b[0] as f32 - 128.0) / 128.0,
+            (false, 16) => i16::from_le_bytes([b[0], b[1]]) as f32 / 32768.0,
+            (false, 24) => (i32::from_le_bytes([0, b[0], b[1], b[2]]) >> 8) as f32 / 8388608.0,
+            (false, _) => i32::from_le_bytes(b.try_into().unwrap()) as f32 / 2147483648.0,
+        })
+        .collect()
+}
+
+fn encode(sample: f32, format: WavFormat, out: &mut Vec<u8>) {
+    let int = |scale: f64| (sample as f64 * scale).round().clamp(-scale, scale - 1.0);
+    match (format.float, format.bits) {
+        (true, 32) => out.extend_from_slice(&sample.to_le_bytes()),
+        (true, _) => out.extend_from_slice(&(sample as f64).to_le_bytes()),
+        (false, 8) => out.push((int(128.0) + 128.0) as u8),
+        (false, 16) => out.extend_from_slice(&(int(32768.0) as i16).to_le_bytes()),
+        (false, 24) => out.extend_from_slice(&(int(8388608.0) as i32).to_le_bytes()[..3]),
+        (false, _) => out.extend_from_slice(&(int(2147483648.0) as i32).to_le_bytes()),
+    }
+}
+
+/// RIFF, `fmt ` and `data` headers for `data_len` bytes of samples.
+fn header(format: WavFormat, data_len: u32) -> Vec<u8> {
+    let block_align = format.channels * format.bits / 8;
+    let tag = if format.float { FLOAT } else { PCM };
+    let mut out = Vec::with_capacity(44 + data_len as usize);
+    out.extend_from_slice(b"RIFF");
+    out.extend_from_slice(&(36 + data_len).to_le_bytes());
+    out.extend_from_slice(b"WAVE");
+    out.extend_from_slice(b"fmt ");
+    out.extend_from_slice(&16u32.to_le_bytes());
+    out.extend_from_slice(&tag.to_le_bytes());
+    out.extend_from_slice(&format.channels.to_le_bytes());
+    out.extend_from_slice(&format.sample_rate.to_le_bytes());
+    out.extend_from_slice(&(format.sample_rate * block_align as u32).to_le_bytes());
+    out.extend_from_slice(&block_align.to_le_bytes());
+    out.extend_from_slice(&format.bits.to_le_bytes());
+    out.extend_from_slice(b"data");
+    out.extend_from_slice(&data_len.to_le_bytes());
+    out
+}
+
+/// `samples` as a mono, 32-bit float WAV file.
+pub fn wav_bytes(samples: &[f32], sample_rate: u32) -> Vec<u8> {
+    Wav {
+        format: WavFormat {
+            channels: 1,
+            sample_rate,
+            bits: 32,
+            float: true,
+        },
+        samples: samples.to_vec(),
+    }
+    .to_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wav_layout() {
+        let wav = wav_bytes(&[1.0, -0.5], 48000);
+        assert_eq!(wav.len(), 44 + 8);
+        assert_eq!(&wav[..4], b"RIFF");
+        assert_eq!(u32::from_le_bytes(wav[4..8].try_into().unwrap()), 44);
+        assert_eq!(&wav[8..16], b"WAVEfmt ");
+        assert_eq!(u16::from_le_bytes([wav[20], wav[21]]), 3);
+        assert_eq!(u32::from_le_bytes(wav[24..28].try_into().unwrap()), 48000);
+        assert_eq!(u32::from_le_bytes(wav[28..32].try_into().unwrap()), 192000);
+        assert_eq!(&wav[36..40], b"data");
+        assert_eq!(u32::from_le_bytes(wav[40..44].try_into().unwrap()), 8);
+        assert_eq!(f32::from_le_bytes(wav[48..52].try_into().unwrap()), -0.5);
+    }
+
+    #[test]
+    fn test_wav_round_trip() {
+        let samples = vec![0.0, 0.5, -0.25, -1.0, 0.75, 0.125];
+        for (bits, float) in [
+            (8, false),
+            (16, false),
+            (24, false),
+            (32, false),
+            (32, true),
+            (64, true),
+        ] {
+            let wav = Wav {
+                format: WavFormat {
+                    channels: 2,
+                    sample_rate: 44100,
+                    bits,
+                    float,
+                },
+                samples: samples.clone(),
+            };
+            let read = Wav::parse(&wav.to_bytes()).unwrap();
+            assert_eq!(read, wav, "{} bits", bits);
+            assert_eq!(read.frames(), 3);
+        }
+        // Full scale clips instead of wrapping around
+        let mut wav = Wav::parse(&wav_bytes(&[1.0], 8000)).unwrap();
+        wav.format.float = false;
+        wav.format.bits = 16;
+        assert_eq!(&wav.to_bytes()[44..], 32767i16.to_le_bytes());
+
+        // Unknown chunks are skipped, odd ones with their padding byte
+        let mut bytes = wav_bytes(&[0.5], 8000);
+        let data = bytes.split_off(36);
+        bytes.extend_from_slice(b"LIST\x03\x00\x00\x00abc\x00");
+        bytes.extend_from_slice(&data);
+        assert_eq!(Wav::parse(&bytes).unwrap().samples, [0.5]);
+        assert!(Wav::parse(b"RIFF\x04\x00\x00\x00WAVE").is_err());
+        assert!(Wav::parse(b"not a wav").is_err());
+    }
+}
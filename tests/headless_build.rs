@@ -0,0 +1,20 @@
+//! The crate without default features, as a headless server builds it: no
+//! window toolkit, only the CLI and the engine. Slow, as it is a build of
+//! its own, so it only runs when asked for:
+//! `cargo test --test headless_build -- --ignored`.
+
+use std::process::Command;
+
+#[test]
+#[ignore]
+fn test_builds_without_gui() {
+    let status = Command::new(env!("CARGO"))
+        .args(["build", "--no-default-features", "--bins"])
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        // A target directory of its own, so the build doesn't wait on the
+        // lock of the one running this test
+        .env("CARGO_TARGET_DIR", env!("CARGO_TARGET_TMPDIR"))
+        .status()
+        .expect("cargo runs");
+    assert!(status.success());
+}